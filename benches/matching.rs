@@ -0,0 +1,150 @@
+//! Benchmarks the matching loop by sweeping an incoming order through a deep book. Placing,
+//! canceling and matching a single order are measured against books of growing depth
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use octomatch::core::{
+    matcher::Matcher,
+    model::{Order, TradingPair},
+    orderbook::{LimitOrderBook, OrderBook},
+    types::{Asset, Long, OrderSide, OrderType, TimeInForce},
+    utils::Util,
+};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+const LEVELS: i64 = 500;
+const ORDERS_PER_LEVEL: i64 = 4;
+/// the number of orders resting in the books single orders are measured against
+const DEPTHS: [i64; 3] = [10, 100, 1_000];
+
+fn create_order(side: OrderSide, price: Decimal, order_type: OrderType, quantity: Long) -> Order {
+    Order {
+        orderid: Uuid::new_v4(),
+        client_order_id: None,
+        account_id: None,
+        tag: None,
+        price,
+        side,
+        quantity,
+        notional: None,
+        display_quantity: None,
+        min_fill: None,
+        client_timestamp: None,
+        hidden_quantity: 0,
+        stop_price: None,
+        order_type,
+        time_in_force: TimeInForce::GoodTillCancel,
+        seq: 0,
+        timestamp: Util::current_time_millis(),
+        trading_pair: TradingPair::from(Asset::ETH, Asset::USDC),
+    }
+}
+
+fn deep_book() -> LimitOrderBook {
+    book_of_depth(LEVELS)
+}
+
+fn book_of_depth(levels: i64) -> LimitOrderBook {
+    let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+    for level in 0..levels {
+        for _ in 0..ORDERS_PER_LEVEL {
+            let _ = orderbook.place(create_order(
+                OrderSide::Ask,
+                Decimal::from(100 + level),
+                OrderType::Limit,
+                10,
+            ));
+        }
+    }
+    orderbook
+}
+
+fn sweep(c: &mut Criterion) {
+    let matcher = Matcher {};
+    let book = deep_book();
+    let quantity = (LEVELS * ORDERS_PER_LEVEL * 10) as Long;
+
+    c.bench_function("market order sweeping the whole book", |b| {
+        b.iter_batched(
+            || book.clone(),
+            |mut orderbook| {
+                let order =
+                    create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, quantity);
+                black_box(matcher.match_order(order, &mut orderbook))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("limit order matching against the top level", |b| {
+        b.iter_batched(
+            || book.clone(),
+            |mut orderbook| {
+                let order = create_order(
+                    OrderSide::Bid,
+                    Decimal::from(100),
+                    OrderType::Limit,
+                    (ORDERS_PER_LEVEL * 10) as Long,
+                );
+                black_box(matcher.match_order(order, &mut orderbook))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn single_orders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single order");
+    for depth in DEPTHS {
+        // each level holds several orders, so this rounds the depth to whole levels
+        let book = book_of_depth(depth / ORDERS_PER_LEVEL);
+
+        group.bench_with_input(BenchmarkId::new("place", depth), &book, |b, book| {
+            b.iter_batched(
+                || book.clone(),
+                |mut orderbook| {
+                    let order =
+                        create_order(OrderSide::Bid, Decimal::from(50), OrderType::Limit, 10);
+                    black_box(orderbook.place(order))
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("cancel", depth), &book, |b, book| {
+            b.iter_batched(
+                || {
+                    let mut orderbook = book.clone();
+                    let order =
+                        create_order(OrderSide::Ask, Decimal::from(150), OrderType::Limit, 10);
+                    let _ = orderbook.place(order);
+                    (orderbook, order.orderid)
+                },
+                |(mut orderbook, orderid)| black_box(orderbook.cancel(orderid)),
+                BatchSize::LargeInput,
+            )
+        });
+
+        // filled by the top order alone, which is the most common match
+        for time_in_force in [TimeInForce::GoodTillCancel, TimeInForce::FillOrKill] {
+            let id = BenchmarkId::new(format!("match the top {:?}", time_in_force), depth);
+            group.bench_with_input(id, &book, |b, book| {
+                b.iter_batched(
+                    || book.clone(),
+                    |mut orderbook| {
+                        let order = Order {
+                            time_in_force,
+                            ..create_order(OrderSide::Bid, Decimal::from(100), OrderType::Limit, 5)
+                        };
+                        black_box(Matcher.match_order(order, &mut orderbook))
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sweep, single_orders);
+criterion_main!(benches);
@@ -0,0 +1,174 @@
+//! Runs random sequences of requests through an engine with two books, and checks the books
+//! are consistent after every request. Run it with `cargo fuzz run engine` from the root of the
+//! project, which starts from the sequences in `fuzz/corpus/engine`.
+//!
+//! The input is read as steps of eight bytes, any trailing bytes are ignored:
+//!
+//! | byte | meaning                                                                          |
+//! |------|----------------------------------------------------------------------------------|
+//! | 0    | the request: place, quote, cancel, cancel by client id or cancel a level (mod 5) |
+//! | 1    | bit 0 the pair, bit 1 the side, bits 2-4 the order type, bits 5-6 the time in    |
+//! |      | force, bit 7 an iceberg                                                          |
+//! | 2    | the price, in quarter steps from 90.00                                           |
+//! | 3    | the stop price, peg offset, client order id, quote spread or display quantity   |
+//! | 4-7  | the quantity as a big endian u32, or the largest quantity when every bit is set  |
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use octomatch::{
+    core::{
+        model::TradingPair,
+        router::{CancelOrder, PlaceOrder, Request, Response},
+        types::{
+            Asset, Long, OrderId, OrderSide, OrderStatus, OrderType, PegReference, TimeInForce,
+        },
+    },
+    Engine, EngineConfig,
+};
+use rust_decimal::Decimal;
+use std::num::NonZeroUsize;
+
+fn pair(flags: u8) -> TradingPair {
+    match flags & 1 {
+        0 => TradingPair::from(Asset::BTC, Asset::USDC),
+        _ => TradingPair::from(Asset::ETH, Asset::USDT),
+    }
+}
+
+fn side(flags: u8) -> OrderSide {
+    match flags & 2 {
+        0 => OrderSide::Bid,
+        _ => OrderSide::Ask,
+    }
+}
+
+/// Prices are kept close together, so most orders cross or join a level of the book
+fn price(byte: u8) -> Decimal {
+    Decimal::new(9_000 + 25 * i64::from(byte % 64), 2)
+}
+
+fn order_type(flags: u8, byte: u8) -> OrderType {
+    match (flags >> 2) & 7 {
+        1 => OrderType::Market,
+        2 => OrderType::Stop,
+        3 => OrderType::StopLimit,
+        4 => OrderType::Pegged {
+            reference: match byte % 3 {
+                0 => PegReference::BestBid,
+                1 => PegReference::BestAsk,
+                _ => PegReference::Mid,
+            },
+            offset: Decimal::new(i64::from(byte as i8), 2),
+        },
+        _ => OrderType::Limit,
+    }
+}
+
+fn time_in_force(flags: u8) -> TimeInForce {
+    match (flags >> 5) & 3 {
+        1 => TimeInForce::ImmediateOrCancel,
+        2 => TimeInForce::FillOrKill,
+        _ => TimeInForce::GoodTillCancel,
+    }
+}
+
+fn quantity(bytes: &[u8]) -> Long {
+    match u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) {
+        u32::MAX => Long::MAX,
+        quantity => Long::from(quantity),
+    }
+}
+
+fn request(step: &[u8], placed: &[(TradingPair, OrderId)]) -> Request {
+    let (flags, byte) = (step[1], step[3]);
+    match step[0] % 5 {
+        0 => {
+            let order_type = order_type(flags, byte);
+            let mut place = PlaceOrder::from(
+                price(step[2]),
+                quantity(&step[4..]),
+                side(flags),
+                order_type,
+                pair(flags),
+            )
+            .with_time_in_force(time_in_force(flags))
+            .with_client_order_id(u64::from(byte));
+            if matches!(order_type, OrderType::Stop | OrderType::StopLimit) {
+                place = place.with_stop_price(price(byte));
+            }
+            if flags & 0x80 != 0 {
+                place = place.with_display_quantity(Long::from(byte));
+            }
+            Request::PlaceOrder(place)
+        }
+        1 => {
+            let bid = price(step[2]);
+            let ask = bid + Decimal::new(25 * i64::from(byte % 8), 2);
+            let quantity = quantity(&step[4..]);
+            let order = |price, side| {
+                PlaceOrder::from(price, quantity, side, OrderType::Limit, pair(flags))
+            };
+            Request::PlaceQuote {
+                bid: order(bid, OrderSide::Bid),
+                ask: order(ask, OrderSide::Ask),
+                account_id: u64::from(flags >> 4),
+            }
+        }
+        2 => {
+            let index = usize::from(u16::from_be_bytes([step[2], byte]));
+            match placed.get(index % placed.len().max(1)) {
+                Some(&(trading_pair, orderid)) => {
+                    Request::Cancel(CancelOrder::from(orderid, trading_pair))
+                }
+                None => Request::GetBook {
+                    trading_pair: pair(flags),
+                },
+            }
+        }
+        3 => Request::CancelByClientId {
+            client_order_id: u64::from(byte),
+            trading_pair: pair(flags),
+        },
+        _ => Request::CancelLevel {
+            trading_pair: pair(flags),
+            side: side(flags),
+            price: price(step[2]),
+        },
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let btc_usdc = TradingPair::from(Asset::BTC, Asset::USDC);
+    let eth_usdt = TradingPair::from(Asset::ETH, Asset::USDT);
+    // without a cap on fills, a large order against a small iceberg replenishes it a slice at
+    // a time until the fuzzer runs out of memory
+    let engine = Engine::new(
+        EngineConfig::build(vec![btc_usdc, eth_usdt])
+            .with_tick_size(eth_usdt, Decimal::new(25, 2))
+            .with_max_fills_per_order(btc_usdc, NonZeroUsize::new(256).unwrap())
+            .with_max_fills_per_order(eth_usdt, NonZeroUsize::new(4).unwrap()),
+    );
+    let mut placed = Vec::new();
+    for step in data.chunks_exact(8) {
+        let request = request(step, &placed);
+        let trading_pair = match &request {
+            Request::PlaceOrder(_) | Request::PlaceQuote { .. } => Some(pair(step[1])),
+            _ => None,
+        };
+        if let (
+            Ok(Response::Placed { events, .. } | Response::Quoted { events, .. }),
+            Some(trading_pair),
+        ) = (engine.dispatch(request.clone()), trading_pair)
+        {
+            placed.extend(
+                events
+                    .iter()
+                    .filter(|event| event.status == OrderStatus::Created)
+                    .map(|event| (trading_pair, event.orderid)),
+            );
+        }
+        if let Err(violation) = engine.check_invariants() {
+            panic!("{} after {:?}", violation, request);
+        }
+    }
+});
@@ -10,13 +10,13 @@ use rust_decimal_macros::dec;
 use uuid::Uuid;
 
 fn main() {
-    let mut engine = Engine::new(EngineConfig::build(vec![
+    let engine = Engine::new(EngineConfig::build(vec![
         TradingPair::from(Asset::BTC, Asset::USDC),
         TradingPair::from(Asset::BTC, Asset::USDT),
     ]));
 
     for _ in 1..5 {
-        engine.dispatch(Request::PlaceOrder({
+        let _ = engine.dispatch(Request::PlaceOrder({
             PlaceOrder::from(
                 dec!(20.00),
                 10,
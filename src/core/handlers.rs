@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use super::{
+    model::{EngineEvent, Order, TradingPair},
+    orderbook::OrderBook,
+    router::{Execution, Request, Response, Router},
+    types::Failure,
+};
+
+/// Handles one kind of [Request] for the [Router]. Every kind of request the router accepts has
+/// a handler registered under its [Request::kind], so supporting a new kind of request only
+/// takes registering its handler with [Router::with_handler].
+///
+/// Handlers are called with the books of the router already locked, once the request has been
+/// validated. Any function with the same signature as [RequestHandler::handle] is a handler
+pub trait RequestHandler<T>: Send + Sync {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure>;
+}
+
+impl<T, F> RequestHandler<T> for F
+where
+    F: Fn(&Router<T>, &mut HashMap<TradingPair, T>, Request) -> Result<Response, Failure>
+        + Send
+        + Sync,
+{
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        self(router, books, request)
+    }
+}
+
+pub(crate) fn book_not_found(trading_pair: TradingPair) -> Failure {
+    Failure::BookNotFound(format!("No book found for trading pair {:?}", trading_pair))
+}
+
+fn unexpected(request: &Request, handler: &str) -> Failure {
+    Failure::UnsupportedOperation(format!(
+        "{} requests cannot be handled by the {} handler",
+        request.kind(),
+        handler
+    ))
+}
+
+fn record_placed<T: OrderBook>(router: &Router<T>, order: Order, execution: &Execution) {
+    router.log_event(EngineEvent::Placed(order));
+    router.record_execution(order.trading_pair, execution);
+}
+
+/// Matches a new order against its book, and rests whatever is left of it
+pub struct PlaceOrderHandler;
+
+impl<T: OrderBook> RequestHandler<T> for PlaceOrderHandler {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::PlaceOrder(p) = request else {
+            return Err(unexpected(&request, Request::PLACE_ORDER));
+        };
+        let order = router.resolve_order(p.to_order(router.now()), books)?;
+        let book = books
+            .get_mut(&order.trading_pair)
+            .ok_or_else(|| book_not_found(order.trading_pair))?;
+        // the orders that expired are gone from the book whether or not the order is placed
+        let expired = book.purge_expired(order.timestamp);
+        router.record_terminal(&expired);
+        let mut execution = router.execute(order, book)?;
+        record_placed(router, order, &execution);
+        execution.events.splice(0..0, expired);
+        Ok(Response::Placed {
+            orderid: order.orderid,
+            events: execution.events,
+        })
+    }
+}
+
+/// Places both sides of a quote, once the book has accepted each of them
+pub struct PlaceQuoteHandler;
+
+impl<T: OrderBook> RequestHandler<T> for PlaceQuoteHandler {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::PlaceQuote {
+            bid,
+            ask,
+            account_id,
+        } = request
+        else {
+            return Err(unexpected(&request, Request::PLACE_QUOTE));
+        };
+        let (bid, ask) = router.resolve_quote(&bid, &ask, account_id, books)?;
+        let book = books
+            .get_mut(&bid.trading_pair)
+            .ok_or_else(|| book_not_found(bid.trading_pair))?;
+        // both sides are checked before either is placed, so one rejected side rejects the quote
+        router.record_terminal(&book.purge_expired(bid.timestamp));
+        if let Some(failure) = router
+            .admits(&bid, book)
+            .or_else(|| router.admits(&ask, book))
+        {
+            return Err(failure);
+        }
+        let mut events = Vec::new();
+        for order in [bid, ask] {
+            let execution = router.execute_admitted(order, book)?;
+            record_placed(router, order, &execution);
+            events.extend(execution.events);
+        }
+        Ok(Response::Quoted {
+            bid: bid.orderid,
+            ask: ask.orderid,
+            events,
+        })
+    }
+}
+
+/// Places two linked orders in the books of different pairs, once both books have accepted
+/// them. Every book is behind the one lock the router takes before calling its handlers, so
+/// both books are held together without an order to take their locks in that could deadlock
+pub struct PlaceLinkedHandler;
+
+impl<T: OrderBook> RequestHandler<T> for PlaceLinkedHandler {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::PlaceLinked { first, second } = request else {
+            return Err(unexpected(&request, Request::PLACE_LINKED));
+        };
+        let (first, second) = router.resolve_linked(&first, &second, books)?;
+        // both are checked before either is placed, and neither is a market order, so once both
+        // are accepted neither can fail to be placed and nothing has to be rolled back
+        for order in [&first, &second] {
+            let book = books
+                .get_mut(&order.trading_pair)
+                .ok_or_else(|| book_not_found(order.trading_pair))?;
+            router.record_terminal(&book.purge_expired(order.timestamp));
+            if let Some(failure) = router.admits(order, book) {
+                return Err(failure);
+            }
+        }
+        let mut events = Vec::new();
+        for order in [first, second] {
+            let book = books
+                .get_mut(&order.trading_pair)
+                .ok_or_else(|| book_not_found(order.trading_pair))?;
+            let execution = router.execute_admitted(order, book)?;
+            record_placed(router, order, &execution);
+            events.extend(execution.events);
+        }
+        Ok(Response::Linked {
+            first: first.orderid,
+            second: second.orderid,
+            events,
+        })
+    }
+}
+
+/// Cancels an order by the id the engine gave it
+pub struct CancelHandler;
+
+impl<T: OrderBook> RequestHandler<T> for CancelHandler {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::Cancel(cancel) = request else {
+            return Err(unexpected(&request, Request::CANCEL));
+        };
+        let book = books
+            .get_mut(&cancel.trading_pair)
+            .ok_or_else(|| book_not_found(cancel.trading_pair))?;
+        let event = book.cancel(cancel.orderid)?;
+        router.record_terminal(&[event]);
+        router.log_event(EngineEvent::Canceled {
+            orderid: cancel.orderid,
+            trading_pair: cancel.trading_pair,
+        });
+        router.record_execution(cancel.trading_pair, &router.settle(book));
+        Ok(Response::Accepted)
+    }
+}
+
+/// Cancels an order by the id the client placed it with
+pub struct CancelByClientIdHandler;
+
+impl<T: OrderBook> RequestHandler<T> for CancelByClientIdHandler {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::CancelByClientId {
+            client_order_id,
+            trading_pair,
+        } = request
+        else {
+            return Err(unexpected(&request, Request::CANCEL_BY_CLIENT_ID));
+        };
+        let book = books
+            .get_mut(&trading_pair)
+            .ok_or_else(|| book_not_found(trading_pair))?;
+        let event = book.cancel_by_client_id(client_order_id)?;
+        router.log_event(EngineEvent::Canceled {
+            orderid: event.orderid,
+            trading_pair,
+        });
+        router.record_terminal(&[event]);
+        router.record_execution(trading_pair, &router.settle(book));
+        Ok(Response::Accepted)
+    }
+}
+
+/// Cancels every order resting at a price on one side of a book
+pub struct CancelLevelHandler;
+
+impl<T: OrderBook> RequestHandler<T> for CancelLevelHandler {
+    fn handle(
+        &self,
+        router: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::CancelLevel {
+            trading_pair,
+            side,
+            price,
+        } = request
+        else {
+            return Err(unexpected(&request, Request::CANCEL_LEVEL));
+        };
+        let book = books
+            .get_mut(&trading_pair)
+            .ok_or_else(|| book_not_found(trading_pair))?;
+        let events = book.cancel_level(side, price);
+        for event in &events {
+            router.log_event(EngineEvent::Canceled {
+                orderid: event.orderid,
+                trading_pair,
+            });
+        }
+        router.record_terminal(&events);
+        if !events.is_empty() {
+            router.record_execution(trading_pair, &router.settle(book));
+        }
+        Ok(Response::Canceled(
+            events.iter().map(|event| event.orderid).collect(),
+        ))
+    }
+}
+
+/// Gets every price level of a book
+pub struct GetBookHandler;
+
+impl<T: OrderBook> RequestHandler<T> for GetBookHandler {
+    fn handle(
+        &self,
+        _: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::GetBook { trading_pair } = request else {
+            return Err(unexpected(&request, Request::GET_BOOK));
+        };
+        books
+            .get(&trading_pair)
+            .map(|book| Response::Book(book.snapshot()))
+            .ok_or_else(|| book_not_found(trading_pair))
+    }
+}
+
+/// Sums up a book at a glance
+pub struct SummaryHandler;
+
+impl<T: OrderBook> RequestHandler<T> for SummaryHandler {
+    fn handle(
+        &self,
+        _: &Router<T>,
+        books: &mut HashMap<TradingPair, T>,
+        request: Request,
+    ) -> Result<Response, Failure> {
+        let Request::Summary { trading_pair } = request else {
+            return Err(unexpected(&request, Request::SUMMARY));
+        };
+        books
+            .get(&trading_pair)
+            .map(|book| {
+                let summary = book.snapshot().summary(book.get_spread(), book.mid_price());
+                Response::Summary(summary)
+            })
+            .ok_or_else(|| book_not_found(trading_pair))
+    }
+}
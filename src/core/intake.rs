@@ -0,0 +1,182 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    sync::{Condvar, LockResult, Mutex, MutexGuard, PoisonError, TryLockError, TryLockResult},
+    time::{Duration, Instant},
+};
+
+/// A lock on books that requests can wait for in an intake, see
+/// [RouterConfig::intake_capacity](super::router::RouterConfig::intake_capacity). Waiting
+/// requests block until the books are let go, and are let through in the order they arrived
+pub struct IntakeLock<B> {
+    books: Mutex<B>,
+    /// the tickets of the requests waiting for the books, the next to be let through at the
+    /// front, with the ticket handed to the next request to arrive
+    queue: Mutex<(VecDeque<u64>, u64)>,
+    /// woken every time the books are let go, or the front of the queue changes
+    turn: Condvar,
+}
+
+impl<B> IntakeLock<B> {
+    pub fn new(books: B) -> Self {
+        Self {
+            books: Mutex::new(books),
+            queue: Mutex::new((VecDeque::new(), 0)),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Takes the lock, blocking for as long as it is held, without waiting in the intake
+    pub fn lock(&self) -> LockResult<IntakeGuard<'_, B>> {
+        self.books
+            .lock()
+            .map(|books| self.guard(books))
+            .map_err(|poisoned| PoisonError::new(self.guard(poisoned.into_inner())))
+    }
+
+    /// Takes the lock if it's free, without waiting
+    pub fn try_lock(&self) -> TryLockResult<IntakeGuard<'_, B>> {
+        match self.books.try_lock() {
+            Ok(books) => Ok(self.guard(books)),
+            Err(TryLockError::Poisoned(poisoned)) => Err(TryLockError::Poisoned(PoisonError::new(
+                self.guard(poisoned.into_inner()),
+            ))),
+            Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Waits in the intake for the lock, behind every request that arrived before, until the
+    /// timeout. A wait that times out ends as if the lock was busy
+    pub fn lock_within(&self, timeout: Duration) -> TryLockResult<IntakeGuard<'_, B>> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        let ticket = queue.1;
+        queue.0.push_back(ticket);
+        queue.1 += 1;
+        let books = loop {
+            if queue.0.front() == Some(&ticket) {
+                match self.try_lock() {
+                    Err(TryLockError::WouldBlock) => (),
+                    locked => break locked,
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break Err(TryLockError::WouldBlock);
+            }
+            queue = self
+                .turn
+                .wait_timeout(queue, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        };
+        // whether the wait ends with the lock or not, the next request is at the front now
+        queue.0.retain(|waiting| *waiting != ticket);
+        self.turn.notify_all();
+        books
+    }
+
+    /// Gets the books without locking, as nothing else can hold the lock
+    pub fn get_mut(&mut self) -> LockResult<&mut B> {
+        self.books.get_mut()
+    }
+
+    fn guard<'a>(&'a self, books: MutexGuard<'a, B>) -> IntakeGuard<'a, B> {
+        IntakeGuard {
+            books: Some(books),
+            lock: self,
+        }
+    }
+}
+
+/// The books of an [IntakeLock] while it is held. Letting them go wakes the requests waiting
+/// in the intake
+pub struct IntakeGuard<'a, B> {
+    /// only taken when the guard is dropped, so the books are let go before waking anyone
+    books: Option<MutexGuard<'a, B>>,
+    lock: &'a IntakeLock<B>,
+}
+
+impl<B> Deref for IntakeGuard<'_, B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        self.books
+            .as_ref()
+            .expect("books are held until the guard is dropped")
+    }
+}
+
+impl<B> DerefMut for IntakeGuard<'_, B> {
+    fn deref_mut(&mut self) -> &mut B {
+        self.books
+            .as_mut()
+            .expect("books are held until the guard is dropped")
+    }
+}
+
+impl<B> Drop for IntakeGuard<'_, B> {
+    fn drop(&mut self) {
+        drop(self.books.take());
+        // the queue is locked before waking the requests, so one that just found the books busy
+        // is already waiting to be woken
+        let _queue = self
+            .lock
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.lock.turn.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn requests_waiting_in_the_intake_get_the_lock_in_the_order_they_arrived() {
+        let lock = IntakeLock::new(Vec::new());
+        let held = lock.lock().unwrap();
+
+        let lock = &lock;
+        thread::scope(|scope| {
+            let waiting: Vec<_> = (0..4)
+                .map(|request| {
+                    let waiter = scope.spawn(move || {
+                        lock.lock_within(Duration::from_secs(10))
+                            .map(|mut arrived| arrived.push(request))
+                            .is_ok()
+                    });
+                    // each request is in the queue before the next arrives
+                    while lock.queue.lock().unwrap().0.len() <= request {
+                        thread::yield_now();
+                    }
+                    waiter
+                })
+                .collect();
+            drop(held);
+            assert!(waiting.into_iter().all(|waiter| waiter.join().unwrap()));
+        });
+        assert_eq!(*lock.lock().unwrap(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn a_request_that_times_out_leaves_the_intake_to_the_ones_behind_it() {
+        let lock = IntakeLock::new(());
+        let held = lock.lock().unwrap();
+
+        thread::scope(|scope| {
+            let first = scope.spawn(|| lock.lock_within(Duration::ZERO).is_ok());
+            assert!(!first.join().unwrap());
+            let second = scope.spawn(|| lock.lock_within(Duration::from_secs(10)).is_ok());
+            while lock.queue.lock().unwrap().0.is_empty() {
+                thread::yield_now();
+            }
+            drop(held);
+            assert!(second.join().unwrap());
+        });
+        assert!(lock.queue.lock().unwrap().0.is_empty());
+    }
+}
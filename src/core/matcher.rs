@@ -1,9 +1,12 @@
-use std::fmt::Debug;
+use std::{cmp::Ordering, fmt::Debug};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 
 use super::{
-    model::Order,
+    model::{Event, Order},
     orderbook::OrderBook,
-    types::{Long, OrderSide, OrderStatus, OrderType, Trade},
+    types::{Failure, Long, OrderSide, OrderStatus, OrderType, TieBreak, TimeInForce, Trade},
+    utils::Util,
 };
 
 /// A match is a structure which contains a list of executed orders (trades) as well as fields
@@ -18,6 +21,33 @@ pub struct Match<T> {
 
     /// number of items left to complete a full match
     qty_left: Long,
+
+    /// number of items the incoming order asked for
+    original_qty: Long,
+
+    /// for notional orders, the notional the incoming order asked for
+    original_notional: Option<Decimal>,
+
+    /// number of items of the incoming order that were matched
+    filled_qty: Long,
+
+    /// the notional (price times quantity) of the incoming order that was matched
+    filled_notional: Decimal,
+
+    /// the lifecycle events of every order the match touched, in the order they happened
+    events: Vec<Event>,
+
+    /// the trades in which the incoming order matched an order of its own account
+    wash_trades: Vec<T>,
+}
+
+impl<T> Default for Match<T>
+where
+    T: Clone + Debug + Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> Match<T>
@@ -29,6 +59,12 @@ where
             matches: Vec::with_capacity(4),
             state: MatchState::NoMatch,
             qty_left: 0,
+            original_qty: 0,
+            original_notional: None,
+            filled_qty: 0,
+            filled_notional: Decimal::ZERO,
+            events: Vec::new(),
+            wash_trades: Vec::new(),
         }
     }
 
@@ -40,16 +76,35 @@ where
         self.matches.clone()
     }
 
+    pub fn add_event(&mut self, event: Event) {
+        self.events.push(event)
+    }
+
+    /// Gets the events of the match: a fill for each side of every trade, followed by the
+    /// incoming order being created on the book if any of it was left to rest
+    pub fn get_events(&self) -> Vec<Event> {
+        self.events.clone()
+    }
+
+    /// Flags a trade in which the incoming order matched an order of its own account
+    pub fn add_wash_trade(&mut self, trade: T) {
+        self.wash_trades.push(trade)
+    }
+
+    pub fn get_wash_trades(&self) -> Vec<T> {
+        self.wash_trades.clone()
+    }
+
     pub fn update_state(&mut self, state: MatchState) {
         match state {
             MatchState::Full | MatchState::NoMatch => self.update_qty_left(0),
-            MatchState::Partial => (),
+            MatchState::Partial | MatchState::NoLiquidity | MatchState::FillLimitReached => (),
         }
         self.state = state
     }
 
     pub fn get_state(&self) -> MatchState {
-        self.state.clone()
+        self.state
     }
 
     pub fn update_qty_left(&mut self, qty: Long) {
@@ -63,6 +118,47 @@ where
     pub fn is_partial(&self) -> bool {
         self.state == MatchState::Partial
     }
+
+    pub fn update_original_qty(&mut self, qty: Long) {
+        self.original_qty = qty
+    }
+
+    pub fn original_quantity(&self) -> Long {
+        self.original_qty
+    }
+
+    pub fn update_original_notional(&mut self, notional: Option<Decimal>) {
+        self.original_notional = notional
+    }
+
+    /// Records that part of the incoming order was matched at the given price
+    pub fn add_fill(&mut self, quantity: Long, price: Decimal) {
+        self.filled_qty = self.filled_qty.saturating_add(quantity);
+        self.filled_notional = self
+            .filled_notional
+            .saturating_add(Util::notional(price, quantity));
+    }
+
+    /// Gets the number of items of the incoming order that were matched
+    pub fn filled_quantity(&self) -> Long {
+        self.filled_qty
+    }
+
+    /// Gets the notional (price times quantity) of the incoming order that was matched
+    pub fn filled_notional(&self) -> Decimal {
+        self.filled_notional
+    }
+
+    /// Gets the share of the incoming order that was matched, from zero to one. For notional
+    /// orders this is the share of the notional that was spent
+    pub fn fill_ratio(&self) -> Decimal {
+        match self.original_notional {
+            Some(notional) if !notional.is_zero() => self.filled_notional / notional,
+            Some(_) => Decimal::ZERO,
+            None if self.original_qty == 0 => Decimal::ZERO,
+            None => Decimal::from(self.filled_qty) / Decimal::from(self.original_qty),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -70,6 +166,13 @@ pub enum MatchState {
     Full,
     Partial,
     NoMatch,
+    /// a market order found no orders on the opposite side of the book to match against, or
+    /// too few to make its minimum fill
+    NoLiquidity,
+    /// the incoming order took as many fills as the book allows a single order, see
+    /// [BookConfig::max_fills_per_order](super::orderbook::BookConfig::max_fills_per_order).
+    /// The quantity left was canceled, and never rests on the book
+    FillLimitReached,
 }
 /// Implements a matcher with takes an order and its respective book and attempts to find a set
 /// of matching trades (bids to asks and vice-versa)
@@ -77,175 +180,414 @@ pub enum MatchState {
 pub struct Matcher;
 
 impl Matcher {
-    pub fn match_order<T: OrderBook>(&self, order: Order, orderbook: &mut T) -> Match<Trade> {
+    /// Matches an order against the book it was routed to, resting whatever is left of it when
+    /// it is a limit order. Like [OrderBook::place], an order for a different trading pair than
+    /// the book is rejected, so a misrouted order never trades
+    pub fn match_order<T: OrderBook>(
+        &self,
+        order: Order,
+        orderbook: &mut T,
+    ) -> Result<Match<Trade>, Failure> {
+        if order.trading_pair != orderbook.trading_pair() {
+            return Err(Failure::InvalidOrderForBook(format!(
+                "Order {} is for {}, not the {} book",
+                order.orderid,
+                order.trading_pair,
+                orderbook.trading_pair()
+            )));
+        }
+        Ok(Self::match_in_book(order, orderbook))
+    }
+
+    fn match_in_book<T: OrderBook>(order: Order, orderbook: &mut T) -> Match<Trade> {
+        // a pegged order is matched like a limit order at the price its reference puts it at
+        let order = match order.order_type {
+            OrderType::Pegged { reference, offset } => Order {
+                price: orderbook
+                    .peg_price(reference, offset)
+                    .unwrap_or(order.price),
+                ..order
+            },
+            _ => order,
+        };
         let mut matches = Match::new();
+        matches.update_original_qty(order.quantity);
+        matches.update_original_notional(order.notional);
+        if Self::is_filled_by_top(&order, orderbook) {
+            Self::do_match(&order, orderbook, &mut matches);
+            return matches;
+        }
+        Self::match_through_book(order, orderbook, matches)
+    }
+
+    /// Checks for the common case of the top opposite order filling the incoming order on its
+    /// own. It then takes a single fill, so none of the checks that only matter when an order
+    /// walks the book or rests on it are needed. What this saves is mostly the scan of the whole
+    /// book a fill or kill order otherwise takes, which grows with the depth of the book
+    fn is_filled_by_top<T: OrderBook>(order: &Order, orderbook: &T) -> bool {
+        let prices_itself = match order.order_type {
+            OrderType::Market => false,
+            OrderType::Limit | OrderType::Pegged { .. } => true,
+            OrderType::Stop | OrderType::StopLimit => return false,
+        };
+        match Self::get_opposite_order(order.side, orderbook) {
+            Some(top) => {
+                order.notional.is_none()
+                    && order.quantity > 0
+                    && top.quantity >= order.quantity
+                    && (!prices_itself || Self::is_within_price_limit(order, top))
+            }
+            None => false,
+        }
+    }
+
+    /// Checks that the orders at the top of the book, as many as a single order can take fills
+    /// from, hold enough within the price limit of the order to fill the given quantity of it.
+    /// Resting orders only show part of an iceberg, so its hidden quantity is left out as it
+    /// would take another fill
+    fn is_filled_within_fill_limit<T: OrderBook>(
+        order: &Order,
+        quantity: Long,
+        orderbook: &T,
+    ) -> bool {
+        let Some(max_fills) = orderbook.max_fills_per_order() else {
+            return true;
+        };
+        let top = match order.side {
+            OrderSide::Bid => orderbook.top_n_asks(max_fills.get()),
+            OrderSide::Ask => orderbook.top_n_bids(max_fills.get()),
+        };
+        let fillable = top
+            .iter()
+            .take_while(|resting| {
+                order.order_type == OrderType::Market || Self::is_within_price_limit(order, resting)
+            })
+            .map(|resting| resting.quantity)
+            .fold(0, Long::saturating_add);
+        fillable >= quantity
+    }
+
+    /// Matches an order that may walk several levels of the book, or rest on it
+    fn match_through_book<T: OrderBook>(
+        order: Order,
+        orderbook: &mut T,
+        mut matches: Match<Trade>,
+    ) -> Match<Trade> {
+        // a fill or kill order is only matched if the book holds enough volume to fill it
+        // completely, otherwise it is killed without trading or resting on the book. Stops are
+        // checked when they trigger, as the book they trade against is only known then
+        let is_stop = matches!(order.order_type, OrderType::Stop | OrderType::StopLimit);
+        if order.time_in_force == TimeInForce::FillOrKill
+            && !is_stop
+            && (orderbook.crossing_quantity(&order) < order.quantity
+                || !Self::is_filled_within_fill_limit(&order, order.quantity, orderbook))
+        {
+            return matches;
+        }
+        // an order with a minimum fill doesn't trade unless the book holds enough to make it,
+        // within the fills it may take. The order then rests untouched, or is canceled when it
+        // can't rest. Resting it at a price that crosses the book would leave the book crossed,
+        // so an order that could trade some of its quantity is canceled too
+        if let Some(min_fill) = order.min_fill {
+            let crossing = orderbook.crossing_quantity(&order);
+            if !is_stop
+                && (crossing < min_fill
+                    || !Self::is_filled_within_fill_limit(&order, min_fill, orderbook))
+            {
+                matches.update_qty_left(order.quantity);
+                match order.order_type {
+                    OrderType::Market => matches.update_state(MatchState::NoLiquidity),
+                    _ if crossing > 0 => (),
+                    _ => Self::rest(order, orderbook, &mut matches),
+                }
+                return matches;
+            }
+        }
         match order.order_type {
             // a market order is matched immediately at the best available price. In cases
             // where the engine is unable to fill the match completely, the order is partially
             // filled and the remaining part of the order is left on the book
             OrderType::Market => {
-                if let Some(opp_order) = Self::get_opposite_order(order.side, orderbook) {
-                    Self::do_match(order, opp_order.clone(), orderbook, &mut matches)
+                if Self::get_opposite_order(order.side, orderbook).is_none() {
+                    matches.update_qty_left(order.quantity);
+                    matches.update_state(MatchState::NoLiquidity);
+                    return matches;
                 }
-                // an early return with the state being MatchState::NoMatch
-                return matches;
+                Self::do_match(&order, orderbook, &mut matches);
+                matches
             }
             // a limit order is first matched immediately if possible and if not it is placed into
             // the limit order book to be filled at a later time, when a matching market order is found
-            OrderType::Limit => {
-                if let Some(opp_order) = Self::get_opposite_order(order.side, orderbook) {
-                    // first we do price check to ensure the price variant of the limit order is maintained
-                    if Self::is_within_price_limit(order, *opp_order) {
-                        Self::do_match(order, opp_order.clone(), orderbook, &mut matches);
-                        // if there's a partial match we want to place the remnants on the orderbook
-                        if MatchState::Partial == matches.get_state() {
-                            let mut left_over = order.clone();
-                            left_over.quantity = matches.get_qty_left();
-                            let _ = orderbook.place(left_over);
-                        }
-                        return matches;
+            OrderType::Limit | OrderType::Pegged { .. } => {
+                Self::do_match(&order, orderbook, &mut matches);
+                match matches.get_state() {
+                    // if there's a partial match we want to place the remnants on the orderbook
+                    MatchState::Partial => {
+                        let mut left_over = order;
+                        left_over.quantity = matches.get_qty_left();
+                        Self::rest(left_over, orderbook, &mut matches);
                     }
+                    MatchState::NoMatch => Self::rest(order, orderbook, &mut matches),
+                    MatchState::Full | MatchState::NoLiquidity | MatchState::FillLimitReached => (),
                 }
-                let _ = orderbook.place(order);
-                // an early return with the state being MatchState::NoMatch
-                return matches;
+                matches
+            }
+            // stop orders wait off the book until the market trades through their stop price
+            OrderType::Stop | OrderType::StopLimit => {
+                if let Ok(event) = orderbook.place(order) {
+                    matches.add_event(event);
+                }
+                matches
             }
-            OrderType::Stop => todo!(),
         }
     }
 
-    fn get_opposite_order(side: OrderSide, orderbook: &mut dyn OrderBook) -> Option<&Order> {
+    /// Matches the stop orders that have been triggered by the latest trades in the book. Since
+    /// a triggered stop can trade and trigger other stops in turn, this runs until no stops are
+    /// left to trigger, and returns the match of each stop in the order they were triggered
+    pub fn trigger_stops<T: OrderBook>(&self, orderbook: &mut T) -> Vec<Match<Trade>> {
+        let mut matches = Vec::new();
+        while let Some(stop) = orderbook.take_triggered_stop() {
+            let order = stop.to_triggered(orderbook.now());
+            matches.push(Self::match_in_book(order, orderbook));
+        }
+        matches
+    }
+
+    /// Matches a book left crossed by orders that were rested without being matched, as they
+    /// are when matching in batches. Of the two orders at the top of the book, the one placed
+    /// later is taken off and matched as if it had just arrived, so it trades at the prices of
+    /// the orders that were there before it. Orders stamped with the same time are told apart
+    /// by the tie break. This runs until the book is no longer crossed, and returns the match
+    /// of each order taken off in turn
+    pub fn uncross<T: OrderBook>(
+        &self,
+        orderbook: &mut T,
+        tie_break: TieBreak,
+    ) -> Vec<Match<Trade>> {
+        let mut matches = Vec::new();
+        while let (Some(bid), Some(ask)) = (orderbook.peek_top_bid(), orderbook.peek_top_ask()) {
+            if bid.price < ask.price {
+                break;
+            }
+            // the bid is taken off when it arrived later, or has no priority in a tie
+            let bid_is_taker = match bid.timestamp.cmp(&ask.timestamp) {
+                Ordering::Equal => match tie_break {
+                    TieBreak::Maker => bid.seq > ask.seq,
+                    TieBreak::Taker => bid.seq < ask.seq,
+                },
+                later => later == Ordering::Greater,
+            };
+            let later = match bid_is_taker {
+                true => *bid,
+                false => *ask,
+            };
+            if orderbook.cancel(later.orderid).is_err() {
+                break;
+            }
+            // the hidden part of an iceberg is matched too, and split off again if it rests. A
+            // resting order trades like any other, so its minimum fill no longer holds
+            let order = Order {
+                quantity: later.remaining_quantity(),
+                hidden_quantity: 0,
+                min_fill: None,
+                ..later
+            };
+            matches.push(Self::match_in_book(order, orderbook));
+        }
+        matches
+    }
+
+    /// Places the unmatched part of a limit order on the book, unless its time in force
+    /// requires whatever could not be matched immediately to be canceled
+    fn rest(order: Order, orderbook: &mut dyn OrderBook, matches: &mut Match<Trade>) {
+        match order.time_in_force {
+            TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill => (),
+            TimeInForce::GoodTillCancel | TimeInForce::GoodTillDate(_) => {
+                if let Ok(event) = orderbook.place(order) {
+                    matches.add_event(event);
+                }
+            }
+        }
+    }
+
+    fn get_opposite_order(side: OrderSide, orderbook: &dyn OrderBook) -> Option<&Order> {
         match side {
             OrderSide::Bid => orderbook.peek_top_ask(),
             OrderSide::Ask => orderbook.peek_top_bid(),
         }
     }
 
-    fn is_within_price_limit(order: Order, opp_order: Order) -> bool {
+    /// Gets the order to trade against next on the opposite side of the book. An empty order
+    /// should never rest on the book, but if one does it is cleared rather than traded against
+    fn next_opposite_order(side: OrderSide, orderbook: &mut dyn OrderBook) -> Option<&Order> {
+        match side {
+            OrderSide::Bid => orderbook.best_active_ask(),
+            OrderSide::Ask => orderbook.best_active_bid(),
+        }
+    }
+
+    fn pop_top(side: OrderSide, orderbook: &mut dyn OrderBook) {
+        match side {
+            OrderSide::Ask => orderbook.pop_top_ask(),
+            OrderSide::Bid => orderbook.pop_top_bid(),
+        };
+    }
+
+    /// Gets how much better than its limit an order trading at the given price does. Trades
+    /// always execute at the price of the resting order, so a limit order that crosses the book
+    /// deeper than the top gets the difference. Market orders have no limit to improve on
+    fn price_improvement(order: &Order, price: Decimal) -> Decimal {
+        match (order.order_type, order.side) {
+            (OrderType::Market | OrderType::Stop, _) => Decimal::ZERO,
+            (_, OrderSide::Bid) => order.price - price,
+            (_, OrderSide::Ask) => price - order.price,
+        }
+    }
+
+    /// Checks the resting order is priced at or better than the limit of the incoming order.
+    /// Decimals are compared by value whatever their scale, so a bid at 20.0 reaches an ask at
+    /// 20.00 without either price having to be normalized first
+    fn is_within_price_limit(order: &Order, opp_order: &Order) -> bool {
         match order.side {
             OrderSide::Bid => order.price >= opp_order.price,
             OrderSide::Ask => order.price <= opp_order.price,
         }
     }
 
-    fn do_match(
-        mut incoming_order: Order,
-        opposite_order: Order,
-        orderbook: &mut dyn OrderBook,
-        matches: &mut Match<Trade>,
-    ) {
-        if incoming_order.quantity < opposite_order.quantity {
-            matches.add_match(Trade {
-                orderid: incoming_order.orderid,
-                side: incoming_order.side,
-                price: opposite_order.price,
-                status: OrderStatus::Filled,
-                quantity: incoming_order.quantity,
-                timestamp: 0,
-            });
-
-            matches.add_match(Trade {
-                orderid: opposite_order.orderid,
-                side: opposite_order.side,
-                price: opposite_order.price,
-                status: OrderStatus::PartialFill,
-                quantity: incoming_order.quantity,
-                timestamp: 0,
-            });
-
-            orderbook.modify_quantity(
+    /// Matches the incoming order against the top of the opposite side of the book, until it is
+    /// filled, that side runs dry or (for limit orders) the top price is beyond the limit.
+    /// Resting orders are read by reference and their quantities updated in place, so no
+    /// orders are copied out of the book along the way
+    fn do_match(incoming_order: &Order, orderbook: &mut dyn OrderBook, matches: &mut Match<Trade>) {
+        let timestamp = orderbook.now();
+        let max_fills = orderbook.max_fills_per_order();
+        let mut fills = 0;
+        while let Some(opposite_order) = Self::next_opposite_order(incoming_order.side, orderbook) {
+            // the price variant of a limit order is maintained on every step of the match
+            if incoming_order.order_type != OrderType::Market
+                && !Self::is_within_price_limit(incoming_order, opposite_order)
+            {
+                break;
+            }
+            let (orderid, account_id, tag, side, price, available) = (
                 opposite_order.orderid,
-                opposite_order.quantity - incoming_order.quantity,
+                opposite_order.account_id,
+                opposite_order.tag,
+                opposite_order.side,
+                opposite_order.price,
+                opposite_order.quantity,
             );
-            // the state is full because the engine was able to fully match the incoming order
-            matches.update_state(MatchState::Full);
-        } else if incoming_order.quantity > opposite_order.quantity {
-            matches.add_match(Trade {
-                orderid: incoming_order.orderid,
-                side: incoming_order.side,
-                price: opposite_order.price,
-                status: OrderStatus::PartialFill,
-                quantity: opposite_order.quantity,
-                timestamp: 0,
-            });
-
-            matches.add_match(Trade {
-                orderid: opposite_order.orderid,
-                side: opposite_order.side,
-                price: opposite_order.price,
-                status: OrderStatus::Filled,
-                quantity: opposite_order.quantity,
-                timestamp: 0,
-            });
-
-            // update the quantity of the partially filled order
-            incoming_order.quantity -= opposite_order.quantity;
-
-            // we update the quantity left to match for the primary order
-            matches.update_qty_left(incoming_order.quantity);
-
-            // since the incoming order was partially filled, the state is updated accordingly
-            matches.update_state(MatchState::Partial);
-
-            let some_order = match incoming_order.side {
-                OrderSide::Bid => {
-                    // pop off the current top ask, since it has already been filled
-                    orderbook.pop_top_ask();
-                    // get the current top ask on the book
-                    orderbook.peek_top_ask()
+            // trades are priced at the resting price, with the places the book reports it with
+            let price = orderbook.trade_price(price);
+            // a notional order takes as many whole units as what is left of its notional buys
+            // at this price, so the last fill can leave a little of the notional unspent
+            let quantity_left = match incoming_order.notional {
+                Some(notional) => (notional - matches.filled_notional())
+                    .checked_div(price)
+                    .and_then(|units| units.floor().to_u64())
+                    .unwrap_or(0),
+                None => {
+                    let filled = matches.filled_quantity();
+                    debug_assert!(
+                        filled <= incoming_order.quantity,
+                        "order {} was filled beyond its quantity",
+                        incoming_order.orderid
+                    );
+                    incoming_order.quantity.saturating_sub(filled)
                 }
-                OrderSide::Ask => {
-                    // pop the current top bid since it has been filled
-                    orderbook.pop_top_bid();
-                    // get the current top bid and attempt to fill
-                    orderbook.peek_top_bid()
+            };
+            if quantity_left == 0 {
+                // only a notional order too small to buy another unit gets here
+                if matches.filled_quantity() > 0 {
+                    matches.update_state(MatchState::Full);
                 }
+                break;
+            }
+            let quantity = quantity_left.min(available);
+            let status_of = |filled: bool| match filled {
+                true => OrderStatus::Filled,
+                false => OrderStatus::PartialFill,
             };
 
-            // attempt to fill the rest of the partially filled order
-            if let Some(opposite) = some_order {
-                Self::do_match(incoming_order, opposite.clone(), orderbook, matches)
-            }
-        } else {
-            matches.add_match(Trade {
+            let taker_trade = Trade {
                 orderid: incoming_order.orderid,
+                account_id: incoming_order.account_id,
+                tag: incoming_order.tag,
                 side: incoming_order.side,
-                price: opposite_order.price,
-                status: OrderStatus::Filled,
-                quantity: incoming_order.quantity,
-                timestamp: 0,
-            });
-
-            matches.add_match(Trade {
-                orderid: opposite_order.orderid,
-                side: opposite_order.side,
-                price: opposite_order.price,
-                status: OrderStatus::Filled,
-                quantity: opposite_order.quantity,
-                timestamp: 0,
-            });
-
-            matches.update_state(MatchState::Full);
-
-            match incoming_order.side {
-                OrderSide::Bid => orderbook.pop_top_ask(),
-                OrderSide::Ask => orderbook.pop_top_bid(),
+                price,
+                status: status_of(quantity == quantity_left),
+                quantity,
+                timestamp,
+                price_improvement: Self::price_improvement(incoming_order, price),
             };
+            matches.add_match(taker_trade);
+            matches.add_event(Event::from(&taker_trade));
+
+            let maker_trade = Trade {
+                orderid,
+                account_id,
+                tag,
+                side,
+                price,
+                status: status_of(quantity == available),
+                quantity,
+                timestamp,
+                price_improvement: Decimal::ZERO,
+            };
+            matches.add_match(maker_trade);
+            matches.add_event(Event::from(&maker_trade));
+            matches.add_fill(quantity, price);
+            // executions are recorded on the book once, from the side of the resting order
+            orderbook.record_trade(&maker_trade);
+            // an account trading with itself is allowed, but flagged for the wash trade report
+            if incoming_order.account_id.is_some() && incoming_order.account_id == account_id {
+                matches.add_wash_trade(maker_trade);
+            }
+
+            match available.checked_sub(quantity) {
+                // the matcher reports the fill itself, so the event of the modification is dropped
+                Some(rest) if rest > 0 => {
+                    let _ = orderbook.modify_quantity(orderid, rest);
+                }
+                // the resting order has been filled, so it is taken off the top of the book
+                _ => Self::pop_top(side, orderbook),
+            }
+
+            if quantity == quantity_left {
+                // the state is full because the engine was able to fully match the incoming order
+                matches.update_state(MatchState::Full);
+                return;
+            }
+            // the incoming order was partially filled, so we track the quantity left to match
+            matches.update_qty_left(
+                incoming_order
+                    .quantity
+                    .saturating_sub(matches.filled_quantity()),
+            );
+            fills += 1;
+            if max_fills.is_some_and(|max_fills| fills >= max_fills.get()) {
+                matches.update_state(MatchState::FillLimitReached);
+                return;
+            }
+            matches.update_state(MatchState::Partial);
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::{num::NonZeroUsize, sync::Arc};
+
     use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
     use uuid::Uuid;
 
     use crate::core::{
         model::TradingPair,
-        orderbook::LimitOrderBook,
+        orderbook::{BookConfig, LimitOrderBook},
         types::{Asset, Long},
-        utils::Util,
+        utils::{MockClock, Util},
     };
 
     use super::*;
@@ -256,8 +598,10 @@ mod test {
 
         let matcher = Matcher {};
         let order = create_order(OrderSide::Ask, dec!(2.22), OrderType::Market, 100);
-        let matches = matcher.match_order(order, &mut orderbook);
-        assert_eq!(matches.get_state(), MatchState::NoMatch);
+        let matches = matcher.match_order(order, &mut orderbook).unwrap();
+        assert_eq!(matches.get_state(), MatchState::NoLiquidity);
+        assert!(matches.get_matches().is_empty());
+        assert_eq!(matches.get_qty_left(), 100);
     }
 
     #[test]
@@ -266,12 +610,12 @@ mod test {
 
         let asks = create_orders(OrderSide::Ask);
         for ask in &asks {
-            let _ = orderbook.place(ask.clone());
+            let _ = orderbook.place(*ask);
         }
 
         let matcher = Matcher {};
         let bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Market, 100);
-        let matches = matcher.match_order(bid, &mut orderbook);
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
 
         let trades = matches.get_matches();
         assert!(!trades.is_empty());
@@ -312,12 +656,12 @@ mod test {
 
         let bids = create_orders(OrderSide::Bid);
         for bid in &bids {
-            let _ = orderbook.place(bid.clone());
+            let _ = orderbook.place(*bid);
         }
 
         let matcher = Matcher {};
         let order = create_order(OrderSide::Ask, dec!(5.00), OrderType::Limit, 1000);
-        let matches = matcher.match_order(order, &mut orderbook);
+        let matches = matcher.match_order(order, &mut orderbook).unwrap();
         assert_eq!(matches.get_state(), MatchState::Partial);
         assert_eq!(matches.get_qty_left(), 800);
 
@@ -336,6 +680,804 @@ mod test {
         assert_eq!(ask.quantity, matches.get_qty_left());
     }
 
+    #[test]
+    fn a_limit_ioc_order_matches_what_it_can_and_cancels_the_remainder() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for ask in create_orders(OrderSide::Ask) {
+            let _ = orderbook.place(ask);
+        }
+
+        let matcher = Matcher {};
+        let mut bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Limit, 200);
+        bid.time_in_force = TimeInForce::ImmediateOrCancel;
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        // only the asks at 40.00 and 100.00 are within the limit
+        assert_eq!(matches.get_state(), MatchState::Partial);
+        assert_eq!(matches.get_qty_left(), 50);
+        assert!(orderbook.peek_top_bid().is_none());
+        assert_eq!(orderbook.peek_top_ask().unwrap().price, dec!(550.00));
+    }
+
+    #[test]
+    fn a_limit_fok_order_is_killed_when_it_cannot_be_filled_completely() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for ask in create_orders(OrderSide::Ask) {
+            let _ = orderbook.place(ask);
+        }
+
+        let matcher = Matcher {};
+        let mut bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Limit, 200);
+        bid.time_in_force = TimeInForce::FillOrKill;
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        assert_eq!(matches.get_state(), MatchState::NoMatch);
+        assert!(matches.get_matches().is_empty());
+        assert!(orderbook.peek_top_bid().is_none());
+
+        let top_ask = orderbook.peek_top_ask().unwrap();
+        assert_eq!(top_ask.price, dec!(40.00));
+        assert_eq!(top_ask.quantity, 50);
+    }
+
+    #[test]
+    fn a_limit_fok_order_is_matched_when_it_can_be_filled_completely() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for ask in create_orders(OrderSide::Ask) {
+            let _ = orderbook.place(ask);
+        }
+
+        let matcher = Matcher {};
+        let mut bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Limit, 150);
+        bid.time_in_force = TimeInForce::FillOrKill;
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(matches.get_matches().len(), 4);
+        assert_eq!(orderbook.peek_top_ask().unwrap().price, dec!(550.00));
+    }
+
+    #[test]
+    fn an_iceberg_order_only_shows_its_display_quantity_but_fills_in_full() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+
+        let mut iceberg = create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 100);
+        iceberg.display_quantity = Some(10);
+        let _ = orderbook.place(iceberg);
+        assert_eq!(orderbook.depth(5), (vec![], vec![(dec!(20.00), 10)]));
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(20.00), OrderType::Market, 100);
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        let executed: Long = matches
+            .get_matches()
+            .iter()
+            .filter(|trade| trade.orderid == iceberg.orderid)
+            .map(|trade| trade.quantity)
+            .sum();
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(executed, 100);
+        assert!(orderbook.peek_top_ask().is_none());
+    }
+
+    #[test]
+    fn an_iceberg_order_loses_time_priority_when_it_is_replenished() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+
+        let mut iceberg = create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 30);
+        iceberg.display_quantity = Some(10);
+        iceberg.timestamp -= 2_000;
+        let mut peer = create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 5);
+        peer.timestamp -= 1_000;
+        let _ = orderbook.place(iceberg);
+        let _ = orderbook.place(peer);
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(20.00), OrderType::Market, 12);
+        let trades = matcher
+            .match_order(bid, &mut orderbook)
+            .unwrap()
+            .get_matches();
+
+        // the first slice of the iceberg fills, then the peer is ahead of the replenished slice
+        assert_eq!(trades[1].orderid, iceberg.orderid);
+        assert_eq!(trades[1].quantity, 10);
+        assert_eq!(trades[3].orderid, peer.orderid);
+        assert_eq!(trades[3].quantity, 2);
+        assert_eq!(orderbook.depth(1), (vec![], vec![(dec!(20.00), 13)]));
+    }
+
+    #[test]
+    fn orders_placed_in_the_same_millisecond_are_matched_in_placement_order() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+
+        let first = create_order(OrderSide::Bid, dec!(20.00), OrderType::Limit, 5);
+        let second = Order {
+            orderid: Uuid::new_v4(),
+            ..first
+        };
+        let _ = orderbook.place(first);
+        let _ = orderbook.place(second);
+
+        let matcher = Matcher {};
+        let ask = create_order(OrderSide::Ask, dec!(20.00), OrderType::Market, 5);
+        let trades = matcher
+            .match_order(ask, &mut orderbook)
+            .unwrap()
+            .get_matches();
+        assert_eq!(trades[1].orderid, first.orderid);
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, second.orderid);
+    }
+
+    #[test]
+    fn an_order_routed_to_the_book_of_another_pair_is_rejected() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDC));
+        let _ = orderbook.place(Order {
+            trading_pair: orderbook.trading_pair(),
+            ..create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 5)
+        });
+
+        let bid = create_order(OrderSide::Bid, dec!(20.00), OrderType::Limit, 5);
+        let result = Matcher.match_order(bid, &mut orderbook);
+        assert!(matches!(
+            result.unwrap_err(),
+            Failure::InvalidOrderForBook(_)
+        ));
+        assert_eq!(orderbook.ask_count(), 1);
+        assert_eq!(orderbook.bid_count(), 0);
+    }
+
+    #[test]
+    fn three_orders_at_the_same_price_and_time_are_matched_first_in_first_out() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let first = create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 2);
+        let orders = [
+            first,
+            Order {
+                orderid: Uuid::new_v4(),
+                ..first
+            },
+            Order {
+                orderid: Uuid::new_v4(),
+                ..first
+            },
+        ];
+        for order in orders {
+            let _ = orderbook.place(order);
+        }
+
+        let bid = create_order(OrderSide::Bid, dec!(20.00), OrderType::Market, 6);
+        let makers: Vec<Uuid> = Matcher
+            .match_order(bid, &mut orderbook)
+            .unwrap()
+            .get_matches()
+            .iter()
+            .filter(|trade| trade.side == OrderSide::Ask)
+            .map(|trade| trade.orderid)
+            .collect();
+        assert_eq!(makers, orders.map(|order| order.orderid));
+    }
+
+    #[test]
+    fn an_order_filled_by_the_top_order_matches_the_same_on_the_fast_path() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC))
+            .with_clock(Arc::new(MockClock::at(1_700_000_000_000)));
+        for order in create_orders(OrderSide::Ask) {
+            let _ = orderbook.place(order);
+        }
+        let fill_or_kill = Order {
+            time_in_force: TimeInForce::FillOrKill,
+            ..create_order(OrderSide::Bid, dec!(45.00), OrderType::Limit, 50)
+        };
+        let orders = [
+            create_order(OrderSide::Bid, dec!(40.00), OrderType::Limit, 50),
+            create_order(OrderSide::Bid, dec!(45.00), OrderType::Limit, 20),
+            create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, 10),
+            fill_or_kill,
+        ];
+
+        for order in orders {
+            assert!(Matcher::is_filled_by_top(&order, &orderbook));
+            let mut fast_book = orderbook.clone();
+            let fast = Matcher::match_in_book(order, &mut fast_book);
+
+            let mut general_book = orderbook.clone();
+            let mut general = Match::new();
+            general.update_original_qty(order.quantity);
+            let general = Matcher::match_through_book(order, &mut general_book, general);
+
+            assert_eq!(fast.get_matches(), general.get_matches());
+            assert_eq!(fast.get_events(), general.get_events());
+            assert_eq!(fast.get_state(), general.get_state());
+            assert_eq!(fast_book.depth(5), general_book.depth(5));
+        }
+
+        // an order that walks past the top order takes the general path
+        let sweep = create_order(OrderSide::Bid, dec!(100.00), OrderType::Limit, 51);
+        assert!(!Matcher::is_filled_by_top(&sweep, &orderbook));
+    }
+
+    fn same_time_cross(tie_break: TieBreak) -> (Order, Order, Vec<Trade>) {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let bid = create_order(OrderSide::Bid, dec!(21.00), OrderType::Limit, 5);
+        let ask = Order {
+            timestamp: bid.timestamp,
+            ..create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 5)
+        };
+        let _ = orderbook.place(bid);
+        let _ = orderbook.place(ask);
+
+        let trades = Matcher
+            .uncross(&mut orderbook, tie_break)
+            .iter()
+            .flat_map(|matches| matches.get_matches())
+            .collect();
+        assert!(orderbook.is_empty());
+        (bid, ask, trades)
+    }
+
+    #[test]
+    fn a_same_time_cross_trades_at_the_first_placed_price_with_maker_priority() {
+        let (bid, ask, trades) = same_time_cross(TieBreak::Maker);
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|trade| trade.price == dec!(21.00)));
+        assert_eq!(trades[0].orderid, ask.orderid);
+        assert_eq!(trades[1].orderid, bid.orderid);
+    }
+
+    #[test]
+    fn a_same_time_cross_trades_at_the_last_placed_price_with_taker_priority() {
+        let (bid, ask, trades) = same_time_cross(TieBreak::Taker);
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|trade| trade.price == dec!(20.00)));
+        assert_eq!(trades[0].orderid, bid.orderid);
+        assert_eq!(trades[1].orderid, ask.orderid);
+    }
+
+    #[test]
+    fn a_later_order_is_always_the_taker_whatever_the_tie_break() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let bid = create_order(OrderSide::Bid, dec!(21.00), OrderType::Limit, 5);
+        let ask = Order {
+            timestamp: bid.timestamp + 1,
+            ..create_order(OrderSide::Ask, dec!(20.00), OrderType::Limit, 5)
+        };
+        let _ = orderbook.place(bid);
+        let _ = orderbook.place(ask);
+
+        let matches = Matcher.uncross(&mut orderbook, TieBreak::Taker);
+        assert_eq!(matches[0].get_matches()[0].price, dec!(21.00));
+    }
+
+    #[test]
+    fn a_limit_order_sweeps_price_levels_up_to_its_limit_and_rests_the_remainder() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC))
+            .with_clock(Arc::new(MockClock::at(1_700_000_000_000)));
+        let asks = [
+            create_order(OrderSide::Ask, dec!(100.00), OrderType::Limit, 30),
+            create_order(OrderSide::Ask, dec!(101.00), OrderType::Limit, 20),
+            create_order(OrderSide::Ask, dec!(102.00), OrderType::Limit, 50),
+        ];
+        for ask in asks {
+            let _ = orderbook.place(ask);
+        }
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(101.00), OrderType::Limit, 60);
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        let trade = |order: &Order, price, status, quantity, price_improvement| Trade {
+            orderid: order.orderid,
+            account_id: None,
+            tag: None,
+            side: order.side,
+            price,
+            status,
+            quantity,
+            timestamp: 1_700_000_000_000,
+            price_improvement,
+        };
+        assert_eq!(
+            matches.get_matches(),
+            vec![
+                trade(&bid, dec!(100.00), OrderStatus::PartialFill, 30, dec!(1.00)),
+                trade(&asks[0], dec!(100.00), OrderStatus::Filled, 30, dec!(0)),
+                trade(&bid, dec!(101.00), OrderStatus::PartialFill, 20, dec!(0)),
+                trade(&asks[1], dec!(101.00), OrderStatus::Filled, 20, dec!(0)),
+            ]
+        );
+        assert_eq!(matches.get_state(), MatchState::Partial);
+        assert_eq!(matches.get_qty_left(), 10);
+
+        // the ask above the limit is untouched and the remainder of the bid rests below it
+        assert_eq!(orderbook.peek_top_ask().unwrap().quantity, 50);
+        let resting = orderbook.peek_top_bid().unwrap();
+        assert_eq!(resting.orderid, bid.orderid);
+        assert_eq!(resting.quantity, 10);
+    }
+
+    #[test]
+    fn the_top_asks_are_listed_in_the_order_a_market_bid_consumes_them() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for price in [dec!(21), dec!(20), dec!(22), dec!(20.0), dec!(21), dec!(20)] {
+            let _ = orderbook.place(create_order(OrderSide::Ask, price, OrderType::Limit, 5));
+        }
+
+        let top = orderbook.top_n_asks(10);
+        assert_eq!(top.len(), 6);
+        assert_eq!(orderbook.top_n_asks(2), top[..2]);
+
+        let bid = create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, 30);
+        let trades = Matcher
+            .match_order(bid, &mut orderbook)
+            .unwrap()
+            .get_matches();
+        let consumed: Vec<Uuid> = trades
+            .iter()
+            .filter(|trade| trade.side == OrderSide::Ask)
+            .map(|trade| trade.orderid)
+            .collect();
+        let listed: Vec<Uuid> = top.iter().map(|order| order.orderid).collect();
+        assert_eq!(consumed, listed);
+    }
+
+    #[test]
+    fn the_top_bids_are_listed_in_the_order_a_market_ask_consumes_them() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for price in [dec!(19), dec!(20), dec!(18), dec!(20), dec!(19.00)] {
+            let _ = orderbook.place(create_order(OrderSide::Bid, price, OrderType::Limit, 5));
+        }
+
+        let top = orderbook.top_n_bids(5);
+        let prices: Vec<Decimal> = top.iter().map(|order| order.price).collect();
+        assert_eq!(
+            prices,
+            vec![dec!(20), dec!(20), dec!(19), dec!(19.00), dec!(18)]
+        );
+
+        let ask = create_order(OrderSide::Ask, Decimal::ZERO, OrderType::Market, 25);
+        let trades = Matcher
+            .match_order(ask, &mut orderbook)
+            .unwrap()
+            .get_matches();
+        let consumed: Vec<Uuid> = trades
+            .iter()
+            .filter(|trade| trade.side == OrderSide::Bid)
+            .map(|trade| trade.orderid)
+            .collect();
+        let listed: Vec<Uuid> = top.iter().map(|order| order.orderid).collect();
+        assert_eq!(consumed, listed);
+        assert!(orderbook.top_n_bids(5).is_empty());
+    }
+
+    #[test]
+    fn an_aggressive_limit_bid_trades_at_the_makers_price_and_records_the_improvement() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let ask = create_order(OrderSide::Ask, dec!(100.00), OrderType::Limit, 5);
+        let _ = orderbook.place(ask);
+
+        let bid = create_order(OrderSide::Bid, dec!(110.00), OrderType::Limit, 5);
+        let trades = Matcher
+            .match_order(bid, &mut orderbook)
+            .unwrap()
+            .get_matches();
+
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|trade| trade.price == dec!(100.00)));
+        assert_eq!(trades[0].orderid, bid.orderid);
+        assert_eq!(trades[0].price_improvement, dec!(10.00));
+        assert_eq!(trades[1].orderid, ask.orderid);
+        assert_eq!(trades[1].price_improvement, Decimal::ZERO);
+    }
+
+    #[test]
+    fn an_aggressive_limit_ask_trades_at_the_makers_price_and_records_the_improvement() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Limit, 5);
+        let _ = orderbook.place(bid);
+
+        let ask = create_order(OrderSide::Ask, dec!(92.50), OrderType::Limit, 5);
+        let trades = Matcher
+            .match_order(ask, &mut orderbook)
+            .unwrap()
+            .get_matches();
+
+        assert!(trades.iter().all(|trade| trade.price == dec!(100.00)));
+        assert_eq!(trades[0].orderid, ask.orderid);
+        assert_eq!(trades[0].price_improvement, dec!(7.50));
+        assert_eq!(trades[1].price_improvement, Decimal::ZERO);
+    }
+
+    #[test]
+    fn a_market_order_gets_no_price_improvement() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let _ = orderbook.place(create_order(
+            OrderSide::Ask,
+            dec!(100.00),
+            OrderType::Limit,
+            5,
+        ));
+
+        let bid = create_order(OrderSide::Bid, dec!(110.00), OrderType::Market, 5);
+        let trades = Matcher
+            .match_order(bid, &mut orderbook)
+            .unwrap()
+            .get_matches();
+
+        assert!(trades
+            .iter()
+            .all(|trade| trade.price_improvement == Decimal::ZERO));
+    }
+
+    #[test]
+    fn trade_prices_are_normalized_to_the_quote_precision_of_the_pair() {
+        let mut orderbook = LimitOrderBook::with_config(
+            TradingPair::from(Asset::ETH, Asset::USDC),
+            BookConfig {
+                quote_precision: Some(2),
+                ..Default::default()
+            },
+        );
+        for price in [dec!(20), dec!(20.5), dec!(20.12)] {
+            let _ = orderbook.place(create_order(OrderSide::Ask, price, OrderType::Limit, 5));
+        }
+
+        let bid = create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, 15);
+        let prices: Vec<String> = Matcher
+            .match_order(bid, &mut orderbook)
+            .unwrap()
+            .get_matches()
+            .iter()
+            .map(|trade| trade.price.to_string())
+            .collect();
+        assert_eq!(
+            prices,
+            vec!["20.00", "20.00", "20.12", "20.12", "20.50", "20.50"]
+        );
+    }
+
+    #[test]
+    fn matching_at_the_quantity_boundary_never_wraps() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let matcher = Matcher {};
+        for _ in 0..2 {
+            let _ = orderbook.place(create_order(OrderSide::Ask, dec!(10), OrderType::Limit, 10));
+        }
+
+        // equal quantities fill both sides exactly
+        let equal = create_order(OrderSide::Bid, dec!(10), OrderType::Limit, 10);
+        let matches = matcher.match_order(equal, &mut orderbook).unwrap();
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(matches.get_qty_left(), 0);
+        assert_eq!(orderbook.ask_count(), 1);
+
+        // one more than is resting leaves exactly one unit over, which rests
+        let one_more = create_order(OrderSide::Bid, dec!(10), OrderType::Limit, 11);
+        let matches = matcher.match_order(one_more, &mut orderbook).unwrap();
+        assert_eq!(matches.get_state(), MatchState::Partial);
+        assert_eq!(matches.filled_quantity(), 10);
+        assert_eq!(matches.get_qty_left(), 1);
+        assert_eq!(orderbook.peek_top_bid().unwrap().quantity, 1);
+        assert!(orderbook.peek_top_ask().is_none());
+    }
+
+    #[test]
+    fn the_fill_ratio_reflects_how_much_of_the_order_was_matched() {
+        let matcher = Matcher {};
+
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for bid in create_orders(OrderSide::Bid) {
+            let _ = orderbook.place(bid);
+        }
+        let full = matcher
+            .match_order(
+                create_order(OrderSide::Ask, dec!(5.00), OrderType::Limit, 40),
+                &mut orderbook,
+            )
+            .unwrap();
+        assert_eq!(full.original_quantity(), 40);
+        assert_eq!(full.filled_quantity(), 40);
+        assert_eq!(full.fill_ratio(), Decimal::ONE);
+
+        // 160 of the 200 resting bids are left
+        let partial = matcher
+            .match_order(
+                create_order(OrderSide::Ask, dec!(5.00), OrderType::Limit, 640),
+                &mut orderbook,
+            )
+            .unwrap();
+        assert_eq!(partial.filled_quantity(), 160);
+        assert_eq!(partial.fill_ratio(), dec!(0.25));
+
+        let none = matcher
+            .match_order(
+                create_order(OrderSide::Ask, dec!(5.00), OrderType::Limit, 10),
+                &mut orderbook,
+            )
+            .unwrap();
+        assert_eq!(none.get_state(), MatchState::NoMatch);
+        assert_eq!(none.original_quantity(), 10);
+        assert_eq!(none.filled_quantity(), 0);
+        assert_eq!(none.fill_ratio(), Decimal::ZERO);
+    }
+
+    fn notional_order(notional: Decimal) -> Order {
+        Order {
+            notional: Some(notional),
+            ..create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, 0)
+        }
+    }
+
+    fn book_with_two_ask_levels() -> LimitOrderBook {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        for price in [dec!(100), dec!(101)] {
+            let _ = orderbook.place(create_order(OrderSide::Ask, price, OrderType::Limit, 10));
+        }
+        orderbook
+    }
+
+    #[test]
+    fn a_notional_market_order_fills_across_levels_up_to_its_notional() {
+        let mut orderbook = book_with_two_ask_levels();
+
+        let matcher = Matcher {};
+        // 10 at 100 and 5 at 101 spend 1505, leaving 45 which cannot buy another unit
+        let matches = matcher
+            .match_order(notional_order(dec!(1550)), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(matches.filled_quantity(), 15);
+        assert_eq!(matches.filled_notional(), dec!(1505));
+
+        let trades = matches.get_matches();
+        assert_eq!(trades.len(), 4);
+        assert_eq!((trades[0].price, trades[0].quantity), (dec!(100), 10));
+        assert_eq!(trades[0].status, OrderStatus::PartialFill);
+        assert_eq!((trades[2].price, trades[2].quantity), (dec!(101), 5));
+        assert_eq!(trades[2].status, OrderStatus::Filled);
+        assert_eq!(orderbook.peek_top_ask().unwrap().quantity, 5);
+    }
+
+    #[test]
+    fn a_notional_market_order_falls_short_when_liquidity_runs_out() {
+        let mut orderbook = book_with_two_ask_levels();
+
+        let matcher = Matcher {};
+        let matches = matcher
+            .match_order(notional_order(dec!(5000)), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::Partial);
+        assert_eq!(matches.filled_quantity(), 20);
+        assert_eq!(matches.filled_notional(), dec!(2010));
+        assert_eq!(matches.fill_ratio(), dec!(0.402));
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn prices_of_a_different_scale_match_at_the_exact_limit() {
+        let matcher = Matcher {};
+        for (resting, incoming) in [
+            (OrderSide::Ask, OrderSide::Bid),
+            (OrderSide::Bid, OrderSide::Ask),
+        ] {
+            let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+            let _ = orderbook.place(create_order(resting, dec!(20.00), OrderType::Limit, 5));
+
+            // a cent short of the resting price doesn't reach it
+            let short = match incoming {
+                OrderSide::Bid => dec!(19.99),
+                OrderSide::Ask => dec!(20.01),
+            };
+            let order = create_order(incoming, short, OrderType::Limit, 5);
+            let matches = matcher.match_order(order, &mut orderbook).unwrap();
+            assert!(matches.get_matches().is_empty());
+            let _ = orderbook.cancel(order.orderid);
+
+            for price in [dec!(20.0), dec!(20), dec!(20.000)] {
+                let order = create_order(incoming, price, OrderType::Limit, 1);
+                let matches = matcher.match_order(order, &mut orderbook).unwrap();
+                assert_eq!(
+                    matches.get_state(),
+                    MatchState::Full,
+                    "{:?} at {}",
+                    incoming,
+                    price
+                );
+                assert_eq!(matches.get_matches()[0].price, dec!(20.00));
+            }
+            assert_eq!(orderbook.volume_at_price(resting, dec!(20)), 2);
+            assert_eq!(orderbook.len(), 1);
+        }
+    }
+
+    fn with_min_fill(order: Order, min_fill: Long) -> Order {
+        Order {
+            min_fill: Some(min_fill),
+            ..order
+        }
+    }
+
+    #[test]
+    fn an_order_that_cannot_make_its_minimum_fill_rests_without_trading() {
+        let mut orderbook = book_with_two_ask_levels();
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(99), OrderType::Limit, 15);
+        let matches = matcher
+            .match_order(with_min_fill(bid, 12), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::NoMatch);
+        assert!(matches.get_matches().is_empty());
+        assert_eq!(matches.get_events()[0].status, OrderStatus::Created);
+        assert_eq!(orderbook.peek_top_bid().unwrap().quantity, 15);
+        assert_eq!(orderbook.ask_count(), 2);
+    }
+
+    #[test]
+    fn an_order_that_cannot_make_its_minimum_fill_is_canceled_rather_than_cross_the_book() {
+        let mut orderbook = book_with_two_ask_levels();
+
+        let matcher = Matcher {};
+        // only the 10 at 100 are within the limit of the bid, and resting it at 100 would
+        // leave the book crossed
+        let bid = create_order(OrderSide::Bid, dec!(100), OrderType::Limit, 15);
+        let matches = matcher
+            .match_order(with_min_fill(bid, 12), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::NoMatch);
+        assert_eq!(matches.get_qty_left(), 15);
+        assert!(matches.get_matches().is_empty());
+        assert!(matches.get_events().is_empty());
+        assert_eq!(orderbook.bid_count(), 0);
+        assert_eq!(orderbook.ask_count(), 2);
+    }
+
+    #[test]
+    fn an_order_that_cannot_make_its_minimum_fill_is_canceled_when_it_cannot_rest() {
+        let mut orderbook = book_with_two_ask_levels();
+
+        let matcher = Matcher {};
+        let mut bid = create_order(OrderSide::Bid, dec!(100), OrderType::Limit, 15);
+        bid.time_in_force = TimeInForce::ImmediateOrCancel;
+        let matches = matcher
+            .match_order(with_min_fill(bid, 12), &mut orderbook)
+            .unwrap();
+        assert!(matches.get_matches().is_empty());
+        assert!(matches.get_events().is_empty());
+
+        let market = create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, 25);
+        let matches = matcher
+            .match_order(with_min_fill(market, 21), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::NoLiquidity);
+        assert_eq!(matches.get_qty_left(), 25);
+        assert!(matches.get_matches().is_empty());
+        assert_eq!(orderbook.len(), 2);
+    }
+
+    #[test]
+    fn an_order_that_can_make_its_minimum_fill_trades_as_usual() {
+        let mut orderbook = book_with_two_ask_levels();
+
+        let matcher = Matcher {};
+        // exactly the minimum is within the limit, so the order fills it and rests the rest
+        let bid = create_order(OrderSide::Bid, dec!(100), OrderType::Limit, 15);
+        let matches = matcher
+            .match_order(with_min_fill(bid, 10), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::Partial);
+        assert_eq!(matches.filled_quantity(), 10);
+        assert_eq!(orderbook.peek_top_bid().unwrap().quantity, 5);
+
+        let market = create_order(OrderSide::Bid, Decimal::ZERO, OrderType::Market, 8);
+        let matches = matcher
+            .match_order(with_min_fill(market, 8), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(matches.filled_quantity(), 8);
+        assert_eq!(orderbook.peek_top_ask().unwrap().quantity, 2);
+    }
+
+    fn book_of_tiny_asks(max_fills_per_order: Option<usize>) -> LimitOrderBook {
+        let mut orderbook = LimitOrderBook::with_config(
+            TradingPair::from(Asset::ETH, Asset::USDC),
+            BookConfig {
+                max_fills_per_order: max_fills_per_order.and_then(NonZeroUsize::new),
+                ..Default::default()
+            },
+        );
+        for _ in 0..100 {
+            let _ = orderbook.place(create_order(OrderSide::Ask, dec!(20), OrderType::Limit, 1));
+        }
+        orderbook
+    }
+
+    #[test]
+    fn matching_stops_once_an_order_takes_the_most_fills_the_book_allows() {
+        let mut orderbook = book_of_tiny_asks(Some(10));
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Market, 50);
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        assert_eq!(matches.get_state(), MatchState::FillLimitReached);
+        // a taker and a maker leg for each fill
+        assert_eq!(matches.get_matches().len(), 20);
+        assert_eq!(matches.filled_quantity(), 10);
+        assert_eq!(matches.get_qty_left(), 40);
+        assert_eq!(orderbook.ask_count(), 90);
+    }
+
+    #[test]
+    fn what_is_left_of_a_limit_order_at_the_fill_limit_is_not_rested() {
+        let mut orderbook = book_of_tiny_asks(Some(10));
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(20), OrderType::Limit, 50);
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+
+        assert_eq!(matches.get_state(), MatchState::FillLimitReached);
+        assert_eq!(matches.get_qty_left(), 40);
+        // resting the rest at 20 would have left the book crossed
+        assert_eq!(orderbook.bid_count(), 0);
+        assert!(matches
+            .get_events()
+            .iter()
+            .all(|event| event.status != OrderStatus::Created));
+    }
+
+    #[test]
+    fn a_fill_or_kill_order_is_killed_when_it_needs_more_fills_than_the_book_allows() {
+        let mut orderbook = book_of_tiny_asks(Some(10));
+
+        let matcher = Matcher {};
+        let mut bid = create_order(OrderSide::Bid, dec!(20), OrderType::Limit, 11);
+        bid.time_in_force = TimeInForce::FillOrKill;
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+        assert_eq!(matches.get_state(), MatchState::NoMatch);
+        assert_eq!(orderbook.ask_count(), 100);
+
+        bid.quantity = 10;
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(orderbook.ask_count(), 90);
+    }
+
+    #[test]
+    fn an_order_that_needs_more_fills_than_the_book_allows_for_its_minimum_does_not_trade() {
+        let mut orderbook = book_of_tiny_asks(Some(10));
+
+        let matcher = Matcher {};
+        // the book holds 100 within the limit, but only 10 of them can be filled
+        let bid = create_order(OrderSide::Bid, dec!(20), OrderType::Limit, 50);
+        let matches = matcher
+            .match_order(with_min_fill(bid, 11), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::NoMatch);
+        assert!(matches.get_matches().is_empty());
+        assert_eq!(orderbook.ask_count(), 100);
+        assert_eq!(orderbook.bid_count(), 0);
+
+        let bid = create_order(OrderSide::Bid, dec!(20), OrderType::Limit, 50);
+        let matches = matcher
+            .match_order(with_min_fill(bid, 10), &mut orderbook)
+            .unwrap();
+        assert_eq!(matches.get_state(), MatchState::FillLimitReached);
+        assert_eq!(matches.filled_quantity(), 10);
+    }
+
+    #[test]
+    fn an_order_takes_every_fill_it_needs_without_a_fill_limit() {
+        let mut orderbook = book_of_tiny_asks(None);
+
+        let matcher = Matcher {};
+        let bid = create_order(OrderSide::Bid, dec!(100.00), OrderType::Market, 50);
+        let matches = matcher.match_order(bid, &mut orderbook).unwrap();
+        assert_eq!(matches.get_state(), MatchState::Full);
+        assert_eq!(matches.get_matches().len(), 100);
+    }
+
     fn create_order(
         side: OrderSide,
         price: Decimal,
@@ -344,10 +1486,21 @@ mod test {
     ) -> Order {
         Order {
             orderid: Uuid::new_v4(),
+            client_order_id: None,
+            account_id: None,
+            tag: None,
             price,
             side,
             quantity,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            client_timestamp: None,
+            hidden_quantity: 0,
+            stop_price: None,
             order_type,
+            time_in_force: TimeInForce::GoodTillCancel,
+            seq: 0,
             timestamp: Util::current_time_millis(),
             trading_pair: TradingPair::from(Asset::ETH, Asset::USDC),
         }
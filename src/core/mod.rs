@@ -1,7 +1,11 @@
+pub mod handlers;
+pub mod intake;
 pub mod matcher;
 pub mod model;
 pub mod orderbook;
 pub mod pqueue;
 pub mod router;
+pub mod shards;
+pub mod sinks;
 pub mod types;
 pub mod utils;
@@ -1,19 +1,52 @@
-use rust_decimal::Decimal;
-use std::{cmp::Ordering, fmt::Debug};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug, Display},
+    str::FromStr,
+};
 
 use super::{
     pqueue::KeyIndx,
-    types::{Asset, Failure, Long, OrderId, OrderSide, OrderStatus, OrderType, TimestampMillis},
+    types::{
+        AccountId, Asset, ClientOrderId, Failure, FailureKind, Long, OrderId, OrderSide,
+        OrderStatus, OrderTag, OrderType, TimeInForce, TimestampMillis, Trade,
+    },
+    utils::Util,
 };
 
 #[derive(PartialEq, Eq, Copy, Ord, PartialOrd, Clone, Debug)]
 pub struct Order {
     pub orderid: OrderId,
+    pub client_order_id: Option<ClientOrderId>,
+    pub account_id: Option<AccountId>,
+    /// passed on to the trades and events of the order as it is, see [OrderTag]
+    pub tag: Option<OrderTag>,
     pub price: Decimal,
     pub quantity: Long,
+    /// for market orders placed by the amount to spend, the notional (price times quantity)
+    /// the order fills up to in place of a quantity
+    pub notional: Option<Decimal>,
+    /// for iceberg orders, the most quantity that is shown on the book at any time
+    pub display_quantity: Option<Long>,
+    /// the least quantity the order has to be able to fill when it arrives for it to trade at
+    /// all, see [PlaceOrder::with_min_fill](super::router::PlaceOrder::with_min_fill)
+    pub min_fill: Option<Long>,
+    /// for iceberg orders, the quantity held back from the book to replenish the shown slice
+    pub hidden_quantity: Long,
+    /// for stop orders, the price the market has to trade at for the order to be triggered
+    pub stop_price: Option<Decimal>,
     pub side: OrderSide,
     pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
     pub timestamp: TimestampMillis,
+    /// when the client or feed that sent the order stamped it, as opposed to when the engine
+    /// received it, see [super::router::PlaceOrder::with_client_timestamp]
+    pub client_timestamp: Option<TimestampMillis>,
+    /// the position of the order in the sequence of orders placed in its book, which breaks
+    /// ties between orders placed at the same price in the same millisecond
+    pub seq: u64,
     pub trading_pair: TradingPair,
 }
 
@@ -24,11 +57,125 @@ impl Order {
             price: self.price,
             side: self.side,
             timestamp: self.timestamp,
+            seq: self.seq,
+        }
+    }
+
+    /// Gets the value of what is left of the order at its price, hidden quantity included. Not
+    /// to be confused with the notional a market order may be placed by, which is a target to
+    /// spend rather than a value
+    pub fn notional(&self) -> Decimal {
+        Util::notional(self.price, self.remaining_quantity())
+    }
+
+    /// Gets the quantity of the order that is yet to be filled, including any hidden quantity
+    pub fn remaining_quantity(&self) -> Long {
+        self.quantity.saturating_add(self.hidden_quantity)
+    }
+
+    /// Checks if a stop order is triggered by the market trading at the given price. Buy stops
+    /// trigger when the market trades at or above the stop price, sell stops at or below it
+    pub fn is_triggered_at(&self, price: Decimal) -> bool {
+        match self.stop_price {
+            Some(stop_price) => match self.side {
+                OrderSide::Bid => price >= stop_price,
+                OrderSide::Ask => price <= stop_price,
+            },
+            None => false,
+        }
+    }
+
+    /// Converts a triggered stop order into the order that is matched in its place. Stops
+    /// become market orders, stop limits become limit orders at their limit price
+    pub fn to_triggered(&self, timestamp: TimestampMillis) -> Order {
+        Order {
+            order_type: match self.order_type {
+                OrderType::Stop => OrderType::Market,
+                OrderType::StopLimit => OrderType::Limit,
+                order_type => order_type,
+            },
+            stop_price: None,
+            timestamp,
+            ..*self
+        }
+    }
+
+    /// Checks if the order has outlived its time in force at the given time. Only good till
+    /// date orders ever expire
+    pub fn is_expired(&self, now: TimestampMillis) -> bool {
+        match self.time_in_force {
+            TimeInForce::GoodTillDate(expires_at) => expires_at <= now,
+            _ => false,
+        }
+    }
+
+    /// Converts the order into the equivalent order on the inverse trading pair. Buying the
+    /// order asset of USDC/BTC is the same as selling the order asset of BTC/USDC, so the side
+    /// is flipped, the price is inverted and the quantity is restated in the new order asset.
+    ///
+    /// The quantity, and any minimum fill or display quantity, must convert into a whole number
+    /// of units, otherwise
+    /// the order is rejected.
+    /// Market orders have no price to convert it with, so are always rejected
+    pub fn to_inverse(&self) -> Result<Order, Failure> {
+        if self.notional.is_some() {
+            return Err(Failure::OrderRejected(
+                "Notional orders cannot be placed on an inverse pair".to_string(),
+            ));
+        }
+        if self.order_type == OrderType::Market {
+            return Err(Failure::OrderRejected(
+                "Market orders have no price to restate their quantity with on an inverse pair"
+                    .to_string(),
+            ));
+        }
+        let price = Decimal::ONE.checked_div(self.price).ok_or_else(|| {
+            Failure::OrderRejected("Price must be non-zero to invert an order".to_string())
+        })?;
+        let quantity = self.inverse_quantity(self.quantity)?;
+        let min_fill = self
+            .min_fill
+            .map(|min_fill| self.inverse_quantity(min_fill))
+            .transpose()?;
+        let display_quantity = self
+            .display_quantity
+            .map(|display_quantity| self.inverse_quantity(display_quantity))
+            .transpose()?;
+
+        Ok(Order {
+            price: price.normalize(),
+            quantity,
+            min_fill,
+            display_quantity,
+            stop_price: self
+                .stop_price
+                .and_then(|stop_price| Decimal::ONE.checked_div(stop_price))
+                .map(|stop_price| stop_price.normalize()),
+            side: self.side.opposite(),
+            trading_pair: self.trading_pair.inverse(),
+            ..*self
+        })
+    }
+
+    /// Restates a quantity of the order asset in units of the price asset, at the price of the
+    /// order
+    fn inverse_quantity(&self, quantity: Long) -> Result<Long, Failure> {
+        let out_of_range =
+            || Failure::OrderRejected("Quantity is out of range for the inverse pair".to_string());
+        let converted = Decimal::from(quantity)
+            .checked_mul(self.price)
+            .ok_or_else(out_of_range)?;
+        if !converted.fract().is_zero() {
+            return Err(Failure::OrderRejected(format!(
+                "Quantity {} does not convert into whole units of {:?}",
+                quantity, self.trading_pair.price_asset
+            )));
         }
+        converted.to_u64().ok_or_else(out_of_range)
     }
 }
 
-#[derive(PartialEq, Eq, Copy, Ord, PartialOrd, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Copy, Ord, PartialOrd, Hash, Clone, Debug, Serialize)]
 pub struct TradingPair {
     pub order_asset: Asset,
     pub price_asset: Asset,
@@ -41,6 +188,42 @@ impl TradingPair {
             price_asset,
         }
     }
+
+    /// Gets the pair with the order and price assets swapped, eg BTC/USDC becomes USDC/BTC
+    pub fn inverse(&self) -> Self {
+        Self {
+            order_asset: self.price_asset,
+            price_asset: self.order_asset,
+        }
+    }
+}
+
+impl Display for TradingPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}/{:?}", self.order_asset, self.price_asset)
+    }
+}
+
+impl FromStr for TradingPair {
+    type Err = Failure;
+
+    /// Parses a pair written as its order asset and price asset separated by a slash, eg "BTC/USDC"
+    fn from_str(pair: &str) -> Result<Self, Self::Err> {
+        let (order_asset, price_asset) = pair.split_once('/').ok_or_else(|| {
+            Failure::InvalidTradingPair(format!(
+                "Trading pair {} must be written as ORDER/PRICE, eg BTC/USDC",
+                pair
+            ))
+        })?;
+        let trading_pair = TradingPair::from(order_asset.parse()?, price_asset.parse()?);
+        if trading_pair.order_asset == trading_pair.price_asset {
+            return Err(Failure::InvalidTradingPair(format!(
+                "Trading pair {} must have different order and price assets",
+                pair
+            )));
+        }
+        Ok(trading_pair)
+    }
 }
 
 impl TradingPair {
@@ -54,19 +237,383 @@ impl TradingPair {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Event {
     pub status: OrderStatus,
     pub orderid: OrderId,
-    pub at_price: String,
+    /// the price of the order the event applies to
+    pub at_price: Option<Decimal>,
+    /// the quantity of the order the event applies to, eg what was left when it was canceled
+    pub quantity: Option<Long>,
+    /// the tag the order was placed with, see [OrderTag]
+    pub tag: Option<OrderTag>,
 }
 
 impl Default for Event {
     fn default() -> Self {
         Self {
-            status: OrderStatus::Canceled,
+            status: OrderStatus::Created,
             orderid: Default::default(),
-            at_price: Default::default(),
+            at_price: None,
+            quantity: None,
+            tag: None,
+        }
+    }
+}
+
+impl From<&Trade> for Event {
+    /// Gets the fill event of one side of a trade, carrying the price and quantity it was
+    /// executed at
+    fn from(trade: &Trade) -> Self {
+        Self {
+            status: trade.status,
+            orderid: trade.orderid,
+            at_price: Some(trade.price),
+            quantity: Some(trade.quantity),
+            tag: trade.tag,
+        }
+    }
+}
+
+/// A price on one side of a book, with the total quantity shown at it and the number of orders
+/// resting there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BookLevel {
+    pub price: Decimal,
+    pub quantity: Long,
+    pub orders: usize,
+}
+
+/// Every price level of a book, with bids ordered from highest to lowest and asks from lowest
+/// to highest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BookSnapshot {
+    pub trading_pair: TradingPair,
+    pub bids: Vec<BookLevel>,
+    pub asks: Vec<BookLevel>,
+}
+
+impl BookSnapshot {
+    /// Gets the change of every price level that differs between this snapshot and a later one
+    /// of the same book, the bids then the asks, each from the best price out
+    pub fn deltas(&self, later: &BookSnapshot) -> Vec<DepthDelta> {
+        let mut deltas = level_deltas(self.trading_pair, OrderSide::Bid, &self.bids, &later.bids);
+        // the bids are compared from the lowest price, so they're flipped to start at the best
+        deltas.reverse();
+        deltas.extend(level_deltas(
+            self.trading_pair,
+            OrderSide::Ask,
+            &self.asks,
+            &later.asks,
+        ));
+        deltas
+    }
+
+    /// Adds the levels of another snapshot to this one, summing the levels both have at the
+    /// same price, eg to show a pair split across several books as one
+    pub fn merge(&mut self, other: &BookSnapshot) {
+        self.bids = merge_levels(&self.bids, &other.bids);
+        self.bids.reverse();
+        self.asks = merge_levels(&self.asks, &other.asks);
+    }
+
+    /// Sums up the book at a glance, with the spread and mid price the book gives, see
+    /// [OrderBook::get_spread](super::orderbook::OrderBook::get_spread) and
+    /// [OrderBook::mid_price](super::orderbook::OrderBook::mid_price)
+    pub fn summary(&self, spread: Option<Decimal>, mid_price: Option<Decimal>) -> BookSummary {
+        let volume = |levels: &[BookLevel]| {
+            levels.iter().fold(0 as Long, |total, level| {
+                total.saturating_add(level.quantity)
+            })
+        };
+        BookSummary {
+            trading_pair: self.trading_pair,
+            best_bid: self.bids.first().map(|level| level.price),
+            best_ask: self.asks.first().map(|level| level.price),
+            spread,
+            mid_price,
+            bid_volume: volume(&self.bids),
+            ask_volume: volume(&self.asks),
+            bid_orders: self.bids.iter().map(|level| level.orders).sum(),
+            ask_orders: self.asks.iter().map(|level| level.orders).sum(),
+        }
+    }
+}
+
+/// Sums the levels of one side of two books by price, from the lowest price up
+fn merge_levels(levels: &[BookLevel], others: &[BookLevel]) -> Vec<BookLevel> {
+    let mut merged: BTreeMap<Decimal, BookLevel> = BTreeMap::new();
+    for level in levels.iter().chain(others) {
+        merged
+            .entry(level.price)
+            .and_modify(|merged| {
+                merged.quantity = merged.quantity.saturating_add(level.quantity);
+                merged.orders += level.orders;
+            })
+            .or_insert(*level);
+    }
+    merged.into_values().collect()
+}
+
+/// The state of a book at a glance, eg to print in a terminal. Volumes are the quantity shown on
+/// each side, and pending stops are not counted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BookSummary {
+    pub trading_pair: TradingPair,
+    pub best_bid: Option<Decimal>,
+    pub best_ask: Option<Decimal>,
+    pub spread: Option<Decimal>,
+    pub mid_price: Option<Decimal>,
+    pub bid_volume: Long,
+    pub ask_volume: Long,
+    pub bid_orders: usize,
+    pub ask_orders: usize,
+}
+
+impl Display for BookSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let price = |price: Option<Decimal>| price.map_or("-".to_string(), |p| p.to_string());
+        writeln!(f, "{}", self.trading_pair)?;
+        writeln!(
+            f,
+            "bid {} ask {} spread {} mid {}",
+            price(self.best_bid),
+            price(self.best_ask),
+            price(self.spread),
+            price(self.mid_price)
+        )?;
+        writeln!(f, "bids {} in {} orders", self.bid_volume, self.bid_orders)?;
+        write!(f, "asks {} in {} orders", self.ask_volume, self.ask_orders)
+    }
+}
+
+/// Compares the levels of one side of a book before and after, from the lowest price up
+pub(crate) fn level_deltas(
+    trading_pair: TradingPair,
+    side: OrderSide,
+    before: &[BookLevel],
+    after: &[BookLevel],
+) -> Vec<DepthDelta> {
+    // prices are compared by value, so 20.0 and 20.00 are the same level
+    let before: BTreeMap<Decimal, &BookLevel> =
+        before.iter().map(|level| (level.price, level)).collect();
+    let after: BTreeMap<Decimal, &BookLevel> =
+        after.iter().map(|level| (level.price, level)).collect();
+    let prices: BTreeSet<&Decimal> = before.keys().chain(after.keys()).collect();
+    prices
+        .into_iter()
+        .filter_map(|price| {
+            let (change, level) = match (before.get(price), after.get(price)) {
+                (None, Some(level)) => (LevelChange::Added, **level),
+                (Some(old), Some(level)) if old != level => (LevelChange::Changed, **level),
+                (Some(old), None) => (
+                    LevelChange::Removed,
+                    BookLevel {
+                        quantity: 0,
+                        orders: 0,
+                        ..**old
+                    },
+                ),
+                _ => return None,
+            };
+            Some(DepthDelta {
+                trading_pair,
+                side,
+                change,
+                price: level.price,
+                quantity: level.quantity,
+                orders: level.orders,
+            })
+        })
+        .collect()
+}
+
+/// How a price level of a book changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LevelChange {
+    /// orders rest at a price that had none
+    Added,
+    /// the quantity or number of orders at the price changed
+    Changed,
+    /// the last order at the price left the book
+    Removed,
+}
+
+/// A change to one price level of a book, so the depth of a book can be followed without
+/// taking a snapshot of it after every request. The quantity and number of orders are what the
+/// level holds after the change, both zero when it was removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DepthDelta {
+    pub trading_pair: TradingPair,
+    pub side: OrderSide,
+    pub change: LevelChange,
+    pub price: Decimal,
+    pub quantity: Long,
+    pub orders: usize,
+}
+
+/// Totals of every trade a book has made, kept up as the book trades so they cover more than
+/// the tape holds
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MatchSummary {
+    /// the number of trades made
+    pub trades: u64,
+    /// the total quantity traded
+    pub volume: Long,
+    /// the total notional (price times quantity) traded
+    pub notional: Decimal,
+    /// the trade of the largest quantity, the earliest of them on a tie
+    pub largest_trade: Option<Trade>,
+}
+
+impl MatchSummary {
+    /// Adds a trade to the totals
+    pub fn record(&mut self, trade: &Trade) {
+        self.trades = self.trades.saturating_add(1);
+        self.volume = self.volume.saturating_add(trade.quantity);
+        self.notional = self.notional.saturating_add(trade.notional());
+        match self.largest_trade {
+            Some(largest) if largest.quantity >= trade.quantity => (),
+            _ => self.largest_trade = Some(*trade),
+        }
+    }
+
+    /// Gets the average price trades were made at, weighted by their quantity. Gets None when
+    /// nothing has traded
+    pub fn average_price(&self) -> Option<Decimal> {
+        (self.volume > 0).then(|| self.notional / Decimal::from(self.volume))
+    }
+}
+
+/// A change in the state of an order, as reported by the engine. Events are written to the log
+/// as `key=value` pairs so they can be picked apart by log tooling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EngineEvent {
+    /// an order was accepted by the engine
+    Placed(Order),
+    /// an order traded, this is reported for each side of every trade
+    Matched {
+        trade: Trade,
+        trading_pair: TradingPair,
+    },
+    /// a resting order was canceled
+    Canceled {
+        orderid: OrderId,
+        trading_pair: TradingPair,
+    },
+    /// a price level of a book changed, reported for every level a request changed once the
+    /// request is done
+    Depth(DepthDelta),
+    /// an order was rejected, with the kind of failure it was rejected for. Rejected orders are
+    /// never given an id, so they are told apart by the client order id they were sent with,
+    /// if any. This is only reported when the router is configured to, see
+    /// [RouterConfig::report_rejections](super::router::RouterConfig::report_rejections)
+    Rejected {
+        event: Event,
+        trading_pair: TradingPair,
+        client_order_id: Option<ClientOrderId>,
+        reason: FailureKind,
+    },
+}
+
+impl Display for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineEvent::Placed(order) => write!(
+                f,
+                "event=placed orderid={} pair={} side={:?} type={:?} price={} qty={}",
+                order.orderid,
+                order.trading_pair,
+                order.side,
+                order.order_type,
+                order.price,
+                order.quantity
+            ),
+            EngineEvent::Matched {
+                trade,
+                trading_pair,
+            } => write!(
+                f,
+                "event=matched orderid={} pair={} side={:?} status={:?} price={} qty={}",
+                trade.orderid, trading_pair, trade.side, trade.status, trade.price, trade.quantity
+            ),
+            EngineEvent::Canceled {
+                orderid,
+                trading_pair,
+            } => write!(
+                f,
+                "event=canceled orderid={} pair={}",
+                orderid, trading_pair
+            ),
+            EngineEvent::Depth(delta) => write!(
+                f,
+                "event=depth pair={} side={:?} change={:?} price={} qty={} orders={}",
+                delta.trading_pair,
+                delta.side,
+                delta.change,
+                delta.price,
+                delta.quantity,
+                delta.orders
+            ),
+            EngineEvent::Rejected {
+                event,
+                trading_pair,
+                client_order_id,
+                reason,
+            } => write!(
+                f,
+                "event=rejected pair={} client_order_id={} price={} qty={} reason={}",
+                trading_pair,
+                client_order_id.map_or("-".to_string(), |id| id.to_string()),
+                event.at_price.unwrap_or_default(),
+                event.quantity.unwrap_or_default(),
+                reason
+            ),
+        }
+    }
+}
+
+/// Selects which kinds of [EngineEvent] are written to the log. Everything but the changes to
+/// the depth of books is logged by default
+#[derive(Debug, Clone, Copy)]
+pub struct LogVerbosity {
+    pub placed: bool,
+    pub matched: bool,
+    pub canceled: bool,
+    pub depth: bool,
+    pub rejected: bool,
+}
+
+impl LogVerbosity {
+    pub fn none() -> Self {
+        Self {
+            placed: false,
+            matched: false,
+            canceled: false,
+            depth: false,
+            rejected: false,
+        }
+    }
+
+    pub fn logs(&self, event: &EngineEvent) -> bool {
+        match event {
+            EngineEvent::Placed(_) => self.placed,
+            EngineEvent::Matched { .. } => self.matched,
+            EngineEvent::Canceled { .. } => self.canceled,
+            EngineEvent::Depth(_) => self.depth,
+            EngineEvent::Rejected { .. } => self.rejected,
+        }
+    }
+}
+
+impl Default for LogVerbosity {
+    fn default() -> Self {
+        Self {
+            placed: true,
+            matched: true,
+            canceled: true,
+            depth: false,
+            rejected: true,
         }
     }
 }
@@ -77,6 +624,7 @@ pub struct OrderKey {
     pub price: Decimal,
     pub side: OrderSide,
     pub timestamp: TimestampMillis,
+    pub seq: u64,
 }
 
 impl KeyIndx for OrderKey {}
@@ -84,7 +632,8 @@ impl KeyIndx for OrderKey {}
 // The ordering determines how the orders are arranged in the queue. For price time priority
 // ordering, we want orders inserted based on the price and the time of entry. For Bids this
 // means the highest price gets the top priority, for Asks the lowest price gets the top priority
-// For orders with the same price, the longest staying in the queue gets the higher priority
+// For orders with the same price, the longest staying in the queue gets the higher priority, and
+// orders that arrived in the same millisecond are kept in the sequence they were placed in
 impl Ord for OrderKey {
     fn cmp(&self, other: &Self) -> Ordering {
         if self.price > other.price {
@@ -98,7 +647,10 @@ impl Ord for OrderKey {
                 OrderSide::Ask => Ordering::Greater,
             }
         } else {
-            other.timestamp.cmp(&self.timestamp)
+            other
+                .timestamp
+                .cmp(&self.timestamp)
+                .then_with(|| other.seq.cmp(&self.seq))
         }
     }
 }
@@ -115,5 +667,117 @@ impl PartialEq for OrderKey {
             && self.price == other.price
             && self.side == other.side
             && self.timestamp == other.timestamp
+            && self.seq == other.seq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn a_trading_pair_can_be_parsed_from_its_symbols() {
+        assert_eq!(
+            "BTC/USDC".parse::<TradingPair>(),
+            Ok(TradingPair::from(Asset::BTC, Asset::USDC))
+        );
+        assert_eq!(
+            "eth/usdt".parse::<TradingPair>(),
+            Ok(TradingPair::from(Asset::ETH, Asset::USDT))
+        );
+
+        let pair = TradingPair::from(Asset::DOT, Asset::USDC);
+        assert_eq!(pair.to_string().parse::<TradingPair>(), Ok(pair));
+    }
+
+    #[test]
+    fn a_trading_pair_with_an_unknown_asset_should_not_parse() {
+        assert!(matches!(
+            "DOGE/USDC".parse::<TradingPair>(),
+            Err(Failure::InvalidTradingPair(_))
+        ));
+        assert!(matches!(
+            "BTC/".parse::<TradingPair>(),
+            Err(Failure::InvalidTradingPair(_))
+        ));
+    }
+
+    #[test]
+    fn a_trading_pair_without_a_separator_should_not_parse() {
+        assert!(matches!(
+            "BTCUSDC".parse::<TradingPair>(),
+            Err(Failure::InvalidTradingPair(_))
+        ));
+        assert!(matches!(
+            "BTC-USDC".parse::<TradingPair>(),
+            Err(Failure::InvalidTradingPair(_))
+        ));
+    }
+
+    #[test]
+    fn a_trading_pair_of_the_same_asset_should_not_parse() {
+        assert!(matches!(
+            "BTC/BTC".parse::<TradingPair>(),
+            Err(Failure::InvalidTradingPair(_))
+        ));
+    }
+
+    #[test]
+    fn the_notional_of_a_trade_is_its_price_times_its_quantity() {
+        let trade = Trade {
+            orderid: Default::default(),
+            account_id: None,
+            tag: None,
+            side: OrderSide::Bid,
+            price: dec!(20.05),
+            status: OrderStatus::Filled,
+            quantity: 12,
+            timestamp: 0,
+            price_improvement: Decimal::ZERO,
+        };
+        assert_eq!(trade.notional(), dec!(240.60));
+
+        let large = Trade {
+            price: dec!(2.5),
+            quantity: Long::MAX,
+            ..trade
+        };
+        assert_eq!(large.notional(), dec!(46116860184273879037.5));
+    }
+
+    #[test]
+    fn the_notional_of_an_order_counts_its_hidden_quantity() {
+        let order = Order {
+            orderid: Default::default(),
+            client_order_id: None,
+            account_id: None,
+            tag: None,
+            price: dec!(100.10),
+            quantity: 4,
+            notional: None,
+            display_quantity: Some(4),
+            min_fill: None,
+            client_timestamp: None,
+            hidden_quantity: 6,
+            stop_price: None,
+            side: OrderSide::Ask,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            timestamp: 0,
+            seq: 0,
+            trading_pair: TradingPair::from(Asset::BTC, Asset::USDC),
+        };
+        assert_eq!(order.notional(), dec!(1001.00));
+
+        // a notional beyond what a decimal can hold saturates rather than overflowing
+        let huge = Order {
+            price: dec!(10_000_000_000),
+            quantity: Long::MAX,
+            hidden_quantity: 0,
+            ..order
+        };
+        assert_eq!(huge.notional(), Decimal::MAX);
     }
 }
@@ -1,41 +1,399 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::{self, Display},
+    mem,
+    num::NonZeroUsize,
+    sync::Arc,
+};
 
 use super::{
-    model::{Event, Order, OrderKey, TradingPair},
+    model::{
+        level_deltas, BookLevel, BookSnapshot, DepthDelta, Event, MatchSummary, Order, OrderKey,
+        TradingPair,
+    },
     pqueue::{OrderQueue, PriceTimePriorityOrderQueue},
-    types::{Failure, Long, OrderId, OrderSide, OrderStatus, OrderType},
+    types::{
+        AccountId, ClientOrderId, Depth, Failure, Long, OrderId, OrderSide, OrderStatus, OrderType,
+        PegReference, PriceLevel, RoundingStrategy, StopTrigger, TimestampMillis, Trade,
+    },
+    utils::{Clock, SystemClock, Util},
 };
 use rust_decimal::Decimal;
 
 /// The order queues should be able to hold these number of items when created
 const ORDER_BOOK_INITIAL_CAPACITY: usize = 16;
 
+/// The number of trades each book keeps on its tape, unless configured otherwise
+const DEFAULT_TAPE_CAPACITY: usize = 128;
+
+/// The number of spread samples each book keeps, unless configured otherwise
+const DEFAULT_SPREAD_HISTORY_CAPACITY: usize = 128;
+
+/// What tells an order apart from a second delivery of it: its account, side, price and client
+/// timestamp. The book only knows what is left of a resting order, so the quantity is left out,
+/// or a partly filled order could no longer be told from a second delivery of it. The time the
+/// engine received an order is left out too, as a second delivery always arrives later
+type DuplicateKey = (Option<AccountId>, OrderSide, Decimal, TimestampMillis);
+
+/// Settings that can be tuned for each order book independently
+#[derive(Debug, Clone, Copy)]
+pub struct BookConfig {
+    /// the maximum distance, as a percentage of the last traded price, that an order can be
+    /// priced away from it. No band is enforced until the book has traded at least once
+    pub price_band: Option<Decimal>,
+    /// the smallest price increment of the pair. Orders priced off it are rejected, and prices
+    /// derived from the book, like the spread and mid price, are rounded to it
+    pub tick_size: Option<Decimal>,
+    /// the number of decimal places prices of the pair are reported with, when it has no tick.
+    /// Orders priced with more places are rejected, and trades are always priced with exactly
+    /// this many places
+    pub quote_precision: Option<u32>,
+    /// how prices derived from the book are rounded to the tick or quote precision. A book
+    /// without a rounding of its own takes the rounding of the engine, which rounds midpoints
+    /// away from zero by default
+    pub rounding: Option<RoundingStrategy>,
+    /// the number of recent trades kept on the tape, the oldest are dropped beyond this
+    pub tape_capacity: usize,
+    /// the number of changes of the spread each book keeps, the oldest are dropped beyond this
+    pub spread_history_capacity: usize,
+    /// whether orders that trade at any price are accepted, which are market orders and the
+    /// stop orders that become market orders
+    pub allow_market_orders: bool,
+    /// the most fills a single incoming order can take, which bounds how long matching one
+    /// order can run against a deep book. What is left of the order once it reaches the limit is
+    /// canceled rather than rested, since it could still cross the book. There is no limit by
+    /// default
+    pub max_fills_per_order: Option<NonZeroUsize>,
+    /// the number of orders that were filled, canceled or expired the book keeps, so they can
+    /// still be looked up with [OrderBook::get_order]. The oldest are dropped beyond this, and
+    /// none are kept when it is zero, as by default
+    pub archive_capacity: usize,
+    /// whether an order is rejected when an order of the same account with the same price, side
+    /// and client timestamp is already open, which guards against feeds that deliver an order
+    /// twice. The quantity isn't compared, as a partly filled order no longer shows the quantity
+    /// it was sent with. Orders without a client timestamp are never taken as duplicates, since
+    /// the engine stamps each delivery with the time it arrived, and duplicates are accepted by
+    /// default
+    pub reject_duplicates: bool,
+    /// the price stop orders are triggered by, the last traded price by default
+    pub stop_trigger: StopTrigger,
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        Self {
+            price_band: None,
+            tick_size: None,
+            quote_precision: None,
+            rounding: None,
+            tape_capacity: DEFAULT_TAPE_CAPACITY,
+            spread_history_capacity: DEFAULT_SPREAD_HISTORY_CAPACITY,
+            allow_market_orders: true,
+            max_fills_per_order: None,
+            archive_capacity: 0,
+            reject_duplicates: false,
+            stop_trigger: StopTrigger::default(),
+        }
+    }
+}
+
+impl BookConfig {
+    /// Rounds a price derived from the book using the configured strategy. Prices are rounded
+    /// to a multiple of the tick, and given the same scale as it, or else to the quote
+    /// precision. They are left untouched when neither is configured
+    pub fn round_price(&self, price: Decimal) -> Decimal {
+        match (self.tick_size, self.quote_precision) {
+            (Some(tick), _) if !tick.is_zero() => {
+                let mut rounded = self.rounding().round_dp(price / tick, 0) * tick;
+                rounded.rescale(tick.scale());
+                rounded
+            }
+            (_, Some(precision)) => self.rounding().round_dp(price, precision),
+            _ => price,
+        }
+    }
+
+    /// Gives the price of a trade exactly as many decimal places as the quote precision, so
+    /// every trade of the pair is priced alike, eg 20 becomes 20.00. The price itself is never
+    /// changed, so one with more places than the precision is left as it is
+    pub fn normalize_trade_price(&self, price: Decimal) -> Decimal {
+        match self.quote_precision {
+            Some(precision) if price.scale() <= precision => {
+                let mut normalized = price;
+                normalized.rescale(precision);
+                normalized
+            }
+            _ => price,
+        }
+    }
+
+    fn rounding(&self) -> RoundingStrategy {
+        self.rounding.unwrap_or_default()
+    }
+}
+
 /// This trait defines the operations that can be performed by the orderbook. It
 /// embodies the basic operations that are typical of an orderbook
 pub trait OrderBook {
-    /// Cancel an open order in the book. Cancelling a non-existent order should fail
+    /// Cancel an open order in the book. Cancelling a non-existent order should fail. The event
+    /// returned carries the price of the order and the quantity that was still unfilled
     fn cancel(&mut self, orderid: OrderId) -> Result<Event, Failure>;
 
+    /// Cancel an open order using the id the client placed it with
+    fn cancel_by_client_id(&mut self, client_order_id: ClientOrderId) -> Result<Event, Failure>;
+
     /// Place an order into the book, should the order already exists it should also fail
     fn place(&mut self, order: Order) -> Result<Event, Failure>;
 
-    /// Gets the ask at the top of the book (head of the ask queue)
+    /// Cancels an order and places another in its stead as one operation, so there is never a
+    /// moment where neither is live. Should the new order be rejected, the original is put back
+    /// exactly as it was, priority included. Like [OrderBook::place], the new order is rested
+    /// without being matched, so a new order that would cross the book is rejected
+    fn replace(&mut self, cancel_id: OrderId, new_order: Order) -> Result<Event, Failure>;
+
+    /// Gets an order by its id, whether it is resting, waiting to trigger, or was filled,
+    /// canceled or expired and kept in the archive of the book. Archived orders have no
+    /// quantity left
+    fn get_order(&self, orderid: OrderId) -> Option<Order>;
+
+    /// Gets the ask at the top of the book (head of the ask queue). Asks with nothing shown
+    /// to trade against are skipped
     fn peek_top_ask(&self) -> Option<&Order>;
 
-    /// Gets the bid at the top of the book (head of the bid queue)
+    /// Gets the bid at the top of the book (head of the bid queue). Bids with nothing shown
+    /// to trade against are skipped
     fn peek_top_bid(&self) -> Option<&Order>;
 
+    /// Gets the best bid that has quantity shown to trade against. Orders are never meant to
+    /// rest with nothing shown, but any found at the top are taken off the book first, the way
+    /// a filled order would be
+    fn best_active_bid(&mut self) -> Option<&Order>;
+
+    /// Gets the best ask that has quantity shown to trade against, see
+    /// [OrderBook::best_active_bid]
+    fn best_active_ask(&mut self) -> Option<&Order>;
+
+    /// Gets the bid at the top of the book with the number of bids resting behind it
+    fn top_bid_with_depth(&self) -> Option<(Order, usize)>;
+
+    /// Gets the ask at the top of the book with the number of asks resting behind it
+    fn top_ask_with_depth(&self) -> Option<(Order, usize)>;
+
+    /// Gets the best bid price with the total quantity shown at it, across every bid resting there
+    fn top_bid_level(&self) -> Option<PriceLevel>;
+
+    /// Gets the best ask price with the total quantity shown at it, across every ask resting there
+    fn top_ask_level(&self) -> Option<PriceLevel>;
+
     /// Gets the spread, which is the difference between the top ask and bid prices
     fn get_spread(&self) -> Option<Decimal>;
 
-    /// Allows for the modification of the order quantity in-place
-    fn modify_quantity(&mut self, orderid: OrderId, qty: Long);
+    /// Gets the price halfway between the top bid and ask prices
+    fn mid_price(&self) -> Option<Decimal>;
+
+    /// Gets the mid price weighted by the quantity at the top of each side, which leans towards
+    /// the side with less resting at it as that is the price more likely to trade through next.
+    /// Gets None when either side is empty, or the sides are too large to weigh in a decimal
+    fn microprice(&self) -> Option<Decimal>;
 
-    /// Removes the top bid from the head of the queue
+    /// Gets the microprice over the best `levels` price levels of each side rather than just the
+    /// top. Each side is valued at the average of its level prices weighted by their size, and
+    /// the two are weighted by the size of the opposite side, so over one level this is the
+    /// microprice. Gets None when either side is empty, or the sides are too large to weigh in
+    /// a decimal
+    fn weighted_mid(&self, levels: usize) -> Option<Decimal>;
+
+    /// Gets the average price an order on the given side would fill at for the given quantity,
+    /// by walking the opposite side of the book from the best price out. Gets None when there
+    /// isn't enough quantity resting to fill it
+    fn estimate_fill(&self, side: OrderSide, quantity: Long) -> Option<Decimal>;
+
+    /// Gets the difference between the average prices of buying and of selling the given
+    /// quantity, which widens with size as orders walk deeper into the book. Gets None when
+    /// either side lacks the liquidity to fill it
+    fn effective_spread(&self, quantity: Long) -> Option<Decimal>;
+
+    /// Gets the total value (price times quantity) of the orders resting on one side of the
+    /// book, or on both when no side is given. The hidden quantity of icebergs is counted, while
+    /// pending stops are not
+    fn open_notional(&self, side: Option<OrderSide>) -> Decimal;
+
+    /// Allows for the modification of the order quantity in-place. An order that is reduced keeps
+    /// its time priority, and is reported as modified with its new quantity. An order reduced to
+    /// zero is treated as filled and taken off the book, so no empty orders are left resting. An
+    /// order can't be increased in place, as it would keep a priority it didn't earn for the
+    /// extra quantity, so it has to be replaced instead, see [OrderBook::replace]
+    fn modify_quantity(&mut self, orderid: OrderId, qty: Long) -> Result<Event, Failure>;
+
+    /// Moves every order the account has resting on one side of the book by the same price
+    /// delta. Repriced orders go to the back of their new price levels, in the same order they
+    /// were queued in before. Either every order is repriced or none is: a delta that would take
+    /// a price to zero or below, off the tick, outside the price band or across the book, is
+    /// rejected. Gets the number of orders moved
+    fn reprice(
+        &mut self,
+        account_id: AccountId,
+        side: OrderSide,
+        delta: Decimal,
+    ) -> Result<usize, Failure>;
+
+    /// Removes the top bid from the head of the queue. An iceberg bid with hidden quantity
+    /// left is replenished at the back of its price level instead of leaving the book
     fn pop_top_bid(&mut self) -> Option<Order>;
 
-    /// Removes the top ask from the head of the ask queue
+    /// Removes the top ask from the head of the ask queue. An iceberg ask with hidden quantity
+    /// left is replenished at the back of its price level instead of leaving the book
     fn pop_top_ask(&mut self) -> Option<Order>;
+
+    /// Gets the number of orders resting in the book. Stop orders waiting to be triggered are
+    /// not counted
+    fn len(&self) -> usize;
+
+    /// Checks if the book has no resting orders
+    fn is_empty(&self) -> bool;
+
+    /// Gets the number of bids resting in the book
+    fn bid_count(&self) -> usize;
+
+    /// Gets the number of asks resting in the book
+    fn ask_count(&self) -> usize;
+
+    /// Gets the number of distinct prices bids are resting at. Prices are compared by value, so
+    /// 20.0 and 20.00 are the same level
+    fn bid_levels(&self) -> usize;
+
+    /// Gets the number of distinct prices asks are resting at. Prices are compared by value, so
+    /// 20.0 and 20.00 are the same level
+    fn ask_levels(&self) -> usize;
+
+    /// Gets the total quantity shown at exactly the given price on one side of the book.
+    /// Prices are compared by value, so 20.0 and 20.00 are the same price
+    fn volume_at_price(&self, side: OrderSide, price: Decimal) -> Long;
+
+    /// Gets up to `n` of the bids in the order they would be matched, best first. The queue is
+    /// only ordered at its head, so this copies every bid and sorts them
+    fn top_n_bids(&self, n: usize) -> Vec<Order>;
+
+    /// Gets up to `n` of the asks in the order they would be matched, best first. The queue is
+    /// only ordered at its head, so this copies every ask and sorts them
+    fn top_n_asks(&self, n: usize) -> Vec<Order>;
+
+    /// Gets the stop orders waiting to trigger, in the order they were placed
+    fn pending_stops(&self) -> Vec<Order>;
+
+    /// Gets the number of orders that would be matched before the given order, and the total
+    /// quantity they show. Orders at better prices count as ahead too, as well as those that
+    /// got to the same price first. Gets None for orders that are not resting in the book
+    fn queue_position(&self, orderid: OrderId) -> Option<(usize, Long)>;
+
+    /// Gets the quantity shown at up to `levels` of the best prices on each side of the book,
+    /// as (bids, asks). Bids are ordered from highest to lowest and asks from lowest to highest
+    fn depth(&self, levels: usize) -> Depth;
+
+    /// Gets the quantity shown at every price level of the book, as (bids, asks), eg to chart
+    /// the whole book. Like [OrderBook::depth] without a limit on the levels
+    fn ladder(&self) -> Depth;
+
+    /// Gets every price level of the book, with the number of orders resting at each. Bids are
+    /// ordered from highest to lowest and asks from lowest to highest
+    fn snapshot(&self) -> BookSnapshot;
+
+    /// Starts keeping each price level as it was before it first changes, so
+    /// [OrderBook::take_depth_deltas] can tell how the depth changed without a snapshot
+    fn watch_depth(&mut self);
+
+    /// Gets how each price level changed since [OrderBook::watch_depth], best bid first and
+    /// then from the lowest ask up, and stops watching. Only the levels changed are looked at,
+    /// and nothing is got if the depth wasn't watched
+    fn take_depth_deltas(&mut self) -> Vec<DepthDelta>;
+
+    /// Gets the total quantity resting on the opposite side of the book that the given order
+    /// could be matched against. For limit orders, only prices within the limit are counted
+    fn crossing_quantity(&self, order: &Order) -> Long;
+
+    /// Gets the resting order an incoming order of the given side, price and type would be
+    /// matched against first, without matching it. For limit orders, the best price on the
+    /// opposite side has to be within the limit, while a market order always gets it
+    fn next_match(&self, side: OrderSide, price: Decimal, order_type: OrderType) -> Option<Order>;
+
+    /// Removes every order whose time in force has expired at the given time, including the
+    /// stops waiting to trigger
+    fn purge_expired(&mut self, now: TimestampMillis) -> Vec<Event>;
+
+    /// Cancels every order resting in the book since before the cutoff, oldest first. Icebergs
+    /// count from when their shown slice was last replenished, and pending stops are left alone
+    fn cancel_older_than(&mut self, cutoff: TimestampMillis) -> Vec<Event>;
+
+    /// Cancels every order resting at exactly the given price on one side of the book, in the
+    /// order they would have been matched. Prices are compared by value, so 20.0 and 20.00 are
+    /// the same level
+    fn cancel_level(&mut self, side: OrderSide, price: Decimal) -> Vec<Event>;
+
+    /// Cancels every order the account has open in the book, the bids then the asks in the
+    /// order they would have been matched, followed by its pending stops. Orders placed
+    /// without an account are never canceled this way
+    fn cancel_account_orders(&mut self, account_id: AccountId) -> Vec<Event>;
+
+    /// Checks that an order is acceptable to this book before it is matched
+    fn validate(&self, order: &Order) -> Option<Failure>;
+
+    /// Records an execution that took place in the book
+    fn record_trade(&mut self, trade: &Trade);
+
+    /// Gets the price of the most recent execution in the book
+    fn last_traded_price(&self) -> Option<Decimal>;
+
+    /// Gets up to `n` of the most recent executions in the book, the latest first
+    fn recent_trades(&self, n: usize) -> Vec<Trade>;
+
+    /// Gets up to `n` of the most recent changes of the spread with the time each happened, the
+    /// latest first. The spread is only sampled when it changes, and not while a side is empty
+    fn spread_history(&self, n: usize) -> Vec<(TimestampMillis, Decimal)>;
+
+    /// Gets the totals of every execution in the book, see [MatchSummary]
+    fn match_summary(&self) -> MatchSummary;
+
+    /// Gets the average price the book has traded at, weighted by quantity, rounded like the
+    /// other prices derived from the book. Gets None when nothing has traded
+    fn vwap(&self) -> Option<Decimal>;
+
+    /// Removes and returns the first pending stop order that has been triggered by the market.
+    /// Stops that have expired never trigger
+    fn take_triggered_stop(&mut self) -> Option<Order>;
+
+    /// Empties the book and returns every order it held, eg for settlement on shutdown. Bids
+    /// come first and then asks, each in the order they would have been matched, followed by
+    /// the stops that were waiting to trigger in the order they were placed. The tape and last
+    /// traded price of the book are kept
+    fn drain(&mut self) -> Vec<Order>;
+
+    /// Gets the current time from the clock of the book
+    fn now(&self) -> TimestampMillis;
+
+    /// Gets the trading pair the book holds orders for
+    fn trading_pair(&self) -> TradingPair;
+
+    /// Gets the most fills a single incoming order can take in the book, see
+    /// [BookConfig::max_fills_per_order]
+    fn max_fills_per_order(&self) -> Option<NonZeroUsize>;
+
+    /// Gets the price a trade at the given price is reported with, given the places of the
+    /// quote precision of the book. The price itself is never changed
+    fn trade_price(&self, price: Decimal) -> Decimal;
+
+    /// Rounds a price derived from the book the way its spread and mid price are, see
+    /// [BookConfig::round_price]
+    fn round_price(&self, price: Decimal) -> Decimal;
+
+    /// Gets the price an order pegged to the reference with the given offset is at. Gets None
+    /// when the book has no reference price, or the offset takes it to zero or below
+    fn peg_price(&self, reference: PegReference, offset: Decimal) -> Option<Decimal>;
+
+    /// Moves every resting pegged order whose reference has moved to its new price, at the back
+    /// of its new price level. Pegged orders whose reference is gone, or would take them to a
+    /// price the book doesn't accept, are left where they are. Gets the number of orders moved
+    fn reprice_pegged(&mut self) -> usize;
 }
 
 /// An implementation of the [OrderBook] trait. This implementation uses two queues one for
@@ -46,246 +404,4126 @@ pub trait OrderBook {
 ///
 /// Each orderbook can only trade assets for the trading pair that it supports
 ///
+#[derive(Clone)]
 pub struct LimitOrderBook {
     trading_pair: TradingPair,
     bids: PriceTimePriorityOrderQueue<OrderKey>,
     asks: PriceTimePriorityOrderQueue<OrderKey>,
     orders: HashMap<OrderId, Order>,
+    /// stop orders waiting to be triggered, in the order they were placed
+    stops: Vec<Order>,
+    /// the engine ids of open orders that were placed with a client order id
+    client_ids: HashMap<ClientOrderId, OrderId>,
+    /// the engine ids of the open orders and pending stops of each account
+    accounts: HashMap<AccountId, HashSet<OrderId>>,
+    /// the engine ids of the resting pegged orders, so books without any skip repricing
+    pegged: HashSet<OrderId>,
+    /// the engine id of the open order sent with each duplicate key, when duplicates are
+    /// rejected, see [BookConfig::reject_duplicates]
+    duplicates: HashMap<DuplicateKey, OrderId>,
+    /// the sequence number handed to the next order queued in the book
+    next_seq: u64,
+    config: BookConfig,
+    last_traded_price: Option<Decimal>,
+    /// the most recent executions in the book, oldest at the front
+    tape: VecDeque<Trade>,
+    /// the totals of every execution in the book
+    summary: MatchSummary,
+    /// the spread as of the last change to the top of the book
+    spread: Option<Decimal>,
+    /// the most recent changes of the spread with when they happened, oldest at the front
+    spreads: VecDeque<(TimestampMillis, Decimal)>,
+    /// orders that were filled, canceled or expired, see [BookConfig::archive_capacity]
+    archive: HashMap<OrderId, Order>,
+    /// the ids of the archived orders, oldest at the front
+    archived: VecDeque<OrderId>,
+    /// the price levels changed since [OrderBook::watch_depth], as they were before the first
+    /// change, keyed by the normalized price
+    watched: Option<HashMap<(OrderSide, Decimal), Option<BookLevel>>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl LimitOrderBook {
     pub fn init(trading_pair: TradingPair) -> LimitOrderBook {
+        Self::with_config(trading_pair, BookConfig::default())
+    }
+
+    pub fn with_config(trading_pair: TradingPair, config: BookConfig) -> LimitOrderBook {
         Self {
             trading_pair,
             bids: PriceTimePriorityOrderQueue::with_capacity(ORDER_BOOK_INITIAL_CAPACITY),
             asks: PriceTimePriorityOrderQueue::with_capacity(ORDER_BOOK_INITIAL_CAPACITY),
             orders: HashMap::with_capacity(ORDER_BOOK_INITIAL_CAPACITY),
+            stops: Vec::new(),
+            client_ids: HashMap::new(),
+            accounts: HashMap::new(),
+            pegged: HashSet::new(),
+            duplicates: HashMap::new(),
+            next_seq: 0,
+            config,
+            last_traded_price: None,
+            tape: VecDeque::with_capacity(config.tape_capacity),
+            summary: MatchSummary::default(),
+            spread: None,
+            spreads: VecDeque::with_capacity(config.spread_history_capacity),
+            archive: HashMap::new(),
+            archived: VecDeque::new(),
+            watched: None,
+            clock: Arc::new(SystemClock),
         }
     }
-}
 
-impl OrderBook for LimitOrderBook {
-    fn cancel(&mut self, orderid: OrderId) -> Result<Event, Failure> {
-        match self.orders.remove(&orderid) {
-            Some(order) => {
-                match order.side {
-                    OrderSide::Bid => self.bids.remove(order.to_key()),
-                    OrderSide::Ask => self.asks.remove(order.to_key()),
-                };
-                return Ok(Event {
-                    orderid,
-                    status: OrderStatus::Canceled,
-                    at_price: String::from(""),
-                });
-            }
-            None => Err(Failure::OrderNotFound(
-                "No order found with the given id".to_string(),
-            )),
-        }
+    /// Sets the clock the book reads the time from, for trades and replenished icebergs
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> LimitOrderBook {
+        self.clock = clock;
+        self
     }
 
-    fn place(&mut self, order: Order) -> Result<Event, Failure> {
-        if OrderType::Market == order.order_type {
-            return Err(Failure::OrderRejected(
-                "Only limit orders can be placed in the orderbook".to_string(),
-            ));
-        }
-        if self.trading_pair != order.trading_pair {
-            return Err(Failure::InvalidOrderForBook);
-        }
-
-        self.orders.insert(order.orderid, order);
+    fn take_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
 
-        match order.side {
-            OrderSide::Bid => self.bids.push(order.to_key()),
-            OrderSide::Ask => self.asks.push(order.to_key()),
+    /// Gets up to `n` orders on one side of the book in priority order
+    fn top_n(&self, side: OrderSide, n: usize) -> Vec<Order> {
+        let queue = match side {
+            OrderSide::Bid => &self.bids,
+            OrderSide::Ask => &self.asks,
         };
-        Ok(Event {
-            status: OrderStatus::Created,
-            orderid: order.orderid,
-            at_price: String::from(""),
-        })
+        let mut keys: Vec<&OrderKey> = queue.iter().collect();
+        // keys of a higher priority compare greater
+        keys.sort_unstable_by(|a, b| b.cmp(a));
+        keys.into_iter()
+            .filter_map(|key| self.orders.get(&key.orderid))
+            .take(n)
+            .copied()
+            .collect()
     }
 
-    fn get_spread(&self) -> Option<Decimal> {
-        match self.bids.peek() {
-            Some(bid) => match self.asks.peek() {
-                Some(ask) => Some(bid.price - ask.price),
-                None => None,
-            },
-            None => None,
+    fn queue(&mut self, side: OrderSide) -> &mut PriceTimePriorityOrderQueue<OrderKey> {
+        match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
         }
     }
 
-    fn peek_top_ask(&self) -> Option<&Order> {
-        if let Some(key) = self.asks.peek() {
-            return self.orders.get(&key.orderid);
+    /// Gets the price a stop on the side is compared to its stop price with, as configured by
+    /// the stop trigger. Stops can't trigger while the book has no such price
+    fn stop_reference(&self, side: OrderSide) -> Option<Decimal> {
+        let best = |side: OrderSide| match side {
+            OrderSide::Bid => self.bids.peek().map(|order| order.price),
+            OrderSide::Ask => self.asks.peek().map(|order| order.price),
+        };
+        match self.config.stop_trigger {
+            StopTrigger::LastTrade => self.last_traded_price,
+            StopTrigger::BestPrice => best(side.opposite()),
+            StopTrigger::Mid => {
+                Some((best(OrderSide::Bid)? + best(OrderSide::Ask)?) / Decimal::TWO)
+            }
         }
-        None
     }
 
-    fn peek_top_bid(&self) -> Option<&Order> {
-        if let Some(key) = self.bids.peek() {
-            return self.orders.get(&key.orderid);
+    /// Gets the best price of the orders on one side of the book that are not pegged
+    fn best_unpegged(&self, side: OrderSide) -> Option<Decimal> {
+        let top = match side {
+            OrderSide::Bid => self.bids.peek(),
+            OrderSide::Ask => self.asks.peek(),
+        };
+        // only when the top is pegged does the rest of the side have to be looked at
+        if let Some(top) = top.filter(|top| !self.pegged.contains(&top.orderid)) {
+            return Some(top.price);
+        }
+        let prices = self
+            .orders
+            .values()
+            .filter(|order| order.side == side)
+            .filter(|order| !matches!(order.order_type, OrderType::Pegged { .. }))
+            .map(|order| order.price);
+        match side {
+            OrderSide::Bid => prices.max(),
+            OrderSide::Ask => prices.min(),
         }
-        None
     }
 
-    fn modify_quantity(&mut self, orderid: OrderId, quantity: Long) {
-        if let Some(order) = self.orders.get_mut(&orderid) {
-            order.quantity = quantity
+    /// Prepares an order to rest in the book, giving it the next sequence number and holding
+    /// back the hidden quantity of an iceberg
+    fn prepare_resting(&mut self, order: Order) -> Order {
+        let order = Order {
+            seq: self.take_seq(),
+            ..order
+        };
+        // an iceberg order only shows a slice of its quantity, the rest is held back
+        match order.display_quantity {
+            Some(display) if order.hidden_quantity == 0 && display < order.quantity => Order {
+                quantity: display,
+                hidden_quantity: order.quantity - display,
+                ..order
+            },
+            _ => order,
         }
     }
 
-    fn pop_top_bid(&mut self) -> Option<Order> {
-        if let Some(key) = self.bids.pop() {
-            return self.orders.remove(&key.orderid);
-        }
-        None
+    /// Creates an empty book for another pair, configured like this one and reading the time
+    /// from the same clock
+    pub fn for_pair(&self, trading_pair: TradingPair) -> LimitOrderBook {
+        Self::with_config(trading_pair, self.config).with_clock(self.clock.clone())
     }
 
-    fn pop_top_ask(&mut self) -> Option<Order> {
-        if let Some(key) = self.asks.pop() {
-            return self.orders.remove(&key.orderid);
+    /// Loads resting limit orders straight into the book without matching them, eg to warm up
+    /// a book for a backtest. The queues are rebuilt once for the whole batch rather than once
+    /// per order. Nothing is loaded unless every order is a limit order for the pair of the
+    /// book with an id of its own at a price the book accepts, none of them duplicates, and the
+    /// batch would not leave the book crossed
+    pub fn bulk_load(&mut self, orders: Vec<Order>) -> Result<(), Failure> {
+        let mut client_ids = HashSet::new();
+        let mut duplicates = HashMap::new();
+        let mut orderids = HashSet::with_capacity(orders.len());
+        for order in &orders {
+            if self.trading_pair != order.trading_pair {
+                return Err(wrong_book(order, self.trading_pair));
+            }
+            if order.order_type != OrderType::Limit {
+                return Err(Failure::OrderRejected(
+                    "Only limit orders can be bulk loaded".to_string(),
+                ));
+            }
+            if order.quantity == 0 {
+                return Err(Failure::OrderRejected(
+                    "Quantity must be greater than zero".to_string(),
+                ));
+            }
+            if order.price <= Decimal::ZERO {
+                return Err(Failure::OrderRejected(format!(
+                    "Price must be greater than zero, not {}",
+                    order.price
+                )));
+            }
+            if let Some(failure) = self
+                .reject_price(order.price)
+                .or_else(|| self.reject_duplicate(order))
+            {
+                return Err(failure);
+            }
+            if let Some(key) = self.duplicate_key(order) {
+                if let Some(open) = duplicates.insert(key, order.orderid) {
+                    return Err(Failure::OrderRejected(format!(
+                        "Order {} is a duplicate of the open order {}",
+                        order.orderid, open
+                    )));
+                }
+            }
+            if self.orders.contains_key(&order.orderid)
+                || self.stops.iter().any(|stop| stop.orderid == order.orderid)
+                || !orderids.insert(order.orderid)
+            {
+                return Err(Failure::OrderRejected(format!(
+                    "Order {} is already in the book or the batch",
+                    order.orderid
+                )));
+            }
+            if let Some(client_order_id) = order.client_order_id {
+                if self.client_ids.contains_key(&client_order_id)
+                    || !client_ids.insert(client_order_id)
+                {
+                    return Err(Failure::OrderRejected(format!(
+                        "Client order id {} is already in use",
+                        client_order_id
+                    )));
+                }
+            }
         }
-        None
+        let best = |side: OrderSide| {
+            let resting = match side {
+                OrderSide::Bid => self.peek_top_bid(),
+                OrderSide::Ask => self.peek_top_ask(),
+            };
+            orders
+                .iter()
+                .filter(move |order| order.side == side)
+                .chain(resting)
+                .map(|order| order.price)
+        };
+        if let (Some(bid), Some(ask)) = (best(OrderSide::Bid).max(), best(OrderSide::Ask).min()) {
+            if bid >= ask {
+                return Err(Failure::OrderRejected(format!(
+                    "Bulk loaded orders would cross the book, with a bid at {} and an ask at {}",
+                    bid, ask
+                )));
+            }
+        }
+
+        let (mut bids, mut asks) = (Vec::new(), Vec::new());
+        for order in orders {
+            let order = self.prepare_resting(order);
+            if let Some(client_order_id) = order.client_order_id {
+                self.client_ids.insert(client_order_id, order.orderid);
+            }
+            self.index_order(&order);
+            self.watch_level(order.side, order.price);
+            self.orders.insert(order.orderid, order);
+            match order.side {
+                OrderSide::Bid => bids.push(order.to_key()),
+                OrderSide::Ask => asks.push(order.to_key()),
+            }
+        }
+        self.bids.extend(bids);
+        self.asks.extend(asks);
+        self.sample_spread();
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::str::FromStr;
+    /// Gets the key an order is told apart from a duplicate by, when the book is configured to
+    /// reject duplicates and the order was sent with a client timestamp
+    fn duplicate_key(&self, order: &Order) -> Option<DuplicateKey> {
+        if !self.config.reject_duplicates {
+            return None;
+        }
+        let client_timestamp = order.client_timestamp?;
+        Some((order.account_id, order.side, order.price, client_timestamp))
+    }
 
-    use rust_decimal::Decimal;
-    use rust_decimal_macros::dec;
-    use uuid::Uuid;
+    /// Rejects an order that is open in the book already, going by its account, price, side and
+    /// client timestamp, when the book is configured to reject duplicates
+    fn reject_duplicate(&self, order: &Order) -> Option<Failure> {
+        let open = self.duplicates.get(&self.duplicate_key(order)?)?;
+        Some(Failure::OrderRejected(format!(
+            "Order {} is a duplicate of the open order {}",
+            order.orderid, open
+        )))
+    }
 
-    use crate::core::{
-        model::{Order, TradingPair},
-        types::{Asset, Failure, Long, OrderSide, OrderStatus, OrderType},
-        utils::Util,
-    };
+    /// Takes an order or pending stop out of the book the way a cancel does, but without
+    /// archiving it or sampling the spread, so it can still be put back as it was
+    fn take_out(&mut self, orderid: OrderId) -> Option<Order> {
+        if let Some(order) = self.orders.get(&orderid).copied() {
+            self.watch_level(order.side, order.price);
+            self.orders.remove(&orderid);
+            self.queue(order.side).remove(order.to_key());
+            self.forget_client_id(&order);
+            return Some(order);
+        }
+        let index = self.stops.iter().position(|stop| stop.orderid == orderid)?;
+        let stop = self.stops.remove(index);
+        self.forget_client_id(&stop);
+        Some(stop)
+    }
 
-    use super::{LimitOrderBook, OrderBook};
+    /// Moves the duplicate key of an open order to the price it was reseated at
+    fn rekey_duplicate(&mut self, order: &Order, reseated: &Order) {
+        self.forget_duplicate_key(order);
+        if let Some(key) = self.duplicate_key(reseated) {
+            self.duplicates.insert(key, reseated.orderid);
+        }
+    }
 
-    #[test]
-    fn can_place_a_limit_order_in_the_order_book() {
-        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::ETH));
+    /// Forgets the duplicate key of an order that is no longer open
+    fn forget_duplicate_key(&mut self, order: &Order) {
+        if let Some(key) = self.duplicate_key(order) {
+            if self.duplicates.get(&key) == Some(&order.orderid) {
+                self.duplicates.remove(&key);
+            }
+        }
+    }
 
-        let order = create_order(
-            dec!(200.02),
-            OrderSide::Bid,
-            8,
-            OrderType::Limit,
-            TradingPair {
-                order_asset: Asset::BTC,
-                price_asset: Asset::ETH,
-            },
-        );
-        let result = orderbook.place(order);
+    /// Drops the client order id, the account and the peg of an order that has left the book
+    fn forget_client_id(&mut self, order: &Order) {
+        if let Some(client_order_id) = order.client_order_id {
+            self.client_ids.remove(&client_order_id);
+        }
+        self.pegged.remove(&order.orderid);
+        self.forget_duplicate_key(order);
+        self.forget_account(order);
+    }
 
-        let event = result.unwrap();
-        assert_eq!(OrderStatus::Created, event.status);
+    /// Remembers an order that is open in the book under the account it was placed for, among
+    /// the pegged orders when it is pegged, and by its duplicate key
+    fn index_order(&mut self, order: &Order) {
+        if let OrderType::Pegged { .. } = order.order_type {
+            self.pegged.insert(order.orderid);
+        }
+        // the first order open under a key keeps it, it is the one later orders duplicate
+        if let Some(key) = self.duplicate_key(order) {
+            self.duplicates.entry(key).or_insert(order.orderid);
+        }
+        if let Some(account_id) = order.account_id {
+            self.accounts
+                .entry(account_id)
+                .or_default()
+                .insert(order.orderid);
+        }
+    }
 
-        // we expect the order we placed to be at the top of the book
-        let order_placed = orderbook.peek_top_bid();
-        assert_eq!(*order_placed.unwrap(), order);
+    fn forget_account(&mut self, order: &Order) {
+        let Some(account_id) = order.account_id else {
+            return;
+        };
+        if let Some(orderids) = self.accounts.get_mut(&account_id) {
+            orderids.remove(&order.orderid);
+            if orderids.is_empty() {
+                self.accounts.remove(&account_id);
+            }
+        }
     }
 
-    #[test]
-    fn market_orders_cannot_be_inserted_into_the_orderbook() {
-        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDT));
+    /// Samples the spread after the top of the book may have changed, recording it only when it
+    /// differs from the last sample. A book with an empty side has no spread, so nothing is
+    /// recorded until both sides are back
+    fn sample_spread(&mut self) {
+        let spread = self.get_spread();
+        if spread == self.spread {
+            return;
+        }
+        self.spread = spread;
+        let Some(spread) = spread else {
+            return;
+        };
+        if self.config.spread_history_capacity == 0 {
+            return;
+        }
+        if self.spreads.len() == self.config.spread_history_capacity {
+            self.spreads.pop_front();
+        }
+        self.spreads.push_back((self.clock.now(), spread));
+    }
 
-        let result = orderbook.place(create_order(
-            dec!(200.02),
-            OrderSide::Bid,
-            8,
-            OrderType::Market,
-            TradingPair {
-                order_asset: Asset::BTC,
-                price_asset: Asset::USDT,
+    /// Keeps an order that reached a terminal state, with nothing left of it to fill, dropping
+    /// the oldest archived order when the archive is full
+    fn archive(&mut self, order: Order) {
+        if self.config.archive_capacity == 0 {
+            return;
+        }
+        if self.archive.contains_key(&order.orderid) {
+            self.archived.retain(|orderid| *orderid != order.orderid);
+        }
+        while self.archived.len() >= self.config.archive_capacity {
+            match self.archived.pop_front() {
+                Some(oldest) => self.archive.remove(&oldest),
+                None => break,
+            };
+        }
+        self.archived.push_back(order.orderid);
+        self.archive.insert(
+            order.orderid,
+            Order {
+                quantity: 0,
+                hidden_quantity: 0,
+                ..order
             },
-        ));
-
-        let failure = result.unwrap_err();
-        assert_eq!(
-            Failure::OrderRejected("Only limit orders can be placed in the orderbook".to_string()),
-            failure
         );
     }
 
-    #[test]
-    fn canceling_a_limit_order_is_should_be_allowed() {
-        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDT));
+    /// Removes an order that has been popped off its queue. If the order is an iceberg with
+    /// hidden quantity left, a new slice is shown and queued behind the orders already
+    /// resting at its price, so the order loses its time priority
+    fn take_filled(&mut self, orderid: OrderId) -> Option<Order> {
+        let order = *self.orders.get(&orderid)?;
+        self.watch_level(order.side, order.price);
+        self.orders.remove(&orderid);
+        if order.hidden_quantity > 0 {
+            let slice = order
+                .display_quantity
+                .unwrap_or(order.hidden_quantity)
+                .min(order.hidden_quantity);
+            let replenished = Order {
+                quantity: slice,
+                hidden_quantity: order.hidden_quantity - slice,
+                timestamp: self.clock.now(),
+                seq: self.take_seq(),
+                ..order
+            };
+            self.orders.insert(orderid, replenished);
+            self.queue(order.side).push(replenished.to_key());
+        } else {
+            self.forget_client_id(&order);
+            self.archive(order);
+        }
+        self.sample_spread();
+        Some(order)
+    }
 
-        let result = orderbook.place(create_order(
-            dec!(200.02),
-            OrderSide::Bid,
-            8,
-            OrderType::Limit,
-            TradingPair::from(Asset::BTC, Asset::USDT),
-        ));
+    /// Gets the order at the head of a queue. Orders are never meant to rest with nothing shown,
+    /// but any found at the head are skipped, until they are taken off the book by
+    /// [OrderBook::best_active_bid] or [OrderBook::best_active_ask]
+    fn top_shown<'a>(
+        &'a self,
+        queue: &'a PriceTimePriorityOrderQueue<OrderKey>,
+    ) -> Option<&'a Order> {
+        let top = self.orders.get(&queue.peek()?.orderid)?;
+        if top.quantity > 0 {
+            return Some(top);
+        }
+        // the queue is a heap, so the best of the rest is only found by looking at all of them
+        queue
+            .iter()
+            .filter(|key| {
+                self.orders
+                    .get(&key.orderid)
+                    .is_some_and(|o| o.quantity > 0)
+            })
+            .max()
+            .and_then(|key| self.orders.get(&key.orderid))
+    }
 
-        let event = result.unwrap();
-        let result = orderbook.cancel(event.orderid);
+    /// Rejects an order that would trade with the other side of the book were it rested as it
+    /// is, which a replacement can't do since it is never matched. Stops only rest once they
+    /// trigger, so never cross
+    fn reject_crossing(&self, order: &Order) -> Option<Failure> {
+        let price = match order.order_type {
+            OrderType::Stop | OrderType::StopLimit => return None,
+            OrderType::Pegged { reference, offset } => self.peg_price(reference, offset)?,
+            _ => order.price,
+        };
+        let crosses = match order.side {
+            OrderSide::Bid => self.peek_top_ask().is_some_and(|ask| ask.price <= price),
+            OrderSide::Ask => self.peek_top_bid().is_some_and(|bid| bid.price >= price),
+        };
+        crosses.then(|| {
+            Failure::OrderRejected(format!(
+                "Order {} would cross the book at {}, so can't be rested without matching",
+                order.orderid, price
+            ))
+        })
+    }
 
-        let event = result.unwrap();
-        assert_eq!(OrderStatus::Canceled, event.status);
+    /// Checks a price an order names against the configuration of the book: it has to be a
+    /// multiple of the tick, have no more places than the quote precision, and be within the
+    /// band around the last traded price
+    fn reject_price(&self, price: Decimal) -> Option<Failure> {
+        if let Some(precision) = self.config.quote_precision {
+            if price.normalize().scale() > precision {
+                return Some(Failure::OrderRejected(format!(
+                    "Price {} has more than the {} decimal places of the quote precision",
+                    price, precision
+                )));
+            }
+        }
+        if let Some(tick) = self.config.tick_size.filter(|tick| !tick.is_zero()) {
+            if !(price % tick).is_zero() {
+                return Some(Failure::OrderRejected(format!(
+                    "Price {} is not a multiple of the tick size {}",
+                    price, tick
+                )));
+            }
+        }
+        if let (Some(band), Some(reference)) = (self.config.price_band, self.last_traded_price) {
+            let distance = (price - reference).abs() / reference * Decimal::ONE_HUNDRED;
+            if distance > band {
+                return Some(Failure::OrderRejected(format!(
+                    "Price {} is outside the {}% band around the last traded price {}",
+                    price, band, reference
+                )));
+            }
+        }
+        None
     }
 
-    #[test]
-    fn an_empty_orderbook_should_have_no_spread() {
-        let orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDT));
+    /// Walks the side of the book opposite to `side` from the best price out, and gets the
+    /// unrounded average price the quantity would fill at
+    fn average_fill_price(&self, side: OrderSide, quantity: Long) -> Option<Decimal> {
+        if quantity == 0 {
+            return None;
+        }
+        let mut resting: Vec<&Order> = self
+            .orders
+            .values()
+            .filter(|order| order.side == side.opposite())
+            .collect();
+        match side {
+            OrderSide::Bid => resting.sort_by_key(|order| order.price),
+            OrderSide::Ask => resting.sort_by_key(|order| Reverse(order.price)),
+        }
 
-        let spread = orderbook.get_spread();
-        assert_eq!(spread, None)
+        let mut quantity_left = quantity;
+        let mut notional = Decimal::ZERO;
+        for order in resting {
+            let filled = quantity_left.min(order.remaining_quantity());
+            notional = notional.saturating_add(Util::notional(order.price, filled));
+            quantity_left -= filled;
+            if quantity_left == 0 {
+                return Some(notional / Decimal::from(quantity));
+            }
+        }
+        None
     }
 
-    #[test]
+    /// Checks that every queued key belongs to an order in the book on the same side, that
+    /// every order in the book is queued exactly once with a positive quantity, that the open
+    /// orders of each account are known to it, and that the book isn't crossed. Meant to be
+    /// asserted after every operation in tests
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut queued = HashSet::with_capacity(self.orders.len());
+        for (side, queue) in [(OrderSide::Bid, &self.bids), (OrderSide::Ask, &self.asks)] {
+            for key in queue.iter() {
+                let order = self.orders.get(&key.orderid).ok_or_else(|| {
+                    format!("{:?} {} is queued but not in the book", side, key.orderid)
+                })?;
+                if order.side != side {
+                    return Err(format!(
+                        "{:?} {} is queued with the {:?}s",
+                        order.side, key.orderid, side
+                    ));
+                }
+                if order.to_key() != *key {
+                    return Err(format!("The queued key of {} is out of date", key.orderid));
+                }
+                if order.quantity == 0 {
+                    return Err(format!("{} is resting with no quantity", key.orderid));
+                }
+                if !queued.insert(key.orderid) {
+                    return Err(format!("{} is queued more than once", key.orderid));
+                }
+            }
+        }
+        if queued.len() != self.orders.len() {
+            return Err(format!(
+                "{} orders are in the book but only {} are queued",
+                self.orders.len(),
+                queued.len()
+            ));
+        }
+        let mut open_for_accounts = 0;
+        for order in self.orders.values().chain(&self.stops) {
+            let Some(account_id) = order.account_id else {
+                continue;
+            };
+            let indexed = self
+                .accounts
+                .get(&account_id)
+                .is_some_and(|orderids| orderids.contains(&order.orderid));
+            if !indexed {
+                return Err(format!("{} is not known to its account", order.orderid));
+            }
+            open_for_accounts += 1;
+        }
+        let indexed: usize = self.accounts.values().map(HashSet::len).sum();
+        if indexed != open_for_accounts {
+            return Err(format!(
+                "{} orders are known to accounts but only {} are open",
+                indexed, open_for_accounts
+            ));
+        }
+        let pegged = self
+            .orders
+            .values()
+            .filter(|order| matches!(order.order_type, OrderType::Pegged { .. }))
+            .count();
+        if pegged != self.pegged.len() {
+            return Err(format!(
+                "{} orders are known to be pegged but {} are resting",
+                self.pegged.len(),
+                pegged
+            ));
+        }
+        if let (Some(bid), Some(ask)) = (self.peek_top_bid(), self.peek_top_ask()) {
+            if bid.price >= ask.price {
+                return Err(format!(
+                    "The book is crossed with a top bid of {} and a top ask of {}",
+                    bid.price, ask.price
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn level_count(&self, side: OrderSide) -> usize {
+        self.orders
+            .values()
+            .filter(|order| order.side == side)
+            .map(|order| order.price.normalize())
+            .collect::<HashSet<Decimal>>()
+            .len()
+    }
+
+    /// Keeps the level at the given price as it is now, if the depth is watched and the level
+    /// hasn't changed since. Called before every change to a level
+    fn watch_level(&mut self, side: OrderSide, price: Decimal) {
+        let key = (side, price.normalize());
+        if self
+            .watched
+            .as_ref()
+            .is_some_and(|watched| !watched.contains_key(&key))
+        {
+            let level = self.level_at(side, price);
+            if let Some(watched) = &mut self.watched {
+                watched.insert(key, level);
+            }
+        }
+    }
+
+    /// Gets the level at the given price, if any orders rest there
+    fn level_at(&self, side: OrderSide, price: Decimal) -> Option<BookLevel> {
+        self.orders
+            .values()
+            .filter(|order| order.side == side && order.price == price)
+            .fold(None, |level, order| {
+                let level = level.unwrap_or(BookLevel {
+                    price: order.price,
+                    quantity: 0,
+                    orders: 0,
+                });
+                Some(BookLevel {
+                    quantity: level.quantity.saturating_add(order.quantity),
+                    orders: level.orders + 1,
+                    ..level
+                })
+            })
+    }
+
+    fn aggregate(&self, side: OrderSide) -> BTreeMap<Decimal, BookLevel> {
+        let mut levels = BTreeMap::new();
+        for order in self.orders.values().filter(|order| order.side == side) {
+            let level = levels.entry(order.price).or_insert(BookLevel {
+                price: order.price,
+                quantity: 0,
+                orders: 0,
+            });
+            level.quantity = level.quantity.saturating_add(order.quantity);
+            level.orders += 1;
+        }
+        levels
+    }
+}
+
+impl OrderBook for LimitOrderBook {
+    fn cancel(&mut self, orderid: OrderId) -> Result<Event, Failure> {
+        let order = self.take_out(orderid).ok_or_else(|| {
+            Failure::OrderNotFound("No order found with the given id".to_string())
+        })?;
+        self.archive(order);
+        self.sample_spread();
+        Ok(Event {
+            orderid,
+            status: OrderStatus::Canceled,
+            at_price: Some(order.price),
+            quantity: Some(order.remaining_quantity()),
+            tag: order.tag,
+        })
+    }
+
+    fn cancel_by_client_id(&mut self, client_order_id: ClientOrderId) -> Result<Event, Failure> {
+        match self.client_ids.get(&client_order_id) {
+            Some(orderid) => self.cancel(*orderid),
+            None => Err(Failure::OrderNotFound(format!(
+                "No order found with client order id {}",
+                client_order_id
+            ))),
+        }
+    }
+
+    fn place(&mut self, order: Order) -> Result<Event, Failure> {
+        if OrderType::Market == order.order_type {
+            return Err(Failure::OrderRejected(
+                "Only limit orders can be placed in the orderbook".to_string(),
+            ));
+        }
+        if self.trading_pair != order.trading_pair {
+            return Err(wrong_book(&order, self.trading_pair));
+        }
+        let is_stop = matches!(order.order_type, OrderType::Stop | OrderType::StopLimit);
+        if is_stop && order.stop_price.is_none() {
+            return Err(Failure::OrderRejected(
+                "Stop orders must have a stop price".to_string(),
+            ));
+        }
+        // a pegged order rests at wherever its reference is when it is placed
+        let order = match order.order_type {
+            OrderType::Pegged { reference, offset } => Order {
+                price: self
+                    .peg_price(reference, offset)
+                    .ok_or_else(|| no_peg_price(&order, reference))?,
+                ..order
+            },
+            _ => order,
+        };
+        if let Some(client_order_id) = order.client_order_id {
+            if self.client_ids.contains_key(&client_order_id) {
+                return Err(Failure::OrderRejected(format!(
+                    "Client order id {} is already in use",
+                    client_order_id
+                )));
+            }
+            self.client_ids.insert(client_order_id, order.orderid);
+        }
+        self.index_order(&order);
+        // stop orders are held apart from the queues until they are triggered
+        if is_stop {
+            self.stops.push(order);
+            return Ok(Event {
+                status: OrderStatus::Created,
+                orderid: order.orderid,
+                at_price: Some(order.price),
+                quantity: Some(order.remaining_quantity()),
+                tag: order.tag,
+            });
+        }
+
+        let order = self.prepare_resting(order);
+        self.watch_level(order.side, order.price);
+        self.orders.insert(order.orderid, order);
+
+        match order.side {
+            OrderSide::Bid => self.bids.push(order.to_key()),
+            OrderSide::Ask => self.asks.push(order.to_key()),
+        };
+        self.sample_spread();
+        Ok(Event {
+            status: OrderStatus::Created,
+            orderid: order.orderid,
+            at_price: Some(order.price),
+            quantity: Some(order.remaining_quantity()),
+            tag: order.tag,
+        })
+    }
+
+    fn replace(&mut self, cancel_id: OrderId, new_order: Order) -> Result<Event, Failure> {
+        let stop_index = self.stops.iter().position(|stop| stop.orderid == cancel_id);
+        // the original is taken out first, so the new order can take over its client order id.
+        // It is only archived once the new order is placed, so a rejected replace leaves no
+        // trace of it in the archive or the spread history
+        let original = self.take_out(cancel_id).ok_or_else(|| {
+            Failure::OrderNotFound("No order found with the given id".to_string())
+        })?;
+        let placed = match self
+            .validate(&new_order)
+            .or_else(|| self.reject_crossing(&new_order))
+        {
+            Some(failure) => Err(failure),
+            None => self.place(new_order),
+        };
+        match placed {
+            Ok(_) => self.archive(original),
+            Err(_) => {
+                if let Some(client_order_id) = original.client_order_id {
+                    self.client_ids.insert(client_order_id, original.orderid);
+                }
+                self.index_order(&original);
+                match stop_index {
+                    Some(index) => self.stops.insert(index, original),
+                    None => {
+                        self.queue(original.side).push(original.to_key());
+                        self.orders.insert(original.orderid, original);
+                    }
+                }
+            }
+        }
+        placed
+    }
+
+    fn get_spread(&self) -> Option<Decimal> {
+        match self.bids.peek() {
+            Some(bid) => self
+                .asks
+                .peek()
+                .map(|ask| self.config.round_price(ask.price - bid.price)),
+            None => None,
+        }
+    }
+
+    fn mid_price(&self) -> Option<Decimal> {
+        match self.bids.peek() {
+            Some(bid) => self.asks.peek().map(|ask| {
+                self.config
+                    .round_price((ask.price + bid.price) / Decimal::TWO)
+            }),
+            None => None,
+        }
+    }
+
+    fn microprice(&self) -> Option<Decimal> {
+        let (bid_price, bid_size) = self.top_bid_level()?;
+        let (ask_price, ask_size) = self.top_ask_level()?;
+        // a notional too large for a decimal saturates, so adding the other side to it fails
+        let weighted =
+            Util::notional(bid_price, ask_size).checked_add(Util::notional(ask_price, bid_size))?;
+        let size = Decimal::from(bid_size) + Decimal::from(ask_size);
+        Some(self.config.round_price(weighted / size))
+    }
+
+    fn weighted_mid(&self, levels: usize) -> Option<Decimal> {
+        let (bids, asks) = self.depth(levels);
+        // the average price of the levels of a side weighted by their size, with the total size
+        let weigh = |levels: Vec<PriceLevel>| {
+            let (size, notional) = levels.iter().fold(
+                (Decimal::ZERO, Decimal::ZERO),
+                |(size, notional), &(price, quantity)| {
+                    (
+                        size.saturating_add(Decimal::from(quantity)),
+                        notional.saturating_add(Util::notional(price, quantity)),
+                    )
+                },
+            );
+            (!size.is_zero()).then(|| (notional / size, size))
+        };
+        let (bid_price, bid_size) = weigh(bids)?;
+        let (ask_price, ask_size) = weigh(asks)?;
+        let weighted = bid_price
+            .checked_mul(ask_size)?
+            .checked_add(ask_price.checked_mul(bid_size)?)?;
+        Some(
+            self.config
+                .round_price(weighted / bid_size.saturating_add(ask_size)),
+        )
+    }
+
+    fn open_notional(&self, side: Option<OrderSide>) -> Decimal {
+        self.orders
+            .values()
+            .filter(|order| side.is_none_or(|side| order.side == side))
+            .map(Order::notional)
+            .fold(Decimal::ZERO, Decimal::saturating_add)
+    }
+
+    fn estimate_fill(&self, side: OrderSide, quantity: Long) -> Option<Decimal> {
+        self.average_fill_price(side, quantity)
+            .map(|price| self.config.round_price(price))
+    }
+
+    fn effective_spread(&self, quantity: Long) -> Option<Decimal> {
+        let buy = self.average_fill_price(OrderSide::Bid, quantity)?;
+        let sell = self.average_fill_price(OrderSide::Ask, quantity)?;
+        Some(self.config.round_price(buy - sell))
+    }
+
+    fn get_order(&self, orderid: OrderId) -> Option<Order> {
+        self.orders
+            .get(&orderid)
+            .or_else(|| self.stops.iter().find(|stop| stop.orderid == orderid))
+            .or_else(|| self.archive.get(&orderid))
+            .copied()
+    }
+
+    fn peek_top_ask(&self) -> Option<&Order> {
+        self.top_shown(&self.asks)
+    }
+
+    fn peek_top_bid(&self) -> Option<&Order> {
+        self.top_shown(&self.bids)
+    }
+
+    fn best_active_bid(&mut self) -> Option<&Order> {
+        while self.orders.get(&self.bids.peek()?.orderid)?.quantity == 0 {
+            self.pop_top_bid();
+        }
+        self.peek_top_bid()
+    }
+
+    fn best_active_ask(&mut self) -> Option<&Order> {
+        while self.orders.get(&self.asks.peek()?.orderid)?.quantity == 0 {
+            self.pop_top_ask();
+        }
+        self.peek_top_ask()
+    }
+
+    fn top_bid_with_depth(&self) -> Option<(Order, usize)> {
+        let top = *self.peek_top_bid()?;
+        Some((top, self.bid_count() - 1))
+    }
+
+    fn top_ask_with_depth(&self) -> Option<(Order, usize)> {
+        let top = *self.peek_top_ask()?;
+        Some((top, self.ask_count() - 1))
+    }
+
+    fn top_bid_level(&self) -> Option<PriceLevel> {
+        let price = self.peek_top_bid()?.price;
+        Some((price, self.volume_at_price(OrderSide::Bid, price)))
+    }
+
+    fn top_ask_level(&self) -> Option<PriceLevel> {
+        let price = self.peek_top_ask()?.price;
+        Some((price, self.volume_at_price(OrderSide::Ask, price)))
+    }
+
+    fn modify_quantity(&mut self, orderid: OrderId, quantity: Long) -> Result<Event, Failure> {
+        if let Some(order) = self.orders.get(&orderid).copied() {
+            self.watch_level(order.side, order.price);
+        }
+        let Some(order) = self.orders.get_mut(&orderid) else {
+            return Err(Failure::OrderNotFound(
+                "No order found with the given id".to_string(),
+            ));
+        };
+        if quantity > order.quantity {
+            return Err(Failure::OrderRejected(format!(
+                "Quantity of order {} can only be reduced in place, from {} not to {}",
+                orderid, order.quantity, quantity
+            )));
+        }
+        let event = Event {
+            status: OrderStatus::Modified,
+            orderid,
+            at_price: Some(order.price),
+            quantity: Some(quantity),
+            tag: order.tag,
+        };
+        if quantity > 0 {
+            order.quantity = quantity;
+            return Ok(event);
+        }
+        let order = *order;
+        self.queue(order.side).remove(order.to_key());
+        self.take_filled(orderid);
+        Ok(Event {
+            status: OrderStatus::Filled,
+            ..event
+        })
+    }
+
+    fn reprice(
+        &mut self,
+        account_id: AccountId,
+        side: OrderSide,
+        delta: Decimal,
+    ) -> Result<usize, Failure> {
+        let mut repriced: Vec<Order> = self
+            .orders
+            .values()
+            .filter(|order| order.side == side && order.account_id == Some(account_id))
+            .copied()
+            .collect();
+        // keys of a higher priority compare greater, so this keeps the queue order
+        repriced.sort_by_key(|order| Reverse(order.to_key()));
+        let best_opposite = match side {
+            OrderSide::Bid => self.peek_top_ask(),
+            OrderSide::Ask => self.peek_top_bid(),
+        }
+        .map(|order| order.price);
+        for order in &repriced {
+            let price = order.price + delta;
+            if price <= Decimal::ZERO {
+                return Err(Failure::OrderRejected(format!(
+                    "Repricing order {} by {} would take its price to {}",
+                    order.orderid, delta, price
+                )));
+            }
+            let crosses = match (side, best_opposite) {
+                (OrderSide::Bid, Some(ask)) => price >= ask,
+                (OrderSide::Ask, Some(bid)) => price <= bid,
+                (_, None) => false,
+            };
+            if crosses {
+                return Err(Failure::OrderRejected(format!(
+                    "Repricing order {} to {} would cross the book",
+                    order.orderid, price
+                )));
+            }
+            if let Some(failure) = self.reject_price(price) {
+                return Err(failure);
+            }
+        }
+
+        let timestamp = self.clock.now();
+        for order in &repriced {
+            let reseated = Order {
+                price: order.price + delta,
+                timestamp,
+                seq: self.take_seq(),
+                ..*order
+            };
+            self.watch_level(side, order.price);
+            self.watch_level(side, reseated.price);
+            self.queue(side).remove(order.to_key());
+            self.queue(side).push(reseated.to_key());
+            self.orders.insert(order.orderid, reseated);
+            self.rekey_duplicate(order, &reseated);
+        }
+        self.sample_spread();
+        Ok(repriced.len())
+    }
+
+    fn pop_top_bid(&mut self) -> Option<Order> {
+        if let Some(key) = self.bids.pop() {
+            return self.take_filled(key.orderid);
+        }
+        None
+    }
+
+    fn pop_top_ask(&mut self) -> Option<Order> {
+        if let Some(key) = self.asks.pop() {
+            return self.take_filled(key.orderid);
+        }
+        None
+    }
+
+    fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    fn bid_count(&self) -> usize {
+        self.bids.len()
+    }
+
+    fn ask_count(&self) -> usize {
+        self.asks.len()
+    }
+
+    fn bid_levels(&self) -> usize {
+        self.level_count(OrderSide::Bid)
+    }
+
+    fn ask_levels(&self) -> usize {
+        self.level_count(OrderSide::Ask)
+    }
+
+    fn volume_at_price(&self, side: OrderSide, price: Decimal) -> Long {
+        self.orders
+            .values()
+            .filter(|order| order.side == side && order.price == price)
+            .map(|order| order.quantity)
+            .fold(0, Long::saturating_add)
+    }
+
+    fn top_n_bids(&self, n: usize) -> Vec<Order> {
+        self.top_n(OrderSide::Bid, n)
+    }
+
+    fn queue_position(&self, orderid: OrderId) -> Option<(usize, Long)> {
+        let key = self.orders.get(&orderid)?.to_key();
+        let queue = match key.side {
+            OrderSide::Bid => &self.bids,
+            OrderSide::Ask => &self.asks,
+        };
+        // keys of a higher priority compare greater, so no sorting is needed to find them
+        Some(
+            queue
+                .iter()
+                .filter(|other| **other > key)
+                .filter_map(|other| self.orders.get(&other.orderid))
+                .fold((0, 0), |(orders, quantity), order| {
+                    (orders + 1, quantity.saturating_add(order.quantity))
+                }),
+        )
+    }
+
+    fn top_n_asks(&self, n: usize) -> Vec<Order> {
+        self.top_n(OrderSide::Ask, n)
+    }
+
+    fn pending_stops(&self) -> Vec<Order> {
+        self.stops.clone()
+    }
+
+    fn depth(&self, levels: usize) -> Depth {
+        let bids = self.aggregate(OrderSide::Bid);
+        let asks = self.aggregate(OrderSide::Ask);
+        let price_level = |level: BookLevel| (level.price, level.quantity);
+        (
+            bids.into_values()
+                .rev()
+                .take(levels)
+                .map(price_level)
+                .collect(),
+            asks.into_values().take(levels).map(price_level).collect(),
+        )
+    }
+
+    fn ladder(&self) -> Depth {
+        self.depth(usize::MAX)
+    }
+
+    fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            trading_pair: self.trading_pair,
+            bids: self.aggregate(OrderSide::Bid).into_values().rev().collect(),
+            asks: self.aggregate(OrderSide::Ask).into_values().collect(),
+        }
+    }
+
+    fn watch_depth(&mut self) {
+        self.watched = Some(HashMap::new());
+    }
+
+    fn take_depth_deltas(&mut self) -> Vec<DepthDelta> {
+        let Some(watched) = self.watched.take() else {
+            return Vec::new();
+        };
+        let mut after: HashMap<(OrderSide, Decimal), BookLevel> = HashMap::new();
+        for order in self.orders.values() {
+            let key = (order.side, order.price.normalize());
+            if watched.contains_key(&key) {
+                let level = after.entry(key).or_insert(BookLevel {
+                    price: order.price,
+                    quantity: 0,
+                    orders: 0,
+                });
+                level.quantity = level.quantity.saturating_add(order.quantity);
+                level.orders += 1;
+            }
+        }
+        let levels = |side: OrderSide| {
+            let before: Vec<BookLevel> = watched
+                .iter()
+                .filter(|((level_side, _), _)| *level_side == side)
+                .filter_map(|(_, level)| *level)
+                .collect();
+            let after: Vec<BookLevel> = after
+                .iter()
+                .filter(|((level_side, _), _)| *level_side == side)
+                .map(|(_, level)| *level)
+                .collect();
+            level_deltas(self.trading_pair, side, &before, &after)
+        };
+        let mut deltas = levels(OrderSide::Bid);
+        // the bids are compared from the lowest price, so they're flipped to start at the best
+        deltas.reverse();
+        deltas.extend(levels(OrderSide::Ask));
+        deltas
+    }
+
+    fn crossing_quantity(&self, order: &Order) -> Long {
+        self.orders
+            .values()
+            .filter(|resting| resting.side == order.side.opposite())
+            .filter(|resting| match (order.order_type, order.side) {
+                (OrderType::Market, _) => true,
+                (_, OrderSide::Bid) => resting.price <= order.price,
+                (_, OrderSide::Ask) => resting.price >= order.price,
+            })
+            .map(|resting| resting.remaining_quantity())
+            .fold(0, Long::saturating_add)
+    }
+
+    fn next_match(&self, side: OrderSide, price: Decimal, order_type: OrderType) -> Option<Order> {
+        let top = match side {
+            OrderSide::Bid => self.peek_top_ask(),
+            OrderSide::Ask => self.peek_top_bid(),
+        }?;
+        let within_limit = match (order_type, side) {
+            (OrderType::Market, _) => true,
+            (_, OrderSide::Bid) => top.price <= price,
+            (_, OrderSide::Ask) => top.price >= price,
+        };
+        within_limit.then_some(*top)
+    }
+
+    fn cancel_older_than(&mut self, cutoff: TimestampMillis) -> Vec<Event> {
+        let mut stale: Vec<OrderKey> = self
+            .orders
+            .values()
+            .filter(|order| order.timestamp < cutoff)
+            .map(|order| order.to_key())
+            .collect();
+        stale.sort_by_key(|key| (key.timestamp, key.seq));
+
+        stale
+            .into_iter()
+            .filter_map(|key| self.cancel(key.orderid).ok())
+            .collect()
+    }
+
+    fn cancel_level(&mut self, side: OrderSide, price: Decimal) -> Vec<Event> {
+        let mut level: Vec<OrderKey> = self
+            .orders
+            .values()
+            .filter(|order| order.side == side && order.price == price)
+            .map(|order| order.to_key())
+            .collect();
+        // keys of a higher priority compare greater, so this puts the first to match first
+        level.sort_by_key(|key| Reverse(*key));
+
+        level
+            .into_iter()
+            .filter_map(|key| self.cancel(key.orderid).ok())
+            .collect()
+    }
+
+    fn cancel_account_orders(&mut self, account_id: AccountId) -> Vec<Event> {
+        let Some(orderids) = self.accounts.get(&account_id) else {
+            return Vec::new();
+        };
+        let mut resting: Vec<OrderKey> = orderids
+            .iter()
+            .filter_map(|orderid| self.orders.get(orderid))
+            .map(|order| order.to_key())
+            .collect();
+        // keys of a higher priority compare greater, so this puts the first to match first
+        resting.sort_by_key(|key| (key.side, Reverse(*key)));
+        let stops: Vec<OrderId> = self
+            .stops
+            .iter()
+            .filter(|stop| orderids.contains(&stop.orderid))
+            .map(|stop| stop.orderid)
+            .collect();
+
+        resting
+            .into_iter()
+            .map(|key| key.orderid)
+            .chain(stops)
+            .filter_map(|orderid| self.cancel(orderid).ok())
+            .collect()
+    }
+
+    fn purge_expired(&mut self, now: TimestampMillis) -> Vec<Event> {
+        let expired: Vec<OrderId> = self
+            .orders
+            .values()
+            .filter(|order| order.is_expired(now))
+            .map(|order| order.orderid)
+            .collect();
+
+        let mut events = Vec::with_capacity(expired.len());
+        for orderid in expired {
+            if let Some(order) = self.orders.get(&orderid).copied() {
+                self.watch_level(order.side, order.price);
+            }
+            if let Some(order) = self.orders.remove(&orderid) {
+                match order.side {
+                    OrderSide::Bid => self.bids.remove(order.to_key()),
+                    OrderSide::Ask => self.asks.remove(order.to_key()),
+                };
+                self.forget_client_id(&order);
+                self.archive(order);
+                events.push(Event {
+                    orderid,
+                    status: OrderStatus::Expired,
+                    at_price: Some(order.price),
+                    quantity: Some(order.remaining_quantity()),
+                    tag: order.tag,
+                });
+            }
+        }
+        // stops waiting to trigger expire like the orders resting in the book
+        let (expired, stops) = mem::take(&mut self.stops)
+            .into_iter()
+            .partition(|stop| stop.is_expired(now));
+        self.stops = stops;
+        for stop in expired {
+            self.forget_client_id(&stop);
+            self.archive(stop);
+            events.push(Event {
+                orderid: stop.orderid,
+                status: OrderStatus::Expired,
+                at_price: Some(stop.price),
+                quantity: Some(stop.remaining_quantity()),
+                tag: stop.tag,
+            });
+        }
+        self.sample_spread();
+        events
+    }
+
+    fn validate(&self, order: &Order) -> Option<Failure> {
+        if let Some(client_order_id) = order.client_order_id {
+            if self.client_ids.contains_key(&client_order_id) {
+                return Some(Failure::OrderRejected(format!(
+                    "Client order id {} is already in use",
+                    client_order_id
+                )));
+            }
+        }
+        if let Some(failure) = self.reject_duplicate(order) {
+            return Some(failure);
+        }
+        // a stop has to be triggered by a move in the market that is yet to happen
+        if let (Some(stop_price), Some(reference)) =
+            (order.stop_price, self.stop_reference(order.side))
+        {
+            if order.is_triggered_at(reference) {
+                let name = match self.config.stop_trigger {
+                    StopTrigger::LastTrade => "last traded price",
+                    StopTrigger::BestPrice => "best price",
+                    StopTrigger::Mid => "mid price",
+                };
+                return Some(Failure::OrderRejected(format!(
+                    "Stop price {} has already been reached by the {} {}",
+                    stop_price, name, reference
+                )));
+            }
+        }
+        // market orders take whatever price the book offers, so only limit prices are banded
+        if matches!(order.order_type, OrderType::Market | OrderType::Stop) {
+            return match self.config.allow_market_orders {
+                true => None,
+                false => Some(Failure::OrderRejected(format!(
+                    "{:?} orders are not accepted for {}",
+                    order.order_type, self.trading_pair
+                ))),
+            };
+        }
+        // and pegged orders take their price from the book too
+        if let OrderType::Pegged { reference, offset } = order.order_type {
+            return match self.peg_price(reference, offset) {
+                Some(price) => self.reject_price(price),
+                None => Some(no_peg_price(order, reference)),
+            };
+        }
+        self.reject_price(order.price)
+    }
+
+    fn record_trade(&mut self, trade: &Trade) {
+        self.last_traded_price = Some(trade.price);
+        self.summary.record(trade);
+        if self.config.tape_capacity == 0 {
+            return;
+        }
+        if self.tape.len() == self.config.tape_capacity {
+            self.tape.pop_front();
+        }
+        self.tape.push_back(*trade);
+    }
+
+    fn last_traded_price(&self) -> Option<Decimal> {
+        self.last_traded_price
+    }
+
+    fn match_summary(&self) -> MatchSummary {
+        self.summary
+    }
+
+    fn vwap(&self) -> Option<Decimal> {
+        self.summary
+            .average_price()
+            .map(|price| self.config.round_price(price))
+    }
+
+    fn spread_history(&self, n: usize) -> Vec<(TimestampMillis, Decimal)> {
+        self.spreads.iter().rev().take(n).copied().collect()
+    }
+
+    fn recent_trades(&self, n: usize) -> Vec<Trade> {
+        self.tape.iter().rev().take(n).copied().collect()
+    }
+
+    fn drain(&mut self) -> Vec<Order> {
+        let mut drained = self.top_n(OrderSide::Bid, usize::MAX);
+        drained.extend(self.top_n(OrderSide::Ask, usize::MAX));
+        for order in &drained {
+            self.watch_level(order.side, order.price);
+        }
+        drained.append(&mut self.stops);
+        self.bids = PriceTimePriorityOrderQueue::with_capacity(ORDER_BOOK_INITIAL_CAPACITY);
+        self.asks = PriceTimePriorityOrderQueue::with_capacity(ORDER_BOOK_INITIAL_CAPACITY);
+        self.orders.clear();
+        self.client_ids.clear();
+        self.accounts.clear();
+        self.pegged.clear();
+        self.duplicates.clear();
+        self.sample_spread();
+        drained
+    }
+
+    fn take_triggered_stop(&mut self) -> Option<Order> {
+        // an expired stop never triggers, and is left to be purged
+        let now = self.now();
+        let index = self.stops.iter().position(|stop| {
+            !stop.is_expired(now)
+                && self
+                    .stop_reference(stop.side)
+                    .is_some_and(|reference| stop.is_triggered_at(reference))
+        })?;
+        let stop = self.stops.remove(index);
+        // the order gets its client order id and account back if what is left of it rests
+        // once triggered
+        self.forget_client_id(&stop);
+        Some(stop)
+    }
+
+    fn now(&self) -> TimestampMillis {
+        self.clock.now()
+    }
+
+    fn trading_pair(&self) -> TradingPair {
+        self.trading_pair
+    }
+
+    fn max_fills_per_order(&self) -> Option<NonZeroUsize> {
+        self.config.max_fills_per_order
+    }
+
+    fn trade_price(&self, price: Decimal) -> Decimal {
+        self.config.normalize_trade_price(price)
+    }
+
+    fn round_price(&self, price: Decimal) -> Decimal {
+        self.config.round_price(price)
+    }
+
+    fn peg_price(&self, reference: PegReference, offset: Decimal) -> Option<Decimal> {
+        let price = match reference {
+            PegReference::BestBid => self.best_unpegged(OrderSide::Bid)?,
+            PegReference::BestAsk => self.best_unpegged(OrderSide::Ask)?,
+            PegReference::Mid => {
+                let bid = self.best_unpegged(OrderSide::Bid)?;
+                let ask = self.best_unpegged(OrderSide::Ask)?;
+                (bid + ask) / Decimal::TWO
+            }
+        } + offset;
+        (price > Decimal::ZERO).then_some(price)
+    }
+
+    fn reprice_pegged(&mut self) -> usize {
+        if self.pegged.is_empty() {
+            return 0;
+        }
+        let mut pegged: Vec<Order> = self
+            .pegged
+            .iter()
+            .filter_map(|orderid| self.orders.get(orderid))
+            .copied()
+            .collect();
+        // keys of a higher priority compare greater, so this keeps the queue order
+        pegged.sort_by_key(|order| Reverse(order.to_key()));
+        let timestamp = self.clock.now();
+        let mut repriced = 0;
+        for order in pegged {
+            let OrderType::Pegged { reference, offset } = order.order_type else {
+                continue;
+            };
+            // a price placing the order would reject, off the tick say, is no place to move to
+            let price = match self.peg_price(reference, offset) {
+                Some(price) if price != order.price && self.reject_price(price).is_none() => price,
+                _ => continue,
+            };
+            let reseated = Order {
+                price,
+                timestamp,
+                seq: self.take_seq(),
+                ..order
+            };
+            self.watch_level(order.side, order.price);
+            self.watch_level(order.side, reseated.price);
+            self.queue(order.side).remove(order.to_key());
+            self.queue(order.side).push(reseated.to_key());
+            self.orders.insert(order.orderid, reseated);
+            self.rekey_duplicate(&order, &reseated);
+            repriced += 1;
+        }
+        self.sample_spread();
+        repriced
+    }
+}
+
+fn wrong_book(order: &Order, trading_pair: TradingPair) -> Failure {
+    Failure::InvalidOrderForBook(format!(
+        "Order {} is for {}, not the {} book",
+        order.orderid, order.trading_pair, trading_pair
+    ))
+}
+
+fn no_peg_price(order: &Order, reference: PegReference) -> Failure {
+    Failure::OrderRejected(format!(
+        "No {:?} price to peg order {} to",
+        reference, order.orderid
+    ))
+}
+
+impl Display for LimitOrderBook {
+    /// Draws the book as a price ladder, the way exchanges show their depth: the asks above the
+    /// bids, both from the highest price down, split by a marker showing the spread
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (bids, asks) = self.ladder();
+        writeln!(f, "{}", self.trading_pair)?;
+        for (price, quantity) in asks.iter().rev() {
+            writeln!(f, "ASK {:>16} {:>12}", price, quantity)?;
+        }
+        match self.get_spread() {
+            Some(spread) => writeln!(f, "---- spread {} ----", spread)?,
+            None => writeln!(f, "----")?,
+        }
+        for (price, quantity) in &bids {
+            writeln!(f, "BID {:>16} {:>12}", price, quantity)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, str::FromStr, sync::Arc};
+
+    use proptest::prelude::*;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use crate::core::{
+        matcher::Matcher,
+        model::{Event, Order, TradingPair},
+        pqueue::OrderQueue,
+        types::{
+            Asset, Failure, Long, OrderSide, OrderStatus, OrderType, PegReference,
+            RoundingStrategy, StopTrigger, TimeInForce, Trade,
+        },
+        utils::{MockClock, Util},
+    };
+
+    use super::{BookConfig, LimitOrderBook, OrderBook};
+
+    #[test]
+    fn can_place_a_limit_order_in_the_order_book() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::ETH));
+
+        let order = create_order(
+            dec!(200.02),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            TradingPair {
+                order_asset: Asset::BTC,
+                price_asset: Asset::ETH,
+            },
+        );
+        let result = orderbook.place(order);
+
+        let event = result.unwrap();
+        assert_eq!(OrderStatus::Created, event.status);
+
+        // we expect the order we placed to be at the top of the book
+        let order_placed = orderbook.peek_top_bid();
+        assert_eq!(*order_placed.unwrap(), order);
+    }
+
+    #[test]
+    fn market_orders_cannot_be_inserted_into_the_orderbook() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDT));
+
+        let result = orderbook.place(create_order(
+            dec!(200.02),
+            OrderSide::Bid,
+            8,
+            OrderType::Market,
+            TradingPair {
+                order_asset: Asset::BTC,
+                price_asset: Asset::USDT,
+            },
+        ));
+
+        let failure = result.unwrap_err();
+        assert_eq!(
+            Failure::OrderRejected("Only limit orders can be placed in the orderbook".to_string()),
+            failure
+        );
+    }
+
+    #[test]
+    fn canceling_a_limit_order_is_should_be_allowed() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDT));
+
+        let result = orderbook.place(create_order(
+            dec!(200.02),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            TradingPair::from(Asset::BTC, Asset::USDT),
+        ));
+
+        let event = result.unwrap();
+        let result = orderbook.cancel(event.orderid);
+
+        let event = result.unwrap();
+        assert_eq!(OrderStatus::Canceled, event.status);
+    }
+
+    #[test]
+    fn canceling_an_order_missing_from_its_queue_only_forgets_the_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::ETH);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bids = [dec!(20.00), dec!(19.00)]
+            .map(|price| create_order(price, OrderSide::Bid, 5, OrderType::Limit, trading_pair));
+        for bid in bids {
+            let _ = orderbook.place(bid);
+        }
+        // leave the book inconsistent, with the top bid in the map but not in the queue
+        let key = orderbook.orders[&bids[0].orderid].to_key();
+        orderbook.bids.remove(key);
+
+        let event = orderbook.cancel(bids[0].orderid).unwrap();
+        assert_eq!(event.quantity, Some(5));
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(orderbook.bid_count(), 1);
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, bids[1].orderid);
+    }
+
+    #[test]
+    fn an_order_missing_from_its_queue_breaks_the_invariants() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::ETH);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bid = create_order(
+            dec!(20.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(bid);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+
+        orderbook
+            .bids
+            .remove(orderbook.orders[&bid.orderid].to_key());
+        assert!(orderbook.check_invariants().is_err());
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Place {
+            side: OrderSide,
+            price: Long,
+            quantity: Long,
+            display_quantity: Option<Long>,
+            order_type: OrderType,
+        },
+        Cancel(usize),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        let side = prop_oneof![Just(OrderSide::Bid), Just(OrderSide::Ask)];
+        let order_type = prop_oneof![4 => Just(OrderType::Limit), 1 => Just(OrderType::Market)];
+        prop_oneof![
+            3 => (side, 90..110u64, 1..20u64, proptest::option::of(1..5u64), order_type)
+                .prop_map(|(side, price, quantity, display_quantity, order_type)| {
+                    Operation::Place {
+                        side,
+                        price,
+                        quantity,
+                        display_quantity,
+                        order_type,
+                    }
+                }),
+            1 => any::<usize>().prop_map(Operation::Cancel),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn random_places_and_cancels_keep_the_book_consistent(
+            operations in proptest::collection::vec(operation(), 1..64)
+        ) {
+            let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+            let mut orderbook = LimitOrderBook::init(trading_pair);
+            let mut placed = Vec::new();
+            for operation in operations {
+                match operation {
+                    Operation::Place {
+                        side,
+                        price,
+                        quantity,
+                        display_quantity,
+                        order_type,
+                    } => {
+                        let price = Decimal::from(price);
+                        let mut order = create_order(price, side, quantity, order_type, trading_pair);
+                        if order_type == OrderType::Limit {
+                            order.display_quantity = display_quantity;
+                        }
+                        placed.push(order.orderid);
+                        let _ = Matcher.match_order(order, &mut orderbook);
+                    }
+                    Operation::Cancel(index) if !placed.is_empty() => {
+                        let _ = orderbook.cancel(placed[index % placed.len()]);
+                    }
+                    Operation::Cancel(_) => (),
+                }
+                prop_assert_eq!(orderbook.check_invariants(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn placing_an_order_reports_the_price_and_quantity_it_rests_with() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let ask = create_order(
+            dec!(200.02),
+            OrderSide::Ask,
+            10,
+            OrderType::Limit,
+            trading_pair,
+        );
+
+        let event = orderbook.place(ask).unwrap();
+        assert_eq!(event.status, OrderStatus::Created);
+        assert_eq!(event.at_price, Some(dec!(200.02)));
+        assert_eq!(event.quantity, Some(10));
+    }
+
+    #[test]
+    fn canceling_a_partially_filled_order_reports_the_unfilled_quantity() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+
+        let ask = create_order(
+            dec!(200.02),
+            OrderSide::Ask,
+            10,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(ask);
+        let bid = create_order(
+            dec!(200.02),
+            OrderSide::Bid,
+            4,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = Matcher.match_order(bid, &mut orderbook);
+
+        let event = orderbook.cancel(ask.orderid).unwrap();
+        assert_eq!(OrderStatus::Canceled, event.status);
+        assert_eq!(event.quantity, Some(6));
+        assert_eq!(event.at_price, Some(dec!(200.02)));
+    }
+
+    fn archiving_book(archive_capacity: usize) -> LimitOrderBook {
+        LimitOrderBook::with_config(
+            TradingPair::from(Asset::BTC, Asset::USDT),
+            BookConfig {
+                archive_capacity,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn fill_an_ask(orderbook: &mut LimitOrderBook) -> Order {
+        let trading_pair = orderbook.trading_pair();
+        let ask = create_order(
+            dec!(20.0),
+            OrderSide::Ask,
+            4,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(ask);
+        let bid = create_order(
+            dec!(20.0),
+            OrderSide::Bid,
+            4,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = Matcher.match_order(bid, orderbook);
+        ask
+    }
+
+    #[test]
+    fn a_filled_order_can_still_be_looked_up_when_the_book_archives_orders() {
+        let mut orderbook = archiving_book(8);
+        let ask = fill_an_ask(&mut orderbook);
+
+        assert!(orderbook.is_empty());
+        let archived = orderbook.get_order(ask.orderid).unwrap();
+        assert_eq!(archived.quantity, 0);
+        assert_eq!(archived.remaining_quantity(), 0);
+        assert_eq!((archived.price, archived.side), (ask.price, ask.side));
+
+        let trading_pair = orderbook.trading_pair();
+        let resting = create_order(
+            dec!(21.0),
+            OrderSide::Ask,
+            3,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(resting);
+        assert_eq!(orderbook.get_order(resting.orderid).unwrap().quantity, 3);
+        let _ = orderbook.cancel(resting.orderid);
+        assert_eq!(orderbook.get_order(resting.orderid).unwrap().quantity, 0);
+    }
+
+    #[test]
+    fn terminal_orders_are_forgotten_without_an_archive() {
+        let mut orderbook = archiving_book(0);
+        let ask = fill_an_ask(&mut orderbook);
+        assert_eq!(orderbook.get_order(ask.orderid), None);
+    }
+
+    #[test]
+    fn the_oldest_archived_orders_are_dropped_beyond_the_capacity() {
+        let mut orderbook = archiving_book(2);
+        let asks: Vec<Order> = (0..3).map(|_| fill_an_ask(&mut orderbook)).collect();
+
+        assert_eq!(orderbook.get_order(asks[0].orderid), None);
+        assert!(orderbook.get_order(asks[1].orderid).is_some());
+        assert!(orderbook.get_order(asks[2].orderid).is_some());
+    }
+
+    #[test]
+    fn the_top_of_each_side_is_given_with_the_number_of_orders_behind_it() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        assert_eq!(orderbook.top_bid_with_depth(), None);
+        assert_eq!(orderbook.top_ask_with_depth(), None);
+
+        let ask = create_order(
+            dec!(21.0),
+            OrderSide::Ask,
+            4,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(ask);
+        assert_eq!(orderbook.top_ask_with_depth(), Some((ask, 0)));
+        assert_eq!(orderbook.top_bid_with_depth(), None);
+
+        let bids = [dec!(19.0), dec!(20.0), dec!(20.0)]
+            .map(|price| create_order(price, OrderSide::Bid, 4, OrderType::Limit, trading_pair));
+        for bid in bids {
+            let _ = orderbook.place(bid);
+        }
+        // of the two best bids, the first placed is on top
+        let (top, behind) = orderbook.top_bid_with_depth().unwrap();
+        assert_eq!(top.orderid, bids[1].orderid);
+        assert_eq!(behind, 2);
+        assert_eq!(orderbook.top_ask_with_depth(), Some((ask, 0)));
+    }
+
+    #[test]
+    fn peeking_at_the_top_skips_orders_with_nothing_shown() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let asks = [dec!(21.0), dec!(21.5), dec!(22.0)]
+            .map(|price| create_order(price, OrderSide::Ask, 4, OrderType::Limit, trading_pair));
+        for ask in asks {
+            let _ = orderbook.place(ask);
+        }
+
+        orderbook.orders.get_mut(&asks[0].orderid).unwrap().quantity = 0;
+        orderbook.orders.get_mut(&asks[1].orderid).unwrap().quantity = 0;
+        assert_eq!(orderbook.peek_top_ask().unwrap().orderid, asks[2].orderid);
+        // peeking only reads the book, so the emptied orders are still queued
+        assert_eq!(orderbook.ask_count(), 3);
+
+        orderbook.orders.get_mut(&asks[2].orderid).unwrap().quantity = 0;
+        assert!(orderbook.peek_top_ask().is_none());
+        assert!(orderbook.peek_top_bid().is_none());
+    }
+
+    #[test]
+    fn the_best_active_bid_skips_and_clears_an_emptied_top_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bids = [dec!(20.0), dec!(19.5), dec!(19.0)]
+            .map(|price| create_order(price, OrderSide::Bid, 4, OrderType::Limit, trading_pair));
+        for bid in bids {
+            let _ = orderbook.place(bid);
+        }
+
+        let _ = orderbook.modify_quantity(bids[0].orderid, 0);
+        assert_eq!(
+            orderbook.best_active_bid().map(|order| order.orderid),
+            Some(bids[1].orderid)
+        );
+        assert_eq!(orderbook.bid_count(), 2);
+
+        // an order left resting with nothing shown is taken off the queue on the way
+        orderbook.orders.get_mut(&bids[1].orderid).unwrap().quantity = 0;
+        assert_eq!(
+            orderbook.best_active_bid().map(|order| order.orderid),
+            Some(bids[2].orderid)
+        );
+        assert_eq!(orderbook.bid_count(), 1);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+
+        orderbook.orders.get_mut(&bids[2].orderid).unwrap().quantity = 0;
+        assert!(orderbook.best_active_bid().is_none());
+        assert!(orderbook.best_active_ask().is_none());
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn the_volume_at_a_price_sums_every_order_resting_at_it() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let orders = [
+            create_order(
+                dec!(20.0),
+                OrderSide::Bid,
+                4,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(20.00),
+                OrderSide::Bid,
+                6,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(20.01),
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(20.00),
+                OrderSide::Ask,
+                7,
+                OrderType::Limit,
+                trading_pair,
+            ),
+        ];
+        for order in orders {
+            let _ = orderbook.place(order);
+        }
+
+        assert_eq!(orderbook.volume_at_price(OrderSide::Bid, dec!(20)), 10);
+        assert_eq!(orderbook.volume_at_price(OrderSide::Bid, dec!(20.01)), 5);
+        assert_eq!(orderbook.volume_at_price(OrderSide::Ask, dec!(20.000)), 7);
+        assert_eq!(orderbook.volume_at_price(OrderSide::Bid, dec!(19.99)), 0);
+    }
+
+    #[test]
+    fn the_match_summary_totals_every_trade_of_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                tape_capacity: 1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(orderbook.match_summary().average_price(), None);
+
+        let trade = |price, quantity| Trade {
+            orderid: Uuid::new_v4(),
+            account_id: None,
+            tag: None,
+            side: OrderSide::Ask,
+            price,
+            status: OrderStatus::Filled,
+            quantity,
+            timestamp: 0,
+            price_improvement: Decimal::ZERO,
+        };
+        let largest = trade(dec!(11.00), 6);
+        for fill in [
+            trade(dec!(10.00), 2),
+            largest,
+            trade(dec!(12.00), 6),
+            trade(dec!(9.00), 1),
+        ] {
+            orderbook.record_trade(&fill);
+        }
+
+        // the tape only holds the last trade, the summary covers all of them
+        let summary = orderbook.match_summary();
+        assert_eq!(summary.trades, 4);
+        assert_eq!(summary.volume, 15);
+        assert_eq!(summary.notional, dec!(167.00));
+        assert_eq!(summary.average_price().unwrap().round_dp(4), dec!(11.1333));
+        assert_eq!(summary.largest_trade, Some(largest));
+    }
+
+    #[test]
+    fn the_tape_keeps_the_most_recent_trades_latest_first() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                tape_capacity: 2,
+                ..Default::default()
+            },
+        );
+        let asks = [
+            create_order(
+                dec!(10.00),
+                OrderSide::Ask,
+                1,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(11.00),
+                OrderSide::Ask,
+                1,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(12.00),
+                OrderSide::Ask,
+                1,
+                OrderType::Limit,
+                trading_pair,
+            ),
+        ];
+        for ask in asks {
+            let _ = orderbook.place(ask);
+        }
+        let bid = create_order(
+            dec!(12.00),
+            OrderSide::Bid,
+            3,
+            OrderType::Market,
+            trading_pair,
+        );
+        let _ = Matcher.match_order(bid, &mut orderbook);
+
+        // the trade at 10.00 was the oldest, so it has been evicted
+        let tape = orderbook.recent_trades(5);
+        assert_eq!(tape.len(), 2);
+        assert_eq!(tape[0].orderid, asks[2].orderid);
+        assert_eq!(tape[0].price, dec!(12.00));
+        assert_eq!(tape[1].orderid, asks[1].orderid);
+        assert_eq!(tape[1].price, dec!(11.00));
+        assert_eq!(orderbook.recent_trades(1), vec![tape[0]]);
+    }
+
+    #[test]
+    fn the_book_counts_its_resting_orders_on_each_side() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        assert!(orderbook.is_empty());
+
+        let bid = create_order(
+            dec!(9.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let asks = [
+            create_order(
+                dec!(10.00),
+                OrderSide::Ask,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(11.00),
+                OrderSide::Ask,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+        ];
+        let _ = orderbook.place(bid);
+        for ask in asks {
+            let _ = orderbook.place(ask);
+        }
+        assert_eq!(orderbook.len(), 3);
+        assert_eq!(orderbook.bid_count(), 1);
+        assert_eq!(orderbook.ask_count(), 2);
+
+        let taker = create_order(
+            dec!(10.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Market,
+            trading_pair,
+        );
+        let _ = Matcher.match_order(taker, &mut orderbook);
+        assert_eq!(orderbook.len(), 2);
+        assert_eq!(orderbook.ask_count(), 1);
+
+        let _ = orderbook.cancel(bid.orderid);
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(orderbook.bid_count(), 0);
+        assert!(!orderbook.is_empty());
+    }
+
+    #[test]
+    fn partial_fills_never_leave_an_empty_order_resting() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        for price in [dec!(10.00), dec!(10.00), dec!(11.00)] {
+            let _ = orderbook.place(create_order(
+                price,
+                OrderSide::Ask,
+                7,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+
+        for quantity in [3, 4, 2, 5, 6, 1] {
+            let taker = create_order(
+                dec!(11.00),
+                OrderSide::Bid,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            );
+            let _ = Matcher.match_order(taker, &mut orderbook);
+            assert!(orderbook.orders.values().all(|order| order.quantity > 0));
+            assert_eq!(orderbook.ask_count(), orderbook.len());
+        }
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn an_order_modified_down_to_zero_is_taken_off_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bids = [dec!(10.00), dec!(9.00)]
+            .map(|price| create_order(price, OrderSide::Bid, 5, OrderType::Limit, trading_pair));
+        for bid in bids {
+            let _ = orderbook.place(bid);
+        }
+
+        let event = orderbook.modify_quantity(bids[0].orderid, 0).unwrap();
+        assert_eq!(
+            (event.status, event.quantity),
+            (OrderStatus::Filled, Some(0))
+        );
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(orderbook.bid_count(), 1);
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, bids[1].orderid);
+    }
+
+    #[test]
+    fn a_reduced_order_keeps_its_priority_and_is_reported_as_modified() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bids = [5, 5].map(|quantity| {
+            create_order(
+                dec!(10.00),
+                OrderSide::Bid,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            )
+        });
+        for bid in bids {
+            let _ = orderbook.place(bid);
+        }
+
+        assert_eq!(
+            orderbook.modify_quantity(bids[0].orderid, 3),
+            Ok(Event {
+                status: OrderStatus::Modified,
+                orderid: bids[0].orderid,
+                at_price: Some(dec!(10.00)),
+                quantity: Some(3),
+                tag: None,
+            })
+        );
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, bids[0].orderid);
+        assert_eq!(orderbook.peek_top_bid().unwrap().quantity, 3);
+        assert_eq!(orderbook.volume_at_price(OrderSide::Bid, dec!(10.00)), 8);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn an_order_can_not_be_increased_in_place_or_modified_once_gone() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bid = create_order(
+            dec!(10.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(bid);
+
+        assert_eq!(
+            orderbook.modify_quantity(bid.orderid, 8),
+            Err(Failure::OrderRejected(format!(
+                "Quantity of order {} can only be reduced in place, from 5 not to 8",
+                bid.orderid
+            )))
+        );
+        assert_eq!(orderbook.peek_top_bid().unwrap().quantity, 5);
+
+        assert_eq!(
+            orderbook.modify_quantity(Uuid::new_v4(), 3),
+            Err(Failure::OrderNotFound(
+                "No order found with the given id".to_string()
+            ))
+        );
+        assert!(orderbook.cancel(bid.orderid).is_ok());
+        assert!(matches!(
+            orderbook.modify_quantity(bid.orderid, 3),
+            Err(Failure::OrderNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn orders_can_be_bulk_loaded_without_matching() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let orders: Vec<Order> = (1..=300)
+            .map(|i| {
+                let (side, price) = match i % 2 {
+                    0 => (OrderSide::Bid, Decimal::from(1_000 - i)),
+                    _ => (OrderSide::Ask, Decimal::from(1_000 + i)),
+                };
+                create_order(price, side, 5, OrderType::Limit, trading_pair)
+            })
+            .collect();
+
+        assert_eq!(orderbook.bulk_load(orders), Ok(()));
+        assert_eq!(orderbook.len(), 300);
+        assert_eq!(orderbook.bid_count(), 150);
+        assert_eq!(orderbook.peek_top_bid().unwrap().price, dec!(998));
+        assert_eq!(orderbook.peek_top_ask().unwrap().price, dec!(1001));
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+
+        // bids are popped from the highest price down
+        let _ = orderbook.pop_top_bid();
+        assert_eq!(orderbook.peek_top_bid().unwrap().price, dec!(996));
+    }
+
+    #[test]
+    fn bulk_loading_rejects_the_batch_if_any_order_is_not_a_limit_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let orders = vec![
+            create_order(
+                dec!(10.00),
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(11.00),
+                OrderSide::Ask,
+                5,
+                OrderType::Market,
+                trading_pair,
+            ),
+        ];
+
+        assert_eq!(
+            orderbook.bulk_load(orders),
+            Err(Failure::OrderRejected(
+                "Only limit orders can be bulk loaded".to_string()
+            ))
+        );
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn bulk_loading_rejects_the_batch_if_any_order_id_is_already_used() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let resting = create_order(
+            dec!(10.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(resting);
+        let order = |price| create_order(price, OrderSide::Bid, 5, OrderType::Limit, trading_pair);
+        let repeated = order(dec!(9.00));
+
+        for orders in [
+            vec![
+                repeated,
+                Order {
+                    price: dec!(8.00),
+                    ..repeated
+                },
+            ],
+            vec![
+                order(dec!(9.00)),
+                Order {
+                    price: dec!(8.00),
+                    ..resting
+                },
+            ],
+        ] {
+            let orderid = orders[1].orderid;
+            assert_eq!(
+                orderbook.bulk_load(orders),
+                Err(Failure::OrderRejected(format!(
+                    "Order {} is already in the book or the batch",
+                    orderid
+                )))
+            );
+        }
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn bulk_loading_rejects_the_batch_if_any_price_would_be_rejected_when_placed() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                tick_size: Some(dec!(0.50)),
+                ..Default::default()
+            },
+        );
+        let order = |price| create_order(price, OrderSide::Bid, 5, OrderType::Limit, trading_pair);
+
+        assert_eq!(
+            orderbook.bulk_load(vec![order(dec!(9.50)), order(dec!(-3))]),
+            Err(Failure::OrderRejected(
+                "Price must be greater than zero, not -3".to_string()
+            ))
+        );
+        assert_eq!(
+            orderbook.bulk_load(vec![order(dec!(9.50)), order(dec!(10.3))]),
+            Err(Failure::OrderRejected(
+                "Price 10.3 is not a multiple of the tick size 0.50".to_string()
+            ))
+        );
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn bulk_loading_rejects_the_batch_if_any_order_is_a_duplicate() {
+        let mut orderbook = deduplicating_book();
+        let open = sent_at(dec!(20.00), OrderSide::Bid, 500);
+        let _ = orderbook.place(open);
+
+        let duplicate = sent_at(dec!(20.00), OrderSide::Bid, 500);
+        assert_eq!(
+            orderbook.bulk_load(vec![sent_at(dec!(19.00), OrderSide::Bid, 500), duplicate]),
+            Err(Failure::OrderRejected(format!(
+                "Order {} is a duplicate of the open order {}",
+                duplicate.orderid, open.orderid
+            )))
+        );
+        let (first, second) = (
+            sent_at(dec!(19.00), OrderSide::Bid, 600),
+            sent_at(dec!(19.00), OrderSide::Bid, 600),
+        );
+        assert_eq!(
+            orderbook.bulk_load(vec![first, second]),
+            Err(Failure::OrderRejected(format!(
+                "Order {} is a duplicate of the open order {}",
+                second.orderid, first.orderid
+            )))
+        );
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(orderbook.duplicates.len(), 1);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn bulk_loading_rejects_a_batch_that_would_cross_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let _ = orderbook.place(create_order(
+            dec!(10.00),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        let orders = vec![create_order(
+            dec!(10.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        )];
+
+        assert!(orderbook.bulk_load(orders).is_err());
+        assert_eq!(orderbook.len(), 1);
+    }
+
+    #[test]
+    fn orders_sharing_a_price_make_up_a_single_level() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let prices = [
+            (dec!(10.00), OrderSide::Bid),
+            (dec!(10.0), OrderSide::Bid),
+            (dec!(9.00), OrderSide::Bid),
+            (dec!(11.00), OrderSide::Ask),
+            (dec!(11.00), OrderSide::Ask),
+            (dec!(11.000), OrderSide::Ask),
+        ];
+        for (price, side) in prices {
+            let _ = orderbook.place(create_order(price, side, 5, OrderType::Limit, trading_pair));
+        }
+
+        assert_eq!(orderbook.bid_count(), 3);
+        assert_eq!(orderbook.bid_levels(), 2);
+        assert_eq!(orderbook.ask_count(), 3);
+        assert_eq!(orderbook.ask_levels(), 1);
+    }
+
+    #[test]
+    fn orders_at_distinct_prices_each_make_up_a_level() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        for price in [dec!(10.00), dec!(9.50), dec!(9.00)] {
+            let _ = orderbook.place(create_order(
+                price,
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+
+        assert_eq!(orderbook.bid_levels(), orderbook.bid_count());
+        assert_eq!(orderbook.ask_levels(), 0);
+    }
+
+    #[test]
+    fn resting_quantities_saturate_instead_of_overflowing() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDT);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        for _ in 0..2 {
+            let _ = orderbook.place(create_order(
+                dec!(10.00),
+                OrderSide::Ask,
+                Long::MAX,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+        let bid = create_order(
+            dec!(10.00),
+            OrderSide::Bid,
+            1,
+            OrderType::Limit,
+            trading_pair,
+        );
+
+        assert_eq!(
+            orderbook.volume_at_price(OrderSide::Ask, dec!(10.00)),
+            Long::MAX
+        );
+        assert_eq!(orderbook.crossing_quantity(&bid), Long::MAX);
+        assert_eq!(orderbook.depth(1).1, vec![(dec!(10.00), Long::MAX)]);
+    }
+
+    fn book_with_two_asks() -> (LimitOrderBook, Order, Order) {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let best = create_order(
+            dec!(20.00),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let next = create_order(
+            dec!(21.00),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(next);
+        let _ = orderbook.place(best);
+        (orderbook, best, next)
+    }
+
+    #[test]
+    fn a_crossing_limit_order_would_match_the_best_opposite_order_first() {
+        let (orderbook, best, _) = book_with_two_asks();
+        let next = orderbook.next_match(OrderSide::Bid, dec!(21.00), OrderType::Limit);
+        assert_eq!(next.map(|order| order.orderid), Some(best.orderid));
+        // looking ahead leaves the book as it was
+        assert_eq!(orderbook.ask_count(), 2);
+    }
+
+    #[test]
+    fn a_limit_order_that_does_not_cross_would_match_nothing() {
+        let (orderbook, _, _) = book_with_two_asks();
+        assert!(orderbook
+            .next_match(OrderSide::Bid, dec!(19.99), OrderType::Limit)
+            .is_none());
+        assert!(orderbook
+            .next_match(OrderSide::Ask, dec!(1.00), OrderType::Limit)
+            .is_none());
+    }
+
+    #[test]
+    fn a_market_order_would_always_match_the_top_opposite_order() {
+        let (orderbook, best, _) = book_with_two_asks();
+        let next = orderbook.next_match(OrderSide::Bid, Decimal::ZERO, OrderType::Market);
+        assert_eq!(next.map(|order| order.orderid), Some(best.orderid));
+        assert!(orderbook
+            .next_match(OrderSide::Ask, Decimal::ZERO, OrderType::Market)
+            .is_none());
+    }
+
+    fn pegged_order(side: OrderSide, reference: PegReference, offset: Decimal) -> Order {
+        create_order(
+            Decimal::ZERO,
+            side,
+            5,
+            OrderType::Pegged { reference, offset },
+            TradingPair::from(Asset::ETH, Asset::USDC),
+        )
+    }
+
+    #[test]
+    fn a_pegged_order_follows_its_reference_as_the_top_of_the_book_moves() {
+        let (mut orderbook, best, _) = book_with_two_asks();
+        let peg = pegged_order(OrderSide::Ask, PegReference::BestAsk, dec!(-0.50));
+        assert!(orderbook.place(peg).is_ok());
+        assert_eq!(orderbook.peek_top_ask().unwrap().orderid, peg.orderid);
+        assert_eq!(orderbook.peek_top_ask().unwrap().price, dec!(19.50));
+
+        // a better ask moves the reference, the peg moves with it once repriced
+        let better = create_order(
+            dec!(19.80),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            TradingPair::from(Asset::ETH, Asset::USDC),
+        );
+        let _ = orderbook.place(better);
+        assert_eq!(orderbook.reprice_pegged(), 1);
+        assert_eq!(orderbook.peek_top_ask().unwrap().price, dec!(19.30));
+        assert_eq!(orderbook.reprice_pegged(), 0);
+
+        // and it moves back once that ask is gone
+        let _ = orderbook.cancel(better.orderid);
+        assert_eq!(orderbook.reprice_pegged(), 1);
+        assert_eq!(orderbook.peek_top_ask().unwrap().price, dec!(19.50));
+        assert_eq!(
+            orderbook.peg_price(PegReference::BestAsk, Decimal::ZERO),
+            Some(best.price)
+        );
+    }
+
+    #[test]
+    fn a_pegged_order_is_not_moved_off_the_tick() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                tick_size: Some(dec!(1)),
+                ..Default::default()
+            },
+        );
+        let _ = orderbook.place(create_order(
+            dec!(14),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        let _ = orderbook.place(create_order(
+            dec!(16),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        let peg = pegged_order(OrderSide::Bid, PegReference::Mid, Decimal::ZERO);
+        assert!(orderbook.place(peg).is_ok());
+        assert_eq!(orderbook.orders[&peg.orderid].price, dec!(15));
+
+        // the mid moves to 15.50, which is between two ticks
+        let _ = orderbook.place(create_order(
+            dec!(15),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        assert_eq!(orderbook.reprice_pegged(), 0);
+        assert_eq!(orderbook.orders[&peg.orderid].price, dec!(15));
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn only_the_pegged_orders_resting_in_the_book_are_tracked() {
+        let (mut orderbook, ..) = book_with_two_asks();
+        assert_eq!(orderbook.reprice_pegged(), 0);
+
+        let pegs = [
+            pegged_order(OrderSide::Ask, PegReference::BestAsk, dec!(-0.50)),
+            pegged_order(OrderSide::Ask, PegReference::BestAsk, dec!(-0.25)),
+        ];
+        for peg in pegs {
+            assert!(orderbook.place(peg).is_ok());
+        }
+        assert_eq!(orderbook.pegged, HashSet::from(pegs.map(|peg| peg.orderid)));
+
+        let _ = orderbook.cancel(pegs[0].orderid);
+        let _ = orderbook.pop_top_ask();
+        assert!(orderbook.pegged.is_empty());
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn the_depth_deltas_of_a_watched_book_agree_with_its_snapshots() {
+        let (mut orderbook, best, _) = book_with_two_asks();
+        let peg = pegged_order(OrderSide::Ask, PegReference::BestAsk, dec!(-0.50));
+        let _ = orderbook.place(peg);
+        let before = orderbook.snapshot();
+
+        orderbook.watch_depth();
+        let _ = orderbook.modify_quantity(peg.orderid, 2);
+        let _ = orderbook.cancel(best.orderid);
+        // the best ask moves up, so the peg follows it to a level that had no orders
+        assert_eq!(orderbook.reprice_pegged(), 1);
+        let deltas = orderbook.take_depth_deltas();
+
+        assert_eq!(deltas, before.deltas(&orderbook.snapshot()));
+        assert_eq!(deltas.len(), 3);
+        // the depth is only watched until the deltas are taken
+        let _ = orderbook.pop_top_ask();
+        assert!(orderbook.take_depth_deltas().is_empty());
+    }
+
+    #[test]
+    fn a_pegged_order_is_rejected_when_the_book_has_no_reference_price() {
+        let (mut orderbook, _, _) = book_with_two_asks();
+        let peg = pegged_order(OrderSide::Bid, PegReference::Mid, Decimal::ZERO);
+        assert!(matches!(
+            orderbook.validate(&peg),
+            Some(Failure::OrderRejected(_))
+        ));
+        assert!(orderbook.place(peg).is_err());
+
+        let below_zero = pegged_order(OrderSide::Bid, PegReference::BestAsk, dec!(-20.00));
+        assert!(orderbook.place(below_zero).is_err());
+        assert_eq!(orderbook.bid_count(), 0);
+    }
+
+    #[test]
+    fn the_book_is_displayed_as_a_ladder_with_the_asks_above_the_bids() {
+        let (mut orderbook, _, _) = book_with_two_asks();
+        let _ = orderbook.place(create_order(
+            dec!(19.50),
+            OrderSide::Bid,
+            3,
+            OrderType::Limit,
+            TradingPair::from(Asset::ETH, Asset::USDC),
+        ));
+
+        let ladder = orderbook.to_string();
+        let lines: Vec<Vec<&str>> = ladder
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                vec!["ETH/USDC"],
+                vec!["ASK", "21.00", "5"],
+                vec!["ASK", "20.00", "5"],
+                vec!["----", "spread", "0.50", "----"],
+                vec!["BID", "19.50", "3"],
+            ]
+        );
+    }
+
+    /// Gets a book triggering stops by the given price, with a bid at 19.00, an ask at 21.00,
+    /// and a buy stop at 21.50 placed before the book last traded at 22.00
+    fn book_with_a_stop(trigger: StopTrigger) -> (LimitOrderBook, Order) {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                stop_trigger: trigger,
+                ..Default::default()
+            },
+        );
+        for (price, side) in [(dec!(19.00), OrderSide::Bid), (dec!(21.00), OrderSide::Ask)] {
+            let _ = orderbook.place(create_order(price, side, 5, OrderType::Limit, trading_pair));
+        }
+        let stop = Order {
+            stop_price: Some(dec!(21.50)),
+            ..create_order(dec!(0), OrderSide::Bid, 5, OrderType::Stop, trading_pair)
+        };
+        assert!(orderbook.place(stop).is_ok());
+        orderbook.record_trade(&Trade {
+            orderid: Uuid::new_v4(),
+            account_id: None,
+            tag: None,
+            side: OrderSide::Bid,
+            price: dec!(22.00),
+            status: OrderStatus::Filled,
+            quantity: 1,
+            timestamp: 0,
+            price_improvement: Decimal::ZERO,
+        });
+        (orderbook, stop)
+    }
+
+    #[test]
+    fn a_stop_triggers_by_the_last_trade_but_not_by_the_mid_of_the_same_book() {
+        let (mut orderbook, stop) = book_with_a_stop(StopTrigger::LastTrade);
+        assert_eq!(orderbook.take_triggered_stop(), Some(stop));
+
+        // the mid of 20.00 and the best ask of 21.00 are still short of the stop price
+        for trigger in [StopTrigger::Mid, StopTrigger::BestPrice] {
+            let (mut orderbook, _) = book_with_a_stop(trigger);
+            assert!(orderbook.take_triggered_stop().is_none());
+        }
+    }
+
+    #[test]
+    fn a_stop_triggered_by_the_mid_follows_the_quotes_without_a_trade() {
+        let (mut orderbook, stop) = book_with_a_stop(StopTrigger::Mid);
+        let ask = orderbook.peek_top_ask().unwrap().orderid;
+        assert!(orderbook.cancel(ask).is_ok());
+        let _ = orderbook.place(create_order(
+            dec!(23.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            orderbook.trading_pair,
+        ));
+        // the bid at 23.00 has no ask to trade with, and moves the mid to 21.00
+        assert!(orderbook.take_triggered_stop().is_none());
+
+        let _ = orderbook.place(create_order(
+            dec!(24.00),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            orderbook.trading_pair,
+        ));
+        assert_eq!(orderbook.take_triggered_stop(), Some(stop));
+
+        // and a stop the mid has already reached is rejected
+        let reached = Order {
+            stop_price: Some(dec!(23.50)),
+            ..create_order(
+                dec!(0),
+                OrderSide::Bid,
+                5,
+                OrderType::Stop,
+                orderbook.trading_pair,
+            )
+        };
+        assert_eq!(
+            orderbook.validate(&reached),
+            Some(Failure::OrderRejected(
+                "Stop price 23.50 has already been reached by the mid price 23.50".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn a_pending_stop_order_stays_off_the_top_of_the_book() {
+        let (mut orderbook, best, _) = book_with_two_asks();
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let _ = orderbook.place(create_order(
+            dec!(19.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        let spread = orderbook.get_spread();
+
+        // the stop limit ask is priced below the best ask, so it would be on top if it rested
+        let stops = [
+            Order {
+                stop_price: Some(dec!(25.00)),
+                ..create_order(dec!(0), OrderSide::Bid, 5, OrderType::Stop, trading_pair)
+            },
+            Order {
+                stop_price: Some(dec!(15.00)),
+                ..create_order(
+                    dec!(19.50),
+                    OrderSide::Ask,
+                    5,
+                    OrderType::StopLimit,
+                    trading_pair,
+                )
+            },
+        ];
+        for stop in stops {
+            assert!(orderbook.place(stop).is_ok());
+        }
+
+        assert_eq!(orderbook.peek_top_ask().unwrap().orderid, best.orderid);
+        assert_eq!(orderbook.peek_top_bid().unwrap().price, dec!(19.00));
+        assert_eq!(orderbook.get_spread(), spread);
+        assert_eq!(orderbook.len(), 3);
+    }
+
+    #[test]
+    fn an_empty_orderbook_should_have_no_spread() {
+        let orderbook = LimitOrderBook::init(TradingPair::from(Asset::BTC, Asset::USDT));
+
+        let spread = orderbook.get_spread();
+        assert_eq!(spread, None)
+    }
+
+    #[test]
     fn an_orderbook_with_a_single_bid_or_ask_has_no_spread() {
         let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
 
-        let result = orderbook.place(create_order(
-            dec!(200.02),
+        let result = orderbook.place(create_order(
+            dec!(200.02),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            TradingPair::from(Asset::ETH, Asset::USDC),
+        ));
+
+        let _event = result.unwrap();
+
+        let spread = orderbook.get_spread();
+        assert_eq!(spread, None)
+    }
+
+    #[test]
+    fn the_spread_can_be_gotten_for_a_book_with_both_sides() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+
+        let orders = [
+            create_order(
+                dec!(200.02),
+                OrderSide::Ask,
+                8,
+                OrderType::Limit,
+                TradingPair::from(Asset::ETH, Asset::USDC),
+            ),
+            create_order(
+                dec!(100.02),
+                OrderSide::Bid,
+                8,
+                OrderType::Limit,
+                TradingPair::from(Asset::ETH, Asset::USDC),
+            ),
+        ];
+
+        for order in orders.iter() {
+            let _res = orderbook.place(*order);
+        }
+
+        let spread = orderbook.get_spread().unwrap();
+        assert_eq!(spread, Decimal::from_str("100.00").unwrap());
+    }
+
+    #[test]
+    fn the_spread_and_mid_price_are_rounded_to_the_tick_size() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                tick_size: Some(dec!(0.05)),
+                ..Default::default()
+            },
+        );
+        let _ = orderbook.place(create_order(
+            dec!(100.0),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        let _ = orderbook.place(create_order(
+            dec!(100.15),
+            OrderSide::Ask,
+            8,
+            OrderType::Limit,
+            trading_pair,
+        ));
+
+        assert_eq!(orderbook.get_spread().unwrap().to_string(), "0.15");
+        // the raw mid of 100.075 is exactly halfway between ticks, so it rounds up
+        assert_eq!(orderbook.mid_price().unwrap().to_string(), "100.10");
+    }
+
+    fn deduplicating_book() -> LimitOrderBook {
+        LimitOrderBook::with_config(
+            TradingPair::from(Asset::ETH, Asset::USDC),
+            BookConfig {
+                reject_duplicates: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn sent_at(price: Decimal, side: OrderSide, client_timestamp: u128) -> Order {
+        Order {
+            client_timestamp: Some(client_timestamp),
+            ..create_order(
+                price,
+                side,
+                8,
+                OrderType::Limit,
+                TradingPair::from(Asset::ETH, Asset::USDC),
+            )
+        }
+    }
+
+    #[test]
+    fn a_duplicate_of_an_open_order_is_rejected_when_configured() {
+        let mut orderbook = deduplicating_book();
+        let original = sent_at(dec!(20.00), OrderSide::Bid, 500);
+        assert!(orderbook.place(original).is_ok());
+
+        // the engine receives the second delivery later, and it is rejected all the same
+        let duplicate = Order {
+            orderid: Uuid::new_v4(),
+            timestamp: original.timestamp + 1,
+            ..original
+        };
+        assert_eq!(
+            orderbook.validate(&duplicate),
+            Some(Failure::OrderRejected(format!(
+                "Order {} is a duplicate of the open order {}",
+                duplicate.orderid, original.orderid
+            )))
+        );
+
+        // any difference in the account, price, side or client timestamp is another order
+        let others = [
+            Order {
+                account_id: Some(1),
+                ..duplicate
+            },
+            sent_at(dec!(20.50), OrderSide::Bid, 500),
+            sent_at(dec!(20.00), OrderSide::Ask, 500),
+            sent_at(dec!(20.00), OrderSide::Bid, 501),
+            Order {
+                client_timestamp: None,
+                ..duplicate
+            },
+        ];
+        for other in others {
+            assert_eq!(orderbook.validate(&other), None);
+        }
+
+        // and once the original is gone, the duplicate is just another order
+        let _ = orderbook.cancel(original.orderid);
+        assert_eq!(orderbook.validate(&duplicate), None);
+    }
+
+    #[test]
+    fn what_is_left_of_a_partly_filled_order_is_still_told_apart_from_its_duplicate() {
+        let mut orderbook = deduplicating_book();
+        let _ = orderbook.place(create_order(
+            dec!(20.00),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            TradingPair::from(Asset::ETH, Asset::USDC),
+        ));
+        let bid = sent_at(dec!(20.00), OrderSide::Bid, 500);
+        let matches = Matcher.match_order(bid, &mut orderbook).unwrap();
+        assert_eq!(matches.filled_quantity(), 5);
+        assert_eq!(orderbook.peek_top_bid().unwrap().quantity, 3);
+
+        let duplicate = Order {
+            orderid: Uuid::new_v4(),
+            ..bid
+        };
+        assert!(matches!(
+            orderbook.validate(&duplicate),
+            Some(Failure::OrderRejected(_))
+        ));
+    }
+
+    #[test]
+    fn a_repriced_order_is_told_apart_from_duplicates_at_its_new_price() {
+        let mut orderbook = deduplicating_book();
+        let original = Order {
+            account_id: Some(1),
+            ..sent_at(dec!(20.00), OrderSide::Bid, 500)
+        };
+        assert!(orderbook.place(original).is_ok());
+        assert_eq!(orderbook.reprice(1, OrderSide::Bid, dec!(1.00)), Ok(1));
+
+        let duplicate_at = |price| Order {
+            orderid: Uuid::new_v4(),
+            price,
+            ..original
+        };
+        assert_eq!(orderbook.validate(&duplicate_at(dec!(20.00))), None);
+        assert!(matches!(
+            orderbook.validate(&duplicate_at(dec!(21.00))),
+            Some(Failure::OrderRejected(_))
+        ));
+
+        // once the repriced order is gone, neither price is taken
+        assert!(orderbook.cancel(original.orderid).is_ok());
+        assert_eq!(orderbook.validate(&duplicate_at(dec!(20.00))), None);
+        assert_eq!(orderbook.validate(&duplicate_at(dec!(21.00))), None);
+    }
+
+    #[test]
+    fn duplicates_rest_side_by_side_by_default() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let original = sent_at(dec!(20.00), OrderSide::Ask, 500);
+        let duplicate = Order {
+            orderid: Uuid::new_v4(),
+            ..original
+        };
+
+        assert!(orderbook.place(original).is_ok());
+        assert_eq!(orderbook.validate(&duplicate), None);
+        assert!(orderbook.place(duplicate).is_ok());
+        assert_eq!(orderbook.len(), 2);
+        assert_eq!(orderbook.volume_at_price(OrderSide::Ask, dec!(20.00)), 16);
+    }
+
+    fn account_order(account_id: u64, price: Decimal, side: OrderSide) -> Order {
+        Order {
+            account_id: Some(account_id),
+            ..create_order(
+                price,
+                side,
+                5,
+                OrderType::Limit,
+                TradingPair::from(Asset::ETH, Asset::USDC),
+            )
+        }
+    }
+
+    #[test]
+    fn canceling_an_account_leaves_the_orders_of_other_accounts() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let stop = Order {
+            order_type: OrderType::Stop,
+            stop_price: Some(dec!(25.00)),
+            ..account_order(1, dec!(25.00), OrderSide::Bid)
+        };
+        let orders = [
+            account_order(1, dec!(19.00), OrderSide::Bid),
+            account_order(2, dec!(19.50), OrderSide::Bid),
+            account_order(1, dec!(22.00), OrderSide::Ask),
+            stop,
+            account_order(1, dec!(20.00), OrderSide::Bid),
+            account_order(2, dec!(21.00), OrderSide::Ask),
+        ];
+        for order in orders {
+            let _ = orderbook.place(order);
+        }
+
+        let canceled: Vec<(Uuid, OrderStatus)> = orderbook
+            .cancel_account_orders(1)
+            .iter()
+            .map(|event| (event.orderid, event.status))
+            .collect();
+        assert_eq!(
+            canceled,
+            vec![
+                (orders[4].orderid, OrderStatus::Canceled),
+                (orders[0].orderid, OrderStatus::Canceled),
+                (orders[2].orderid, OrderStatus::Canceled),
+                (stop.orderid, OrderStatus::Canceled),
+            ]
+        );
+        assert_eq!(orderbook.len(), 2);
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, orders[1].orderid);
+        assert_eq!(orderbook.peek_top_ask().unwrap().orderid, orders[5].orderid);
+        assert!(orderbook.stops.is_empty());
+        assert!(orderbook.cancel_account_orders(1).is_empty());
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn orders_that_left_the_book_are_not_canceled_with_their_account() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let filled = account_order(1, dec!(22.00), OrderSide::Ask);
+        let canceled = account_order(1, dec!(23.00), OrderSide::Ask);
+        let _ = orderbook.place(filled);
+        let _ = orderbook.place(canceled);
+        let _ = orderbook.place(create_order(
+            dec!(20.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            TradingPair::from(Asset::ETH, Asset::USDC),
+        ));
+
+        assert_eq!(orderbook.pop_top_ask(), Some(filled));
+        assert!(orderbook.cancel(canceled.orderid).is_ok());
+
+        assert!(orderbook.cancel_account_orders(1).is_empty());
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn repricing_shifts_only_the_accounts_orders_on_that_side() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let orders = [
+            account_order(1, dec!(20.00), OrderSide::Bid),
+            account_order(1, dec!(19.00), OrderSide::Bid),
+            account_order(2, dec!(19.50), OrderSide::Bid),
+            account_order(1, dec!(22.00), OrderSide::Ask),
+        ];
+        for order in orders {
+            let _ = orderbook.place(order);
+        }
+
+        assert_eq!(orderbook.reprice(1, OrderSide::Bid, dec!(0.50)), Ok(2));
+
+        let price_of = |order: &Order| orderbook.orders[&order.orderid].price;
+        assert_eq!(price_of(&orders[0]), dec!(20.50));
+        assert_eq!(price_of(&orders[1]), dec!(19.50));
+        assert_eq!(price_of(&orders[2]), dec!(19.50));
+        assert_eq!(price_of(&orders[3]), dec!(22.00));
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn repriced_orders_lose_their_priority() {
+        let later = Util::current_time_millis() + 1_000;
+        let clock = Arc::new(MockClock::at(later));
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC))
+            .with_clock(clock.clone());
+        let first = account_order(1, dec!(19.00), OrderSide::Bid);
+        let second = account_order(1, dec!(19.50), OrderSide::Bid);
+        let other = account_order(2, dec!(20.00), OrderSide::Bid);
+        for order in [first, second, other] {
+            let _ = orderbook.place(order);
+        }
+
+        assert_eq!(orderbook.reprice(1, OrderSide::Bid, dec!(0.50)), Ok(2));
+
+        // the other account got to 20.00 first, so it stays ahead of the repriced order
+        let top: Vec<Uuid> = orderbook
+            .top_n_bids(3)
+            .iter()
+            .map(|order| order.orderid)
+            .collect();
+        assert_eq!(top, vec![other.orderid, second.orderid, first.orderid]);
+        assert_eq!(orderbook.orders[&second.orderid].timestamp, later);
+    }
+
+    #[test]
+    fn repricing_that_would_cross_the_book_moves_nothing() {
+        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+        let bids = [
+            account_order(1, dec!(20.00), OrderSide::Bid),
+            account_order(1, dec!(19.00), OrderSide::Bid),
+        ];
+        for order in bids {
+            let _ = orderbook.place(order);
+        }
+        let _ = orderbook.place(account_order(2, dec!(21.00), OrderSide::Ask));
+
+        assert!(matches!(
+            orderbook.reprice(1, OrderSide::Bid, dec!(1.00)),
+            Err(Failure::OrderRejected(_))
+        ));
+        assert!(matches!(
+            orderbook.reprice(1, OrderSide::Bid, dec!(-19.00)),
+            Err(Failure::OrderRejected(_))
+        ));
+        assert_eq!(orderbook.orders[&bids[0].orderid].price, dec!(20.00));
+        assert_eq!(orderbook.orders[&bids[1].orderid].price, dec!(19.00));
+    }
+
+    #[test]
+    fn repricing_off_the_tick_or_outside_the_band_moves_nothing() {
+        let mut orderbook = LimitOrderBook::with_config(
+            TradingPair::from(Asset::ETH, Asset::USDC),
+            BookConfig {
+                tick_size: Some(dec!(0.50)),
+                price_band: Some(dec!(10)),
+                ..Default::default()
+            },
+        );
+        let bid = account_order(1, dec!(20.00), OrderSide::Bid);
+        let _ = orderbook.place(bid);
+        orderbook.last_traded_price = Some(dec!(20.00));
+
+        assert_eq!(
+            orderbook.reprice(1, OrderSide::Bid, dec!(0.25)),
+            Err(Failure::OrderRejected(
+                "Price 20.25 is not a multiple of the tick size 0.50".to_string()
+            ))
+        );
+        assert_eq!(
+            orderbook.reprice(1, OrderSide::Bid, dec!(-2.50)),
+            Err(Failure::OrderRejected(
+                "Price 17.50 is outside the 10% band around the last traded price 20.00"
+                    .to_string()
+            ))
+        );
+        assert_eq!(orderbook.orders[&bid.orderid].price, dec!(20.00));
+        assert_eq!(orderbook.reprice(1, OrderSide::Bid, dec!(-1.50)), Ok(1));
+        assert_eq!(orderbook.orders[&bid.orderid].price, dec!(18.50));
+    }
+
+    #[test]
+    fn an_order_priced_off_the_tick_is_rejected() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                tick_size: Some(dec!(0.25)),
+                ..Default::default()
+            },
+        );
+        let order = |price| create_order(price, OrderSide::Bid, 5, OrderType::Limit, trading_pair);
+
+        assert_eq!(orderbook.validate(&order(dec!(20.75))), None);
+        assert_eq!(
+            orderbook.validate(&order(dec!(20.10))),
+            Some(Failure::OrderRejected(
+                "Price 20.10 is not a multiple of the tick size 0.25".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn an_order_priced_beyond_the_quote_precision_is_rejected() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                quote_precision: Some(2),
+                ..Default::default()
+            },
+        );
+        let order = |price| create_order(price, OrderSide::Bid, 5, OrderType::Limit, trading_pair);
+
+        // trailing zeros are not places of their own
+        assert_eq!(orderbook.validate(&order(dec!(20.1))), None);
+        assert_eq!(orderbook.validate(&order(dec!(20.100))), None);
+        assert_eq!(
+            orderbook.validate(&order(dec!(20.125))),
+            Some(Failure::OrderRejected(
+                "Price 20.125 has more than the 2 decimal places of the quote precision"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn replacing_an_order_cancels_it_and_places_the_new_one() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let original = Order {
+            client_order_id: Some(7),
+            ..create_order(
+                dec!(20.00),
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            )
+        };
+        let _ = orderbook.place(original);
+
+        // the new order takes over the client order id of the one it replaces
+        let replacement = Order {
+            client_order_id: Some(7),
+            ..create_order(
+                dec!(20.50),
+                OrderSide::Bid,
+                8,
+                OrderType::Limit,
+                trading_pair,
+            )
+        };
+        let event = orderbook.replace(original.orderid, replacement).unwrap();
+
+        assert_eq!(event.status, OrderStatus::Created);
+        assert_eq!(event.orderid, replacement.orderid);
+        assert_eq!(orderbook.len(), 1);
+        assert_eq!(
+            orderbook.peek_top_bid().unwrap().orderid,
+            replacement.orderid
+        );
+        assert_eq!(orderbook.client_ids.get(&7), Some(&replacement.orderid));
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_rejected_replacement_leaves_the_original_as_it_was() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let original = Order {
+            client_order_id: Some(7),
+            ..create_order(
+                dec!(20.00),
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            )
+        };
+        let behind = create_order(
+            dec!(20.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(original);
+        let _ = orderbook.place(behind);
+
+        let other_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let invalid = create_order(dec!(20.50), OrderSide::Bid, 8, OrderType::Limit, other_pair);
+        assert!(matches!(
+            orderbook.replace(original.orderid, invalid),
+            Err(Failure::InvalidOrderForBook(_))
+        ));
+
+        // the original keeps its place ahead of the order behind it
+        let top: Vec<Uuid> = orderbook
+            .top_n_bids(2)
+            .iter()
+            .map(|order| order.orderid)
+            .collect();
+        assert_eq!(top, vec![original.orderid, behind.orderid]);
+        assert_eq!(orderbook.client_ids.get(&7), Some(&original.orderid));
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_replacement_that_would_cross_the_book_is_rejected() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let original = create_order(
+            dec!(20.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let ask = create_order(
+            dec!(21.00),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(original);
+        let _ = orderbook.place(ask);
+
+        let crossing = create_order(
+            dec!(21.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+        assert!(matches!(
+            orderbook.replace(original.orderid, crossing),
+            Err(Failure::OrderRejected(_))
+        ));
+
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, original.orderid);
+        assert_eq!(orderbook.peek_top_ask().unwrap().orderid, ask.orderid);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_rejected_replacement_leaves_no_trace_in_the_spread_history_or_the_archive() {
+        let mut orderbook = archiving_book(8);
+        let trading_pair = orderbook.trading_pair();
+        let order = |price, side| create_order(price, side, 5, OrderType::Limit, trading_pair);
+        let original = order(dec!(20.00), OrderSide::Bid);
+        let _ = orderbook.place(original);
+        let _ = orderbook.place(order(dec!(21.00), OrderSide::Ask));
+        let history = orderbook.spread_history(8);
+
+        let crossing = order(dec!(21.00), OrderSide::Bid);
+        assert!(orderbook.replace(original.orderid, crossing).is_err());
+        assert_eq!(orderbook.spread_history(8), history);
+        assert_eq!(orderbook.get_order(original.orderid), Some(original));
+
+        // a replacement that is placed archives the original as canceled
+        let replacement = order(dec!(20.50), OrderSide::Bid);
+        assert!(orderbook.replace(original.orderid, replacement).is_ok());
+        assert_eq!(orderbook.get_order(original.orderid).unwrap().quantity, 0);
+    }
+
+    #[test]
+    fn replacing_an_unknown_order_fails() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let order = create_order(
+            dec!(20.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        );
+
+        assert!(matches!(
+            orderbook.replace(Uuid::new_v4(), order),
+            Err(Failure::OrderNotFound(_))
+        ));
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn draining_a_book_returns_every_order_and_leaves_it_empty() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let orders = [
+            Order {
+                client_order_id: Some(1),
+                ..create_order(
+                    dec!(19.00),
+                    OrderSide::Bid,
+                    5,
+                    OrderType::Limit,
+                    trading_pair,
+                )
+            },
+            create_order(
+                dec!(20.00),
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(22.00),
+                OrderSide::Ask,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            create_order(
+                dec!(21.00),
+                OrderSide::Ask,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            Order {
+                stop_price: Some(dec!(23.00)),
+                ..create_order(dec!(0), OrderSide::Bid, 5, OrderType::Stop, trading_pair)
+            },
+        ];
+        for order in orders {
+            assert!(orderbook.place(order).is_ok());
+        }
+
+        let drained: Vec<Uuid> = orderbook
+            .drain()
+            .iter()
+            .map(|order| order.orderid)
+            .collect();
+        let expected: Vec<Uuid> = [1, 0, 3, 2, 4]
+            .iter()
+            .map(|&index| orders[index].orderid)
+            .collect();
+        assert_eq!(drained, expected);
+
+        assert!(orderbook.is_empty());
+        assert!(orderbook.peek_top_bid().is_none());
+        assert!(orderbook.peek_top_ask().is_none());
+        assert!(orderbook.take_triggered_stop().is_none());
+        assert!(orderbook.drain().is_empty());
+        // the client order ids of drained orders are free to be used again
+        assert!(orderbook.place(orders[0]).is_ok());
+    }
+
+    #[test]
+    fn the_top_level_sums_every_order_at_the_best_price() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let orders = [
+            (dec!(20.00), OrderSide::Bid, 5),
+            (dec!(20.0), OrderSide::Bid, 3),
+            (dec!(19.00), OrderSide::Bid, 7),
+            (dec!(21.00), OrderSide::Ask, 4),
+            (dec!(21.00), OrderSide::Ask, 6),
+            (dec!(22.00), OrderSide::Ask, 9),
+        ];
+        for (price, side, quantity) in orders {
+            let _ = orderbook.place(create_order(
+                price,
+                side,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+
+        assert_eq!(orderbook.top_bid_level(), Some((dec!(20.00), 8)));
+        assert_eq!(orderbook.top_ask_level(), Some((dec!(21.00), 10)));
+    }
+
+    #[test]
+    fn the_top_level_of_a_single_order_or_an_empty_side() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        assert_eq!(orderbook.top_bid_level(), None);
+        assert_eq!(orderbook.top_ask_level(), None);
+
+        let _ = orderbook.place(create_order(
+            dec!(20.00),
+            OrderSide::Bid,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        assert_eq!(orderbook.top_bid_level(), Some((dec!(20.00), 5)));
+        assert_eq!(orderbook.top_ask_level(), None);
+    }
+
+    #[test]
+    fn each_order_reports_the_orders_and_quantity_ahead_of_it() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let bids = [3, 5, 2].map(|quantity| {
+            create_order(
+                dec!(20.00),
+                OrderSide::Bid,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            )
+        });
+        for bid in bids {
+            let _ = orderbook.place(bid);
+        }
+        let _ = orderbook.place(create_order(
+            dec!(21.00),
+            OrderSide::Ask,
+            4,
+            OrderType::Limit,
+            trading_pair,
+        ));
+
+        assert_eq!(orderbook.queue_position(bids[0].orderid), Some((0, 0)));
+        assert_eq!(orderbook.queue_position(bids[1].orderid), Some((1, 3)));
+        assert_eq!(orderbook.queue_position(bids[2].orderid), Some((2, 8)));
+
+        // a better priced bid goes ahead of every bid at the lower price
+        let better = create_order(
+            dec!(20.50),
+            OrderSide::Bid,
+            6,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(better);
+        assert_eq!(orderbook.queue_position(better.orderid), Some((0, 0)));
+        assert_eq!(orderbook.queue_position(bids[2].orderid), Some((3, 14)));
+        assert_eq!(orderbook.queue_position(Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn the_spread_and_mid_price_are_not_rounded_without_a_tick_size() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let _ = orderbook.place(create_order(
+            dec!(100.0),
             OrderSide::Bid,
             8,
             OrderType::Limit,
-            TradingPair::from(Asset::ETH, Asset::USDC),
+            trading_pair,
+        ));
+        let _ = orderbook.place(create_order(
+            dec!(100.15),
+            OrderSide::Ask,
+            8,
+            OrderType::Limit,
+            trading_pair,
         ));
 
-        let _event = result.unwrap();
+        assert_eq!(orderbook.get_spread().unwrap(), dec!(0.15));
+        assert_eq!(orderbook.mid_price().unwrap(), dec!(100.075));
+    }
 
-        let spread = orderbook.get_spread();
-        assert_eq!(spread, None)
+    #[test]
+    fn the_spread_is_sampled_each_time_the_top_of_the_book_changes_it() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let clock = Arc::new(MockClock::at(1_000));
+        let mut orderbook = LimitOrderBook::init(trading_pair).with_clock(clock.clone());
+        let order = |price, side| create_order(price, side, 5, OrderType::Limit, trading_pair);
+
+        // no spread while the asks are empty
+        let _ = orderbook.place(order(dec!(99), OrderSide::Bid));
+        clock.advance(10);
+        let _ = orderbook.place(order(dec!(101), OrderSide::Ask));
+        clock.advance(10);
+        let inside = order(dec!(100), OrderSide::Bid);
+        let _ = orderbook.place(inside);
+        clock.advance(10);
+        // a deeper order leaves the top, and so the spread, as it was
+        let _ = orderbook.place(order(dec!(98), OrderSide::Bid));
+        let _ = orderbook.place(order(dec!(101), OrderSide::Ask));
+        clock.advance(10);
+        let _ = orderbook.cancel(inside.orderid);
+
+        assert_eq!(
+            orderbook.spread_history(10),
+            vec![(1_040, dec!(2)), (1_020, dec!(1)), (1_010, dec!(2))]
+        );
+        assert_eq!(orderbook.spread_history(1), vec![(1_040, dec!(2))]);
     }
 
     #[test]
-    fn the_spread_can_be_gotten_for_a_book_with_both_sides() {
-        let mut orderbook = LimitOrderBook::init(TradingPair::from(Asset::ETH, Asset::USDC));
+    fn only_the_configured_number_of_spread_samples_are_kept() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::with_config(
+            trading_pair,
+            BookConfig {
+                spread_history_capacity: 2,
+                ..Default::default()
+            },
+        );
+        let _ = orderbook.place(create_order(
+            dec!(110),
+            OrderSide::Ask,
+            5,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        for price in [dec!(100), dec!(101), dec!(102)] {
+            let _ = orderbook.place(create_order(
+                price,
+                OrderSide::Bid,
+                5,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
 
-        let orders = vec![
+        let spreads: Vec<Decimal> = orderbook
+            .spread_history(10)
+            .into_iter()
+            .map(|(_, spread)| spread)
+            .collect();
+        assert_eq!(spreads, vec![dec!(8), dec!(9)]);
+    }
+
+    #[test]
+    fn the_microprice_leans_towards_the_thinner_side_of_the_top_of_book() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        assert_eq!(orderbook.microprice(), None);
+
+        for (price, side, quantity) in [
+            (dec!(100), OrderSide::Bid, 2),
+            (dec!(100), OrderSide::Bid, 1),
+            (dec!(99), OrderSide::Bid, 50),
+        ] {
+            let _ = orderbook.place(create_order(
+                price,
+                side,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+        assert_eq!(orderbook.microprice(), None);
+
+        let _ = orderbook.place(create_order(
+            dec!(102),
+            OrderSide::Ask,
+            1,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        // (100 * 1 + 102 * 3) / (3 + 1), the deeper level of bids doesn't count
+        assert_eq!(orderbook.microprice().unwrap(), dec!(101.5));
+    }
+
+    #[test]
+    fn the_microprice_is_the_mid_price_when_both_sides_show_the_same_size() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        for (price, side) in [
+            (dec!(100.0), OrderSide::Bid),
+            (dec!(100.15), OrderSide::Ask),
+        ] {
+            let _ = orderbook.place(create_order(price, side, 8, OrderType::Limit, trading_pair));
+        }
+
+        assert_eq!(orderbook.microprice(), orderbook.mid_price());
+        assert_eq!(orderbook.microprice().unwrap(), dec!(100.075));
+    }
+
+    #[test]
+    fn the_microprice_of_a_book_too_large_to_weigh_is_unknown() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let book_of = |quantity| {
+            let mut orderbook = LimitOrderBook::init(trading_pair);
+            for (price, side) in [
+                (dec!(10_000_000_000), OrderSide::Bid),
+                (dec!(20_000_000_000), OrderSide::Ask),
+            ] {
+                let order = create_order(price, side, quantity, OrderType::Limit, trading_pair);
+                assert!(orderbook.place(order).is_ok());
+            }
+            orderbook
+        };
+
+        assert_eq!(
+            book_of(1_000_000_000_000_000_000).microprice(),
+            Some(dec!(15_000_000_000))
+        );
+        assert_eq!(book_of(10_000_000_000_000_000_000).microprice(), None);
+        assert_eq!(book_of(10_000_000_000_000_000_000).weighted_mid(2), None);
+    }
+
+    #[test]
+    fn the_open_notional_sums_the_value_of_the_resting_orders_on_each_side() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        assert_eq!(orderbook.open_notional(None), Decimal::ZERO);
+        let iceberg = Order {
+            display_quantity: Some(1),
+            ..create_order(
+                dec!(19.50),
+                OrderSide::Bid,
+                4,
+                OrderType::Limit,
+                trading_pair,
+            )
+        };
+        let stop = Order {
+            stop_price: Some(dec!(30.00)),
+            ..create_order(
+                dec!(30.00),
+                OrderSide::Bid,
+                10,
+                OrderType::Stop,
+                trading_pair,
+            )
+        };
+        for order in [
             create_order(
-                dec!(200.02),
+                dec!(20.00),
+                OrderSide::Bid,
+                2,
+                OrderType::Limit,
+                trading_pair,
+            ),
+            iceberg,
+            stop,
+            create_order(
+                dec!(21.25),
                 OrderSide::Ask,
-                8,
+                4,
                 OrderType::Limit,
-                TradingPair::from(Asset::ETH, Asset::USDC),
+                trading_pair,
             ),
             create_order(
-                dec!(100.02),
+                dec!(22.00),
+                OrderSide::Ask,
+                1,
+                OrderType::Limit,
+                trading_pair,
+            ),
+        ] {
+            let _ = orderbook.place(order);
+        }
+
+        // 20 * 2 + 19.5 * 4, with all of the iceberg and none of the stop
+        assert_eq!(orderbook.open_notional(Some(OrderSide::Bid)), dec!(118.00));
+        // 21.25 * 4 + 22 * 1
+        assert_eq!(orderbook.open_notional(Some(OrderSide::Ask)), dec!(107.00));
+        assert_eq!(orderbook.open_notional(None), dec!(225.00));
+    }
+
+    #[test]
+    fn the_weighted_mid_over_one_level_is_the_microprice() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        assert_eq!(orderbook.weighted_mid(1), None);
+        for (price, side, quantity) in [
+            (dec!(100), OrderSide::Bid, 3),
+            (dec!(99), OrderSide::Bid, 50),
+            (dec!(102), OrderSide::Ask, 1),
+            (dec!(103), OrderSide::Ask, 7),
+        ] {
+            let _ = orderbook.place(create_order(
+                price,
+                side,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+
+        assert_eq!(orderbook.weighted_mid(1), orderbook.microprice());
+        assert_eq!(orderbook.weighted_mid(0), None);
+    }
+
+    #[test]
+    fn the_ladder_holds_every_level_of_the_book_in_order() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        // the orders arrive out of price order, and several share a level
+        let mut bids = Vec::new();
+        let mut asks = Vec::new();
+        for level in 0..20_i64 {
+            let price = Decimal::new(1_000 - 25 * ((level * 7) % 20), 2);
+            bids.push((price, 1 + level as Long));
+            bids.push((price, 2));
+            let price = Decimal::new(1_100 + 25 * ((level * 3) % 20), 2);
+            asks.push((price, 3));
+        }
+        // the same level, written with a different scale
+        bids.push((dec!(10.0), 5));
+        for (side, orders) in [(OrderSide::Bid, &bids), (OrderSide::Ask, &asks)] {
+            for &(price, quantity) in orders {
+                let _ = orderbook.place(create_order(
+                    price,
+                    side,
+                    quantity,
+                    OrderType::Limit,
+                    trading_pair,
+                ));
+            }
+        }
+
+        let (bid_levels, ask_levels) = orderbook.ladder();
+        assert_eq!((bid_levels.len(), ask_levels.len()), (20, 20));
+        assert!(bid_levels.windows(2).all(|pair| pair[0].0 > pair[1].0));
+        assert!(ask_levels.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(bid_levels[0], (dec!(10.00), 1 + 2 + 5));
+        assert_eq!(bid_levels[19], (dec!(5.25), 18 + 2));
+        assert!(ask_levels.iter().all(|&(_, quantity)| quantity == 3));
+        assert_eq!(
+            bid_levels.iter().map(|level| level.1).sum::<Long>(),
+            bids.iter().map(|order| order.1).sum::<Long>()
+        );
+        assert_eq!((bid_levels, ask_levels), orderbook.depth(usize::MAX));
+    }
+
+    #[test]
+    fn the_weighted_mid_weighs_every_level_within_the_depth() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        for (price, side, quantity) in [
+            (dec!(20), OrderSide::Bid, 2),
+            (dec!(19), OrderSide::Bid, 6),
+            (dec!(18), OrderSide::Bid, 100),
+            (dec!(21), OrderSide::Ask, 3),
+            (dec!(23), OrderSide::Ask, 1),
+        ] {
+            let _ = orderbook.place(create_order(
+                price,
+                side,
+                quantity,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+
+        // the bids average (20 * 2 + 19 * 6) / 8 = 19.25 and the asks (21 * 3 + 23) / 4 = 21.5,
+        // so (19.25 * 4 + 21.5 * 8) / (8 + 4)
+        assert_eq!(orderbook.weighted_mid(2).unwrap(), dec!(20.75));
+        // a side with fewer levels than asked for is weighed over the levels it has
+        // the bids now average 1954 / 108 = 18.0925.., so (18.0925.. * 4 + 21.5 * 108) / (108 + 4)
+        assert_eq!(
+            orderbook.weighted_mid(3).unwrap().round_dp(4),
+            dec!(21.3783)
+        );
+    }
+
+    #[test]
+    fn the_mid_price_is_rounded_to_the_quote_precision_with_the_configured_strategy() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mid_price = |bid: Decimal, ask: Decimal, rounding: RoundingStrategy| {
+            let mut orderbook = LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    quote_precision: Some(2),
+                    rounding: Some(rounding),
+                    ..Default::default()
+                },
+            );
+            let _ = orderbook.place(create_order(
+                bid,
                 OrderSide::Bid,
                 8,
                 OrderType::Limit,
-                TradingPair::from(Asset::ETH, Asset::USDC),
-            ),
+                trading_pair,
+            ));
+            let _ = orderbook.place(create_order(
+                ask,
+                OrderSide::Ask,
+                8,
+                OrderType::Limit,
+                trading_pair,
+            ));
+            orderbook.mid_price().unwrap()
+        };
+
+        // a raw mid of 100.025 sits exactly between two cents
+        let (bid, ask) = (dec!(100.02), dec!(100.03));
+        assert_eq!(mid_price(bid, ask, RoundingStrategy::HalfUp), dec!(100.03));
+        assert_eq!(
+            mid_price(bid, ask, RoundingStrategy::HalfEven),
+            dec!(100.02)
+        );
+        assert_eq!(
+            mid_price(bid, ask, RoundingStrategy::Truncate),
+            dec!(100.02)
+        );
+
+        // a raw mid of 100.0375 is past the midpoint, so only truncation rounds it down
+        let (bid, ask) = (dec!(100.03), dec!(100.045));
+        assert_eq!(mid_price(bid, ask, RoundingStrategy::HalfUp), dec!(100.04));
+        assert_eq!(
+            mid_price(bid, ask, RoundingStrategy::HalfEven),
+            dec!(100.04)
+        );
+        assert_eq!(
+            mid_price(bid, ask, RoundingStrategy::Truncate),
+            dec!(100.03)
+        );
+    }
+
+    #[test]
+    fn the_vwap_is_rounded_to_the_quote_precision_with_the_configured_strategy() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let vwap = |rounding: RoundingStrategy| {
+            let mut orderbook = LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    quote_precision: Some(2),
+                    rounding: Some(rounding),
+                    ..Default::default()
+                },
+            );
+            // a raw average of 100.025 sits exactly between two cents
+            for price in [dec!(100.02), dec!(100.03)] {
+                orderbook.record_trade(&Trade {
+                    orderid: Uuid::new_v4(),
+                    account_id: None,
+                    tag: None,
+                    side: OrderSide::Ask,
+                    price,
+                    status: OrderStatus::Filled,
+                    quantity: 4,
+                    timestamp: 0,
+                    price_improvement: Decimal::ZERO,
+                });
+            }
+            orderbook.vwap().unwrap()
+        };
+
+        assert_eq!(vwap(RoundingStrategy::HalfUp), dec!(100.03));
+        assert_eq!(vwap(RoundingStrategy::HalfEven), dec!(100.02));
+        assert_eq!(vwap(RoundingStrategy::Truncate), dec!(100.02));
+    }
+
+    fn multi_level_book(trading_pair: TradingPair) -> LimitOrderBook {
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+        let levels = [
+            (dec!(101), OrderSide::Ask),
+            (dec!(102), OrderSide::Ask),
+            (dec!(104), OrderSide::Ask),
+            (dec!(99), OrderSide::Bid),
+            (dec!(98), OrderSide::Bid),
+            (dec!(96), OrderSide::Bid),
         ];
+        for (price, side) in levels {
+            let _ = orderbook.place(create_order(
+                price,
+                side,
+                10,
+                OrderType::Limit,
+                trading_pair,
+            ));
+        }
+        orderbook
+    }
 
-        for order in orders.iter() {
-            let _res = orderbook.place(*order);
+    #[test]
+    fn the_fill_estimate_averages_the_prices_walked_through() {
+        let orderbook = multi_level_book(TradingPair::from(Asset::ETH, Asset::USDC));
+
+        assert_eq!(orderbook.estimate_fill(OrderSide::Bid, 10), Some(dec!(101)));
+        // 10 at 101, 10 at 102 and 5 at 104
+        assert_eq!(orderbook.estimate_fill(OrderSide::Bid, 25), Some(dec!(102)));
+        assert_eq!(orderbook.estimate_fill(OrderSide::Ask, 25), Some(dec!(98)));
+        assert_eq!(orderbook.estimate_fill(OrderSide::Ask, 31), None);
+    }
+
+    #[test]
+    fn the_effective_spread_widens_with_size() {
+        let orderbook = multi_level_book(TradingPair::from(Asset::ETH, Asset::USDC));
+
+        assert_eq!(orderbook.effective_spread(10), orderbook.get_spread());
+        assert_eq!(orderbook.effective_spread(10), Some(dec!(2)));
+        assert_eq!(orderbook.effective_spread(20), Some(dec!(3)));
+        assert_eq!(orderbook.effective_spread(25), Some(dec!(4)));
+        assert_eq!(orderbook.effective_spread(31), None);
+    }
+
+    #[test]
+    fn good_till_date_orders_are_purged_once_they_expire() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+
+        let mut expiring = create_order(
+            dec!(100.00),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            trading_pair,
+        );
+        expiring.time_in_force = TimeInForce::GoodTillDate(expiring.timestamp + 1_000);
+        let resting = create_order(
+            dec!(90.00),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = orderbook.place(expiring);
+        let _ = orderbook.place(resting);
+
+        assert!(orderbook.purge_expired(expiring.timestamp).is_empty());
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, expiring.orderid);
+
+        let events = orderbook.purge_expired(expiring.timestamp + 1_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].orderid, expiring.orderid);
+        assert_eq!(events[0].status, OrderStatus::Expired);
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, resting.orderid);
+    }
+
+    #[test]
+    fn good_till_date_stops_never_trigger_once_they_expire_and_are_purged() {
+        let (orderbook, stop) = book_with_a_stop(StopTrigger::LastTrade);
+        let clock = Arc::new(MockClock::at(999));
+        let mut orderbook = orderbook.with_clock(clock.clone());
+        assert!(orderbook.cancel(stop.orderid).is_ok());
+        let stop = Order {
+            orderid: Uuid::new_v4(),
+            time_in_force: TimeInForce::GoodTillDate(1_000),
+            ..stop
+        };
+        assert!(orderbook.place(stop).is_ok());
+
+        // the book last traded at 22.00, past the stop price, but the stop has expired
+        clock.set(1_000);
+        assert!(orderbook.take_triggered_stop().is_none());
+
+        let events = orderbook.purge_expired(1_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].orderid, stop.orderid);
+        assert_eq!(events[0].status, OrderStatus::Expired);
+        assert!(orderbook.pending_stops().is_empty());
+    }
+
+    #[test]
+    fn only_orders_placed_before_the_cutoff_are_canceled() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let mut orderbook = LimitOrderBook::init(trading_pair);
+
+        let orders = [
+            (dec!(100.00), OrderSide::Bid, 3_000),
+            (dec!(101.00), OrderSide::Ask, 1_000),
+            (dec!(99.00), OrderSide::Bid, 2_000),
+            (dec!(102.00), OrderSide::Ask, 5_000),
+        ]
+        .map(|(price, side, timestamp)| {
+            let mut order = create_order(price, side, 8, OrderType::Limit, trading_pair);
+            order.timestamp = timestamp;
+            order
+        });
+        for order in orders {
+            let _ = orderbook.place(order);
         }
 
-        let spread = orderbook.get_spread().unwrap();
-        assert_eq!(spread, Decimal::from_str("-100.00").unwrap());
+        let events = orderbook.cancel_older_than(3_000);
+        let canceled: Vec<_> = events.iter().map(|event| event.orderid).collect();
+        assert_eq!(canceled, vec![orders[1].orderid, orders[2].orderid]);
+        assert!(events
+            .iter()
+            .all(|event| event.status == OrderStatus::Canceled));
+
+        assert_eq!(orderbook.len(), 2);
+        assert_eq!(orderbook.peek_top_bid().unwrap().orderid, orders[0].orderid);
+        assert_eq!(orderbook.peek_top_ask().unwrap().orderid, orders[3].orderid);
+        assert_eq!(orderbook.check_invariants(), Ok(()));
     }
 
     fn create_order(
@@ -297,10 +4535,21 @@ mod test {
     ) -> Order {
         Order {
             orderid: Uuid::new_v4(),
+            client_order_id: None,
+            account_id: None,
+            tag: None,
             price,
             side,
             quantity,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            client_timestamp: None,
+            hidden_quantity: 0,
+            stop_price: None,
             order_type,
+            time_in_force: TimeInForce::GoodTillCancel,
+            seq: 0,
             timestamp: Util::current_time_millis(),
             trading_pair,
         }
@@ -21,16 +21,33 @@ pub trait OrderQueue<T: KeyIndx> {
     /// Removes the item at the head of the queue
     fn pop(&mut self) -> Option<T>;
 
-    /// Removes the specified item from the queue. This operation rebalances the queue
+    /// Removes the specified item from the queue. This operation rebalances the queue, unless
+    /// the item isn't queued in which case nothing is done and None is returned
     fn remove(&mut self, item: T) -> Option<T>;
+
+    /// Gets the number of items in the queue
+    fn len(&self) -> usize;
+
+    /// Checks if the queue has no items
+    fn is_empty(&self) -> bool;
 }
 
 /// Simple implemenatation of the order queue. Uses a binary heap as a priority queue
 /// Orders are prioritized by time and price
+#[derive(Clone)]
 pub struct PriceTimePriorityOrderQueue<T> {
     heap: BinaryHeap<T>,
 }
 
+impl<T> Default for PriceTimePriorityOrderQueue<T>
+where
+    T: KeyIndx,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> PriceTimePriorityOrderQueue<T>
 where
     T: KeyIndx,
@@ -45,6 +62,19 @@ where
             heap: BinaryHeap::with_capacity(capacity),
         }
     }
+
+    /// Adds every item to the queue at once, rebuilding the heap in a single pass rather than
+    /// sifting each item in on its own
+    pub fn extend(&mut self, items: impl IntoIterator<Item = T>) {
+        let mut keys = std::mem::take(&mut self.heap).into_vec();
+        keys.extend(items);
+        self.heap = BinaryHeap::from(keys);
+    }
+
+    /// Iterates over the items in the queue, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter()
+    }
 }
 
 impl<T> OrderQueue<T> for PriceTimePriorityOrderQueue<T>
@@ -64,22 +94,33 @@ where
     }
 
     fn remove(&mut self, item: T) -> Option<T> {
+        if !self.heap.iter().any(|k| *k == item) {
+            return None;
+        }
         // unfortunately this is the most efficient way to do this using a binary heap
         // rebuilding the binary heap everytime a removal occurs can be costly for large N.
         // For the time being i'll leave this implementation while i research alternative
         // representations
-        let mut key_vec = self.heap.to_owned().into_vec();
+        let mut key_vec = std::mem::take(&mut self.heap).into_vec();
         key_vec.retain(|k| *k != item);
         self.heap = key_vec.into();
         Some(item)
     }
+
+    fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::core::{
         model::{Order, OrderKey, TradingPair},
-        types::{Asset, Long, OrderSide, OrderType, TimestampMillis},
+        types::{Asset, Long, OrderSide, OrderType, TimeInForce, TimestampMillis},
     };
     use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
@@ -91,7 +132,7 @@ mod test {
     fn can_get_an_inserted_order_back_from_queue() {
         let mut pq: PriceTimePriorityOrderQueue<OrderKey> = PriceTimePriorityOrderQueue::new();
 
-        let orders = vec![
+        let orders = [
             create_order(
                 dec!(200.02),
                 OrderSide::Bid,
@@ -124,7 +165,7 @@ mod test {
     fn orders_at_the_same_price_are_prioritized_by_time() {
         let mut pq: PriceTimePriorityOrderQueue<OrderKey> = PriceTimePriorityOrderQueue::new();
 
-        let orders = vec![
+        let orders = [
             create_order(
                 dec!(200.02),
                 OrderSide::Bid,
@@ -169,6 +210,33 @@ mod test {
         assert_eq!(None, pq.peek());
     }
 
+    #[test]
+    fn removing_an_order_that_is_not_queued_leaves_the_queue_untouched() {
+        let mut pq: PriceTimePriorityOrderQueue<OrderKey> = PriceTimePriorityOrderQueue::new();
+
+        let queued = create_order(
+            dec!(200.02),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            TradingPair::from(Asset::DOT, Asset::USDT),
+            1678170180000,
+        );
+        let missing = create_order(
+            dec!(300.02),
+            OrderSide::Bid,
+            8,
+            OrderType::Limit,
+            TradingPair::from(Asset::DOT, Asset::USDT),
+            1678170180000,
+        );
+
+        pq.push(queued.to_key());
+        assert_eq!(None, pq.remove(missing.to_key()));
+        assert_eq!(1, pq.len());
+        assert_eq!(queued.to_key(), *pq.peek().unwrap());
+    }
+
     #[test]
     fn orders_can_be_poped_from_queue_when_needed() {
         let mut pq: PriceTimePriorityOrderQueue<OrderKey> = PriceTimePriorityOrderQueue::new();
@@ -196,10 +264,21 @@ mod test {
     ) -> Order {
         Order {
             orderid: Uuid::new_v4(),
+            client_order_id: None,
+            account_id: None,
+            tag: None,
             price,
             side,
             quantity,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            client_timestamp: None,
+            hidden_quantity: 0,
+            stop_price: None,
             order_type,
+            time_in_force: TimeInForce::GoodTillCancel,
+            seq: 0,
             timestamp,
             trading_pair,
         }
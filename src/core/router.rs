@@ -1,37 +1,304 @@
-use std::{collections::HashMap, convert, sync::Mutex};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert,
+    fmt::{self, Debug},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError, TryLockError,
+    },
+    time::Duration,
+};
 
+use log::info;
 use rust_decimal::Decimal;
 use uuid::Uuid;
 
 use super::{
-    matcher::Matcher,
-    model::{Order, TradingPair},
-    orderbook::OrderBook,
-    types::{Failure, Long, OrderId, OrderSide, OrderType},
-    utils::Util,
+    handlers::{
+        book_not_found, CancelByClientIdHandler, CancelHandler, CancelLevelHandler, GetBookHandler,
+        PlaceLinkedHandler, PlaceOrderHandler, PlaceQuoteHandler, RequestHandler, SummaryHandler,
+    },
+    intake::{IntakeGuard, IntakeLock},
+    matcher::{Match, MatchState, Matcher},
+    model::{BookSnapshot, BookSummary, EngineEvent, Event, LogVerbosity, Order, TradingPair},
+    orderbook::{LimitOrderBook, OrderBook},
+    shards::{self, Shards},
+    types::{
+        AccountId, Asset, ClientOrderId, Failure, Long, OrderId, OrderSide, OrderStatus, OrderTag,
+        OrderType, ShardPolicy, TieBreak, TimeInForce, TimestampMillis, Trade,
+    },
+    utils::{Clock, SystemClock},
 };
 
+// quotes and linked orders carry two orders, boxing them would only add an allocation to each
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum Request {
     PlaceOrder(PlaceOrder),
+    /// places a bid and an ask for the same account together, or neither of them if either is
+    /// rejected. The bid has to be priced below the ask, so the two can never match each other
+    PlaceQuote {
+        bid: PlaceOrder,
+        ask: PlaceOrder,
+        account_id: AccountId,
+    },
+    /// places two orders in the books of different pairs together, or neither of them if either
+    /// is rejected, eg for the two legs of a spread. Neither can be a market order, which could
+    /// find nothing to match once the other has been placed
+    PlaceLinked {
+        first: PlaceOrder,
+        second: PlaceOrder,
+    },
     Cancel(CancelOrder),
+    /// cancels an order using the id the client placed it with
+    CancelByClientId {
+        client_order_id: ClientOrderId,
+        trading_pair: TradingPair,
+    },
+    /// cancels every order resting at a price on one side of a book
+    CancelLevel {
+        trading_pair: TradingPair,
+        side: OrderSide,
+        price: Decimal,
+    },
+    /// gets every price level of a book
+    GetBook {
+        trading_pair: TradingPair,
+    },
+    /// gets the best prices, spread, mid price, volume and order count of each side of a book
+    Summary {
+        trading_pair: TradingPair,
+    },
+    /// a kind of request that is handled by a handler registered with the router under the
+    /// same kind, see [Router::with_handler]
+    Custom {
+        kind: String,
+        trading_pair: TradingPair,
+    },
+}
+
+/// What the router answers a request with when it succeeds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// the request was carried out, or held until the router resumes
+    Accepted,
+    /// the price levels of the requested book
+    Book(BookSnapshot),
+    /// the state of the requested book at a glance, which prints as a few lines of text
+    Summary(BookSummary),
+    /// the id the engine gave the requested order, which it can be canceled by, with the events
+    /// of every order placing it touched, in order
+    Placed {
+        orderid: OrderId,
+        events: Vec<Event>,
+    },
+    /// the ids the engine gave both sides of the requested quote, with the events of every
+    /// order placing them touched, in order
+    Quoted {
+        bid: OrderId,
+        ask: OrderId,
+        events: Vec<Event>,
+    },
+    /// the ids the engine gave both of the requested linked orders, with the events of every
+    /// order placing them touched, in order
+    Linked {
+        first: OrderId,
+        second: OrderId,
+        events: Vec<Event>,
+    },
+    /// the ids of the orders that were canceled, in the order they would have been matched
+    Canceled(Vec<OrderId>),
+}
+
+/// What came of running an order through the matcher
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Execution {
+    /// the trades made, including those of any stop orders the order triggered
+    pub trades: Vec<Trade>,
+    /// the lifecycle events of every order that was touched, in the order they happened
+    pub events: Vec<Event>,
+    /// the trades in which an order matched another order of its own account, from the side
+    /// of the resting order
+    pub wash_trades: Vec<Trade>,
+}
+
+impl Execution {
+    fn add_match(&mut self, matches: &Match<Trade>) {
+        self.trades.extend(matches.get_matches());
+        self.events.extend(matches.get_events());
+        self.wash_trades.extend(matches.get_wash_trades());
+    }
+
+    fn extend(&mut self, other: Execution) {
+        self.trades.extend(other.trades);
+        self.events.extend(other.events);
+        self.wash_trades.extend(other.wash_trades);
+    }
 }
 
 impl Request {
-    fn validate(&self) -> Option<Failure> {
+    pub const PLACE_ORDER: &'static str = "place_order";
+    pub const PLACE_QUOTE: &'static str = "place_quote";
+    pub const PLACE_LINKED: &'static str = "place_linked";
+    pub const CANCEL: &'static str = "cancel";
+    pub const CANCEL_BY_CLIENT_ID: &'static str = "cancel_by_client_id";
+    pub const CANCEL_LEVEL: &'static str = "cancel_level";
+    pub const GET_BOOK: &'static str = "get_book";
+    pub const SUMMARY: &'static str = "summary";
+
+    /// Gets the kind of the request, which selects the handler the router passes it to
+    pub fn kind(&self) -> &str {
+        match self {
+            Request::PlaceOrder(_) => Self::PLACE_ORDER,
+            Request::PlaceQuote { .. } => Self::PLACE_QUOTE,
+            Request::PlaceLinked { .. } => Self::PLACE_LINKED,
+            Request::Cancel(_) => Self::CANCEL,
+            Request::CancelByClientId { .. } => Self::CANCEL_BY_CLIENT_ID,
+            Request::CancelLevel { .. } => Self::CANCEL_LEVEL,
+            Request::GetBook { .. } => Self::GET_BOOK,
+            Request::Summary { .. } => Self::SUMMARY,
+            Request::Custom { kind, .. } => kind,
+        }
+    }
+
+    /// Gets the pair the request was made for. An order for the inverse of a pair is placed in
+    /// the book of that pair
+    pub fn trading_pair(&self) -> TradingPair {
+        match self {
+            Request::PlaceOrder(p) => p.trading_pair,
+            Request::PlaceQuote { bid, .. } => bid.trading_pair,
+            Request::PlaceLinked { first, .. } => first.trading_pair,
+            Request::Cancel(c) => c.trading_pair,
+            Request::CancelByClientId { trading_pair, .. }
+            | Request::CancelLevel { trading_pair, .. }
+            | Request::GetBook { trading_pair }
+            | Request::Summary { trading_pair }
+            | Request::Custom { trading_pair, .. } => *trading_pair,
+        }
+    }
+
+    /// Gets the event each order the request places is reported with when the request is
+    /// rejected, with the client order id it was sent with. Rejected orders are never given an
+    /// id, so the events carry the nil id
+    fn rejected_events(&self) -> Vec<(TradingPair, Option<ClientOrderId>, Event)> {
+        let orders = match self {
+            Request::PlaceOrder(p) => vec![p],
+            Request::PlaceQuote { bid, ask, .. } => vec![bid, ask],
+            Request::PlaceLinked { first, second } => vec![first, second],
+            _ => Vec::new(),
+        };
+        orders
+            .into_iter()
+            .map(|p| {
+                let event = Event {
+                    status: OrderStatus::Rejected,
+                    orderid: OrderId::nil(),
+                    // market orders take the prices of the book, so they have none of their own
+                    at_price: (p.order_type != OrderType::Market).then_some(p.price),
+                    quantity: Some(p.quantity),
+                    tag: p.tag,
+                };
+                (p.trading_pair, p.client_order_id, event)
+            })
+            .collect()
+    }
+
+    /// Gets every pair the request was made for, which is the pairs of both orders when they are
+    /// linked
+    fn trading_pairs(&self) -> Vec<TradingPair> {
+        match self {
+            Request::PlaceLinked { first, second } => vec![first.trading_pair, second.trading_pair],
+            _ => vec![self.trading_pair()],
+        }
+    }
+
+    fn validate(&self, now: TimestampMillis, config: &RouterConfig) -> Option<Failure> {
         match self {
-            Request::PlaceOrder(p) => p.validate(),
+            Request::PlaceOrder(p) => p.validate(now, config),
+            Request::PlaceQuote { bid, ask, .. } => Self::validate_quote(bid, ask, now, config),
+            Request::PlaceLinked { first, second } => {
+                Self::validate_linked(first, second, now, config)
+            }
             Request::Cancel(c) => c.trading_pair.validate(),
+            Request::CancelByClientId { trading_pair, .. }
+            | Request::CancelLevel { trading_pair, .. } => trading_pair.validate(),
+            Request::GetBook { trading_pair }
+            | Request::Summary { trading_pair }
+            | Request::Custom { trading_pair, .. } => trading_pair.validate(),
         }
     }
+
+    fn validate_quote(
+        bid: &PlaceOrder,
+        ask: &PlaceOrder,
+        now: TimestampMillis,
+        config: &RouterConfig,
+    ) -> Option<Failure> {
+        if bid.side != OrderSide::Bid || ask.side != OrderSide::Ask {
+            return Some(Failure::OrderRejected(
+                "A quote must be made of a bid and an ask".to_string(),
+            ));
+        }
+        if bid.order_type != OrderType::Limit || ask.order_type != OrderType::Limit {
+            return Some(Failure::OrderRejected(
+                "Both sides of a quote must be limit orders".to_string(),
+            ));
+        }
+        if bid.trading_pair != ask.trading_pair {
+            return Some(Failure::OrderRejected(
+                "Both sides of a quote must be for the same trading pair".to_string(),
+            ));
+        }
+        if bid.price >= ask.price {
+            return Some(Failure::OrderRejected(format!(
+                "Quote is crossed, the bid at {} must be below the ask at {}",
+                bid.price, ask.price
+            )));
+        }
+        bid.validate(now, config)
+            .or_else(|| ask.validate(now, config))
+    }
+
+    fn validate_linked(
+        first: &PlaceOrder,
+        second: &PlaceOrder,
+        now: TimestampMillis,
+        config: &RouterConfig,
+    ) -> Option<Failure> {
+        if first.order_type == OrderType::Market || second.order_type == OrderType::Market {
+            return Some(Failure::OrderRejected(
+                "Linked orders can't be market orders".to_string(),
+            ));
+        }
+        if first.trading_pair == second.trading_pair {
+            return Some(Failure::OrderRejected(
+                "Linked orders must be for different trading pairs".to_string(),
+            ));
+        }
+        first
+            .validate(now, config)
+            .or_else(|| second.validate(now, config))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct PlaceOrder {
+    client_order_id: Option<ClientOrderId>,
+    account_id: Option<AccountId>,
+    tag: Option<OrderTag>,
     price: Decimal,
     quantity: Long,
+    notional: Option<Decimal>,
+    display_quantity: Option<Long>,
+    min_fill: Option<Long>,
+    stop_price: Option<Decimal>,
     side: OrderSide,
     order_type: OrderType,
+    time_in_force: TimeInForce,
+    /// how long the order is good for from when it is received, see [PlaceOrder::with_ttl_millis]
+    ttl_millis: Option<u64>,
+    /// when the client stamped the order, see [PlaceOrder::with_client_timestamp]
+    client_timestamp: Option<TimestampMillis>,
     trading_pair: TradingPair,
 }
 
@@ -44,39 +311,266 @@ impl PlaceOrder {
         trading_pair: TradingPair,
     ) -> Self {
         Self {
+            client_order_id: None,
+            account_id: None,
+            tag: None,
             price,
             quantity,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            stop_price: None,
             side,
             order_type,
+            time_in_force: TimeInForce::default(),
+            ttl_millis: None,
+            client_timestamp: None,
+            trading_pair,
+        }
+    }
+
+    /// Creates a market order for the given quantity. Market orders trade at the prices of the
+    /// orders they match, so they are given no price of their own
+    pub fn market(quantity: Long, side: OrderSide, trading_pair: TradingPair) -> Self {
+        Self::from(
+            Decimal::ZERO,
+            quantity,
+            side,
+            OrderType::Market,
             trading_pair,
+        )
+    }
+
+    /// Creates a market order that spends up to the given notional (price times quantity)
+    /// instead of filling a set quantity, eg buying 100 USDC worth of BTC. Only whole units are
+    /// filled, so the amount actually spent can fall just short of the notional
+    pub fn from_notional(notional: Decimal, side: OrderSide, trading_pair: TradingPair) -> Self {
+        Self {
+            notional: Some(notional),
+            ..Self::market(0, side, trading_pair)
         }
     }
 
-    pub fn to_order(&self) -> Order {
+    /// Sets the execution policy of the order. Orders are good till canceled by default
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    /// Turns the order into an iceberg order, which only ever shows the given quantity on the
+    /// book. The rest of the order is hidden and used to replenish the shown slice as it fills
+    pub fn with_display_quantity(mut self, display_quantity: Long) -> Self {
+        self.display_quantity = Some(display_quantity);
+        self
+    }
+
+    /// Keeps the order from trading unless at least the given quantity can be filled as soon as
+    /// it arrives. Otherwise it rests without trading, or is canceled if it can't rest or its
+    /// price crosses the book. Once resting, it trades like any other order
+    pub fn with_min_fill(mut self, min_fill: Long) -> Self {
+        self.min_fill = Some(min_fill);
+        self
+    }
+
+    /// Tags the order with an id of the client's choosing, which it can later be canceled by
+    pub fn with_client_order_id(mut self, client_order_id: ClientOrderId) -> Self {
+        self.client_order_id = Some(client_order_id);
+        self
+    }
+
+    /// Makes the order good for the given number of milliseconds from when the router receives
+    /// it, after which it expires like a good till date order
+    pub fn with_ttl_millis(mut self, ttl_millis: u64) -> Self {
+        self.ttl_millis = Some(ttl_millis);
+        self
+    }
+
+    /// Tags the order with an opaque value of the integrator's choosing, eg the strategy that
+    /// placed it. The engine passes it on to the trades and events of the order untouched
+    pub fn with_tag(mut self, tag: OrderTag) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Tags the order with the account it is placed by
+    pub fn with_account_id(mut self, account_id: AccountId) -> Self {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    /// Sets the price the market has to trade at for a stop or stop limit order to trigger
+    pub fn with_stop_price(mut self, stop_price: Decimal) -> Self {
+        self.stop_price = Some(stop_price);
+        self
+    }
+
+    /// Stamps the order with the time the client or feed sent it at, which a pair that rejects
+    /// duplicates tells a delivered twice order by, see
+    /// [EngineConfig::with_duplicate_rejection](crate::EngineConfig::with_duplicate_rejection)
+    pub fn with_client_timestamp(mut self, client_timestamp: TimestampMillis) -> Self {
+        self.client_timestamp = Some(client_timestamp);
+        self
+    }
+
+    /// Creates the order to be matched, stamped with the time it was received at. The price of
+    /// a market order is ignored, and the order is created without one
+    pub fn to_order(&self, timestamp: TimestampMillis) -> Order {
         Order {
             orderid: Uuid::new_v4(),
-            price: self.price,
+            client_order_id: self.client_order_id,
+            account_id: self.account_id,
+            tag: self.tag,
+            price: match self.order_type {
+                OrderType::Market => Decimal::ZERO,
+                _ => self.price,
+            },
             quantity: self.quantity,
+            notional: self.notional,
+            display_quantity: self.display_quantity,
+            min_fill: self.min_fill,
+            hidden_quantity: 0,
+            stop_price: self.stop_price,
             side: self.side,
             order_type: self.order_type,
+            time_in_force: match self.ttl_millis {
+                Some(ttl) => TimeInForce::GoodTillDate(timestamp.saturating_add(ttl.into())),
+                None => self.time_in_force,
+            },
             trading_pair: self.trading_pair,
-            timestamp: Util::current_time_millis(),
+            timestamp,
+            client_timestamp: self.client_timestamp,
+            seq: 0,
         }
     }
-    pub fn validate(&self) -> Option<Failure> {
-        if self.quantity <= 0 {
+    /// Checks the order is well formed as of the given time, and within the limits the router
+    /// is configured with
+    pub fn validate(&self, now: TimestampMillis, config: &RouterConfig) -> Option<Failure> {
+        match self.notional {
+            Some(_) if self.order_type != OrderType::Market => {
+                return Some(Failure::OrderRejected(
+                    "Only market orders can have a notional".to_string(),
+                ));
+            }
+            Some(notional) if notional <= Decimal::ZERO => {
+                return Some(Failure::OrderRejected(
+                    "Notional must be greater than zero".to_string(),
+                ));
+            }
+            Some(_) if self.time_in_force == TimeInForce::FillOrKill => {
+                return Some(Failure::OrderRejected(
+                    "Fill or kill orders must have a quantity".to_string(),
+                ));
+            }
+            Some(_) => (),
+            None if self.quantity == 0 => {
+                return Some(Failure::OrderRejected(
+                    "Quantity must be greater than zero".to_string(),
+                ));
+            }
+            None => (),
+        }
+        if self.order_type == OrderType::Market && self.price < Decimal::ZERO {
+            return Some(Failure::OrderRejected(
+                "Market orders trade at the prices of the book, so cannot have a negative price"
+                    .to_string(),
+            ));
+        }
+        // only limit prices are traded at, the book prices the other kinds of order
+        let has_limit = matches!(self.order_type, OrderType::Limit | OrderType::StopLimit);
+        if has_limit && self.price <= Decimal::ZERO {
+            return Some(Failure::OrderRejected(format!(
+                "Price must be greater than zero, not {}",
+                self.price
+            )));
+        }
+        match self.stop_price {
+            Some(stop_price) if stop_price <= Decimal::ZERO => {
+                return Some(Failure::OrderRejected(format!(
+                    "Stop price must be greater than zero, not {}",
+                    stop_price
+                )));
+            }
+            _ => (),
+        }
+        match config.max_order_quantity {
+            Some(max) if self.quantity > max => {
+                return Some(Failure::OrderRejected(format!(
+                    "Quantity {} exceeds the maximum of {} per order",
+                    self.quantity, max
+                )));
+            }
+            _ => (),
+        }
+        let is_stop = matches!(self.order_type, OrderType::Stop | OrderType::StopLimit);
+        if is_stop && self.stop_price.is_none() {
+            return Some(Failure::OrderRejected(
+                "Stop orders must have a stop price".to_string(),
+            ));
+        }
+        if !is_stop && self.stop_price.is_some() {
             return Some(Failure::OrderRejected(
-                "Quantity must be greater than zero".to_string(),
+                "Only stop orders can have a stop price".to_string(),
             ));
         }
-        return self.trading_pair.validate();
+        if let Some(display_quantity) = self.display_quantity {
+            if self.order_type != OrderType::Limit {
+                return Some(Failure::OrderRejected(
+                    "Only limit orders can have a display quantity".to_string(),
+                ));
+            }
+            if display_quantity == 0 {
+                return Some(Failure::OrderRejected(
+                    "Display quantity must be greater than zero".to_string(),
+                ));
+            }
+        }
+        match self.min_fill {
+            Some(0) => {
+                return Some(Failure::OrderRejected(
+                    "Minimum fill must be greater than zero".to_string(),
+                ));
+            }
+            Some(_) if self.notional.is_some() => {
+                return Some(Failure::OrderRejected(
+                    "Orders placed by notional cannot have a minimum fill".to_string(),
+                ));
+            }
+            Some(min_fill) if min_fill > self.quantity => {
+                return Some(Failure::OrderRejected(format!(
+                    "Minimum fill {} exceeds the quantity {} of the order",
+                    min_fill, self.quantity
+                )));
+            }
+            _ => (),
+        }
+        if let TimeInForce::GoodTillDate(expires_at) = self.time_in_force {
+            if expires_at <= now {
+                return Some(Failure::OrderRejected(
+                    "Good till date orders must expire in the future".to_string(),
+                ));
+            }
+        }
+        match self.ttl_millis {
+            Some(0) => {
+                return Some(Failure::OrderRejected(
+                    "Time to live must be greater than zero".to_string(),
+                ));
+            }
+            Some(_) if self.time_in_force != TimeInForce::GoodTillCancel => {
+                return Some(Failure::OrderRejected(
+                    "Only good till cancel orders can have a time to live".to_string(),
+                ));
+            }
+            _ => (),
+        }
+        self.trading_pair.validate()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CancelOrder {
-    orderid: OrderId,
-    trading_pair: TradingPair,
+    pub(crate) orderid: OrderId,
+    pub(crate) trading_pair: TradingPair,
 }
 
 impl CancelOrder {
@@ -88,145 +582,3953 @@ impl CancelOrder {
     }
 }
 
+/// The number of terminal orders the router remembers, unless configured otherwise
+const DEFAULT_HISTORY_CAPACITY: usize = 1024;
+/// The number of wash trades the router remembers, unless configured otherwise
+const DEFAULT_WASH_TRADE_CAPACITY: usize = 1024;
+/// How long a request waits in the intake for the books, unless configured otherwise
+const DEFAULT_INTAKE_TIMEOUT_MILLIS: u64 = 100;
+
+fn poisoned_books() -> Failure {
+    Failure::Internal(
+        "The books were left poisoned by a request that failed part way through".to_string(),
+    )
+}
+
+/// Options that change how the router resolves requests to books
+#[derive(Debug, Clone, Copy)]
+pub struct RouterConfig {
+    /// when set, an order for a pair with no book of its own is routed to the book of the
+    /// inverse pair (eg USDC/BTC to BTC/USDC), with its side and price inverted
+    pub allow_inverse_pairs: bool,
+    /// the order state transitions that are written to the log
+    pub verbosity: LogVerbosity,
+    /// when set, orders placed while the router is paused are held and processed on resume,
+    /// instead of being rejected
+    pub queue_while_paused: bool,
+    /// when set, orders for a larger quantity than this are rejected
+    pub max_order_quantity: Option<Long>,
+    /// the number of orders that reached a terminal state the router remembers, the oldest
+    /// being forgotten first. No history is kept when this is zero
+    pub history_capacity: usize,
+    /// the number of wash trades the router remembers, the oldest being forgotten first. No
+    /// wash trades are kept when this is zero
+    pub wash_trade_capacity: usize,
+    /// when set, orders are rested on their book without being matched, and books are only
+    /// matched when the router ticks, see [Router::tick]
+    pub match_in_batches: bool,
+    /// which of two crossing orders stamped with the same time has priority when a book is
+    /// matched on a tick
+    pub tie_break: TieBreak,
+    /// when set, the number of requests that can wait for the books while another request has
+    /// them. A request that finds the books busy fails straight away when it isn't set, and
+    /// when every place in the intake is taken
+    pub intake_capacity: Option<usize>,
+    /// the longest a request waits in the intake for the books, in milliseconds, before it
+    /// fails as over capacity
+    pub intake_timeout_millis: u64,
+    /// when set, every order of a rejected request is reported as an [EngineEvent::Rejected]
+    /// with the reason, as well as the request failing
+    pub report_rejections: bool,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            allow_inverse_pairs: false,
+            verbosity: LogVerbosity::default(),
+            queue_while_paused: false,
+            max_order_quantity: None,
+            history_capacity: DEFAULT_HISTORY_CAPACITY,
+            wash_trade_capacity: DEFAULT_WASH_TRADE_CAPACITY,
+            match_in_batches: false,
+            tie_break: TieBreak::default(),
+            intake_capacity: None,
+            intake_timeout_millis: DEFAULT_INTAKE_TIMEOUT_MILLIS,
+            report_rejections: false,
+        }
+    }
+}
+
+/// The log target that engine events are written to
+pub const EVENT_LOG_TARGET: &str = "octomatch::events";
+
+/// Receives the [EngineEvent]s of a [Router] as they are reported, see [Router::subscribe].
+/// Every leg of a trade is reported as its own [EngineEvent::Matched], so an order that fills
+/// over several trades is seen filling bit by bit. Subscribers are called with the books
+/// locked, so they should hand events off rather than do slow work. Any function taking an
+/// event is a subscriber
+pub trait Subscriber: Send + Sync {
+    fn notify(&self, event: &EngineEvent);
+}
+
+impl<F> Subscriber for F
+where
+    F: Fn(&EngineEvent) + Send + Sync,
+{
+    fn notify(&self, event: &EngineEvent) {
+        self(event)
+    }
+}
+
 /// The router interface is responsible for handling different request types and routing an
 /// order to the appropriate order book, for matching
-#[derive(Debug)]
 pub struct Router<T> {
-    books: Mutex<HashMap<TradingPair, T>>,
+    books: IntakeLock<HashMap<TradingPair, T>>,
     matcher: Matcher,
+    config: RouterConfig,
+    clock: Arc<dyn Clock>,
+    paused: AtomicBool,
+    /// whether new orders are rejected outright, see [Router::engage_kill_switch]
+    kill_switch: AtomicBool,
+    /// the number of requests waiting in the intake for the books, see
+    /// [RouterConfig::intake_capacity]
+    waiting: AtomicUsize,
+    /// orders placed while paused, when the router is configured to queue them
+    held: Mutex<Vec<Request>>,
+    /// the handler of each kind of request, keyed by [Request::kind]
+    handlers: HashMap<String, Box<dyn RequestHandler<T>>>,
+    /// every trade in which an account matched its own order, see [Router::wash_trades]
+    wash_trades: Mutex<VecDeque<Trade>>,
+    /// the position of each account in each pair it has traded, kept up to date as its trades
+    /// are made, see [Router::realized_pnl]
+    positions: Mutex<HashMap<(AccountId, TradingPair), Position>>,
+    /// the most recent orders to be filled, canceled or expired, with when that happened
+    history: Mutex<VecDeque<(OrderId, OrderStatus, TimestampMillis)>>,
+    /// everything the events of the router are passed to, see [Router::subscribe]
+    subscribers: Vec<Box<dyn Subscriber>>,
+    /// the shards of the pairs that are split across several books, see [Router::with_shards]
+    shards: HashMap<TradingPair, Shards<T>>,
+}
+
+/// The position of an account in a pair, long when its quantity is above zero and short when
+/// below, held at the average price it was opened at, with the profit or loss it has realized
+#[derive(Debug, Default)]
+struct Position {
+    quantity: Decimal,
+    average_price: Decimal,
+    realized: Decimal,
+}
+
+impl Position {
+    /// Adds a trade to the position, realizing the profit or loss of any part of it that
+    /// reduces the position. A profit or loss too large for a decimal saturates
+    fn fill(&mut self, trade: &Trade) {
+        let quantity = Decimal::from(trade.quantity);
+        let signed = match trade.side {
+            OrderSide::Bid => quantity,
+            OrderSide::Ask => -quantity,
+        };
+        // a trade on the same side as the position adds to it at a new average price, moved
+        // towards the price of the trade by its share of the position, which can't overflow
+        if self.quantity.is_zero() || self.quantity.is_sign_positive() == signed.is_sign_positive()
+        {
+            let held = self.quantity.abs();
+            let share = quantity / held.saturating_add(quantity);
+            self.average_price += (trade.price - self.average_price) * share;
+            self.quantity = self.quantity.saturating_add(signed);
+            return;
+        }
+        let closed = self.quantity.abs().min(quantity);
+        let pnl = match self.quantity.is_sign_positive() {
+            true => (trade.price - self.average_price).saturating_mul(closed),
+            false => (self.average_price - trade.price).saturating_mul(closed),
+        };
+        self.realized = self.realized.saturating_add(pnl);
+        self.quantity += signed;
+        if self.quantity.is_zero() {
+            self.average_price = Decimal::ZERO;
+        } else if closed < quantity {
+            // the trade closed the position and opened one on the other side at its price
+            self.average_price = trade.price;
+        }
+    }
+}
+
+impl<T> Debug for Router<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("config", &self.config)
+            .field("clock", &self.clock)
+            .field("paused", &self.paused)
+            .field("handlers", &self.handlers.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Default for Router<T>
+where
+    T: OrderBook,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl<T> Router<T>
 where
     T: OrderBook,
 {
     pub fn new() -> Self {
-        Self {
-            books: Mutex::new(HashMap::with_capacity(16)),
-            matcher: Matcher,
-        }
+        Self::with_config(HashMap::with_capacity(16), RouterConfig::default())
     }
     pub fn with_books(books: HashMap<TradingPair, T>) -> Self {
+        Self::with_config(books, RouterConfig::default())
+    }
+
+    pub fn with_config(books: HashMap<TradingPair, T>, config: RouterConfig) -> Self {
         Self {
-            books: Mutex::new(books),
+            books: IntakeLock::new(books),
             matcher: Matcher,
+            config,
+            clock: Arc::new(SystemClock),
+            paused: AtomicBool::new(false),
+            kill_switch: AtomicBool::new(false),
+            waiting: AtomicUsize::new(0),
+            held: Mutex::new(Vec::new()),
+            handlers: HashMap::new(),
+            wash_trades: Mutex::new(VecDeque::with_capacity(config.wash_trade_capacity)),
+            positions: Mutex::new(HashMap::new()),
+            history: Mutex::new(VecDeque::with_capacity(config.history_capacity)),
+            subscribers: Vec::new(),
+            shards: HashMap::new(),
         }
+        .with_handler(Request::PLACE_ORDER, PlaceOrderHandler)
+        .with_handler(Request::PLACE_QUOTE, PlaceQuoteHandler)
+        .with_handler(Request::PLACE_LINKED, PlaceLinkedHandler)
+        .with_handler(Request::CANCEL, CancelHandler)
+        .with_handler(Request::CANCEL_BY_CLIENT_ID, CancelByClientIdHandler)
+        .with_handler(Request::CANCEL_LEVEL, CancelLevelHandler)
+        .with_handler(Request::GET_BOOK, GetBookHandler)
+        .with_handler(Request::SUMMARY, SummaryHandler)
     }
 
-    pub fn handle(&self, request: Request) -> Result<(), Failure> {
-        match request.validate() {
-            Some(failure) => Err(failure),
-            None => match request {
-                Request::PlaceOrder(p) => {
-                    let order = p.to_order();
-
-                    return self
-                        .books
-                        .try_lock()
-                        .map_err(|_| Failure::EngineOverCapacity)
-                        .map(|mut book| match book.get_mut(&order.trading_pair) {
-                            Some(book) => {
-                                self.matcher.match_order(order, book);
-                                Ok(())
-                            }
-                            None => Err(Failure::BookNotFound(format!(
-                                "No book found for trading pair {:?}",
-                                p.trading_pair
-                            ))),
-                        })
-                        .and_then(convert::identity);
-                }
-                Request::Cancel(cancel) => {
-                    return self
-                        .books
-                        .try_lock()
-                        .map_err(|_| Failure::EngineOverCapacity)
-                        .map(|mut book| match book.get_mut(&cancel.trading_pair) {
-                            Some(book) => {
-                                let _ = book.cancel(cancel.orderid);
-                                Ok(())
-                            }
-                            None => Err(Failure::BookNotFound(format!(
-                                "No book found for trading pair {:?}",
-                                cancel.trading_pair
-                            ))),
-                        })
-                        .and_then(convert::identity);
-                }
-            },
+    /// Splits a pair across several books, each with a lock of its own, and spreads its orders
+    /// over them by the policy, see [Shards]. Limit and market orders, cancels and book
+    /// requests are taken for a sharded pair, while other kinds of request are not supported.
+    /// Any book the router had for the pair is replaced. A pair can't be split across no books,
+    /// so the router is left as it is when none are given
+    pub fn with_shards(
+        mut self,
+        trading_pair: TradingPair,
+        books: Vec<T>,
+        policy: ShardPolicy,
+    ) -> Self {
+        if books.is_empty() {
+            return self;
         }
+        self.books
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&trading_pair);
+        self.shards.insert(trading_pair, Shards::new(books, policy));
+        self
     }
-}
 
-#[cfg(test)]
-mod test {
-    use rust_decimal_macros::dec;
+    /// Registers the handler for a kind of request, replacing any handler already registered
+    /// for it. This is how [Request::Custom] requests are given their behaviour
+    pub fn with_handler(
+        mut self,
+        kind: impl Into<String>,
+        handler: impl RequestHandler<T> + 'static,
+    ) -> Self {
+        self.handlers.insert(kind.into(), Box::new(handler));
+        self
+    }
 
-    use crate::core::{orderbook::LimitOrderBook, types::Asset};
+    /// Passes every event the router reports to the subscriber from now on, whatever the
+    /// verbosity of the log is
+    pub fn subscribe(&mut self, subscriber: impl Subscriber + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
 
-    use super::*;
+    /// Sets the clock orders are stamped with when they are received. Books should be given the
+    /// same clock, so the times of orders and of the trades they make agree
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-    #[test]
-    fn placing_an_order_in_an_empty_book_should_fail() {
-        let request = Request::PlaceOrder(PlaceOrder {
-            price: dec!(300.00),
-            quantity: 2,
-            side: OrderSide::Bid,
-            order_type: OrderType::Limit,
-            trading_pair: TradingPair::from(Asset::BTC, Asset::USDC),
-        });
+    /// Stops matching orders until the router is resumed. Cancels are still processed, while new
+    /// orders are rejected or held for later, depending on how the router is configured. The
+    /// stops and pegged orders that cancels reach are left until the router is resumed
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
 
-        let router: Router<LimitOrderBook> = Router::new();
-        let result = router.handle(request);
-        dbg!(&result);
-        assert!(result.is_err());
-        assert_eq!(
-            result.err().unwrap(),
-            Failure::BookNotFound(
-                format!(
-                    "No book found for trading pair {:?}",
-                    TradingPair::from(Asset::BTC, Asset::USDC)
-                )
-                .to_string()
-            )
-        )
+    /// Resumes matching, processing any orders that were held while paused in the order they
+    /// arrived. The books are settled first, as cancels made while paused may have reached
+    /// stops and pegged orders. The result of each held order is returned in that same order
+    pub fn resume(&self) -> Vec<Result<Response, Failure>> {
+        // the held orders are replayed before the router is resumed, and orders placed in the
+        // meantime wait on the lock to be held, so none of them can run ahead of the held ones
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        self.settle_books();
+        let results = held
+            .drain(..)
+            .map(|request| self.report_rejections(request, |request| self.route(request, false)))
+            .collect();
+        self.paused.store(false, Ordering::SeqCst);
+        results
     }
 
-    #[test]
-    fn an_invalid_order_should_fail_placement() {
-        let request = Request::PlaceOrder(PlaceOrder {
-            price: dec!(300.00),
-            quantity: 0,
-            side: OrderSide::Bid,
-            order_type: OrderType::Limit,
-            trading_pair: TradingPair::from(Asset::BTC, Asset::USDC),
-        });
+    /// Rejects every new order until the kill switch is disengaged, eg when a risk limit is
+    /// breached. Unlike pausing, orders are never held for later, and cancels are still
+    /// processed so participants can take their orders off the books
+    pub fn engage_kill_switch(&self) {
+        self.kill_switch.store(true, Ordering::SeqCst);
+    }
 
-        let router: Router<LimitOrderBook> = Router::new();
-        let result = router.handle(request);
-        assert!(result.is_err());
-        assert_eq!(
-            result.err().unwrap(),
-            Failure::OrderRejected("Quantity must be greater than zero".to_string())
-        )
+    /// Takes new orders again. Orders rejected while the kill switch was engaged are not
+    /// replayed
+    pub fn disengage_kill_switch(&self) {
+        self.kill_switch.store(false, Ordering::SeqCst);
     }
 
-    #[test]
-    fn a_valid_order_should_be_routed_successfully() {
-        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+    pub fn is_kill_switch_engaged(&self) -> bool {
+        self.kill_switch.load(Ordering::SeqCst)
+    }
 
-        let request = Request::PlaceOrder(PlaceOrder {
-            price: dec!(300.00),
-            quantity: 10,
-            side: OrderSide::Bid,
-            order_type: OrderType::Limit,
-            trading_pair,
-        });
+    /// Gets the current time from the clock of the router
+    pub fn now(&self) -> TimestampMillis {
+        self.clock.now()
+    }
 
-        let router = Router::with_books(HashMap::from([(
-            TradingPair::from(Asset::BTC, Asset::USDC),
-            LimitOrderBook::init(trading_pair),
-        )]));
-        let result = router.handle(request);
-        assert!(result.is_ok())
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Gets the trading pairs the router has books for, in order
+    pub fn pairs(&self) -> Vec<TradingPair> {
+        let mut pairs: Vec<TradingPair> = self
+            .books
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .chain(self.shards.keys())
+            .copied()
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    /// Checks if the router has a book for the trading pair. Inverse pairs are not considered
+    pub fn supports(&self, trading_pair: &TradingPair) -> bool {
+        self.shards.contains_key(trading_pair)
+            || self
+                .books
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .contains_key(trading_pair)
+    }
+
+    /// Gets the best price on one side of the book of a pair. The best price of a sharded pair
+    /// is the best of all its shards
+    pub fn best_price(&self, trading_pair: TradingPair, side: OrderSide) -> Option<Decimal> {
+        if let Some(shards) = self.shards.get(&trading_pair) {
+            return shards.best_price(side);
+        }
+        let books = self.books.lock().unwrap_or_else(PoisonError::into_inner);
+        let book = books.get(&trading_pair)?;
+        match side {
+            OrderSide::Bid => book.peek_top_bid().map(|order| order.price),
+            OrderSide::Ask => book.peek_top_ask().map(|order| order.price),
+        }
+    }
+
+    /// Holds back an order placed while the router is paused. An order that had to wait for
+    /// the router to finish resuming is carried out instead, after the orders held before it
+    fn hold(&self, request: Request) -> Result<Response, Failure> {
+        if !self.config.queue_while_paused {
+            return Err(Failure::EnginePaused);
+        }
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        if !self.is_paused() {
+            drop(held);
+            return self.route(request, true);
+        }
+        held.push(request);
+        Ok(Response::Accepted)
+    }
+
+    /// Takes the lock on the books. A busy lock means the engine is over capacity, unless the
+    /// router has an intake to wait for it in, while a poisoned one means a request panicked
+    /// part way through changing a book, which is left in a state that can't be trusted to
+    /// match against
+    fn lock_books(&self) -> Result<IntakeGuard<'_, HashMap<TradingPair, T>>, Failure> {
+        self.lock(&self.books)
+    }
+
+    /// Takes a lock on books the way [Router::lock_books] does, for the books of the router or
+    /// one of its shards
+    fn lock<'a, B>(&self, books: &'a IntakeLock<B>) -> Result<IntakeGuard<'a, B>, Failure> {
+        match books.try_lock() {
+            Ok(books) => Ok(books),
+            Err(TryLockError::WouldBlock) => self.wait_for_books(books),
+            Err(TryLockError::Poisoned(_)) => Err(poisoned_books()),
+        }
+    }
+
+    /// Waits in the intake for the books to be free, when it has room, until the intake times
+    /// out. Waiting requests are let through in the order they arrived
+    fn wait_for_books<'a, B>(
+        &self,
+        books: &'a IntakeLock<B>,
+    ) -> Result<IntakeGuard<'a, B>, Failure> {
+        let Some(capacity) = self.config.intake_capacity else {
+            return Err(Failure::EngineOverCapacity);
+        };
+        // a place is taken before waiting, and given back however the wait ends
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= capacity {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(Failure::EngineOverCapacity);
+        }
+        let timeout = Duration::from_millis(self.config.intake_timeout_millis);
+        let books = match books.lock_within(timeout) {
+            Ok(books) => Ok(books),
+            Err(TryLockError::Poisoned(_)) => Err(poisoned_books()),
+            Err(TryLockError::WouldBlock) => Err(Failure::EngineOverCapacity),
+        };
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        books
+    }
+
+    /// Finds the book an order should be matched against. Orders for a registered pair are
+    /// returned untouched, while orders for the inverse of a registered pair are converted
+    /// into an equivalent order on that pair, if the router is configured to allow it
+    pub fn resolve_order(
+        &self,
+        order: Order,
+        books: &HashMap<TradingPair, T>,
+    ) -> Result<Order, Failure> {
+        if books.contains_key(&order.trading_pair) {
+            return Ok(order);
+        }
+        if self.config.allow_inverse_pairs && books.contains_key(&order.trading_pair.inverse()) {
+            return order.to_inverse();
+        }
+        Err(book_not_found(order.trading_pair))
+    }
+
+    /// Creates both sides of a quote for the account, and finds the book they are placed in.
+    /// The two sides are always resolved to the same book
+    pub fn resolve_quote(
+        &self,
+        bid: &PlaceOrder,
+        ask: &PlaceOrder,
+        account_id: AccountId,
+        books: &HashMap<TradingPair, T>,
+    ) -> Result<(Order, Order), Failure> {
+        let now = self.now();
+        let bid = bid.clone().with_account_id(account_id).to_order(now);
+        let ask = ask.clone().with_account_id(account_id).to_order(now);
+        Ok((
+            self.resolve_order(bid, books)?,
+            self.resolve_order(ask, books)?,
+        ))
+    }
+
+    /// Creates both of a pair of linked orders, and finds the books they are placed in. The two
+    /// orders have to be placed in different books, so an order for one pair can't be linked to
+    /// an order for its inverse
+    pub fn resolve_linked(
+        &self,
+        first: &PlaceOrder,
+        second: &PlaceOrder,
+        books: &HashMap<TradingPair, T>,
+    ) -> Result<(Order, Order), Failure> {
+        let now = self.now();
+        let first = self.resolve_order(first.to_order(now), books)?;
+        let second = self.resolve_order(second.to_order(now), books)?;
+        if first.trading_pair == second.trading_pair {
+            return Err(Failure::OrderRejected(format!(
+                "Linked orders must be placed in different books, both are for {}",
+                first.trading_pair
+            )));
+        }
+        Ok((first, second))
+    }
+
+    /// Checks that an order would be accepted by its book, and by the router when it matches in
+    /// batches, without placing it. An accepted order that is not a market order can't fail to
+    /// execute, as the book only rejects orders it would not accept
+    pub fn admits(&self, order: &Order, book: &T) -> Option<Failure> {
+        book.validate(order)
+            .or_else(|| match self.config.match_in_batches {
+                true => Self::cannot_rest(order),
+                false => None,
+            })
+    }
+
+    /// Runs an order through the matcher once the book has dropped its expired orders and
+    /// confirmed that the order is acceptable. The execution includes the trades and events of
+    /// any stop orders that were triggered by the order trading, and starts with the events of
+    /// the orders that expired. Nothing is recorded, so the orders that expired are lost when
+    /// the order fails, unless the book was purged of them first
+    pub fn execute(&self, order: Order, book: &mut T) -> Result<Execution, Failure> {
+        let expired = book.purge_expired(order.timestamp);
+        if let Some(failure) = book.validate(&order) {
+            return Err(failure);
+        }
+        let mut execution = self.execute_admitted(order, book)?;
+        execution.events.splice(0..0, expired);
+        Ok(execution)
+    }
+
+    /// Runs an order that was already admitted to its book through the matcher, without
+    /// checking it again. Orders placed together are all checked before the first of them is
+    /// executed, since the trades of one can move the book in a way the checks of the next
+    /// would reject, once it has already been placed
+    pub fn execute_admitted(&self, order: Order, book: &mut T) -> Result<Execution, Failure> {
+        if self.config.match_in_batches {
+            return self.rest(order, book).map(|event| Execution {
+                events: vec![event],
+                ..Default::default()
+            });
+        }
+        let matches = self.matcher.match_order(order, book)?;
+        if matches.get_state() == MatchState::NoLiquidity {
+            return Err(Failure::NoLiquidity(format!(
+                "No {:?} orders to match market order {} against",
+                order.side.opposite(),
+                order.orderid
+            )));
+        }
+        let mut execution = Execution::default();
+        execution.add_match(&matches);
+        for stop in self.matcher.trigger_stops(book) {
+            execution.add_match(&stop);
+        }
+        execution.extend(self.repeg(book));
+        Ok(execution)
+    }
+
+    /// Moves the pegged orders of a book to follow their references after the book changed,
+    /// and matches those the move leaves crossing the book. Matching moves the references in
+    /// turn, so this runs until the pegged orders settle. When matching in batches, pegged
+    /// orders are only moved, and left for the next tick to match
+    pub fn repeg(&self, book: &mut T) -> Execution {
+        let mut execution = Execution::default();
+        while book.reprice_pegged() > 0 && !self.config.match_in_batches {
+            let matches = self.matcher.uncross(book, self.config.tie_break);
+            if matches.is_empty() {
+                break;
+            }
+            for matches in matches
+                .iter()
+                .chain(self.matcher.trigger_stops(book).iter())
+            {
+                execution.add_match(matches);
+            }
+        }
+        execution
+    }
+
+    /// Brings a book back in line after orders were canceled out of it. Taking out the best
+    /// order can move the price stops are triggered by as well as the references of pegged
+    /// orders, so the stops it triggers are matched before the pegged orders are moved. When
+    /// matching in batches, the stops are left for the next tick, and while paused the book is
+    /// left for the router to settle when it resumes
+    pub fn settle(&self, book: &mut T) -> Execution {
+        if self.is_paused() {
+            return Execution::default();
+        }
+        self.settle_unpaused(book)
+    }
+
+    fn settle_unpaused(&self, book: &mut T) -> Execution {
+        let mut execution = Execution::default();
+        if !self.config.match_in_batches {
+            for stop in self.matcher.trigger_stops(book) {
+                execution.add_match(&stop);
+            }
+        }
+        execution.extend(self.repeg(book));
+        execution
+    }
+
+    /// Settles every book, and every shard, in the order of their pairs. Books that can't be
+    /// locked are left as they are
+    fn settle_books(&self) {
+        if let Ok(mut books) = self.lock_books() {
+            let mut pairs: Vec<TradingPair> = books.keys().copied().collect();
+            pairs.sort();
+            for trading_pair in pairs {
+                self.reporting_depth(&mut books, &[trading_pair], |books| {
+                    if let Some(book) = books.get_mut(&trading_pair) {
+                        let execution = self.settle_unpaused(book);
+                        self.record_execution(trading_pair, &execution);
+                    }
+                });
+            }
+        }
+        let mut sharded: Vec<(&TradingPair, &Shards<T>)> = self.shards.iter().collect();
+        sharded.sort_by_key(|(trading_pair, _)| **trading_pair);
+        for (&trading_pair, shards) in sharded {
+            for book in shards.books() {
+                if let Ok(mut book) = self.lock(book) {
+                    let execution = self.settle_unpaused(&mut book);
+                    self.record_execution(trading_pair, &execution);
+                }
+            }
+        }
+    }
+
+    /// Records what an execution in the book of a pair did: the wash trades and trades of
+    /// accounts it made, and the orders it took to a terminal state. Each trade is logged
+    pub fn record_execution(&self, trading_pair: TradingPair, execution: &Execution) {
+        self.report_wash_trades(&execution.wash_trades);
+        self.record_account_trades(trading_pair, &execution.trades);
+        self.record_terminal(&execution.events);
+        for &trade in &execution.trades {
+            self.log_event(EngineEvent::Matched {
+                trade,
+                trading_pair,
+            });
+        }
+    }
+
+    /// Rejects an order that isn't allowed to wait for a match, which are the only orders
+    /// accepted when matching in batches
+    fn cannot_rest(order: &Order) -> Option<Failure> {
+        let waits = !matches!(
+            order.time_in_force,
+            TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill
+        );
+        (order.order_type == OrderType::Market || !waits).then(|| {
+            Failure::OrderRejected(
+                "Only orders that can rest on the book are accepted when matching in batches"
+                    .to_string(),
+            )
+        })
+    }
+
+    /// Rests an order on its book without matching it, to be matched on the next tick. Only
+    /// orders that are allowed to wait for a match are accepted
+    fn rest(&self, order: Order, book: &mut T) -> Result<Event, Failure> {
+        if let Some(failure) = Self::cannot_rest(&order) {
+            return Err(failure);
+        }
+        let event = book.place(order)?;
+        book.reprice_pegged();
+        Ok(event)
+    }
+
+    /// Matches every book that orders were rested in since the last tick, when the router
+    /// matches in batches. Crossing orders are matched in the order they arrived, and any stops
+    /// that triggers are matched after them. Gets every trade made, book by book
+    pub fn tick(&self) -> Result<Vec<Trade>, Failure> {
+        if self.is_paused() {
+            return Err(Failure::EnginePaused);
+        }
+        let mut books = self.lock_books()?;
+        let mut pairs: Vec<TradingPair> = books.keys().copied().collect();
+        pairs.sort();
+        let mut trades = Vec::new();
+        for trading_pair in pairs {
+            self.reporting_depth(&mut books, &[trading_pair], |books| {
+                if let Some(book) = books.get_mut(&trading_pair) {
+                    trades.extend(self.uncross(trading_pair, book));
+                }
+            });
+        }
+        Ok(trades)
+    }
+
+    /// Matches the orders left crossing a book, and the stops they trigger, on a tick
+    fn uncross(&self, trading_pair: TradingPair, book: &mut T) -> Vec<Trade> {
+        let mut execution = Execution::default();
+        for matches in self.matcher.uncross(book, self.config.tie_break) {
+            execution.add_match(&matches);
+        }
+        for stop in self.matcher.trigger_stops(book) {
+            execution.add_match(&stop);
+        }
+        self.record_execution(trading_pair, &execution);
+        execution.trades
+    }
+
+    /// Moves every order resting in the book of one pair to the book of another, eg when a
+    /// pair is renamed. Orders keep their priority, and are rewritten to the new pair, though
+    /// pegged orders are moved last so the prices they peg to are there first. A book is made
+    /// for the new pair with `new_book` when the router has none, otherwise its book has to be
+    /// empty. The book of the old pair is only emptied once every order is in the new book:
+    /// should any be rejected, the books are left as they were. Gets the number of orders moved
+    pub fn migrate_pair(
+        &self,
+        from: TradingPair,
+        to: TradingPair,
+        new_book: impl FnOnce(&T, TradingPair) -> T,
+    ) -> Result<usize, Failure> {
+        if from == to {
+            return Err(Failure::InvalidTradingPair(format!(
+                "Orders of {} can't be migrated to the same pair",
+                from
+            )));
+        }
+        let mut books = self.lock_books()?;
+        let source = books.get(&from).ok_or_else(|| {
+            Failure::BookNotFound(format!("No book found for trading pair {}", from))
+        })?;
+        let mut orders = source.top_n_bids(usize::MAX);
+        orders.extend(source.top_n_asks(usize::MAX));
+        orders.extend(source.pending_stops());
+        let (mut orders, pegged): (Vec<Order>, Vec<Order>) = orders
+            .into_iter()
+            .partition(|order| !matches!(order.order_type, OrderType::Pegged { .. }));
+        orders.extend(pegged);
+        let created = match books.get(&to) {
+            Some(destination) if !destination.is_empty() => {
+                return Err(Failure::InvalidTradingPair(format!(
+                    "Orders of {} can't be migrated to {}, as its book already has orders",
+                    from, to
+                )));
+            }
+            Some(_) => false,
+            None => {
+                let destination = new_book(source, to);
+                books.insert(to, destination);
+                true
+            }
+        };
+        let destination = books.get_mut(&to).ok_or_else(|| {
+            Failure::BookNotFound(format!("No book found for trading pair {}", to))
+        })?;
+        let placed = orders.iter().try_for_each(|order| {
+            destination
+                .place(Order {
+                    trading_pair: to,
+                    ..*order
+                })
+                .map(|_| ())
+        });
+        if let Err(failure) = placed {
+            destination.drain();
+            if created {
+                books.remove(&to);
+            }
+            return Err(failure);
+        }
+        if let Some(source) = books.get_mut(&from) {
+            source.drain();
+        }
+        Ok(orders.len())
+    }
+
+    /// Remembers the orders that events have taken to a terminal state, which is being fully
+    /// filled, canceled or expired. Events of any other status are ignored
+    pub fn record_terminal(&self, events: &[Event]) {
+        let capacity = self.config.history_capacity;
+        if capacity == 0 {
+            return;
+        }
+        let now = self.now();
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        for event in events {
+            if !matches!(
+                event.status,
+                OrderStatus::Filled | OrderStatus::Canceled | OrderStatus::Expired
+            ) {
+                continue;
+            }
+            if history.len() == capacity {
+                history.pop_front();
+            }
+            history.push_back((event.orderid, event.status, now));
+        }
+    }
+
+    /// Gets the orders that were fully filled, canceled or expired, oldest first. Only the most
+    /// recent orders are kept, up to the history capacity the router is configured with
+    pub fn terminal_orders(&self) -> Vec<(OrderId, OrderStatus)> {
+        self.history
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|&(orderid, status, _)| (orderid, status))
+            .collect()
+    }
+
+    /// Gets the time an order reached its terminal state, if it is still in the history
+    pub fn terminated_at(&self, orderid: OrderId) -> Option<TimestampMillis> {
+        self.history
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .rev()
+            .find(|(id, ..)| *id == orderid)
+            .map(|&(.., timestamp)| timestamp)
+    }
+
+    /// Adds trades to the wash trade report, forgetting the oldest once it is at capacity
+    pub fn report_wash_trades(&self, trades: &[Trade]) {
+        let capacity = self.config.wash_trade_capacity;
+        if capacity == 0 {
+            return;
+        }
+        let mut wash_trades = self
+            .wash_trades
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for trade in trades {
+            if wash_trades.len() == capacity {
+                wash_trades.pop_front();
+            }
+            wash_trades.push_back(*trade);
+        }
+    }
+
+    /// Gets the trades in which an account was on both sides, so was both the maker and the
+    /// taker, in the order they were made. Wash trades are still executed, only reported here.
+    /// Only the most recent are kept, up to the wash trade capacity the router is configured with
+    pub fn wash_trades(&self) -> Vec<Trade> {
+        self.wash_trades
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Adds the trades made in a pair by orders placed for an account to the positions of
+    /// their accounts. Trades of no account are ignored
+    pub fn record_account_trades(&self, trading_pair: TradingPair, trades: &[Trade]) {
+        let mut positions = self
+            .positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for trade in trades {
+            if let Some(account_id) = trade.account_id {
+                positions
+                    .entry((account_id, trading_pair))
+                    .or_default()
+                    .fill(trade);
+            }
+        }
+    }
+
+    /// Gets the profit or loss an account has realized from its trades, by the quote asset it
+    /// was realized in, using the average cost of its position in each pair. Only the part of a
+    /// trade that reduces a position realizes anything: selling more than was bought opens a
+    /// short, which is realized when it is bought back
+    pub fn realized_pnl(&self, account_id: AccountId) -> HashMap<Asset, Decimal> {
+        let positions = self
+            .positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut realized: HashMap<Asset, Decimal> = HashMap::new();
+        for ((account, trading_pair), position) in positions.iter() {
+            if *account != account_id {
+                continue;
+            }
+            let total = realized.entry(trading_pair.price_asset).or_default();
+            *total = total.saturating_add(position.realized);
+        }
+        realized
+    }
+
+    /// Writes an engine event to the log, if the verbosity of the router selects it, and passes
+    /// it to every subscriber
+    pub fn log_event(&self, event: EngineEvent) {
+        if self.config.verbosity.logs(&event) {
+            info!(target: EVENT_LOG_TARGET, "{}", event);
+        }
+        for subscriber in &self.subscribers {
+            subscriber.notify(&event);
+        }
+    }
+
+    /// Runs an operation on the books, then reports every price level it changed in the books
+    /// of the given pairs as an [EngineEvent::Depth]. The books only keep the levels as they
+    /// change, so this is only done when the depth is logged or there are subscribers to
+    /// report it to
+    fn reporting_depth<R>(
+        &self,
+        books: &mut HashMap<TradingPair, T>,
+        pairs: &[TradingPair],
+        operation: impl FnOnce(&mut HashMap<TradingPair, T>) -> R,
+    ) -> R {
+        if !self.config.verbosity.depth && self.subscribers.is_empty() {
+            return operation(books);
+        }
+        for pair in pairs {
+            if let Some(book) = books.get_mut(pair) {
+                book.watch_depth();
+            }
+        }
+        let result = operation(books);
+        for pair in pairs {
+            let Some(book) = books.get_mut(pair) else {
+                continue;
+            };
+            for delta in book.take_depth_deltas() {
+                self.log_event(EngineEvent::Depth(delta));
+            }
+        }
+        result
+    }
+
+    /// Carries out a request, passing it to the handler registered for its kind. When the
+    /// router reports rejections, the orders of a request that fails are reported as rejected
+    pub fn handle(&self, request: Request) -> Result<Response, Failure> {
+        self.report_rejections(request, |request| self.route(request, true))
+    }
+
+    /// Routes a request, reporting the orders it places as rejected when it fails, if the
+    /// router is configured to
+    fn report_rejections(
+        &self,
+        request: Request,
+        route: impl FnOnce(Request) -> Result<Response, Failure>,
+    ) -> Result<Response, Failure> {
+        if !self.config.report_rejections {
+            return route(request);
+        }
+        let rejected = request.rejected_events();
+        let result = route(request);
+        if let Err(reason) = &result {
+            for (trading_pair, client_order_id, event) in rejected {
+                self.log_event(EngineEvent::Rejected {
+                    event,
+                    trading_pair,
+                    client_order_id,
+                    reason: reason.kind(),
+                });
+            }
+        }
+        result
+    }
+
+    /// Carries out a request. Orders placed while the router is paused are held, unless they
+    /// are the held orders being replayed on resume
+    fn route(&self, request: Request, may_hold: bool) -> Result<Response, Failure> {
+        let places = matches!(
+            request,
+            Request::PlaceOrder(_) | Request::PlaceQuote { .. } | Request::PlaceLinked { .. }
+        );
+        match request.validate(self.now(), &self.config) {
+            Some(failure) => Err(failure),
+            None if places && self.is_kill_switch_engaged() => Err(Failure::KillSwitchEngaged),
+            None if places && may_hold && self.is_paused() => self.hold(request),
+            None if self.shards.contains_key(&request.trading_pair()) => {
+                self.route_to_shards(request)
+            }
+            None => match self.handlers.get(request.kind()) {
+                Some(handler) => {
+                    let pairs: Vec<TradingPair> = request
+                        .trading_pairs()
+                        .into_iter()
+                        .flat_map(|pair| [pair, pair.inverse()])
+                        .collect();
+                    self.lock_books()
+                        .map(|mut books| {
+                            self.reporting_depth(&mut books, &pairs, |books| {
+                                handler.handle(self, books, request)
+                            })
+                        })
+                        .and_then(convert::identity)
+                }
+                None => Err(Failure::UnsupportedOperation(format!(
+                    "No handler is registered for {} requests",
+                    request.kind()
+                ))),
+            },
+        }
+    }
+
+    /// Places an order for a sharded pair. Every shard is locked, always in the same order so
+    /// two requests can't each hold a shard the other waits for, and the order is matched
+    /// against the best price across them a level at a time, until it's filled or no shard has
+    /// a price it can trade at. Only then does what's left rest in the shard its policy picks,
+    /// so the pair is never left crossed. Sharded pairs are matched as orders arrive, even when
+    /// the router matches in batches. Stops and pegged orders follow the prices of a single
+    /// book, and orders for a notional are only sized as they match, so neither is taken
+    fn place_in_shards(&self, order: Order, shards: &Shards<T>) -> Result<Execution, Failure> {
+        let stops_or_pegs = matches!(
+            order.order_type,
+            OrderType::Stop | OrderType::StopLimit | OrderType::Pegged { .. }
+        );
+        if stops_or_pegs || order.notional.is_some() {
+            return Err(Failure::UnsupportedOperation(format!(
+                "Only limit and market orders for a quantity are taken for the sharded pair {}",
+                order.trading_pair
+            )));
+        }
+        let mut books = shards
+            .books()
+            .iter()
+            .map(|book| self.lock(book))
+            .collect::<Result<Vec<_>, _>>()?;
+        let home = shards.shard_for(&order);
+        let mut execution = Execution::default();
+        for book in books.iter_mut() {
+            execution.events.extend(book.purge_expired(order.timestamp));
+        }
+        let fillable = shards::fillable(&books, &order);
+        let short_of_min_fill = order.min_fill.is_some_and(|min_fill| fillable < min_fill);
+        let rejected = books[home].validate(&order).or_else(|| {
+            (order.order_type == OrderType::Market && (fillable == 0 || short_of_min_fill)).then(
+                || {
+                    Failure::NoLiquidity(format!(
+                        "No {:?} orders to match market order {} against",
+                        order.side.opposite(),
+                        order.orderid
+                    ))
+                },
+            )
+        });
+        if let Some(failure) = rejected {
+            self.record_terminal(&execution.events);
+            return Err(failure);
+        }
+        // like a fill or kill order on a single book, it's killed without trading when the
+        // shards together can't fill it
+        if order.time_in_force == TimeInForce::FillOrKill && fillable < order.quantity {
+            return Ok(execution);
+        }
+
+        let max_fills = books[home].max_fills_per_order();
+        let (mut remaining, mut fills) = (order.quantity, 0);
+        while remaining > 0 && !short_of_min_fill {
+            let Some((index, price)) = shards::best_to_match(&books, &order) else {
+                break;
+            };
+            let fills_left = max_fills.map(|max_fills| max_fills.get() - fills);
+            let quantity = match fills_left {
+                Some(0) => break,
+                Some(n) => shards::level_quantity(&*books[index], order.side.opposite(), price, n)
+                    .min(remaining),
+                None => remaining,
+            };
+            // a slice of the order only takes the one level, and never rests
+            let slice = Order {
+                order_type: OrderType::Limit,
+                price,
+                quantity,
+                time_in_force: TimeInForce::ImmediateOrCancel,
+                min_fill: None,
+                ..order
+            };
+            let matches = self.matcher.match_order(slice, &mut *books[index])?;
+            let filled = quantity - matches.get_qty_left();
+            fills += matches
+                .get_matches()
+                .iter()
+                .filter(|trade| trade.orderid == order.orderid)
+                .count();
+            execution.add_match(&matches);
+            if filled == 0 {
+                break;
+            }
+            remaining -= filled;
+        }
+        // each slice reports itself filled once it takes its level, so the fills of the order
+        // are restated by how much of the whole order they leave filled
+        let status_at = |filled| match filled == order.quantity {
+            true => OrderStatus::Filled,
+            false => OrderStatus::PartialFill,
+        };
+        let mut filled = 0;
+        for trade in execution.trades.iter_mut() {
+            if trade.orderid == order.orderid {
+                filled += trade.quantity;
+                trade.status = status_at(filled);
+            }
+        }
+        filled = 0;
+        for event in execution.events.iter_mut() {
+            if event.orderid == order.orderid {
+                filled += event.quantity.unwrap_or(0);
+                event.status = status_at(filled);
+            }
+        }
+        let rests = matches!(
+            order.time_in_force,
+            TimeInForce::GoodTillCancel | TimeInForce::GoodTillDate(_)
+        );
+        if remaining > 0
+            && rests
+            && order.order_type == OrderType::Limit
+            && shards::best_to_match(&books, &order).is_none()
+        {
+            let rested = books[home].place(Order {
+                quantity: remaining,
+                ..order
+            })?;
+            execution.events.push(rested);
+        }
+        Ok(execution)
+    }
+
+    /// Carries out a request for a sharded pair. An order is matched across every shard and
+    /// rests in the shard its policy picks, a cancel looks for the order in every shard, and
+    /// the book of the pair is its shards summed together. The depth of shards is not reported
+    fn route_to_shards(&self, request: Request) -> Result<Response, Failure> {
+        let trading_pair = request.trading_pair();
+        let shards = self
+            .shards
+            .get(&trading_pair)
+            .ok_or_else(|| book_not_found(trading_pair))?;
+        match request {
+            Request::PlaceOrder(p) => {
+                let order = p.to_order(self.now());
+                let execution = self.place_in_shards(order, shards)?;
+                self.log_event(EngineEvent::Placed(order));
+                self.record_execution(trading_pair, &execution);
+                Ok(Response::Placed {
+                    orderid: order.orderid,
+                    events: execution.events,
+                })
+            }
+            Request::Cancel(cancel) => {
+                for book in shards.books() {
+                    let mut book = self.lock(book)?;
+                    if let Ok(event) = book.cancel(cancel.orderid) {
+                        self.record_terminal(&[event]);
+                        self.log_event(EngineEvent::Canceled {
+                            orderid: cancel.orderid,
+                            trading_pair,
+                        });
+                        self.record_execution(trading_pair, &self.settle(&mut book));
+                        return Ok(Response::Accepted);
+                    }
+                }
+                Err(Failure::OrderNotFound(
+                    "No order found with the given id".to_string(),
+                ))
+            }
+            Request::GetBook { .. } => Ok(Response::Book(shards.snapshot(trading_pair))),
+            Request::Summary { .. } => Ok(Response::Summary(shards.summary(trading_pair))),
+            request => Err(Failure::UnsupportedOperation(format!(
+                "{} requests are not supported for the sharded pair {}",
+                request.kind(),
+                trading_pair
+            ))),
+        }
+    }
+}
+
+impl<T> Router<T>
+where
+    T: OrderBook + Clone,
+{
+    /// Runs a request against a copy of its book and returns the trades it would produce,
+    /// without changing the state of any book
+    pub fn simulate(&self, request: &Request) -> Result<Vec<Trade>, Failure> {
+        let now = self.now();
+        if let Some(failure) = request.validate(now, &self.config) {
+            return Err(failure);
+        }
+        if self.shards.contains_key(&request.trading_pair()) {
+            return Err(Failure::UnsupportedOperation(format!(
+                "Requests for the sharded pair {} cannot be simulated",
+                request.trading_pair()
+            )));
+        }
+        let books = self.lock_books()?;
+        match request {
+            Request::PlaceOrder(p) => {
+                let order = self.resolve_order(p.to_order(now), &books)?;
+                let mut book = books
+                    .get(&order.trading_pair)
+                    .cloned()
+                    .ok_or_else(|| book_not_found(order.trading_pair))?;
+                self.execute(order, &mut book)
+                    .map(|execution| execution.trades)
+            }
+            Request::PlaceQuote {
+                bid,
+                ask,
+                account_id,
+            } => {
+                let (bid, ask) = self.resolve_quote(bid, ask, *account_id, &books)?;
+                let mut book = books
+                    .get(&bid.trading_pair)
+                    .cloned()
+                    .ok_or_else(|| book_not_found(bid.trading_pair))?;
+                // both sides are checked before either is placed, the way the quote handler does
+                book.purge_expired(bid.timestamp);
+                if let Some(failure) = self
+                    .admits(&bid, &book)
+                    .or_else(|| self.admits(&ask, &book))
+                {
+                    return Err(failure);
+                }
+                let mut trades = self.execute_admitted(bid, &mut book)?.trades;
+                trades.extend(self.execute_admitted(ask, &mut book)?.trades);
+                Ok(trades)
+            }
+            Request::PlaceLinked { first, second } => {
+                let (first, second) = self.resolve_linked(first, second, &books)?;
+                let mut trades = Vec::new();
+                for order in [first, second] {
+                    let mut book = books
+                        .get(&order.trading_pair)
+                        .cloned()
+                        .ok_or_else(|| book_not_found(order.trading_pair))?;
+                    trades.extend(self.execute(order, &mut book)?.trades);
+                }
+                Ok(trades)
+            }
+            Request::Custom { kind, .. } => Err(Failure::UnsupportedOperation(format!(
+                "{} requests cannot be simulated",
+                kind
+            ))),
+            // canceling or reading a book never produces trades, so only the book lookup is checked
+            Request::Cancel(CancelOrder { trading_pair, .. })
+            | Request::CancelByClientId { trading_pair, .. }
+            | Request::CancelLevel { trading_pair, .. }
+            | Request::GetBook { trading_pair }
+            | Request::Summary { trading_pair } => {
+                if books.contains_key(trading_pair) {
+                    Ok(Vec::new())
+                } else {
+                    Err(book_not_found(*trading_pair))
+                }
+            }
+        }
+    }
+}
+
+impl Router<LimitOrderBook> {
+    /// Checks the invariants of every book, see [LimitOrderBook::check_invariants]. A violation
+    /// is reported with the pair of the book it was found in
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let books = self.books.lock().unwrap_or_else(PoisonError::into_inner);
+        for (trading_pair, book) in books.iter() {
+            book.check_invariants()
+                .map_err(|violation| format!("{}: {}", trading_pair, violation))?;
+        }
+        for (trading_pair, shards) in &self.shards {
+            for (index, book) in shards.books().iter().enumerate() {
+                let book = book.lock().unwrap_or_else(PoisonError::into_inner);
+                book.check_invariants().map_err(|violation| {
+                    format!("{} shard {}: {}", trading_pair, index, violation)
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, thread};
+
+    use rust_decimal_macros::dec;
+
+    use crate::core::{
+        model::{BookLevel, DepthDelta, LevelChange},
+        orderbook::{BookConfig, LimitOrderBook},
+        types::{Asset, OrderStatus, PegReference, StopTrigger},
+        utils::{LogicalClock, MockClock},
+    };
+
+    use super::*;
+
+    fn router_with_a_pegged_bid() -> (Router<LimitOrderBook>, TradingPair, OrderId) {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = |price, side, order_type| {
+            let place = PlaceOrder::from(price, 5, side, order_type, trading_pair);
+            placed_ids(router.handle(Request::PlaceOrder(place)))[0]
+        };
+        place(dec!(19.00), OrderSide::Bid, OrderType::Limit);
+        let ask = place(dec!(21.00), OrderSide::Ask, OrderType::Limit);
+        // the peg refers to the asks, which come after the bids in the book
+        let pegged = OrderType::Pegged {
+            reference: PegReference::BestAsk,
+            offset: dec!(-1.00),
+        };
+        place(Decimal::ZERO, OrderSide::Bid, pegged);
+        (router, trading_pair, ask)
+    }
+
+    #[test]
+    fn a_cancel_that_moves_the_best_price_triggers_the_stops_it_reaches() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        for by_client_id in [false, true] {
+            let router = Router::with_books(HashMap::from([(
+                trading_pair,
+                LimitOrderBook::with_config(
+                    trading_pair,
+                    BookConfig {
+                        stop_trigger: StopTrigger::BestPrice,
+                        ..Default::default()
+                    },
+                ),
+            )]));
+            let best = PlaceOrder::from(
+                dec!(21.00),
+                5,
+                OrderSide::Ask,
+                OrderType::Limit,
+                trading_pair,
+            )
+            .with_client_order_id(1);
+            let best = placed_ids(router.handle(Request::PlaceOrder(best)))[0];
+            let next = PlaceOrder::from(
+                dec!(22.00),
+                5,
+                OrderSide::Ask,
+                OrderType::Limit,
+                trading_pair,
+            );
+            let _ = router.handle(Request::PlaceOrder(next));
+            let stop = PlaceOrder::from(dec!(0), 5, OrderSide::Bid, OrderType::Stop, trading_pair)
+                .with_stop_price(dec!(21.50));
+            let _ = router.handle(Request::PlaceOrder(stop));
+
+            // taking out the ask at 21.00 moves the best ask past the stop price
+            let cancel = match by_client_id {
+                false => Request::Cancel(CancelOrder::from(best, trading_pair)),
+                true => Request::CancelByClientId {
+                    client_order_id: 1,
+                    trading_pair,
+                },
+            };
+            assert!(router.handle(cancel).is_ok());
+            let books = router.books.lock().unwrap();
+            let book = books.get(&trading_pair).unwrap();
+            assert!(book.pending_stops().is_empty());
+            assert!(book.peek_top_ask().is_none());
+        }
+    }
+
+    #[test]
+    fn a_cancel_while_paused_leaves_the_stops_it_reaches_until_the_router_resumes() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    stop_trigger: StopTrigger::BestPrice,
+                    ..Default::default()
+                },
+            ),
+        )]));
+        let ask =
+            |price| PlaceOrder::from(price, 5, OrderSide::Ask, OrderType::Limit, trading_pair);
+        let best = placed_ids(router.handle(Request::PlaceOrder(ask(dec!(21.00)))))[0];
+        let _ = router.handle(Request::PlaceOrder(ask(dec!(22.00))));
+        let stop = PlaceOrder::from(dec!(0), 5, OrderSide::Bid, OrderType::Stop, trading_pair)
+            .with_stop_price(dec!(21.50));
+        let _ = router.handle(Request::PlaceOrder(stop));
+
+        router.pause();
+        let cancel = Request::Cancel(CancelOrder::from(best, trading_pair));
+        assert!(router.handle(cancel).is_ok());
+        {
+            let books = router.books.lock().unwrap();
+            let book = books.get(&trading_pair).unwrap();
+            assert_eq!(book.pending_stops().len(), 1);
+            assert_eq!(book.top_ask_level(), Some((dec!(22.00), 5)));
+        }
+
+        assert!(router.resume().is_empty());
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.pending_stops().is_empty());
+        assert!(book.peek_top_ask().is_none());
+    }
+
+    #[test]
+    fn pegged_orders_are_migrated_after_the_prices_they_peg_to() {
+        let (router, from, _) = router_with_a_pegged_bid();
+        let to = TradingPair::from(Asset::BTC, Asset::USDT);
+
+        assert_eq!(
+            router.migrate_pair(from, to, |book, trading_pair| book.for_pair(trading_pair)),
+            Ok(3)
+        );
+        let books = router.books.lock().unwrap();
+        assert_eq!(
+            books.get(&to).unwrap().depth(5),
+            (
+                vec![(dec!(20.00), 5), (dec!(19.00), 5)],
+                vec![(dec!(21.00), 5)]
+            )
+        );
+        assert!(books.get(&from).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_migration_that_fails_part_way_leaves_the_books_as_they_were() {
+        let (router, from, ask) = router_with_a_pegged_bid();
+        let to = TradingPair::from(Asset::BTC, Asset::USDT);
+        // the peg stays where it is once the ask is gone, but has nothing to peg to if moved
+        let _ = router.handle(Request::Cancel(CancelOrder::from(ask, from)));
+
+        assert!(matches!(
+            router.migrate_pair(from, to, |book, trading_pair| book.for_pair(trading_pair)),
+            Err(Failure::OrderRejected(_))
+        ));
+        let books = router.books.lock().unwrap();
+        assert_eq!(
+            books.get(&from).unwrap().depth(5),
+            (vec![(dec!(20.00), 5), (dec!(19.00), 5)], vec![])
+        );
+        assert!(!books.contains_key(&to));
+    }
+
+    #[test]
+    fn placing_an_order_in_an_empty_book_should_fail() {
+        let request = Request::PlaceOrder(PlaceOrder {
+            client_order_id: None,
+            account_id: None,
+            tag: None,
+            price: dec!(300.00),
+            quantity: 2,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            client_timestamp: None,
+            stop_price: None,
+            side: OrderSide::Bid,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            ttl_millis: None,
+            trading_pair: TradingPair::from(Asset::BTC, Asset::USDC),
+        });
+
+        let router: Router<LimitOrderBook> = Router::new();
+        let result = router.handle(request);
+        dbg!(&result);
+        assert!(result.is_err());
+        assert_eq!(
+            result.err().unwrap(),
+            Failure::BookNotFound(
+                format!(
+                    "No book found for trading pair {:?}",
+                    TradingPair::from(Asset::BTC, Asset::USDC)
+                )
+                .to_string()
+            )
+        )
+    }
+
+    #[test]
+    fn an_invalid_order_should_fail_placement() {
+        let request = Request::PlaceOrder(PlaceOrder {
+            client_order_id: None,
+            account_id: None,
+            tag: None,
+            price: dec!(300.00),
+            quantity: 0,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            client_timestamp: None,
+            stop_price: None,
+            side: OrderSide::Bid,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            ttl_millis: None,
+            trading_pair: TradingPair::from(Asset::BTC, Asset::USDC),
+        });
+
+        let router: Router<LimitOrderBook> = Router::new();
+        let result = router.handle(request);
+        assert!(result.is_err());
+        assert_eq!(
+            result.err().unwrap(),
+            Failure::OrderRejected("Quantity must be greater than zero".to_string())
+        )
+    }
+
+    #[test]
+    fn a_valid_order_should_be_routed_successfully() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+
+        let request = Request::PlaceOrder(PlaceOrder {
+            client_order_id: None,
+            account_id: None,
+            tag: None,
+            price: dec!(300.00),
+            quantity: 10,
+            notional: None,
+            display_quantity: None,
+            min_fill: None,
+            client_timestamp: None,
+            stop_price: None,
+            side: OrderSide::Bid,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::GoodTillCancel,
+            ttl_millis: None,
+            trading_pair,
+        });
+
+        let router = Router::with_books(HashMap::from([(
+            TradingPair::from(Asset::BTC, Asset::USDC),
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let result = router.handle(request);
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    fn subscribers_are_told_how_each_request_changed_the_depth_of_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let mut router = account_router();
+        let deltas = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&deltas);
+        router.subscribe(move |event: &EngineEvent| {
+            if let EngineEvent::Depth(delta) = event {
+                received.lock().unwrap().push(*delta);
+            }
+        });
+        let delta = |side, change, price, quantity, orders| DepthDelta {
+            trading_pair,
+            side,
+            change,
+            price,
+            quantity,
+            orders,
+        };
+        let take_deltas = || std::mem::take(&mut *deltas.lock().unwrap());
+
+        let ask = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let Ok(Response::Placed { events: placed, .. }) = router.handle(Request::PlaceOrder(ask))
+        else {
+            panic!("the ask should have been placed");
+        };
+        assert_eq!(
+            take_deltas(),
+            vec![delta(OrderSide::Ask, LevelChange::Added, dec!(20.00), 5, 1)]
+        );
+
+        let bid = PlaceOrder::from(
+            dec!(20.00),
+            2,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = router.handle(Request::PlaceOrder(bid));
+        assert_eq!(
+            take_deltas(),
+            vec![delta(
+                OrderSide::Ask,
+                LevelChange::Changed,
+                dec!(20.00),
+                3,
+                1
+            )]
+        );
+
+        // a request that leaves the book as it was changes no levels
+        let _ = router.handle(Request::GetBook { trading_pair });
+        assert!(take_deltas().is_empty());
+
+        let bid = PlaceOrder::from(
+            dec!(19.50),
+            4,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = router.handle(Request::PlaceOrder(bid));
+        let _ = router.handle(Request::Cancel(CancelOrder::from(
+            placed[0].orderid,
+            trading_pair,
+        )));
+        assert_eq!(
+            take_deltas(),
+            vec![
+                delta(OrderSide::Bid, LevelChange::Added, dec!(19.50), 4, 1),
+                delta(OrderSide::Ask, LevelChange::Removed, dec!(20.00), 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_tag_of_an_order_is_passed_on_to_its_trades_and_events_unchanged() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = account_router();
+        let tagged = |price, quantity, side, tag| {
+            Request::PlaceOrder(
+                PlaceOrder::from(price, quantity, side, OrderType::Limit, trading_pair)
+                    .with_tag(tag),
+            )
+        };
+        let Ok(Response::Placed { events: placed, .. }) =
+            router.handle(tagged(dec!(20.00), 4, OrderSide::Ask, 11))
+        else {
+            panic!("the ask should have been placed");
+        };
+        assert_eq!(placed[0].status, OrderStatus::Created);
+        assert_eq!(placed[0].tag, Some(11));
+
+        let bid = tagged(dec!(20.50), 6, OrderSide::Bid, u64::MAX);
+        let trades = router.simulate(&bid).unwrap();
+        assert_eq!(trades[0].tag, Some(u64::MAX));
+        assert_eq!(trades[1].tag, Some(11));
+
+        let Ok(Response::Placed { events, .. }) = router.handle(bid) else {
+            panic!("the bid should have been placed");
+        };
+        let tags: Vec<(OrderStatus, Option<OrderTag>)> = events
+            .iter()
+            .map(|event| (event.status, event.tag))
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                (OrderStatus::PartialFill, Some(u64::MAX)),
+                (OrderStatus::Filled, Some(11)),
+                (OrderStatus::Created, Some(u64::MAX)),
+            ]
+        );
+
+        // orders placed without a tag have none
+        let Ok(Response::Placed { events, .. }) =
+            router.handle(Request::PlaceOrder(PlaceOrder::from(
+                dec!(21.00),
+                1,
+                OrderSide::Ask,
+                OrderType::Limit,
+                trading_pair,
+            )))
+        else {
+            panic!("the ask should have been placed");
+        };
+        assert_eq!(events[0].tag, None);
+    }
+
+    #[test]
+    fn the_price_of_a_market_order_does_not_change_what_it_trades_at() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = account_router();
+        place_for(&router, dec!(20.00), 10, OrderSide::Ask, 1);
+        place_for(&router, dec!(20.50), 10, OrderSide::Ask, 1);
+
+        let fills = |price| {
+            let market =
+                PlaceOrder::from(price, 15, OrderSide::Bid, OrderType::Market, trading_pair);
+            router
+                .simulate(&Request::PlaceOrder(market))
+                .unwrap()
+                .iter()
+                .map(|trade| (trade.price, trade.quantity, trade.price_improvement))
+                .collect::<Vec<_>>()
+        };
+        let expected = fills(dec!(0));
+        assert_eq!(expected[0], (dec!(20.00), 10, dec!(0)));
+        assert_eq!(expected[2], (dec!(20.50), 5, dec!(0)));
+        assert_eq!(fills(dec!(1)), expected);
+        assert_eq!(fills(dec!(1000)), expected);
+
+        let market = PlaceOrder::market(15, OrderSide::Bid, trading_pair);
+        assert_eq!(market.to_order(0).price, dec!(0));
+        assert_eq!(
+            PlaceOrder::from(
+                dec!(1000),
+                15,
+                OrderSide::Bid,
+                OrderType::Market,
+                trading_pair
+            )
+            .to_order(0)
+            .price,
+            dec!(0)
+        );
+        let Ok(Response::Placed { events, .. }) = router.handle(Request::PlaceOrder(market)) else {
+            panic!("the market order should have been placed");
+        };
+        assert_eq!(events[0].at_price, Some(dec!(20.00)));
+    }
+
+    #[test]
+    fn a_market_order_with_a_negative_price_is_rejected() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let market = PlaceOrder::from(dec!(-1), 5, OrderSide::Bid, OrderType::Market, trading_pair);
+        assert!(matches!(
+            market.validate(0, &RouterConfig::default()),
+            Some(Failure::OrderRejected(_))
+        ));
+        assert_eq!(
+            PlaceOrder::market(5, OrderSide::Bid, trading_pair)
+                .validate(0, &RouterConfig::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn a_minimum_fill_has_to_be_within_the_quantity_of_the_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let config = RouterConfig::default();
+        let bid = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let rejected = [
+            bid.clone().with_min_fill(0),
+            bid.clone().with_min_fill(6),
+            PlaceOrder::from_notional(dec!(100), OrderSide::Bid, trading_pair).with_min_fill(1),
+        ];
+        for order in rejected {
+            assert!(matches!(
+                order.validate(0, &config),
+                Some(Failure::OrderRejected(_))
+            ));
+        }
+        assert_eq!(bid.with_min_fill(5).validate(0, &config), None);
+    }
+
+    #[test]
+    fn a_market_order_cannot_be_placed_on_an_inverse_pair() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                allow_inverse_pairs: true,
+                ..Default::default()
+            },
+        );
+        let market = PlaceOrder::market(100, OrderSide::Ask, trading_pair.inverse());
+        assert!(matches!(
+            router.handle(Request::PlaceOrder(market)),
+            Err(Failure::OrderRejected(_))
+        ));
+    }
+
+    #[test]
+    fn an_order_on_the_inverse_pair_is_matched_against_the_registered_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                allow_inverse_pairs: true,
+                ..Default::default()
+            },
+        );
+
+        // sell 10 BTC at 20 USDC each
+        let ask = Request::PlaceOrder(PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        ));
+        assert!(router.handle(ask).is_ok());
+
+        // selling 200 USDC at 0.05 BTC each is the same as buying 10 BTC at 20 USDC each
+        let inverse_ask = Request::PlaceOrder(PlaceOrder::from(
+            dec!(0.05),
+            200,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair.inverse(),
+        ));
+        assert!(router.handle(inverse_ask).is_ok());
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_ask().is_none());
+        assert!(book.peek_top_bid().is_none());
+    }
+
+    #[test]
+    fn the_minimum_fill_of_an_order_on_the_inverse_pair_is_restated_too() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                allow_inverse_pairs: true,
+                ..Default::default()
+            },
+        );
+        let ask = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        );
+        assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+
+        // a minimum of 101 USDC is 5.05 BTC, which isn't a whole number of units
+        let inverse_ask = PlaceOrder::from(
+            dec!(0.05),
+            200,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair.inverse(),
+        );
+        assert!(matches!(
+            router.handle(Request::PlaceOrder(inverse_ask.clone().with_min_fill(101))),
+            Err(Failure::OrderRejected(_))
+        ));
+
+        // a minimum of 100 USDC is the 5 BTC resting in the book
+        assert!(router
+            .handle(Request::PlaceOrder(inverse_ask.with_min_fill(100)))
+            .is_ok());
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_ask().is_none());
+        assert_eq!(book.peek_top_bid().unwrap().quantity, 5);
+    }
+
+    #[test]
+    fn the_display_quantity_of_an_iceberg_on_the_inverse_pair_is_restated_too() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                allow_inverse_pairs: true,
+                ..Default::default()
+            },
+        );
+        let inverse_bid = PlaceOrder::from(
+            dec!(0.05),
+            200,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair.inverse(),
+        );
+        // showing 50 USDC is 2.5 BTC, which isn't a whole number of units
+        assert!(matches!(
+            router.handle(Request::PlaceOrder(
+                inverse_bid.clone().with_display_quantity(50)
+            )),
+            Err(Failure::OrderRejected(_))
+        ));
+
+        // 200 USDC showing 40 at a time is 10 BTC showing 2
+        assert!(router
+            .handle(Request::PlaceOrder(inverse_bid.with_display_quantity(40)))
+            .is_ok());
+        let books = router.books.lock().unwrap();
+        let ask = books.get(&trading_pair).unwrap().peek_top_ask().unwrap();
+        assert_eq!((ask.quantity, ask.hidden_quantity), (2, 8));
+    }
+
+    #[test]
+    fn an_order_too_large_to_restate_on_the_inverse_pair_is_rejected() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                allow_inverse_pairs: true,
+                ..Default::default()
+            },
+        );
+        let bid = PlaceOrder::from(
+            dec!(10_000_000_000),
+            10_000_000_000_000_000_000,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair.inverse(),
+        );
+        assert_eq!(
+            router.handle(Request::PlaceOrder(bid)),
+            Err(Failure::OrderRejected(
+                "Quantity is out of range for the inverse pair".to_string()
+            ))
+        );
+
+        // the books are left as they were for the requests that follow
+        let ask = PlaceOrder::from(dec!(20), 1, OrderSide::Ask, OrderType::Limit, trading_pair);
+        assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+    }
+
+    #[test]
+    fn an_order_on_the_inverse_pair_is_rejected_when_inverse_pairs_are_disabled() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let result = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(0.05),
+            200,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair.inverse(),
+        )));
+        assert_eq!(
+            result.err().unwrap(),
+            Failure::BookNotFound(format!(
+                "No book found for trading pair {:?}",
+                trading_pair.inverse()
+            ))
+        );
+    }
+
+    #[test]
+    fn simulating_an_order_returns_its_trades_without_changing_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let _ = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        )));
+
+        let trades = router
+            .simulate(&Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                10,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            )))
+            .unwrap();
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|trade| trade.quantity == 10));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        let ask = book.peek_top_ask().unwrap();
+        assert_eq!(ask.quantity, 10);
+        assert_eq!(ask.price, dec!(20.00));
+        assert!(book.peek_top_bid().is_none());
+    }
+
+    #[test]
+    fn orders_priced_outside_the_band_around_the_last_trade_are_rejected() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    price_band: Some(dec!(10)),
+                    ..Default::default()
+                },
+            ),
+        )]));
+        let place = |price, side| {
+            router.handle(Request::PlaceOrder(PlaceOrder::from(
+                price,
+                1,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            )))
+        };
+
+        // with no trades there is no reference price, so any price is accepted
+        assert!(place(dec!(100.00), OrderSide::Ask).is_ok());
+        assert!(place(dec!(100.00), OrderSide::Bid).is_ok());
+
+        assert!(place(dec!(109.00), OrderSide::Ask).is_ok());
+        assert_eq!(
+            place(dec!(111.00), OrderSide::Ask).unwrap_err(),
+            Failure::OrderRejected(
+                "Price 111.00 is outside the 10% band around the last traded price 100.00"
+                    .to_string()
+            )
+        );
+    }
+
+    /// Sets up a book with asks at 100.00 and a stop limit bid triggered at 100.00
+    fn book_with_stop_limit_bid(limit_price: Decimal) -> (Router<LimitOrderBook>, TradingPair) {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        for quantity in [5, 10] {
+            let _ = router.handle(Request::PlaceOrder(PlaceOrder::from(
+                dec!(100.00),
+                quantity,
+                OrderSide::Ask,
+                OrderType::Limit,
+                trading_pair,
+            )));
+        }
+        let stop_limit = PlaceOrder::from(
+            limit_price,
+            3,
+            OrderSide::Bid,
+            OrderType::StopLimit,
+            trading_pair,
+        )
+        .with_stop_price(dec!(100.00));
+        assert!(router.handle(Request::PlaceOrder(stop_limit)).is_ok());
+        (router, trading_pair)
+    }
+
+    #[test]
+    fn a_triggered_stop_limit_rests_when_its_limit_price_does_not_cross() {
+        let (router, trading_pair) = book_with_stop_limit_bid(dec!(99.00));
+        {
+            let books = router.books.lock().unwrap();
+            assert!(books.get(&trading_pair).unwrap().peek_top_bid().is_none());
+        }
+
+        let _ = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(100.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Market,
+            trading_pair,
+        )));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        let bid = book.peek_top_bid().unwrap();
+        assert_eq!(bid.order_type, OrderType::Limit);
+        assert_eq!(bid.price, dec!(99.00));
+        assert_eq!(bid.quantity, 3);
+        assert_eq!(book.peek_top_ask().unwrap().quantity, 10);
+    }
+
+    #[test]
+    fn a_triggered_stop_limit_rests_under_its_client_order_id() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = |price, quantity, side, order_type| {
+            PlaceOrder::from(price, quantity, side, order_type, trading_pair)
+        };
+        let ask = place(dec!(12), 1, OrderSide::Ask, OrderType::Limit);
+        assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+        let stop = place(dec!(12), 6, OrderSide::Bid, OrderType::StopLimit)
+            .with_stop_price(dec!(12))
+            .with_client_order_id(42);
+        assert!(router.handle(Request::PlaceOrder(stop)).is_ok());
+
+        let market = place(dec!(0), 1, OrderSide::Bid, OrderType::Market);
+        assert!(router.handle(Request::PlaceOrder(market)).is_ok());
+        // the stop triggered with nothing left to trade against, so all of it rests
+        {
+            let books = router.books.lock().unwrap();
+            assert_eq!(books[&trading_pair].top_bid_level(), Some((dec!(12), 6)));
+        }
+
+        assert!(router
+            .handle(Request::CancelByClientId {
+                client_order_id: 42,
+                trading_pair,
+            })
+            .is_ok());
+        let books = router.books.lock().unwrap();
+        assert!(books[&trading_pair].is_empty());
+    }
+
+    #[test]
+    fn a_fill_or_kill_stop_waits_and_is_killed_when_it_triggers_without_enough_to_fill() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = |price, quantity, side, order_type| {
+            PlaceOrder::from(price, quantity, side, order_type, trading_pair)
+        };
+        for quantity in [5, 10] {
+            let ask = place(dec!(100.00), quantity, OrderSide::Ask, OrderType::Limit);
+            let _ = router.handle(Request::PlaceOrder(ask));
+        }
+        // the stop can't fill when it's placed, but the book isn't known until it triggers
+        let stop = place(dec!(100.00), 20, OrderSide::Bid, OrderType::StopLimit)
+            .with_stop_price(dec!(100.00))
+            .with_time_in_force(TimeInForce::FillOrKill);
+        let Ok(Response::Placed { events, .. }) = router.handle(Request::PlaceOrder(stop)) else {
+            panic!("the stop should have been placed");
+        };
+        assert_eq!(events[0].status, OrderStatus::Created);
+
+        let market = place(dec!(0), 5, OrderSide::Bid, OrderType::Market);
+        let _ = router.handle(Request::PlaceOrder(market));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_bid().is_none());
+        assert_eq!(book.peek_top_ask().unwrap().quantity, 10);
+        assert_eq!(book.len(), 1);
+    }
+
+    #[test]
+    fn a_triggered_stop_limit_fills_when_its_limit_price_crosses() {
+        let (router, trading_pair) = book_with_stop_limit_bid(dec!(101.00));
+
+        let trades = router
+            .simulate(&Request::PlaceOrder(PlaceOrder::from(
+                dec!(100.00),
+                5,
+                OrderSide::Bid,
+                OrderType::Market,
+                trading_pair,
+            )))
+            .unwrap();
+        assert_eq!(trades.len(), 4);
+        assert_eq!(trades[2].quantity, 3);
+        assert_eq!(trades[2].price, dec!(100.00));
+
+        let _ = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(100.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Market,
+            trading_pair,
+        )));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_bid().is_none());
+        assert_eq!(book.peek_top_ask().unwrap().quantity, 7);
+    }
+
+    #[test]
+    fn an_order_can_be_canceled_by_its_client_order_id() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        )
+        .with_client_order_id(42);
+        let _ = router.handle(Request::PlaceOrder(place));
+
+        let result = router.handle(Request::CancelByClientId {
+            client_order_id: 42,
+            trading_pair,
+        });
+        assert!(result.is_ok());
+
+        let books = router.books.lock().unwrap();
+        assert!(books.get(&trading_pair).unwrap().peek_top_bid().is_none());
+    }
+
+    #[test]
+    fn canceling_a_level_cancels_every_order_at_that_price_and_side_only() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = |price, side| {
+            placed_ids(router.handle(Request::PlaceOrder(PlaceOrder::from(
+                price,
+                5,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            ))))[0]
+        };
+        let first = place(dec!(20.00), OrderSide::Ask);
+        let second = place(dec!(20.0), OrderSide::Ask);
+        let third = place(dec!(20.000), OrderSide::Ask);
+        place(dec!(20.50), OrderSide::Ask);
+        place(dec!(19.00), OrderSide::Bid);
+        let cancel_level = |side, price| {
+            router.handle(Request::CancelLevel {
+                trading_pair,
+                side,
+                price,
+            })
+        };
+
+        assert_eq!(
+            cancel_level(OrderSide::Ask, dec!(20)),
+            Ok(Response::Canceled(vec![first, second, third]))
+        );
+        assert_eq!(
+            cancel_level(OrderSide::Bid, dec!(20.50)),
+            Ok(Response::Canceled(vec![]))
+        );
+        let books = router.books.lock().unwrap();
+        assert_eq!(
+            books.get(&trading_pair).unwrap().depth(5),
+            (vec![(dec!(19.00), 5)], vec![(dec!(20.50), 5)])
+        );
+    }
+
+    #[test]
+    fn getting_a_book_returns_every_level_in_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let orders = [
+            (dec!(19.00), 5, OrderSide::Bid),
+            (dec!(20.00), 10, OrderSide::Bid),
+            (dec!(20.0), 4, OrderSide::Bid),
+            (dec!(22.00), 7, OrderSide::Ask),
+            (dec!(21.00), 3, OrderSide::Ask),
+            (dec!(22.00), 1, OrderSide::Ask),
+        ];
+        for (price, quantity, side) in orders {
+            let place = PlaceOrder::from(price, quantity, side, OrderType::Limit, trading_pair);
+            let _ = router.handle(Request::PlaceOrder(place));
+        }
+
+        let level = |price, quantity, orders| BookLevel {
+            price,
+            quantity,
+            orders,
+        };
+        assert_eq!(
+            router.handle(Request::GetBook { trading_pair }),
+            Ok(Response::Book(BookSnapshot {
+                trading_pair,
+                bids: vec![level(dec!(20.00), 14, 2), level(dec!(19.00), 5, 1)],
+                asks: vec![level(dec!(21.00), 3, 1), level(dec!(22.00), 8, 2)],
+            }))
+        );
+    }
+
+    #[test]
+    fn a_book_snapshot_serializes_with_its_levels_in_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = router.handle(Request::PlaceOrder(place));
+
+        let Ok(Response::Book(snapshot)) = router.handle(Request::GetBook { trading_pair }) else {
+            panic!("expected a book snapshot");
+        };
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            concat!(
+                r#"{"trading_pair":{"order_asset":"BTC","price_asset":"USDC"},"#,
+                r#""bids":[{"price":"20.00","quantity":10,"orders":1}],"asks":[]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn getting_an_unknown_book_fails() {
+        let router: Router<LimitOrderBook> = Router::new();
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+
+        assert_eq!(
+            router.handle(Request::GetBook { trading_pair }),
+            Err(Failure::BookNotFound(format!(
+                "No book found for trading pair {:?}",
+                trading_pair
+            )))
+        );
+    }
+
+    #[test]
+    fn summarizing_a_book_gives_its_best_prices_and_totals() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let summarize = || match router.handle(Request::Summary { trading_pair }) {
+            Ok(Response::Summary(summary)) => summary,
+            response => panic!("expected a summary, not {:?}", response),
+        };
+        let orders = [
+            (dec!(19.00), 5, OrderSide::Bid),
+            (dec!(20.00), 10, OrderSide::Bid),
+            (dec!(22.00), 7, OrderSide::Ask),
+            (dec!(21.00), 3, OrderSide::Ask),
+        ];
+        for (price, quantity, side) in orders {
+            let place = PlaceOrder::from(price, quantity, side, OrderType::Limit, trading_pair);
+            let _ = router.handle(Request::PlaceOrder(place));
+        }
+
+        let summary = summarize();
+        assert_eq!(
+            summary,
+            BookSummary {
+                trading_pair,
+                best_bid: Some(dec!(20.00)),
+                best_ask: Some(dec!(21.00)),
+                spread: Some(dec!(1.00)),
+                mid_price: Some(dec!(20.5)),
+                bid_volume: 15,
+                ask_volume: 10,
+                bid_orders: 2,
+                ask_orders: 2,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            concat!(
+                "BTC/USDC\nbid 20.00 ask 21.00 spread 1.00 mid 20.50\n",
+                "bids 15 in 2 orders\nasks 10 in 2 orders"
+            )
+        );
+
+        for price in [dec!(21.00), dec!(22.00)] {
+            let _ = router.handle(Request::CancelLevel {
+                trading_pair,
+                side: OrderSide::Ask,
+                price,
+            });
+        }
+        assert_eq!(
+            summarize().to_string(),
+            concat!(
+                "BTC/USDC\nbid 20.00 ask - spread - mid -\n",
+                "bids 15 in 2 orders\nasks 0 in 0 orders"
+            )
+        );
+    }
+
+    #[test]
+    fn a_summary_gives_the_spread_and_mid_price_of_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    quote_precision: Some(2),
+                    ..Default::default()
+                },
+            ),
+        )]));
+        for (price, side) in [(dec!(20.00), OrderSide::Bid), (dec!(20.25), OrderSide::Ask)] {
+            let place = PlaceOrder::from(price, 1, side, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+
+        let Ok(Response::Summary(summary)) = router.handle(Request::Summary { trading_pair })
+        else {
+            panic!("expected a summary");
+        };
+        let books = router.books.lock().unwrap();
+        assert_eq!(summary.spread, books[&trading_pair].get_spread());
+        assert_eq!(summary.mid_price, books[&trading_pair].mid_price());
+        assert_eq!(summary.mid_price.unwrap().to_string(), "20.13");
+    }
+
+    #[test]
+    fn canceling_an_unknown_order_fails() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let result = router.handle(Request::Cancel(CancelOrder::from(
+            OrderId::new_v4(),
+            trading_pair,
+        )));
+        assert_eq!(
+            result.unwrap_err(),
+            Failure::OrderNotFound("No order found with the given id".to_string())
+        );
+    }
+
+    #[test]
+    fn canceling_an_unknown_client_order_id_fails() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let result = router.handle(Request::CancelByClientId {
+            client_order_id: 42,
+            trading_pair,
+        });
+        assert_eq!(
+            result.unwrap_err(),
+            Failure::OrderNotFound("No order found with client order id 42".to_string())
+        );
+    }
+
+    #[test]
+    fn a_limit_order_that_partially_fills_and_rests_reports_each_event_in_order() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let ask = PlaceOrder::from(
+            dec!(20.00),
+            4,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let Ok(Response::Placed { events, .. }) = router.handle(Request::PlaceOrder(ask)) else {
+            panic!("expected the ask to be placed");
+        };
+        let ask_id = events[0].orderid;
+
+        let bid = PlaceOrder::from(
+            dec!(20.50),
+            10,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let Ok(Response::Placed { events, .. }) = router.handle(Request::PlaceOrder(bid)) else {
+            panic!("expected the bid to be placed");
+        };
+        let bid_id = events[0].orderid;
+        let event = |status, orderid, price, quantity| Event {
+            status,
+            orderid,
+            at_price: Some(price),
+            quantity: Some(quantity),
+            tag: None,
+        };
+        assert_eq!(
+            events,
+            vec![
+                event(OrderStatus::PartialFill, bid_id, dec!(20.00), 4),
+                event(OrderStatus::Filled, ask_id, dec!(20.00), 4),
+                event(OrderStatus::Created, bid_id, dec!(20.50), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_account_matching_its_own_order_is_reported_as_a_wash_trade() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = |price, quantity, side, account_id| {
+            let place = PlaceOrder::from(price, quantity, side, OrderType::Limit, trading_pair)
+                .with_account_id(account_id);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        };
+
+        place(dec!(20.00), 5, OrderSide::Ask, 1);
+        place(dec!(20.50), 5, OrderSide::Ask, 2);
+        place(dec!(21.00), 8, OrderSide::Bid, 1);
+
+        // both asks were matched, but only the one of the same account is a wash trade
+        let wash_trades = router.wash_trades();
+        assert_eq!(wash_trades.len(), 1);
+        assert_eq!(wash_trades[0].side, OrderSide::Ask);
+        assert_eq!(wash_trades[0].price, dec!(20.00));
+        assert_eq!(wash_trades[0].quantity, 5);
+    }
+
+    #[test]
+    fn only_the_most_recent_wash_trades_are_kept() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                wash_trade_capacity: 2,
+                ..Default::default()
+            },
+        );
+        for price in [dec!(20.00), dec!(21.00), dec!(22.00)] {
+            for side in [OrderSide::Ask, OrderSide::Bid] {
+                let place = PlaceOrder::from(price, 5, side, OrderType::Limit, trading_pair)
+                    .with_account_id(1);
+                assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+            }
+        }
+
+        let prices: Vec<_> = router
+            .wash_trades()
+            .iter()
+            .map(|trade| trade.price)
+            .collect();
+        assert_eq!(prices, vec![dec!(21.00), dec!(22.00)]);
+    }
+
+    fn account_router() -> Router<LimitOrderBook> {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]))
+    }
+
+    fn place_for(
+        router: &Router<LimitOrderBook>,
+        price: Decimal,
+        quantity: Long,
+        side: OrderSide,
+        account_id: AccountId,
+    ) {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let place = PlaceOrder::from(price, quantity, side, OrderType::Limit, trading_pair)
+            .with_account_id(account_id);
+        assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+    }
+
+    #[test]
+    fn the_realized_pnl_of_an_account_uses_the_average_cost_of_its_position() {
+        let router = account_router();
+        // buys 10 at 20 and 10 at 22, so holds 20 at an average of 21
+        place_for(&router, dec!(20.00), 10, OrderSide::Ask, 2);
+        place_for(&router, dec!(20.00), 10, OrderSide::Bid, 1);
+        place_for(&router, dec!(22.00), 10, OrderSide::Ask, 2);
+        place_for(&router, dec!(22.00), 10, OrderSide::Bid, 1);
+        assert_eq!(
+            router.realized_pnl(1),
+            HashMap::from([(Asset::USDC, dec!(0))])
+        );
+
+        // sells 15 at 25, realizing 4 on each
+        place_for(&router, dec!(25.00), 15, OrderSide::Bid, 2);
+        place_for(&router, dec!(25.00), 15, OrderSide::Ask, 1);
+        assert_eq!(
+            router.realized_pnl(1),
+            HashMap::from([(Asset::USDC, dec!(60))])
+        );
+        // the other side sold short at an average of 21, and bought back at 25
+        assert_eq!(
+            router.realized_pnl(2),
+            HashMap::from([(Asset::USDC, dec!(-60))])
+        );
+        assert!(router.realized_pnl(3).is_empty());
+    }
+
+    #[test]
+    fn the_realized_pnl_of_an_account_is_kept_apart_for_each_quote_asset() {
+        let btc_usdc = TradingPair::from(Asset::BTC, Asset::USDC);
+        let eth_usdt = TradingPair::from(Asset::ETH, Asset::USDT);
+        let router = Router::with_books(HashMap::from([
+            (btc_usdc, LimitOrderBook::init(btc_usdc)),
+            (eth_usdt, LimitOrderBook::init(eth_usdt)),
+        ]));
+        let trade = |trading_pair, price, side: OrderSide| {
+            for (side, account_id) in [(side, 1), (side.opposite(), 2)] {
+                let place = PlaceOrder::from(price, 5, side, OrderType::Limit, trading_pair)
+                    .with_account_id(account_id);
+                assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+            }
+        };
+
+        trade(btc_usdc, dec!(20.00), OrderSide::Bid);
+        trade(btc_usdc, dec!(22.00), OrderSide::Ask);
+        trade(eth_usdt, dec!(30.00), OrderSide::Bid);
+        trade(eth_usdt, dec!(27.00), OrderSide::Ask);
+
+        assert_eq!(
+            router.realized_pnl(1),
+            HashMap::from([(Asset::USDC, dec!(10)), (Asset::USDT, dec!(-15))])
+        );
+    }
+
+    #[test]
+    fn the_realized_pnl_of_large_trades_saturates_rather_than_overflowing() {
+        let router = account_router();
+        let quantity = 10_000_000_000_000_000_000;
+        place_for(&router, dec!(10_000_000_000), quantity, OrderSide::Ask, 2);
+        place_for(&router, dec!(10_000_000_000), quantity, OrderSide::Bid, 1);
+        place_for(&router, dec!(30_000_000_000), quantity, OrderSide::Bid, 2);
+        place_for(&router, dec!(30_000_000_000), quantity, OrderSide::Ask, 1);
+
+        assert_eq!(
+            router.realized_pnl(1),
+            HashMap::from([(Asset::USDC, Decimal::MAX)])
+        );
+        assert_eq!(
+            router.realized_pnl(2),
+            HashMap::from([(Asset::USDC, Decimal::MIN)])
+        );
+    }
+
+    #[test]
+    fn selling_more_than_was_bought_opens_a_short_that_realizes_when_bought_back() {
+        let router = account_router();
+        place_for(&router, dec!(20.00), 5, OrderSide::Ask, 2);
+        place_for(&router, dec!(20.00), 5, OrderSide::Bid, 1);
+
+        // sells 5 more than it holds, closing the long at 24 and opening a short at 24
+        place_for(&router, dec!(24.00), 10, OrderSide::Bid, 2);
+        place_for(&router, dec!(24.00), 10, OrderSide::Ask, 1);
+        assert_eq!(
+            router.realized_pnl(1),
+            HashMap::from([(Asset::USDC, dec!(20))])
+        );
+
+        place_for(&router, dec!(23.00), 5, OrderSide::Ask, 2);
+        place_for(&router, dec!(23.00), 5, OrderSide::Bid, 1);
+        assert_eq!(
+            router.realized_pnl(1),
+            HashMap::from([(Asset::USDC, dec!(25))])
+        );
+    }
+
+    #[test]
+    fn orders_of_different_accounts_or_without_one_are_not_wash_trades() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let ask = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let bid = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+
+        let _ = router.handle(Request::PlaceOrder(ask.clone().with_account_id(1)));
+        let _ = router.handle(Request::PlaceOrder(bid.clone().with_account_id(2)));
+        let _ = router.handle(Request::PlaceOrder(ask));
+        let _ = router.handle(Request::PlaceOrder(bid));
+
+        assert!(router.wash_trades().is_empty());
+        let books = router.books.lock().unwrap();
+        assert_eq!(books.get(&trading_pair).unwrap().recent_trades(10).len(), 2);
+    }
+
+    fn placed_ids(result: Result<Response, Failure>) -> Vec<OrderId> {
+        match result {
+            Ok(Response::Placed { events, .. }) => {
+                events.iter().map(|event| event.orderid).collect()
+            }
+            other => panic!("expected the order to be placed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_pegged_order_is_matched_once_its_reference_moves_it_across_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let limit = |price, side| {
+            Request::PlaceOrder(PlaceOrder::from(
+                price,
+                5,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            ))
+        };
+        let _ = router.handle(limit(dec!(20.00), OrderSide::Bid));
+        let ask = placed_ids(router.handle(limit(dec!(21.50), OrderSide::Ask)));
+        let peg = placed_ids(router.handle(Request::PlaceOrder(PlaceOrder::from(
+            Decimal::ZERO,
+            5,
+            OrderSide::Bid,
+            OrderType::Pegged {
+                reference: PegReference::BestBid,
+                offset: dec!(1.00),
+            },
+            trading_pair,
+        ))));
+        let book = |router: &Router<LimitOrderBook>| {
+            router
+                .books
+                .lock()
+                .unwrap()
+                .get(&trading_pair)
+                .unwrap()
+                .depth(5)
+        };
+        assert_eq!(book(&router).0, vec![(dec!(21.00), 5), (dec!(20.00), 5)]);
+
+        // a better bid takes the peg to 21.60, through the ask at 21.50
+        let events = match router.handle(limit(dec!(20.60), OrderSide::Bid)) {
+            Ok(Response::Placed { events, .. }) => events,
+            other => panic!("expected the order to be placed, got {:?}", other),
+        };
+        let filled: Vec<OrderId> = events
+            .iter()
+            .filter(|event| event.status == OrderStatus::Filled)
+            .map(|event| event.orderid)
+            .collect();
+        assert_eq!(filled, vec![peg[0], ask[0]]);
+        assert!(events
+            .iter()
+            .filter(|event| event.status == OrderStatus::Filled)
+            .all(|event| event.at_price == Some(dec!(21.50))));
+        assert_eq!(
+            book(&router),
+            (vec![(dec!(20.60), 5), (dec!(20.00), 5)], vec![])
+        );
+    }
+
+    #[test]
+    fn a_busy_lock_is_over_capacity_while_a_poisoned_one_is_an_internal_failure() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = || {
+            Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                5,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            ))
+        };
+        {
+            let _books = router.books.lock().unwrap();
+            assert_eq!(router.handle(place()), Err(Failure::EngineOverCapacity));
+        }
+
+        // a request panicking while it holds the books poisons them
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _books = router.books.lock().unwrap();
+            panic!("failed part way through a request");
+        }));
+        assert!(matches!(router.handle(place()), Err(Failure::Internal(_))));
+        assert!(matches!(router.tick(), Err(Failure::Internal(_))));
+        assert!(matches!(
+            router.simulate(&place()),
+            Err(Failure::Internal(_))
+        ));
+        // telling which pairs are supported is still safe
+        assert!(router.supports(&trading_pair));
+    }
+
+    fn router_with_intake(capacity: usize, timeout_millis: u64) -> Router<LimitOrderBook> {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                intake_capacity: Some(capacity),
+                intake_timeout_millis: timeout_millis,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn bid(price: Decimal) -> Request {
+        Request::PlaceOrder(PlaceOrder::from(
+            price,
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            TradingPair::from(Asset::BTC, Asset::USDC),
+        ))
+    }
+
+    #[test]
+    fn requests_wait_in_the_intake_while_the_books_are_busy() {
+        let router = router_with_intake(8, 10_000);
+        let books = router.books.lock().unwrap();
+
+        std::thread::scope(|scope| {
+            let waiting: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| router.handle(bid(dec!(20.00)))))
+                .collect();
+            // the books are only let go once every request is waiting for them
+            while router.waiting.load(Ordering::SeqCst) < 4 {
+                std::thread::yield_now();
+            }
+            drop(books);
+            for request in waiting {
+                assert!(matches!(
+                    request.join().unwrap(),
+                    Ok(Response::Placed { .. })
+                ));
+            }
+        });
+
+        assert_eq!(router.waiting.load(Ordering::SeqCst), 0);
+        let books = router.books.lock().unwrap();
+        assert_eq!(books.values().next().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn requests_are_over_capacity_once_the_intake_is_full_or_times_out() {
+        let router = router_with_intake(1, 10_000);
+        let books = router.books.lock().unwrap();
+
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| router.handle(bid(dec!(20.00))));
+            while router.waiting.load(Ordering::SeqCst) < 1 {
+                std::thread::yield_now();
+            }
+            assert_eq!(
+                router.handle(bid(dec!(21.00))),
+                Err(Failure::EngineOverCapacity)
+            );
+            drop(books);
+            assert!(waiting.join().unwrap().is_ok());
+        });
+
+        let router = router_with_intake(1, 0);
+        let _books = router.books.lock().unwrap();
+        assert_eq!(
+            router.handle(bid(dec!(20.00))),
+            Err(Failure::EngineOverCapacity)
+        );
+        assert_eq!(router.waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn a_simulation_waits_in_the_intake_like_any_other_request() {
+        let router = router_with_intake(1, 10_000);
+        let books = router.books.lock().unwrap();
+
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| router.simulate(&bid(dec!(20.00))));
+            while router.waiting.load(Ordering::SeqCst) < 1 {
+                std::thread::yield_now();
+            }
+            drop(books);
+            assert_eq!(waiting.join().unwrap(), Ok(Vec::new()));
+        });
+        assert_eq!(router.waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn canceled_expired_and_filled_orders_are_kept_in_the_history() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let clock = Arc::new(MockClock::at(1_000));
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair).with_clock(clock.clone()),
+        )]))
+        .with_clock(clock.clone());
+        let limit = |price, side| PlaceOrder::from(price, 5, side, OrderType::Limit, trading_pair);
+
+        let canceled =
+            placed_ids(router.handle(Request::PlaceOrder(limit(dec!(18.00), OrderSide::Bid))))[0];
+        let _ = router.handle(Request::Cancel(CancelOrder::from(canceled, trading_pair)));
+
+        let expiring =
+            limit(dec!(19.00), OrderSide::Bid).with_time_in_force(TimeInForce::GoodTillDate(2_000));
+        let expired = placed_ids(router.handle(Request::PlaceOrder(expiring)))[0];
+
+        clock.set(3_000);
+        let maker =
+            placed_ids(router.handle(Request::PlaceOrder(limit(dec!(20.00), OrderSide::Ask))))[1];
+        let taker =
+            placed_ids(router.handle(Request::PlaceOrder(limit(dec!(20.00), OrderSide::Bid))))[0];
+
+        assert_eq!(
+            router.terminal_orders(),
+            vec![
+                (canceled, OrderStatus::Canceled),
+                (expired, OrderStatus::Expired),
+                (taker, OrderStatus::Filled),
+                (maker, OrderStatus::Filled),
+            ]
+        );
+        assert_eq!(router.terminated_at(canceled), Some(1_000));
+        assert_eq!(router.terminated_at(expired), Some(3_000));
+    }
+
+    #[test]
+    fn orders_that_expire_before_a_rejected_order_are_kept_in_the_history() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let clock = Arc::new(MockClock::at(1_000));
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair).with_clock(clock.clone()),
+        )]))
+        .with_clock(clock.clone());
+        let limit = |price| {
+            PlaceOrder::from(price, 5, OrderSide::Bid, OrderType::Limit, trading_pair)
+                .with_client_order_id(7)
+        };
+
+        let _ = router.handle(Request::PlaceOrder(limit(dec!(18.00))));
+        let expiring = PlaceOrder::from(
+            dec!(19.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        )
+        .with_time_in_force(TimeInForce::GoodTillDate(2_000));
+        let expired = placed_ids(router.handle(Request::PlaceOrder(expiring)))[0];
+
+        // the order is rejected for reusing a client order id, after the book dropped the
+        // expired. A simulation only drops it from a copy of the book, so records nothing
+        clock.set(3_000);
+        for _ in 0..2 {
+            assert!(router
+                .simulate(&Request::PlaceOrder(limit(dec!(17.00))))
+                .is_err());
+        }
+        assert!(router.terminal_orders().is_empty());
+        assert!(router
+            .handle(Request::PlaceOrder(limit(dec!(17.00))))
+            .is_err());
+        assert_eq!(
+            router.terminal_orders(),
+            vec![(expired, OrderStatus::Expired)]
+        );
+        assert_eq!(router.terminated_at(expired), Some(3_000));
+    }
+
+    #[test]
+    fn an_order_with_a_time_to_live_expires_once_it_has_passed() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let clock = Arc::new(MockClock::at(1_000));
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair).with_clock(clock.clone()),
+        )]))
+        .with_clock(clock.clone());
+        let limit =
+            |price| PlaceOrder::from(price, 5, OrderSide::Bid, OrderType::Limit, trading_pair);
+        let expired_by = |result| match result {
+            Ok(Response::Placed { events, .. }) => events
+                .iter()
+                .filter(|event: &&Event| event.status == OrderStatus::Expired)
+                .map(|event| event.orderid)
+                .collect::<Vec<OrderId>>(),
+            other => panic!("expected the order to be placed, got {:?}", other),
+        };
+
+        let short_lived = placed_ids(router.handle(Request::PlaceOrder(
+            limit(dec!(19.00)).with_ttl_millis(5_000),
+        )))[0];
+
+        // the order is still live up to the end of its time to live
+        clock.advance(4_999);
+        assert!(expired_by(router.handle(Request::PlaceOrder(limit(dec!(18.00))))).is_empty());
+
+        clock.advance(1);
+        assert_eq!(
+            expired_by(router.handle(Request::PlaceOrder(limit(dec!(17.00))))),
+            vec![short_lived]
+        );
+        let books = router.books.lock().unwrap();
+        assert_eq!(books.get(&trading_pair).unwrap().bid_count(), 2);
+    }
+
+    #[test]
+    fn a_time_to_live_must_be_positive_and_only_for_good_till_cancel_orders() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let limit = PlaceOrder::from(
+            dec!(19.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let config = RouterConfig::default();
+
+        assert!(limit
+            .clone()
+            .with_ttl_millis(1)
+            .validate(0, &config)
+            .is_none());
+        assert!(limit
+            .clone()
+            .with_ttl_millis(0)
+            .validate(0, &config)
+            .is_some());
+        assert!(limit
+            .with_time_in_force(TimeInForce::ImmediateOrCancel)
+            .with_ttl_millis(1)
+            .validate(0, &config)
+            .is_some());
+    }
+
+    #[test]
+    fn the_history_only_keeps_the_most_recent_terminal_orders() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                history_capacity: 2,
+                ..Default::default()
+            },
+        );
+
+        let mut canceled = Vec::new();
+        for price in [dec!(18.00), dec!(19.00), dec!(20.00)] {
+            let bid = PlaceOrder::from(price, 5, OrderSide::Bid, OrderType::Limit, trading_pair);
+            let orderid = placed_ids(router.handle(Request::PlaceOrder(bid)))[0];
+            let _ = router.handle(Request::Cancel(CancelOrder::from(orderid, trading_pair)));
+            canceled.push((orderid, OrderStatus::Canceled));
+        }
+
+        assert_eq!(router.terminal_orders(), canceled[1..]);
+    }
+
+    fn batch_router(trading_pair: TradingPair) -> Router<LimitOrderBook> {
+        Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                match_in_batches: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn orders_matched_in_batches_only_trade_when_the_router_ticks() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = batch_router(trading_pair);
+        let orders = [
+            (dec!(20.00), 5, OrderSide::Ask),
+            (dec!(20.50), 5, OrderSide::Ask),
+            (dec!(21.00), 8, OrderSide::Bid),
+        ];
+        for (price, quantity, side) in orders {
+            let place = PlaceOrder::from(price, quantity, side, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+        {
+            let books = router.books.lock().unwrap();
+            let book = books.get(&trading_pair).unwrap();
+            assert!(book.recent_trades(10).is_empty());
+            assert_eq!(book.len(), 3);
+        }
+
+        // the bid arrived last, so it takes the prices of the asks resting before it
+        let trades = router.tick().unwrap();
+        let fills: Vec<(OrderSide, Decimal, Long)> = trades
+            .iter()
+            .map(|trade| (trade.side, trade.price, trade.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (OrderSide::Bid, dec!(20.00), 5),
+                (OrderSide::Ask, dec!(20.00), 5),
+                (OrderSide::Bid, dec!(20.50), 3),
+                (OrderSide::Ask, dec!(20.50), 3),
+            ]
+        );
+        {
+            let books = router.books.lock().unwrap();
+            let book = books.get(&trading_pair).unwrap();
+            assert!(book.peek_top_bid().is_none());
+            assert_eq!(book.top_ask_level(), Some((dec!(20.50), 2)));
+        }
+        assert!(router.tick().unwrap().is_empty());
+    }
+
+    #[test]
+    fn an_order_with_a_minimum_fill_is_matched_like_any_other_at_a_tick() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = batch_router(trading_pair);
+        let ask = PlaceOrder::from(dec!(10), 1, OrderSide::Ask, OrderType::Limit, trading_pair);
+        let bid = PlaceOrder::from(dec!(10), 10, OrderSide::Bid, OrderType::Limit, trading_pair)
+            .with_min_fill(10);
+        for place in [ask, bid] {
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+
+        // the bid was resting before the tick, so its minimum no longer holds
+        let trades = router.tick().unwrap();
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|trade| trade.quantity == 1));
+        assert_eq!(router.check_invariants(), Ok(()));
+        let books = router.books.lock().unwrap();
+        assert_eq!(books[&trading_pair].top_bid_level(), Some((dec!(10), 9)));
+    }
+
+    #[test]
+    fn an_order_short_of_its_minimum_fill_does_not_rest_across_the_book() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let ask = PlaceOrder::from(dec!(12), 1, OrderSide::Ask, OrderType::Limit, trading_pair);
+        assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+
+        let bid = PlaceOrder::from(dec!(13), 5, OrderSide::Bid, OrderType::Limit, trading_pair)
+            .with_min_fill(5);
+        assert!(router.handle(Request::PlaceOrder(bid)).is_ok());
+        assert_eq!(router.check_invariants(), Ok(()));
+        let books = router.books.lock().unwrap();
+        assert!(books[&trading_pair].peek_top_bid().is_none());
+        assert_eq!(books[&trading_pair].top_ask_level(), Some((dec!(12), 1)));
+    }
+
+    #[test]
+    fn orders_that_cannot_rest_are_rejected_when_matching_in_batches() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = batch_router(trading_pair);
+        let ask = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let _ = router.handle(Request::PlaceOrder(ask));
+
+        let market = PlaceOrder::from(dec!(0), 5, OrderSide::Bid, OrderType::Market, trading_pair);
+        let immediate = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        )
+        .with_time_in_force(TimeInForce::ImmediateOrCancel);
+        for place in [market, immediate] {
+            assert!(matches!(
+                router.handle(Request::PlaceOrder(place)),
+                Err(Failure::OrderRejected(_))
+            ));
+        }
+        assert!(router.tick().unwrap().is_empty());
+    }
+
+    #[test]
+    fn orders_placed_while_paused_are_held_until_the_router_resumes() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                queue_while_paused: true,
+                ..Default::default()
+            },
+        );
+
+        router.pause();
+        let result = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        )));
+        assert!(result.is_ok());
+        {
+            let books = router.books.lock().unwrap();
+            assert!(books.get(&trading_pair).unwrap().peek_top_ask().is_none());
+        }
+
+        let results = router.resume();
+        assert!(matches!(results[..], [Ok(Response::Placed { .. })]));
+        let books = router.books.lock().unwrap();
+        assert_eq!(
+            books
+                .get(&trading_pair)
+                .unwrap()
+                .peek_top_ask()
+                .unwrap()
+                .quantity,
+            10
+        );
+    }
+
+    #[test]
+    fn an_order_placed_while_the_router_resumes_runs_after_the_held_orders() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_config(
+            HashMap::from([(trading_pair, LimitOrderBook::init(trading_pair))]),
+            RouterConfig {
+                queue_while_paused: true,
+                ..Default::default()
+            },
+        );
+        let place = |side| {
+            Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                1,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            ))
+        };
+
+        router.pause();
+        for _ in 0..50 {
+            assert_eq!(router.handle(place(OrderSide::Ask)), Ok(Response::Accepted));
+        }
+        thread::scope(|scope| {
+            let resumed = scope.spawn(|| router.resume());
+            // the bid is either held along with the asks, or placed once they all rest, so it
+            // never rests on its own ahead of them
+            match router.handle(place(OrderSide::Bid)) {
+                Ok(Response::Accepted) => (),
+                Ok(Response::Placed { events, .. }) => {
+                    assert!(events.iter().any(|e| e.status == OrderStatus::Filled))
+                }
+                result => panic!("the bid should have been placed or held, not {:?}", result),
+            }
+            assert!(resumed.join().unwrap().iter().all(Result::is_ok));
+        });
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_bid().is_none());
+        assert_eq!(book.ask_count(), 49);
+    }
+
+    #[test]
+    fn replaying_requests_on_a_logical_clock_produces_identical_trades() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let replay = || {
+            let clock = Arc::new(LogicalClock::default());
+            let router = Router::with_books(HashMap::from([(
+                trading_pair,
+                LimitOrderBook::init(trading_pair).with_clock(clock.clone()),
+            )]))
+            .with_clock(clock);
+            let requests = [
+                (dec!(20.00), 5, OrderSide::Ask, OrderType::Limit),
+                (dec!(20.50), 3, OrderSide::Ask, OrderType::Limit),
+                (dec!(20.00), 2, OrderSide::Ask, OrderType::Limit),
+                (dec!(21.00), 6, OrderSide::Bid, OrderType::Limit),
+                (dec!(0), 4, OrderSide::Bid, OrderType::Market),
+            ];
+            for (price, quantity, side, order_type) in requests {
+                let place = PlaceOrder::from(price, quantity, side, order_type, trading_pair);
+                assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+            }
+            let books = router.books.lock().unwrap();
+            // order ids are random, so only the rest of each trade is compared
+            books
+                .get(&trading_pair)
+                .unwrap()
+                .recent_trades(usize::MAX)
+                .into_iter()
+                .map(|trade| Trade {
+                    orderid: Uuid::nil(),
+                    ..trade
+                })
+                .collect::<Vec<Trade>>()
+        };
+
+        let trades = replay();
+        assert_eq!(trades.len(), 4);
+        assert_eq!(trades, replay());
+    }
+
+    #[test]
+    fn orders_are_prioritized_by_the_time_the_clock_gives_them() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let clock = Arc::new(MockClock::at(2_000));
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair).with_clock(clock.clone()),
+        )]))
+        .with_clock(clock.clone());
+        let bid = || {
+            PlaceOrder::from(
+                dec!(20.00),
+                5,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            )
+        };
+
+        let _ = router.handle(Request::PlaceOrder(bid()));
+        // the second bid is stamped earlier, so it is ahead of the first at the same price
+        clock.set(1_000);
+        let _ = router.handle(Request::PlaceOrder(bid()));
+
+        clock.set(3_000);
+        let ask = PlaceOrder::from(
+            dec!(20.00),
+            5,
+            OrderSide::Ask,
+            OrderType::Market,
+            trading_pair,
+        );
+        let _ = router.handle(Request::PlaceOrder(ask));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert_eq!(book.peek_top_bid().unwrap().timestamp, 2_000);
+        let trades = book.recent_trades(1);
+        assert_eq!(trades[0].timestamp, 3_000);
+    }
+
+    #[test]
+    fn a_custom_request_is_passed_to_the_handler_registered_for_it() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]))
+        .with_handler(
+            "cancel_all",
+            |router: &Router<LimitOrderBook>,
+             books: &mut HashMap<TradingPair, LimitOrderBook>,
+             request: Request| {
+                let Request::Custom { trading_pair, .. } = request else {
+                    unreachable!();
+                };
+                let book = books.get_mut(&trading_pair).unwrap();
+                for event in book.cancel_older_than(TimestampMillis::MAX) {
+                    router.log_event(EngineEvent::Canceled {
+                        orderid: event.orderid,
+                        trading_pair,
+                    });
+                }
+                Ok(Response::Accepted)
+            },
+        );
+        for price in [dec!(20.00), dec!(19.00)] {
+            let place = PlaceOrder::from(price, 10, OrderSide::Bid, OrderType::Limit, trading_pair);
+            let _ = router.handle(Request::PlaceOrder(place));
+        }
+
+        let result = router.handle(Request::Custom {
+            kind: "cancel_all".to_string(),
+            trading_pair,
+        });
+        assert_eq!(result, Ok(Response::Accepted));
+
+        let books = router.books.lock().unwrap();
+        assert!(books.get(&trading_pair).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_custom_request_without_a_handler_is_unsupported() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let result = router.handle(Request::Custom {
+            kind: "cancel_all".to_string(),
+            trading_pair,
+        });
+        assert_eq!(
+            result,
+            Err(Failure::UnsupportedOperation(
+                "No handler is registered for cancel_all requests".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn only_market_orders_can_be_placed_by_notional() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let limit = PlaceOrder {
+            order_type: OrderType::Limit,
+            ..PlaceOrder::from_notional(dec!(100.00), OrderSide::Bid, trading_pair)
+        };
+        assert_eq!(
+            router.handle(Request::PlaceOrder(limit)),
+            Err(Failure::OrderRejected(
+                "Only market orders can have a notional".to_string()
+            ))
+        );
+
+        let empty = PlaceOrder::from_notional(Decimal::ZERO, OrderSide::Bid, trading_pair);
+        assert_eq!(
+            router.handle(Request::PlaceOrder(empty)),
+            Err(Failure::OrderRejected(
+                "Notional must be greater than zero".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn limit_orders_must_be_priced_above_zero() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+        let place = |price, order_type| {
+            router.handle(Request::PlaceOrder(PlaceOrder::from(
+                price,
+                3,
+                OrderSide::Bid,
+                order_type,
+                trading_pair,
+            )))
+        };
+
+        assert_eq!(
+            place(dec!(0.00), OrderType::Limit),
+            Err(Failure::OrderRejected(
+                "Price must be greater than zero, not 0.00".to_string()
+            ))
+        );
+        assert_eq!(
+            place(dec!(-5.00), OrderType::Limit),
+            Err(Failure::OrderRejected(
+                "Price must be greater than zero, not -5.00".to_string()
+            ))
+        );
+        assert!(matches!(
+            place(dec!(0.01), OrderType::Limit),
+            Ok(Response::Placed { .. })
+        ));
+        // a stop limit trades at its limit price once triggered, so it is checked too
+        let stop_limit = PlaceOrder::from(
+            dec!(0),
+            3,
+            OrderSide::Bid,
+            OrderType::StopLimit,
+            trading_pair,
+        )
+        .with_stop_price(dec!(100.00));
+        assert!(matches!(
+            router.handle(Request::PlaceOrder(stop_limit)),
+            Err(Failure::OrderRejected(_))
+        ));
+    }
+
+    #[test]
+    fn a_stop_order_without_a_stop_price_is_rejected() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let result = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(100.00),
+            3,
+            OrderSide::Bid,
+            OrderType::Stop,
+            trading_pair,
+        )));
+        assert_eq!(
+            result.unwrap_err(),
+            Failure::OrderRejected("Stop orders must have a stop price".to_string())
+        );
+    }
+
+    #[test]
+    fn a_stop_order_that_the_market_has_already_traded_through_is_rejected() {
+        let (router, trading_pair) = book_with_stop_limit_bid(dec!(99.00));
+        let _ = router.handle(Request::PlaceOrder(PlaceOrder::from(
+            dec!(100.00),
+            1,
+            OrderSide::Bid,
+            OrderType::Market,
+            trading_pair,
+        )));
+
+        // with the last trade at 100.00, a buy stop has to sit above it
+        let inverted = PlaceOrder::from(
+            dec!(100.00),
+            3,
+            OrderSide::Bid,
+            OrderType::Stop,
+            trading_pair,
+        )
+        .with_stop_price(dec!(95.00));
+        assert_eq!(
+            router.handle(Request::PlaceOrder(inverted)).unwrap_err(),
+            Failure::OrderRejected(
+                "Stop price 95.00 has already been reached by the last traded price 100.00"
+                    .to_string()
+            )
+        );
+
+        let sell_stop = PlaceOrder::from(
+            dec!(100.00),
+            3,
+            OrderSide::Ask,
+            OrderType::Stop,
+            trading_pair,
+        )
+        .with_stop_price(dec!(95.00));
+        assert!(router.handle(Request::PlaceOrder(sell_stop)).is_ok());
+    }
+
+    fn quote(bid: Decimal, ask: Decimal, trading_pair: TradingPair) -> Request {
+        Request::PlaceQuote {
+            bid: PlaceOrder::from(bid, 5, OrderSide::Bid, OrderType::Limit, trading_pair),
+            ask: PlaceOrder::from(ask, 5, OrderSide::Ask, OrderType::Limit, trading_pair),
+            account_id: 7,
+        }
+    }
+
+    #[test]
+    fn a_two_sided_quote_places_both_of_its_sides() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        let Ok(Response::Quoted { bid, ask, events }) =
+            router.handle(quote(dec!(99.00), dec!(101.00), trading_pair))
+        else {
+            panic!("expected the quote to be placed");
+        };
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|event| event.status == OrderStatus::Created));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert_eq!(book.peek_top_bid().unwrap().orderid, bid);
+        assert_eq!(book.peek_top_ask().unwrap().orderid, ask);
+        let (bid, ask) = (book.peek_top_bid().unwrap(), book.peek_top_ask().unwrap());
+        assert_eq!((bid.price, bid.account_id), (dec!(99.00), Some(7)));
+        assert_eq!((ask.price, ask.account_id), (dec!(101.00), Some(7)));
+    }
+
+    #[test]
+    fn a_crossed_quote_is_rejected_without_placing_either_side() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::init(trading_pair),
+        )]));
+
+        assert_eq!(
+            router.handle(quote(dec!(101.00), dec!(101.00), trading_pair)),
+            Err(Failure::OrderRejected(
+                "Quote is crossed, the bid at 101.00 must be below the ask at 101.00".to_string()
+            ))
+        );
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_bid().is_none());
+        assert!(book.peek_top_ask().is_none());
+    }
+
+    #[test]
+    fn a_quote_is_placed_whole_when_its_bid_trades_the_ask_out_of_the_band() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    price_band: Some(dec!(10)),
+                    ..Default::default()
+                },
+            ),
+        )]));
+        for side in [OrderSide::Ask, OrderSide::Bid] {
+            let place = PlaceOrder::from(dec!(100.00), 1, side, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+        let resting = PlaceOrder::from(
+            dec!(91.00),
+            1,
+            OrderSide::Ask,
+            OrderType::Limit,
+            trading_pair,
+        );
+        assert!(router.handle(Request::PlaceOrder(resting)).is_ok());
+
+        // the bid trades at 91.00, which leaves the ask outside the band around the last trade,
+        // but both sides were within it when the quote was accepted
+        let trades = router
+            .simulate(&quote(dec!(100.00), dec!(105.00), trading_pair))
+            .unwrap();
+        assert!(!trades.is_empty() && trades.iter().all(|trade| trade.price == dec!(91.00)));
+        let Ok(Response::Quoted { ask, .. }) =
+            router.handle(quote(dec!(100.00), dec!(105.00), trading_pair))
+        else {
+            panic!("the quote should have been placed");
+        };
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert_eq!(book.last_traded_price(), Some(dec!(91.00)));
+        assert_eq!(book.peek_top_ask().unwrap().orderid, ask);
+    }
+
+    #[test]
+    fn a_quote_with_one_side_rejected_by_the_book_places_neither_side() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let router = Router::with_books(HashMap::from([(
+            trading_pair,
+            LimitOrderBook::with_config(
+                trading_pair,
+                BookConfig {
+                    price_band: Some(dec!(10)),
+                    ..Default::default()
+                },
+            ),
+        )]));
+        for side in [OrderSide::Ask, OrderSide::Bid] {
+            let place = PlaceOrder::from(dec!(100.00), 1, side, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+
+        // the ask is outside the band around the last trade at 100.00
+        let result = router.handle(quote(dec!(95.00), dec!(115.00), trading_pair));
+        assert!(matches!(result, Err(Failure::OrderRejected(_))));
+
+        let books = router.books.lock().unwrap();
+        let book = books.get(&trading_pair).unwrap();
+        assert!(book.peek_top_bid().is_none());
+        assert!(book.peek_top_ask().is_none());
+    }
+
+    fn books_for_a_spread() -> (Router<LimitOrderBook>, TradingPair, TradingPair) {
+        let btc_usdc = TradingPair::from(Asset::BTC, Asset::USDC);
+        let eth_usdc = TradingPair::from(Asset::ETH, Asset::USDC);
+        let router = Router::with_books(HashMap::from([
+            (btc_usdc, LimitOrderBook::init(btc_usdc)),
+            (eth_usdc, LimitOrderBook::init(eth_usdc)),
+        ]));
+        let resting = PlaceOrder::from(dec!(10.00), 5, OrderSide::Bid, OrderType::Limit, eth_usdc)
+            .with_client_order_id(1);
+        assert!(router.handle(Request::PlaceOrder(resting)).is_ok());
+        (router, btc_usdc, eth_usdc)
+    }
+
+    #[test]
+    fn linked_orders_are_placed_in_both_of_their_books() {
+        let (router, btc_usdc, eth_usdc) = books_for_a_spread();
+
+        let Ok(Response::Linked {
+            first,
+            second,
+            events,
+        }) = router.handle(Request::PlaceLinked {
+            first: PlaceOrder::from(dec!(300.00), 1, OrderSide::Bid, OrderType::Limit, btc_usdc),
+            second: PlaceOrder::from(dec!(10.00), 5, OrderSide::Ask, OrderType::Limit, eth_usdc),
+        })
+        else {
+            panic!("expected both linked orders to be placed");
+        };
+        let statuses: Vec<(OrderId, OrderStatus)> = events
+            .iter()
+            .map(|event| (event.orderid, event.status))
+            .collect();
+        // the second order fills against the bid resting in its book
+        assert_eq!(statuses.len(), 3);
+        assert_eq!(statuses[0], (first, OrderStatus::Created));
+        assert_eq!(statuses[1], (second, OrderStatus::Filled));
+
+        let books = router.books.lock().unwrap();
+        assert_eq!(books[&btc_usdc].peek_top_bid().unwrap().orderid, first);
+        assert!(books[&eth_usdc].peek_top_bid().is_none());
+        assert!(books[&eth_usdc].peek_top_ask().is_none());
+    }
+
+    #[test]
+    fn a_linked_order_rejected_by_its_book_places_neither_order() {
+        let (router, btc_usdc, eth_usdc) = books_for_a_spread();
+
+        // the second order reuses the client order id of the bid resting in its book
+        let second = PlaceOrder::from(dec!(10.00), 5, OrderSide::Ask, OrderType::Limit, eth_usdc)
+            .with_client_order_id(1);
+        assert_eq!(
+            router.handle(Request::PlaceLinked {
+                first: PlaceOrder::from(
+                    dec!(300.00),
+                    1,
+                    OrderSide::Bid,
+                    OrderType::Limit,
+                    btc_usdc
+                ),
+                second,
+            }),
+            Err(Failure::OrderRejected(
+                "Client order id 1 is already in use".to_string()
+            ))
+        );
+
+        let books = router.books.lock().unwrap();
+        assert!(books[&btc_usdc].is_empty());
+        assert_eq!(books[&eth_usdc].len(), 1);
+    }
+
+    #[test]
+    fn linked_orders_must_be_placed_in_different_books() {
+        let (router, btc_usdc, _) = books_for_a_spread();
+        let order = |side| PlaceOrder::from(dec!(300.00), 1, side, OrderType::Limit, btc_usdc);
+
+        assert_eq!(
+            router.handle(Request::PlaceLinked {
+                first: order(OrderSide::Bid),
+                second: order(OrderSide::Ask),
+            }),
+            Err(Failure::OrderRejected(
+                "Linked orders must be for different trading pairs".to_string()
+            ))
+        );
+        assert!(router.books.lock().unwrap()[&btc_usdc].is_empty());
+    }
+
+    fn sharded(policy: ShardPolicy) -> (Router<LimitOrderBook>, TradingPair) {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let shards = (0..2).map(|_| LimitOrderBook::init(trading_pair)).collect();
+        let router = Router::new().with_shards(trading_pair, shards, policy);
+        (router, trading_pair)
+    }
+
+    fn shard_lens(router: &Router<LimitOrderBook>, trading_pair: TradingPair) -> Vec<usize> {
+        router.shards[&trading_pair]
+            .books()
+            .iter()
+            .map(|book| book.lock().unwrap().len())
+            .collect()
+    }
+
+    #[test]
+    fn orders_are_spread_across_the_shards_of_a_pair() {
+        let (router, trading_pair) = sharded(ShardPolicy::RoundRobin);
+        for price in [dec!(10.00), dec!(11.00), dec!(12.00), dec!(13.00)] {
+            let place = PlaceOrder::from(price, 1, OrderSide::Bid, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+        assert_eq!(shard_lens(&router, trading_pair), vec![2, 2]);
+        assert!(router.supports(&trading_pair));
+        assert_eq!(router.pairs(), vec![trading_pair]);
+
+        // orders priced in the same band always go to the same shard
+        let (router, trading_pair) = sharded(ShardPolicy::PriceBand { width: dec!(10) });
+        for price in [dec!(15.00), dec!(25.00), dec!(19.99), dec!(35.00)] {
+            let place = PlaceOrder::from(price, 1, OrderSide::Bid, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+        assert_eq!(shard_lens(&router, trading_pair), vec![1, 3]);
+        assert_eq!(router.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn the_top_of_a_sharded_book_is_the_best_of_every_shard() {
+        let (router, trading_pair) = sharded(ShardPolicy::RoundRobin);
+        // the orders alternate between the two shards
+        for (price, side) in [
+            (dec!(20.00), OrderSide::Bid),
+            (dec!(19.00), OrderSide::Bid),
+            (dec!(22.00), OrderSide::Ask),
+            (dec!(21.00), OrderSide::Ask),
+            (dec!(19.00), OrderSide::Bid),
+        ] {
+            let place = PlaceOrder::from(price, 2, side, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+
+        assert_eq!(
+            router.best_price(trading_pair, OrderSide::Bid),
+            Some(dec!(20.00))
+        );
+        assert_eq!(
+            router.best_price(trading_pair, OrderSide::Ask),
+            Some(dec!(21.00))
+        );
+        let Ok(Response::Book(book)) = router.handle(Request::GetBook { trading_pair }) else {
+            panic!("expected the book of the pair");
+        };
+        let level = |price, quantity, orders| BookLevel {
+            price,
+            quantity,
+            orders,
+        };
+        assert_eq!(
+            book.bids,
+            vec![level(dec!(20.00), 2, 1), level(dec!(19.00), 4, 2)]
+        );
+        assert_eq!(
+            book.asks,
+            vec![level(dec!(21.00), 2, 1), level(dec!(22.00), 2, 1)]
+        );
+
+        // a market order trades at the best price of any shard
+        let market = PlaceOrder::market(2, OrderSide::Bid, trading_pair);
+        let Ok(Response::Placed { events, .. }) = router.handle(Request::PlaceOrder(market)) else {
+            panic!("expected the market order to fill");
+        };
+        assert_eq!(events[0].at_price, Some(dec!(21.00)));
+        assert_eq!(
+            router.best_price(trading_pair, OrderSide::Ask),
+            Some(dec!(22.00))
+        );
+    }
+
+    #[test]
+    fn a_sharded_pair_is_summed_up_across_its_shards() {
+        let (router, trading_pair) = sharded(ShardPolicy::RoundRobin);
+        // the bid goes to the first shard and the ask to the second
+        for (price, side) in [(dec!(20.00), OrderSide::Bid), (dec!(21.00), OrderSide::Ask)] {
+            let place = PlaceOrder::from(price, 2, side, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(place)).is_ok());
+        }
+        assert_eq!(shard_lens(&router, trading_pair), vec![1, 1]);
+
+        let Ok(Response::Summary(summary)) = router.handle(Request::Summary { trading_pair })
+        else {
+            panic!("expected a summary");
+        };
+        assert_eq!(
+            summary.to_string(),
+            concat!(
+                "BTC/USDC\nbid 20.00 ask 21.00 spread 1.00 mid 20.50\n",
+                "bids 2 in 1 orders\nasks 2 in 1 orders"
+            )
+        );
+    }
+
+    #[test]
+    fn a_crossing_order_is_matched_across_the_shards_best_price_first() {
+        let (router, trading_pair) = sharded(ShardPolicy::RoundRobin);
+        // the asks at 21.00 and 22.00 go to the first shard, and the one at 20.00 to the second
+        for price in [dec!(21.00), dec!(20.00), dec!(22.00)] {
+            let ask = PlaceOrder::from(price, 2, OrderSide::Ask, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+        }
+
+        let bid = PlaceOrder::from(
+            dec!(21.50),
+            5,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        );
+        let Ok(Response::Placed { events, .. }) = router.handle(Request::PlaceOrder(bid)) else {
+            panic!("expected the bid to be placed");
+        };
+        let prices: Vec<Option<Decimal>> = events.iter().map(|event| event.at_price).collect();
+        assert_eq!(
+            prices,
+            vec![
+                Some(dec!(20.00)),
+                Some(dec!(20.00)),
+                Some(dec!(21.00)),
+                Some(dec!(21.00)),
+                Some(dec!(21.50))
+            ]
+        );
+        assert_eq!(events[4].status, OrderStatus::Created);
+        // what's left of the bid rests without crossing the ask left in either shard
+        assert_eq!(
+            router.best_price(trading_pair, OrderSide::Bid),
+            Some(dec!(21.50))
+        );
+        assert_eq!(
+            router.best_price(trading_pair, OrderSide::Ask),
+            Some(dec!(22.00))
+        );
+        assert_eq!(shard_lens(&router, trading_pair), vec![1, 1]);
+    }
+
+    #[test]
+    fn an_order_matched_across_capped_shards_is_only_filled_once_all_of_it_is() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let config = BookConfig {
+            max_fills_per_order: NonZeroUsize::new(2),
+            ..Default::default()
+        };
+        let shards = (0..2)
+            .map(|_| LimitOrderBook::with_config(trading_pair, config))
+            .collect();
+        let router = Router::new().with_shards(trading_pair, shards, ShardPolicy::RoundRobin);
+        for price in [dec!(10), dec!(11)] {
+            let ask = PlaceOrder::from(price, 3, OrderSide::Ask, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+        }
+
+        let bid = PlaceOrder::from(dec!(11), 10, OrderSide::Bid, OrderType::Limit, trading_pair);
+        let Ok(Response::Placed { orderid, events }) = router.handle(Request::PlaceOrder(bid))
+        else {
+            panic!("expected the bid to be placed");
+        };
+        let statuses: Vec<(OrderStatus, Option<Long>)> = events
+            .iter()
+            .filter(|event| event.orderid == orderid)
+            .map(|event| (event.status, event.quantity))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (OrderStatus::PartialFill, Some(3)),
+                (OrderStatus::PartialFill, Some(3)),
+                (OrderStatus::Created, Some(4)),
+            ]
+        );
+        assert!(router
+            .terminal_orders()
+            .iter()
+            .all(|(terminated, _)| *terminated != orderid));
+    }
+
+    #[test]
+    fn a_fill_or_kill_order_is_filled_from_every_shard_or_not_at_all() {
+        let (router, trading_pair) = sharded(ShardPolicy::RoundRobin);
+        for price in [dec!(20.00), dec!(21.00)] {
+            let ask = PlaceOrder::from(price, 2, OrderSide::Ask, OrderType::Limit, trading_pair);
+            assert!(router.handle(Request::PlaceOrder(ask)).is_ok());
+        }
+        let fill_or_kill = |quantity| {
+            PlaceOrder::from(
+                dec!(21.00),
+                quantity,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            )
+            .with_time_in_force(TimeInForce::FillOrKill)
+        };
+
+        let Ok(Response::Placed { events, .. }) =
+            router.handle(Request::PlaceOrder(fill_or_kill(5)))
+        else {
+            panic!("expected the bid to be killed");
+        };
+        assert!(events.is_empty());
+        assert_eq!(shard_lens(&router, trading_pair), vec![1, 1]);
+
+        assert!(router.handle(Request::PlaceOrder(fill_or_kill(4))).is_ok());
+        assert_eq!(shard_lens(&router, trading_pair), vec![0, 0]);
+    }
+
+    #[test]
+    fn a_sharded_pair_only_takes_limit_and_market_orders_and_cancels_of_its_orders() {
+        let (router, trading_pair) = sharded(ShardPolicy::RoundRobin);
+        let stop = PlaceOrder::from(dec!(0), 2, OrderSide::Bid, OrderType::Stop, trading_pair)
+            .with_stop_price(dec!(21.00));
+        assert!(matches!(
+            router.handle(Request::PlaceOrder(stop)),
+            Err(Failure::UnsupportedOperation(_))
+        ));
+        assert!(matches!(
+            router.handle(Request::Cancel(CancelOrder::from(
+                Uuid::new_v4(),
+                trading_pair
+            ))),
+            Err(Failure::OrderNotFound(_))
+        ));
+
+        // a pair can't be split across no books
+        let router = Router::<LimitOrderBook>::new().with_shards(
+            trading_pair,
+            Vec::new(),
+            ShardPolicy::RoundRobin,
+        );
+        assert!(!router.supports(&trading_pair));
     }
 }
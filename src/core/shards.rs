@@ -0,0 +1,211 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    PoisonError,
+};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use super::{
+    intake::{IntakeGuard, IntakeLock},
+    model::{BookSnapshot, BookSummary, Order, TradingPair},
+    orderbook::OrderBook,
+    types::{Long, OrderSide, OrderType, ShardPolicy},
+};
+
+/// The books a hot pair is split across, each behind a lock of its own so cancels and reads of
+/// different shards don't wait on each other. An order is matched against every shard, best
+/// price first, with all of them locked, so the pair as a whole is never left crossed. What's
+/// left of it rests in the shard its policy picks
+pub struct Shards<T> {
+    books: Vec<IntakeLock<T>>,
+    policy: ShardPolicy,
+    /// the shard the next order is placed in, when they are placed in turn
+    next: AtomicUsize,
+}
+
+impl<T: OrderBook> Shards<T> {
+    pub fn new(books: Vec<T>, policy: ShardPolicy) -> Self {
+        Self {
+            books: books.into_iter().map(IntakeLock::new).collect(),
+            policy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the lock of every shard, in order
+    pub fn books(&self) -> &[IntakeLock<T>] {
+        &self.books
+    }
+
+    /// Picks the shard an order rests in, by the policy. Market orders never rest, so the shard
+    /// only checks they are acceptable
+    pub fn shard_for(&self, order: &Order) -> usize {
+        let count = self.books.len().max(1);
+        match self.policy {
+            ShardPolicy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % count,
+            ShardPolicy::PriceBand { width } if width > Decimal::ZERO => {
+                let band = (order.price / width).floor().to_usize().unwrap_or(0);
+                band % count
+            }
+            ShardPolicy::PriceBand { .. } => 0,
+        }
+    }
+
+    /// Gets the best price on one side of the pair, across every shard
+    pub fn best_price(&self, side: OrderSide) -> Option<Decimal> {
+        self.best(side).map(|(_, price)| price)
+    }
+
+    /// Gets every price level of the pair, with the levels of the shards at the same price
+    /// summed together
+    pub fn snapshot(&self, trading_pair: TradingPair) -> BookSnapshot {
+        let mut snapshot = BookSnapshot {
+            trading_pair,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        };
+        for book in &self.books {
+            snapshot.merge(&lock(book).snapshot());
+        }
+        snapshot
+    }
+
+    /// Sums up the pair at a glance, with every shard locked in order so the summary is of one
+    /// moment. The best prices can rest in different shards, so the spread and mid price are
+    /// worked out from them and rounded as the shards round their own
+    pub fn summary(&self, trading_pair: TradingPair) -> BookSummary {
+        let books: Vec<_> = self.books.iter().map(lock).collect();
+        let mut snapshot = BookSnapshot {
+            trading_pair,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        };
+        for book in &books {
+            snapshot.merge(&book.snapshot());
+        }
+        let best_bid = snapshot.bids.first().map(|level| level.price);
+        let best_ask = snapshot.asks.first().map(|level| level.price);
+        let (spread, mid_price) = match (books.first(), best_bid.zip(best_ask)) {
+            (Some(book), Some((bid, ask))) => (
+                Some(book.round_price(ask - bid)),
+                Some(book.round_price((ask + bid) / Decimal::TWO)),
+            ),
+            _ => (None, None),
+        };
+        snapshot.summary(spread, mid_price)
+    }
+
+    /// Finds the shard with the best price on one side, with the price. The first of them is
+    /// found when several share it
+    fn best(&self, side: OrderSide) -> Option<(usize, Decimal)> {
+        let prices =
+            self.books.iter().enumerate().filter_map(|(index, book)| {
+                top_price(&*lock(book), side).map(|price| (index, price))
+            });
+        prices.reduce(|best, next| {
+            let better = match side {
+                OrderSide::Bid => next.1 > best.1,
+                OrderSide::Ask => next.1 < best.1,
+            };
+            if better {
+                next
+            } else {
+                best
+            }
+        })
+    }
+}
+
+/// Finds the shard with the best price an order can trade at, with the price. When several
+/// shards share it, the one whose order there was placed first is found
+pub fn best_to_match<T: OrderBook>(
+    books: &[IntakeGuard<'_, T>],
+    order: &Order,
+) -> Option<(usize, Decimal)> {
+    let side = order.side.opposite();
+    books
+        .iter()
+        .enumerate()
+        .filter_map(|(index, book)| {
+            let top = match side {
+                OrderSide::Bid => book.peek_top_bid(),
+                OrderSide::Ask => book.peek_top_ask(),
+            }?;
+            is_within_limit(order, top.price).then_some((index, top.price, top.timestamp))
+        })
+        .min_by_key(|&(index, price, timestamp)| {
+            let price = match side {
+                OrderSide::Bid => -price,
+                OrderSide::Ask => price,
+            };
+            (price, timestamp, index)
+        })
+        .map(|(index, price, _)| (index, price))
+}
+
+/// Gets the quantity an order could be filled with across every shard, as it would be matched
+/// against them. When the books cap the fills an order can take, only that many of the best
+/// orders are counted, as [Matcher](super::matcher::Matcher) does for a single book
+pub fn fillable<T: OrderBook>(books: &[IntakeGuard<'_, T>], order: &Order) -> Long {
+    let Some(max_fills) = books.first().and_then(|book| book.max_fills_per_order()) else {
+        return books
+            .iter()
+            .map(|book| book.crossing_quantity(order))
+            .fold(0, Long::saturating_add);
+    };
+    let mut top: Vec<Order> = books
+        .iter()
+        .flat_map(|book| top_n(&**book, order.side.opposite(), max_fills.get()))
+        .filter(|resting| is_within_limit(order, resting.price))
+        .collect();
+    top.sort_by_key(|resting| {
+        let price = match order.side {
+            OrderSide::Bid => resting.price,
+            OrderSide::Ask => -resting.price,
+        };
+        (price, resting.timestamp)
+    });
+    top.iter()
+        .take(max_fills.get())
+        .map(|resting| resting.quantity)
+        .fold(0, Long::saturating_add)
+}
+
+/// Gets the quantity shown by up to `n` of the first orders resting at a price on one side of a
+/// book
+pub fn level_quantity<T: OrderBook>(book: &T, side: OrderSide, price: Decimal, n: usize) -> Long {
+    top_n(book, side, n)
+        .iter()
+        .take_while(|resting| resting.price == price)
+        .map(|resting| resting.quantity)
+        .fold(0, Long::saturating_add)
+}
+
+fn top_n<T: OrderBook>(book: &T, side: OrderSide, n: usize) -> Vec<Order> {
+    match side {
+        OrderSide::Bid => book.top_n_bids(n),
+        OrderSide::Ask => book.top_n_asks(n),
+    }
+}
+
+/// Checks that an order could trade at a price on the opposite side, which a market order can
+/// at any price
+fn is_within_limit(order: &Order, price: Decimal) -> bool {
+    match (order.order_type, order.side) {
+        (OrderType::Market, _) => true,
+        (_, OrderSide::Bid) => price <= order.price,
+        (_, OrderSide::Ask) => price >= order.price,
+    }
+}
+
+/// Locks a shard to read from it. Reads go ahead on a poisoned shard, as they change nothing
+fn lock<T>(book: &IntakeLock<T>) -> IntakeGuard<'_, T> {
+    book.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn top_price<T: OrderBook>(book: &T, side: OrderSide) -> Option<Decimal> {
+    match side {
+        OrderSide::Bid => book.peek_top_bid().map(|order| order.price),
+        OrderSide::Ask => book.peek_top_ask().map(|order| order.price),
+    }
+}
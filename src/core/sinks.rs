@@ -0,0 +1,101 @@
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex, PoisonError,
+};
+
+use log::info;
+
+use super::{
+    model::EngineEvent,
+    router::{Subscriber, EVENT_LOG_TARGET},
+};
+
+/// An output the events of an engine are written to, configured with
+/// [EngineConfig::with_event_sink](crate::EngineConfig::with_event_sink). Unlike a
+/// [Subscriber], a sink is handed each event to keep, and is only ever called for one event at
+/// a time. Sinks are called with the books locked, so they should hand events off rather than do
+/// slow work
+pub trait EventSink: Send {
+    fn emit(&mut self, event: EngineEvent);
+}
+
+impl<S: EventSink + ?Sized> EventSink for Box<S> {
+    fn emit(&mut self, event: EngineEvent) {
+        (**self).emit(event)
+    }
+}
+
+/// A sink is passed the events of a router like any other subscriber, one at a time
+impl<S: EventSink> Subscriber for Mutex<S> {
+    fn notify(&self, event: &EngineEvent) {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .emit(*event);
+    }
+}
+
+/// Writes every event to the log under [EVENT_LOG_TARGET], whatever the verbosity of the engine
+#[derive(Debug, Default)]
+pub struct LogSink;
+
+impl EventSink for LogSink {
+    fn emit(&mut self, event: EngineEvent) {
+        info!(target: EVENT_LOG_TARGET, "{}", event);
+    }
+}
+
+/// Keeps every event in memory, eg to assert on in tests. Clones share the same events, so a
+/// clone kept back can read what the engine wrote to the one it was given
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    events: Arc<Mutex<Vec<EngineEvent>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets every event written to the sink so far, oldest first
+    pub fn events(&self) -> Vec<EngineEvent> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl EventSink for MemorySink {
+    fn emit(&mut self, event: EngineEvent) {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event);
+    }
+}
+
+/// Sends every event down a channel, so they can be consumed on another thread. Events are
+/// dropped once the receiving end is, and the channel closes when the engine is dropped
+#[derive(Debug)]
+pub struct ChannelSink {
+    sender: Sender<EngineEvent>,
+}
+
+impl ChannelSink {
+    pub fn new(sender: Sender<EngineEvent>) -> Self {
+        Self { sender }
+    }
+
+    /// Creates a sink with a new channel, and the end its events are received from
+    pub fn channel() -> (Self, Receiver<EngineEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self::new(sender), receiver)
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn emit(&mut self, event: EngineEvent) {
+        // a receiver that hung up doesn't want any more events
+        let _ = self.sender.send(event);
+    }
+}
@@ -1,11 +1,27 @@
 use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{error::Error, fmt, str::FromStr};
+
+use super::utils::Util;
 use uuid::Uuid;
 
 pub type OrderId = Uuid;
+/// An id chosen by the client for its own order, as opposed to the [OrderId] the engine assigns
+pub type ClientOrderId = u64;
+/// An id for the account an order was placed by
+pub type AccountId = u64;
+/// An opaque tag an integrator attaches to an order, eg the id of the strategy or the source
+/// that placed it. The engine never reads it, and only passes it on to the trades and events of
+/// the order
+pub type OrderTag = u64;
 pub type Long = u64;
 pub type TimestampMillis = u128;
+/// A price on one side of the book with the total quantity resting at it
+pub type PriceLevel = (Decimal, Long);
+/// The price levels of a book as (bids, asks), each side ordered from the best price out
+pub type Depth = (Vec<PriceLevel>, Vec<PriceLevel>);
 
-#[derive(Eq, PartialEq, Copy, Ord, PartialOrd, Clone, Hash, Debug)]
+#[derive(Eq, PartialEq, Copy, Ord, PartialOrd, Clone, Hash, Debug, Serialize)]
 pub enum Asset {
     BTC,
     ETH,
@@ -14,27 +30,264 @@ pub enum Asset {
     DOT,
 }
 
+impl FromStr for Asset {
+    type Err = Failure;
+
+    /// Parses an asset from its symbol, eg "BTC". Symbols are matched case insensitively
+    fn from_str(symbol: &str) -> Result<Self, Self::Err> {
+        match symbol.trim().to_ascii_uppercase().as_str() {
+            "BTC" => Ok(Asset::BTC),
+            "ETH" => Ok(Asset::ETH),
+            "USDT" => Ok(Asset::USDT),
+            "USDC" => Ok(Asset::USDC),
+            "DOT" => Ok(Asset::DOT),
+            _ => Err(Failure::InvalidTradingPair(format!(
+                "Unknown asset {}",
+                symbol
+            ))),
+        }
+    }
+}
+
+/// One side of an execution. Trades are only made by the matcher, or else with [Trade::new],
+/// so the fields are read with the getters of the same name
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Trade {
-    pub orderid: OrderId,
-    pub side: OrderSide,
-    pub price: Decimal,
-    pub status: OrderStatus,
-    pub quantity: Long,
-    pub timestamp: Long,
+    pub(crate) orderid: OrderId,
+    /// the account of the order that traded, when it was placed for one
+    pub(crate) account_id: Option<AccountId>,
+    /// the tag of the order that traded, when it was placed with one
+    pub(crate) tag: Option<OrderTag>,
+    pub(crate) side: OrderSide,
+    pub(crate) price: Decimal,
+    pub(crate) status: OrderStatus,
+    pub(crate) quantity: Long,
+    pub(crate) timestamp: TimestampMillis,
+    /// how much better than its limit price the order traded, which is only ever above zero for
+    /// a limit order crossing the book. Makers trade at their own price, so get no improvement
+    pub(crate) price_improvement: Decimal,
 }
 
-#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy)]
+impl Trade {
+    /// Creates the trade of one side of an execution, for an order with no account and no
+    /// price improvement. Fails when the quantity is zero or the price isn't above zero, since
+    /// no such trade can come out of a book
+    pub fn new(
+        orderid: OrderId,
+        side: OrderSide,
+        price: Decimal,
+        quantity: Long,
+        status: OrderStatus,
+        timestamp: TimestampMillis,
+    ) -> Result<Self, Failure> {
+        if quantity == 0 {
+            return Err(Failure::OrderRejected(
+                "A trade needs a quantity above zero".to_string(),
+            ));
+        }
+        if price <= Decimal::ZERO {
+            return Err(Failure::OrderRejected(format!(
+                "A trade needs a price above zero, not {}",
+                price
+            )));
+        }
+        Ok(Self {
+            orderid,
+            account_id: None,
+            tag: None,
+            side,
+            price,
+            status,
+            quantity,
+            timestamp,
+            price_improvement: Decimal::ZERO,
+        })
+    }
+
+    /// Sets the account of the order that traded
+    pub fn with_account_id(mut self, account_id: AccountId) -> Self {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    /// Sets the tag of the order that traded
+    pub fn with_tag(mut self, tag: OrderTag) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Sets how much better than its limit price the order traded. An improvement below zero
+    /// would mean the order traded through its limit, so is taken as none
+    pub fn with_price_improvement(mut self, price_improvement: Decimal) -> Self {
+        self.price_improvement = price_improvement.max(Decimal::ZERO);
+        self
+    }
+
+    /// Gets the engine id of the order that traded
+    pub fn orderid(&self) -> OrderId {
+        self.orderid
+    }
+
+    /// Gets the account of the order that traded, when it was placed for one
+    pub fn account_id(&self) -> Option<AccountId> {
+        self.account_id
+    }
+
+    /// Gets the tag of the order that traded, when it was placed with one
+    pub fn tag(&self) -> Option<OrderTag> {
+        self.tag
+    }
+
+    /// Gets the side of the order that traded
+    pub fn side(&self) -> OrderSide {
+        self.side
+    }
+
+    /// Gets the price the trade was made at, which is the price of the resting order
+    pub fn price(&self) -> Decimal {
+        self.price
+    }
+
+    /// Gets whether the trade filled what was left of the order, or only part of it
+    pub fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    /// Gets the quantity that changed hands, which is never zero
+    pub fn quantity(&self) -> Long {
+        self.quantity
+    }
+
+    /// Gets when the trade was made
+    pub fn timestamp(&self) -> TimestampMillis {
+        self.timestamp
+    }
+
+    /// Gets how much better than its limit price the order traded
+    pub fn price_improvement(&self) -> Decimal {
+        self.price_improvement
+    }
+
+    /// Gets the notional (price times quantity) that changed hands in the trade
+    pub fn notional(&self) -> Decimal {
+        Util::notional(self.price, self.quantity)
+    }
+}
+
+#[derive(Eq, PartialEq, PartialOrd, Ord, Hash, Clone, Debug, Copy, Serialize)]
 pub enum OrderSide {
     Bid,
     Ask,
 }
 
+impl OrderSide {
+    pub fn opposite(&self) -> Self {
+        match self {
+            OrderSide::Bid => OrderSide::Ask,
+            OrderSide::Ask => OrderSide::Bid,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy)]
 pub enum OrderType {
     Market,
     Limit,
+    /// becomes a market order once the market trades through its stop price
     Stop,
+    /// becomes a limit order at its price once the market trades through its stop price
+    StopLimit,
+    /// rests like a limit order whose price follows a reference price of the book, offset by a
+    /// fixed amount. It is repriced whenever the reference moves
+    Pegged {
+        reference: PegReference,
+        offset: Decimal,
+    },
+}
+
+/// The price of the book a pegged order follows. References are taken from the orders that
+/// are not pegged themselves, so pegged orders never chase each other
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy)]
+pub enum PegReference {
+    BestBid,
+    BestAsk,
+    /// halfway between the best bid and the best ask
+    Mid,
+}
+
+/// The price of the book stop orders are compared to their stop price with, to decide when they
+/// trigger
+#[derive(Eq, PartialEq, Clone, Debug, Copy, Default)]
+pub enum StopTrigger {
+    /// the price of the last trade of the book, so stops only trigger when the market trades
+    #[default]
+    LastTrade,
+    /// the best price on the side a stop trades against, so buy stops follow the best ask and
+    /// sell stops the best bid
+    BestPrice,
+    /// halfway between the best bid and the best ask
+    Mid,
+}
+
+/// How the orders of a pair that is split across several books are spread over them, see
+/// [Shards](super::shards::Shards)
+#[derive(PartialEq, Clone, Debug, Copy)]
+pub enum ShardPolicy {
+    /// each order goes to the next shard in turn
+    RoundRobin,
+    /// prices are split into bands of the given width, and every order priced in the same band
+    /// goes to the same shard, so orders at one price always meet
+    PriceBand { width: Decimal },
+}
+
+/// The execution policy of an order, which determines how long it stays active. This is
+/// independent of the [OrderType], which determines how the order is priced
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy, Default)]
+pub enum TimeInForce {
+    /// rests on the book until it is filled or canceled
+    #[default]
+    GoodTillCancel,
+    /// matches as much as possible immediately, any remainder is canceled
+    ImmediateOrCancel,
+    /// matches in full immediately or not at all
+    FillOrKill,
+    /// rests on the book until it is filled, canceled or the given time passes
+    GoodTillDate(TimestampMillis),
+}
+
+/// How decimal results are rounded when they have more places than a pair supports
+#[derive(Eq, PartialEq, Clone, Debug, Copy, Default)]
+pub enum RoundingStrategy {
+    /// rounds to the nearest value, with midpoints rounded away from zero
+    #[default]
+    HalfUp,
+    /// rounds to the nearest value, with midpoints rounded to the even neighbour
+    HalfEven,
+    /// drops the extra places
+    Truncate,
+}
+
+impl RoundingStrategy {
+    pub fn round_dp(&self, value: Decimal, dp: u32) -> Decimal {
+        let strategy = match self {
+            RoundingStrategy::HalfUp => rust_decimal::RoundingStrategy::MidpointAwayFromZero,
+            RoundingStrategy::HalfEven => rust_decimal::RoundingStrategy::MidpointNearestEven,
+            RoundingStrategy::Truncate => rust_decimal::RoundingStrategy::ToZero,
+        };
+        value.round_dp_with_strategy(dp, strategy)
+    }
+}
+
+/// Decides which of two crossing orders stamped with the same time has priority when a crossed
+/// book is matched, since their timestamps can't tell which arrived first. The order with
+/// priority is the maker, so the trade happens at its price
+#[derive(Eq, PartialEq, Clone, Debug, Copy, Default)]
+pub enum TieBreak {
+    /// the order that was put in the book first has priority, as is conventional
+    #[default]
+    Maker,
+    /// the order that was put in the book last has priority
+    Taker,
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
@@ -45,15 +298,243 @@ pub enum OrderStatus {
     Canceled,
     Rejected,
     Expired,
+    /// the quantity of a resting order was reduced in place, see
+    /// [OrderBook::modify_quantity](super::orderbook::OrderBook::modify_quantity)
+    Modified,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Failure {
+    /// the books are busy with another request
     EngineOverCapacity,
-    InvalidOrderForBook,
+    EnginePaused,
+    /// the kill switch of the engine is engaged, so no new orders are taken
+    KillSwitchEngaged,
+    /// the order is for a different pair than the book it was given to
+    InvalidOrderForBook(String),
     OrderNotFound(String),
     BookNotFound(String),
     OrderRejected(String),
     UnsupportedOperation(String),
     InvalidTradingPair(String),
+    NoLiquidity(String),
+    /// the engine is in a state it can't safely carry on from, eg a request failed part way
+    /// through changing a book
+    Internal(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::EngineOverCapacity => {
+                write!(f, "engine over capacity: the books are busy, try again")
+            }
+            Failure::EnginePaused => write!(f, "engine paused: no requests are being taken"),
+            Failure::KillSwitchEngaged => {
+                write!(f, "kill switch engaged: only cancels are being taken")
+            }
+            Failure::InvalidOrderForBook(reason) => write!(f, "invalid order for book: {}", reason),
+            Failure::OrderNotFound(reason) => write!(f, "order not found: {}", reason),
+            Failure::BookNotFound(reason) => write!(f, "book not found: {}", reason),
+            Failure::OrderRejected(reason) => write!(f, "order rejected: {}", reason),
+            Failure::UnsupportedOperation(reason) => {
+                write!(f, "unsupported operation: {}", reason)
+            }
+            Failure::InvalidTradingPair(reason) => write!(f, "invalid trading pair: {}", reason),
+            Failure::NoLiquidity(reason) => write!(f, "no liquidity: {}", reason),
+            Failure::Internal(reason) => write!(f, "internal error: {}", reason),
+        }
+    }
+}
+
+impl Error for Failure {}
+
+impl Failure {
+    /// Gets the kind of the failure, without its message
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            Failure::EngineOverCapacity => FailureKind::EngineOverCapacity,
+            Failure::EnginePaused => FailureKind::EnginePaused,
+            Failure::KillSwitchEngaged => FailureKind::KillSwitchEngaged,
+            Failure::InvalidOrderForBook(_) => FailureKind::InvalidOrderForBook,
+            Failure::OrderNotFound(_) => FailureKind::OrderNotFound,
+            Failure::BookNotFound(_) => FailureKind::BookNotFound,
+            Failure::OrderRejected(_) => FailureKind::OrderRejected,
+            Failure::UnsupportedOperation(_) => FailureKind::UnsupportedOperation,
+            Failure::InvalidTradingPair(_) => FailureKind::InvalidTradingPair,
+            Failure::NoLiquidity(_) => FailureKind::NoLiquidity,
+            Failure::Internal(_) => FailureKind::Internal,
+        }
+    }
+}
+
+/// The kind of a [Failure] without its message, so it can be carried in events, which are
+/// [Copy]. It's written to the log in snake case, eg `order_rejected`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    EngineOverCapacity,
+    EnginePaused,
+    KillSwitchEngaged,
+    InvalidOrderForBook,
+    OrderNotFound,
+    BookNotFound,
+    OrderRejected,
+    UnsupportedOperation,
+    InvalidTradingPair,
+    NoLiquidity,
+    Internal,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            FailureKind::EngineOverCapacity => "engine_over_capacity",
+            FailureKind::EnginePaused => "engine_paused",
+            FailureKind::KillSwitchEngaged => "kill_switch_engaged",
+            FailureKind::InvalidOrderForBook => "invalid_order_for_book",
+            FailureKind::OrderNotFound => "order_not_found",
+            FailureKind::BookNotFound => "book_not_found",
+            FailureKind::OrderRejected => "order_rejected",
+            FailureKind::UnsupportedOperation => "unsupported_operation",
+            FailureKind::InvalidTradingPair => "invalid_trading_pair",
+            FailureKind::NoLiquidity => "no_liquidity",
+            FailureKind::Internal => "internal",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    use super::{Failure, OrderSide, OrderStatus, Trade};
+
+    #[test]
+    fn a_trade_can_be_built_for_a_fixture_and_compared() {
+        let orderid = Uuid::new_v4();
+        let trade = Trade::new(
+            orderid,
+            OrderSide::Bid,
+            dec!(20.50),
+            10,
+            OrderStatus::Filled,
+            1_000,
+        )
+        .unwrap()
+        .with_account_id(7)
+        .with_tag(3)
+        .with_price_improvement(dec!(0.25));
+
+        let expected = Trade {
+            orderid,
+            account_id: Some(7),
+            tag: Some(3),
+            side: OrderSide::Bid,
+            price: dec!(20.50),
+            status: OrderStatus::Filled,
+            quantity: 10,
+            timestamp: 1_000,
+            price_improvement: dec!(0.25),
+        };
+        assert_eq!(trade, expected);
+        assert_eq!(trade.orderid(), orderid);
+        assert_eq!(trade.account_id(), Some(7));
+        assert_eq!(trade.price_improvement(), dec!(0.25));
+        assert_eq!(trade.notional(), dec!(205.00));
+        assert_ne!(
+            trade,
+            Trade {
+                quantity: 9,
+                ..expected
+            }
+        );
+    }
+
+    #[test]
+    fn a_trade_needs_a_quantity_and_a_price_above_zero() {
+        let trade = |price, quantity| {
+            Trade::new(
+                Uuid::new_v4(),
+                OrderSide::Ask,
+                price,
+                quantity,
+                OrderStatus::PartialFill,
+                0,
+            )
+        };
+        assert_eq!(
+            trade(dec!(20), 0),
+            Err(Failure::OrderRejected(
+                "A trade needs a quantity above zero".to_string()
+            ))
+        );
+        assert_eq!(
+            trade(dec!(0), 5),
+            Err(Failure::OrderRejected(
+                "A trade needs a price above zero, not 0".to_string()
+            ))
+        );
+        assert!(trade(dec!(-1), 5).is_err());
+        assert!(trade(dec!(20), 5).is_ok());
+    }
+
+    #[test]
+    fn it_should_describe_every_failure_with_its_kind_and_reason() {
+        let cases = [
+            (
+                Failure::EngineOverCapacity,
+                "engine over capacity: the books are busy, try again",
+            ),
+            (
+                Failure::EnginePaused,
+                "engine paused: no requests are being taken",
+            ),
+            (
+                Failure::InvalidOrderForBook("order for BTC/USDC".to_string()),
+                "invalid order for book: order for BTC/USDC",
+            ),
+            (
+                Failure::OrderNotFound("no order 1".to_string()),
+                "order not found: no order 1",
+            ),
+            (
+                Failure::BookNotFound("no book for ETH/USDC".to_string()),
+                "book not found: no book for ETH/USDC",
+            ),
+            (
+                Failure::OrderRejected("quantity is zero".to_string()),
+                "order rejected: quantity is zero",
+            ),
+            (
+                Failure::UnsupportedOperation("no handler".to_string()),
+                "unsupported operation: no handler",
+            ),
+            (
+                Failure::InvalidTradingPair("Unknown asset XRP".to_string()),
+                "invalid trading pair: Unknown asset XRP",
+            ),
+            (
+                Failure::NoLiquidity("empty book".to_string()),
+                "no liquidity: empty book",
+            ),
+            (
+                Failure::Internal("poisoned lock".to_string()),
+                "internal error: poisoned lock",
+            ),
+        ];
+        for (failure, expected) in cases {
+            assert_eq!(failure.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn it_should_be_usable_as_a_boxed_error() {
+        let error: Box<dyn std::error::Error> = Box::new(Failure::EnginePaused);
+        assert_eq!(
+            error.to_string(),
+            "engine paused: no requests are being taken"
+        );
+    }
 }
@@ -1,6 +1,15 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use super::types::TimestampMillis;
+use rust_decimal::Decimal;
+
+use super::types::{Long, TimestampMillis};
 
 pub struct Util;
 
@@ -11,4 +20,85 @@ impl Util {
             .unwrap()
             .as_millis()
     }
+
+    /// Gets the notional (price times quantity) of a quantity at a price. Quantities convert to
+    /// decimals exactly, and a notional too large for a decimal saturates at its maximum
+    /// instead of overflowing
+    pub fn notional(price: Decimal, quantity: Long) -> Decimal {
+        price
+            .checked_mul(Decimal::from(quantity))
+            .unwrap_or(match price.is_sign_negative() {
+                true => Decimal::MIN,
+                false => Decimal::MAX,
+            })
+    }
+}
+
+/// A source of the current time. Every timestamp the engine hands out (on orders, trades and
+/// replenished icebergs) is read from a clock, so time can be controlled in tests and backtests
+pub trait Clock: Debug + Send + Sync {
+    /// Gets the number of milliseconds since the unix epoch
+    fn now(&self) -> TimestampMillis;
+}
+
+/// Reads the time from the system. This is the clock used unless another one is configured
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> TimestampMillis {
+        Util::current_time_millis()
+    }
+}
+
+/// A clock that only moves when it is told to, for deterministic tests and replays
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Mutex<TimestampMillis>,
+}
+
+impl MockClock {
+    pub fn at(now: TimestampMillis) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Moves the clock to the given time, which may be earlier than its current time
+    pub fn set(&self, now: TimestampMillis) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    /// Moves the clock forward by the given number of milliseconds
+    pub fn advance(&self, millis: TimestampMillis) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += millis;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> TimestampMillis {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A clock that counts instead of telling the time. Every reading is one tick after the last,
+/// so the same requests are always given the same timestamps and replays match exactly
+#[derive(Debug, Default)]
+pub struct LogicalClock {
+    next: AtomicU64,
+}
+
+impl LogicalClock {
+    /// Creates a clock whose first reading is the given tick
+    pub fn starting_at(tick: u64) -> Self {
+        Self {
+            next: AtomicU64::new(tick),
+        }
+    }
+}
+
+impl Clock for LogicalClock {
+    fn now(&self) -> TimestampMillis {
+        TimestampMillis::from(self.next.fetch_add(1, Ordering::SeqCst))
+    }
 }
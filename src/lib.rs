@@ -4,9 +4,10 @@
 //! matching engines work. It isn't meant to be complete solution but
 //! it should help as a learning tool.
 //!
-//! The current implementation is single threaded and provides no
-//! interface for sending commands into the system ie it has no
-//! server or a cli
+//! The current implementation provides no interface for sending
+//! commands into the system ie it has no server or a cli. An engine
+//! can be shared across threads in an `Arc` and dispatched to from any
+//! of them
 //!
 //! Since it is still a work in progress those will be added much later
 //!
@@ -17,14 +18,14 @@
 //!     core::{
 //!         model::TradingPair,
 //!         router::{CancelOrder, PlaceOrder, Request},
-//!         types::{Asset, OrderSide, OrderType},
+//!         types::{Asset, OrderSide, OrderType, TimeInForce},
 //!         },
 //!         Engine, EngineConfig,
 //!     };
 //!     use rust_decimal_macros::dec;
 //!     use uuid::Uuid;
 //!
-//!     let mut engine = Engine::new(EngineConfig::build(vec![
+//!     let engine = Engine::new(EngineConfig::build(vec![
 //!         TradingPair::from(Asset::BTC, Asset::USDC),
 //!          TradingPair::from(Asset::BTC, Asset::USDT),
 //!      ]));
@@ -45,51 +46,1030 @@
 //! of the requests you disptach, in real time
 //!
 
+use crate::core::model::LogVerbosity;
 use crate::core::model::TradingPair;
+use crate::core::orderbook::BookConfig;
 use crate::core::orderbook::LimitOrderBook;
 use crate::core::router::Request;
+use crate::core::router::Response;
 use crate::core::router::Router;
-use log::error;
-use log::info;
+use crate::core::router::RouterConfig;
+use crate::core::router::Subscriber;
+use crate::core::sinks::EventSink;
+use crate::core::types::AccountId;
+use crate::core::types::Asset;
+use crate::core::types::Failure;
+use crate::core::types::Long;
+use crate::core::types::OrderId;
+use crate::core::types::OrderSide;
+use crate::core::types::OrderStatus;
+use crate::core::types::RoundingStrategy;
+use crate::core::types::ShardPolicy;
+use crate::core::types::StopTrigger;
+use crate::core::types::TieBreak;
+use crate::core::types::Trade;
+use crate::core::utils::Clock;
+use crate::core::utils::SystemClock;
+use log::{error, warn};
+use rust_decimal::Decimal;
 use std::collections::HashMap;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
 
 pub mod core;
 
 /// Configuration for tweaking the engine. Will have support for configuring threadpools much later
 pub struct EngineConfig {
     books: Vec<TradingPair>,
+    book_configs: HashMap<TradingPair, BookConfig>,
+    router: RouterConfig,
+    rounding: RoundingStrategy,
+    clock: Arc<dyn Clock>,
+    event_sink: Option<Box<dyn EventSink>>,
+    shards: HashMap<TradingPair, (NonZeroUsize, ShardPolicy)>,
 }
 
 impl EngineConfig {
     pub fn build(books: Vec<TradingPair>) -> Self {
-        Self { books }
+        Self {
+            books,
+            book_configs: HashMap::new(),
+            router: RouterConfig::default(),
+            rounding: RoundingStrategy::default(),
+            clock: Arc::new(SystemClock),
+            event_sink: None,
+            shards: HashMap::new(),
+        }
+    }
+
+    /// Rejects limit orders on the pair priced more than `percent` away from the last traded
+    /// price of its book
+    pub fn with_price_band(mut self, trading_pair: TradingPair, percent: Decimal) -> Self {
+        self.book_configs
+            .entry(trading_pair)
+            .or_default()
+            .price_band = Some(percent);
+        self
+    }
+
+    /// Sets the smallest price increment of the pair. Orders priced off it are rejected, and
+    /// prices derived from its book (like the spread and mid price) are rounded to it
+    pub fn with_tick_size(mut self, trading_pair: TradingPair, tick_size: Decimal) -> Self {
+        self.book_configs.entry(trading_pair).or_default().tick_size = Some(tick_size);
+        self
+    }
+
+    /// Sets the number of decimal places prices derived from the pair's book are reported
+    /// with. A tick size, when configured, takes precedence. Orders priced with more places
+    /// are rejected, and trades of the pair are always priced with exactly this many places
+    pub fn with_quote_precision(mut self, trading_pair: TradingPair, precision: u32) -> Self {
+        self.book_configs
+            .entry(trading_pair)
+            .or_default()
+            .quote_precision = Some(precision);
+        self
+    }
+
+    /// Sets whether the pair accepts market orders, and the stop orders that become market
+    /// orders. Illiquid pairs can turn them off, so orders only trade at a price they name.
+    /// Every pair accepts them by default
+    pub fn with_market_orders(mut self, trading_pair: TradingPair, allow: bool) -> Self {
+        self.book_configs
+            .entry(trading_pair)
+            .or_default()
+            .allow_market_orders = allow;
+        self
+    }
+
+    /// Caps the number of fills a single order can take on the pair, which bounds how long
+    /// matching one order can take against a deep book. Whatever is left of an order at the cap
+    /// is canceled. Orders take as many fills as they need by default
+    pub fn with_max_fills_per_order(
+        mut self,
+        trading_pair: TradingPair,
+        max_fills: NonZeroUsize,
+    ) -> Self {
+        self.book_configs
+            .entry(trading_pair)
+            .or_default()
+            .max_fills_per_order = Some(max_fills);
+        self
+    }
+
+    /// Sets whether the pair rejects an order when an order of the same account with the same
+    /// price, side and client timestamp is already open in its book, for feeds that can deliver
+    /// the same order twice. Only orders sent with a client timestamp are checked, see
+    /// [PlaceOrder::with_client_timestamp](crate::core::router::PlaceOrder::with_client_timestamp).
+    /// Duplicates are accepted by default
+    pub fn with_duplicate_rejection(mut self, trading_pair: TradingPair, reject: bool) -> Self {
+        self.book_configs
+            .entry(trading_pair)
+            .or_default()
+            .reject_duplicates = reject;
+        self
+    }
+
+    /// Chooses the price of the pair's book that stop orders are triggered by, see
+    /// [StopTrigger]. Stops are triggered by the last traded price by default
+    pub fn with_stop_trigger(mut self, trading_pair: TradingPair, trigger: StopTrigger) -> Self {
+        self.book_configs
+            .entry(trading_pair)
+            .or_default()
+            .stop_trigger = trigger;
+        self
+    }
+
+    /// Splits the pair across `count` books, each with a lock of its own, and rests its orders
+    /// in them by the policy. Orders are matched across every book, and the pair only takes
+    /// limit and market orders, cancels and book requests, see
+    /// [Router::with_shards](crate::core::router::Router::with_shards). The pair gets a book
+    /// whether or not it was built with the engine
+    pub fn with_shards(
+        mut self,
+        trading_pair: TradingPair,
+        count: NonZeroUsize,
+        policy: ShardPolicy,
+    ) -> Self {
+        self.shards.insert(trading_pair, (count, policy));
+        self
+    }
+
+    /// Chooses how reported prices, like the mid price and VWAP, are rounded on every book that
+    /// doesn't set its own rounding. Midpoints are rounded away from zero by default
+    pub fn with_rounding(mut self, rounding: RoundingStrategy) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Chooses how reported prices are rounded on the book of the pair, in place of the rounding
+    /// of the engine
+    pub fn with_pair_rounding(
+        mut self,
+        trading_pair: TradingPair,
+        rounding: RoundingStrategy,
+    ) -> Self {
+        self.book_configs.entry(trading_pair).or_default().rounding = Some(rounding);
+        self
+    }
+
+    /// Sets the clock every timestamp in the engine is read from. The system clock is used by
+    /// default
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Allows orders for the inverse of a configured pair (eg USDC/BTC when only BTC/USDC is
+    /// loaded) to be routed to the configured book. Market orders can't be placed on an inverse
+    /// pair, as they have no price to restate their quantity with. Disabled by default
+    pub fn with_inverse_pairs(mut self, allow: bool) -> Self {
+        self.router.allow_inverse_pairs = allow;
+        self
+    }
+
+    /// Holds orders placed while the engine is paused and processes them when it resumes. By
+    /// default they are rejected instead
+    pub fn with_queue_while_paused(mut self, queue: bool) -> Self {
+        self.router.queue_while_paused = queue;
+        self
+    }
+
+    /// Reports every order of a rejected request as an event carrying the kind of failure, as
+    /// well as failing the request, so subscribers see rejections too. Rejections are only
+    /// returned by default
+    pub fn with_rejection_reports(mut self, report: bool) -> Self {
+        self.router.report_rejections = report;
+        self
+    }
+
+    /// Rests orders without matching them, and only matches books when the engine ticks, see
+    /// [Engine::tick]. Orders are matched as they arrive by default
+    pub fn with_batch_matching(mut self, batches: bool) -> Self {
+        self.router.match_in_batches = batches;
+        self
+    }
+
+    /// Sets which of two crossing orders stamped with the same time has priority when a book is
+    /// matched on a tick. The order placed first has priority by default, see [TieBreak]
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.router.tie_break = tie_break;
+        self
+    }
+
+    /// Rejects orders for a larger quantity than the given maximum. Orders of any quantity are
+    /// accepted by default
+    pub fn with_max_order_quantity(mut self, max_order_quantity: Long) -> Self {
+        self.router.max_order_quantity = Some(max_order_quantity);
+        self
+    }
+
+    /// Lets up to `capacity` requests wait for the books while another request has them, each
+    /// for at most `timeout_millis`, so a moment of contention doesn't fail them. Waiting
+    /// requests are let through in the order they arrived. Only a request that finds the intake
+    /// full, or times out in it, fails with [Failure::EngineOverCapacity]. By default a request
+    /// fails as soon as the books are busy
+    pub fn with_intake(mut self, capacity: usize, timeout_millis: u64) -> Self {
+        self.router.intake_capacity = Some(capacity);
+        self.router.intake_timeout_millis = timeout_millis;
+        self
+    }
+
+    /// Sets the number of filled, canceled and expired orders the engine remembers
+    pub fn with_history_capacity(mut self, capacity: usize) -> Self {
+        self.router.history_capacity = capacity;
+        self
+    }
+
+    /// Sets the number of wash trades the engine remembers, the oldest being forgotten first
+    pub fn with_wash_trade_capacity(mut self, capacity: usize) -> Self {
+        self.router.wash_trade_capacity = capacity;
+        self
+    }
+
+    /// Chooses which order state transitions are logged. Every transition is logged by default
+    pub fn with_verbosity(mut self, verbosity: LogVerbosity) -> Self {
+        self.router.verbosity = verbosity;
+        self
+    }
+
+    /// Writes every event of the engine to the sink as well as the log, eg to send them down a
+    /// channel with a [ChannelSink](crate::core::sinks::ChannelSink). Replaces any sink set
+    /// before
+    pub fn with_event_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.event_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Gets the configuration of the book of a pair, which takes the rounding of the engine
+    /// unless it sets its own
+    fn book_config(&self, trading_pair: TradingPair) -> BookConfig {
+        let book_config = self
+            .book_configs
+            .get(&trading_pair)
+            .copied()
+            .unwrap_or_default();
+        BookConfig {
+            rounding: book_config.rounding.or(Some(self.rounding)),
+            ..book_config
+        }
     }
 }
 
-/// The driver for the order matching engine. Current implementation is single threaded
+/// The driver for the order matching engine. Requests can be dispatched from any thread, so an
+/// engine can be shared behind an [Arc]. The books are locked for one request at a time, and a
+/// request that finds them busy fails with [Failure::EngineOverCapacity] so it can be retried,
+/// unless the engine has an intake for it to wait in, see [EngineConfig::with_intake]
 pub struct Engine {
-    /// a single threaded router for manging requests to the engine
+    /// the router for managing requests to the engine, which locks the books for each request
     router: Router<LimitOrderBook>,
 }
 
 impl Engine {
-    pub fn new(config: EngineConfig) -> Self {
-        let trading_pairs = config.books;
-        let mut books: HashMap<TradingPair, LimitOrderBook> =
-            HashMap::with_capacity(trading_pairs.len());
-        for trading_pair in trading_pairs {
-            books.insert(trading_pair, LimitOrderBook::init(trading_pair));
+    pub fn new(mut config: EngineConfig) -> Self {
+        let trading_pairs = mem::take(&mut config.books);
+        if trading_pairs.is_empty() && config.shards.is_empty() {
+            warn!("The engine has no books configured, so every order placed with it will fail");
         }
-        Self {
-            router: Router::with_books(books),
+        let new_book = |trading_pair: TradingPair| {
+            LimitOrderBook::with_config(trading_pair, config.book_config(trading_pair))
+                .with_clock(config.clock.clone())
+        };
+        let books: HashMap<TradingPair, LimitOrderBook> = trading_pairs
+            .into_iter()
+            .map(|trading_pair| (trading_pair, new_book(trading_pair)))
+            .collect();
+        let mut router = Router::with_config(books, config.router);
+        for (trading_pair, (count, policy)) in &config.shards {
+            let shards = (0..count.get()).map(|_| new_book(*trading_pair)).collect();
+            router = router.with_shards(*trading_pair, shards, *policy);
+        }
+        let mut router = router.with_clock(config.clock);
+        if let Some(sink) = config.event_sink {
+            router.subscribe(Mutex::new(sink));
+        }
+        Self { router }
+    }
+
+    pub fn dispatch(&self, request: Request) -> Result<Response, Failure> {
+        let result = self.router.handle(request.clone());
+        if let Err(failure) = &result {
+            error!("Dispatching request {:?} failed {:?}", request, failure);
+        }
+        result
+    }
+
+    /// Passes every event of the engine to the subscriber as it is reported, eg to stream fills
+    /// to a UI. Each leg of a trade is its own event, see [Subscriber]
+    pub fn subscribe(&mut self, subscriber: impl Subscriber + 'static) {
+        self.router.subscribe(subscriber);
+    }
+
+    /// Moves every order resting in the book of one pair to the book of another, eg when a
+    /// pair is renamed, see [Router::migrate_pair]. A book configured like the old one is
+    /// made for the new pair when the engine has none
+    pub fn migrate_pair(&mut self, from: TradingPair, to: TradingPair) -> Result<usize, Failure> {
+        self.router
+            .migrate_pair(from, to, |book, trading_pair| book.for_pair(trading_pair))
+    }
+
+    /// Halts matching, eg for a maintenance window. Cancels are still processed while paused,
+    /// though the stops they reach only trigger once the engine resumes
+    pub fn pause(&self) {
+        self.router.pause();
+    }
+
+    /// Resumes matching, processing any orders that were held while the engine was paused
+    pub fn resume(&self) -> Vec<Result<Response, Failure>> {
+        let results = self.router.resume();
+        for failure in results.iter().filter_map(|result| result.as_ref().err()) {
+            error!("Processing a held request failed {:?}", failure);
+        }
+        results
+    }
+
+    /// Rejects every new order with [Failure::KillSwitchEngaged] until the kill switch is
+    /// disengaged, while cancels are still processed so participants can exit their orders.
+    /// Unlike pausing, no orders are held for later
+    pub fn engage_kill_switch(&self) {
+        warn!("The kill switch was engaged, new orders are rejected until it is disengaged");
+        self.router.engage_kill_switch();
+    }
+
+    /// Takes new orders again after the kill switch was engaged
+    pub fn disengage_kill_switch(&self) {
+        self.router.disengage_kill_switch();
+    }
+
+    /// Checks if the engine has at least one book to place orders in
+    pub fn is_ready(&self) -> bool {
+        !self.router.pairs().is_empty()
+    }
+
+    /// Matches the orders placed since the last tick, when the engine matches in batches
+    pub fn tick(&self) -> Result<Vec<Trade>, Failure> {
+        let result = self.router.tick();
+        if let Err(failure) = &result {
+            error!("Matching a batch failed {:?}", failure);
+        }
+        result
+    }
+
+    /// Gets the trading pairs the engine has books for, in order
+    pub fn pairs(&self) -> Vec<TradingPair> {
+        self.router.pairs()
+    }
+
+    /// Checks if the engine has a book for the trading pair, so a request can be checked before
+    /// it is dispatched
+    pub fn supports(&self, trading_pair: &TradingPair) -> bool {
+        self.router.supports(trading_pair)
+    }
+
+    /// Gets the best price on one side of the book of a pair, across every shard of a sharded
+    /// pair
+    pub fn best_price(&self, trading_pair: TradingPair, side: OrderSide) -> Option<Decimal> {
+        self.router.best_price(trading_pair, side)
+    }
+
+    /// Gets the most recent orders to be fully filled, canceled or expired, oldest first
+    pub fn terminal_orders(&self) -> Vec<(OrderId, OrderStatus)> {
+        self.router.terminal_orders()
+    }
+
+    /// Gets the profit or loss an account has realized from its trades, by quote asset, see
+    /// [Router::realized_pnl]
+    pub fn realized_pnl(&self, account_id: AccountId) -> HashMap<Asset, Decimal> {
+        self.router.realized_pnl(account_id)
+    }
+
+    /// Gets the most recent trades in which an account was both the maker and the taker
+    pub fn wash_trades(&self) -> Vec<Trade> {
+        self.router.wash_trades()
+    }
+
+    /// Validates a request and returns the trades it would produce if it were dispatched. The
+    /// books are left exactly as they were, so this is safe to call before committing an order
+    pub fn simulate(&self, request: &Request) -> Result<Vec<Trade>, Failure> {
+        self.router.simulate(request)
+    }
+
+    /// Checks that no book of the engine is in an inconsistent state, eg crossed or with orders
+    /// missing from its queues, see [LimitOrderBook::check_invariants]. Meant for tests and
+    /// fuzzing, as it locks every book while it runs
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.router.check_invariants()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use rust_decimal_macros::dec;
+
+    use crate::core::{
+        model::EngineEvent,
+        router::{CancelOrder, PlaceOrder, EVENT_LOG_TARGET},
+        sinks::ChannelSink,
+        types::{Asset, OrderSide, OrderType, TimeInForce},
+    };
+
+    use super::*;
+
+    /// Keeps every engine event written to the log so tests can inspect them
+    struct CapturingLogger {
+        records: Mutex<Vec<String>>,
+    }
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == EVENT_LOG_TARGET && metadata.level() <= Level::Info
         }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                self.records.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    /// Gets the captured records mentioning the given pair. Tests run in parallel and share the
+    /// logger, so each test should use a pair no other test logs events for
+    fn captured_for(trading_pair: TradingPair) -> Vec<String> {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Info);
+        let pair = format!("pair={}", trading_pair);
+        LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.contains(&pair))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn a_book_rounds_with_the_engine_rounding_unless_it_sets_its_own() {
+        let btc_usdc = TradingPair::from(Asset::BTC, Asset::USDC);
+        let eth_usdt = TradingPair::from(Asset::ETH, Asset::USDT);
+        let config = EngineConfig::build(vec![btc_usdc, eth_usdt])
+            .with_quote_precision(btc_usdc, 2)
+            .with_quote_precision(eth_usdt, 2)
+            .with_pair_rounding(btc_usdc, RoundingStrategy::HalfEven)
+            .with_rounding(RoundingStrategy::Truncate);
+
+        assert_eq!(
+            config.book_config(btc_usdc).rounding,
+            Some(RoundingStrategy::HalfEven)
+        );
+        assert_eq!(
+            config.book_config(eth_usdt).rounding,
+            Some(RoundingStrategy::Truncate)
+        );
+    }
+
+    #[test]
+    fn matching_orders_log_an_event_for_each_transition_and_trade() {
+        let trading_pair = TradingPair::from(Asset::DOT, Asset::ETH);
+        captured_for(trading_pair);
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+
+        for side in [OrderSide::Ask, OrderSide::Bid] {
+            let _ = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+                dec!(2.50),
+                4,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            )));
+        }
+
+        let records = captured_for(trading_pair);
+        assert_eq!(records.len(), 4);
+        assert!(records[0].starts_with("event=placed"));
+        assert!(records[0].ends_with("pair=DOT/ETH side=Ask type=Limit price=2.50 qty=4"));
+        assert!(records[1].starts_with("event=placed"));
+        assert!(records[1].ends_with("pair=DOT/ETH side=Bid type=Limit price=2.50 qty=4"));
+        assert!(records[2].starts_with("event=matched"));
+        assert!(records[2].ends_with("pair=DOT/ETH side=Bid status=Filled price=2.50 qty=4"));
+        assert!(records[3].starts_with("event=matched"));
+        assert!(records[3].ends_with("pair=DOT/ETH side=Ask status=Filled price=2.50 qty=4"));
     }
 
-    pub fn dispatch(&mut self, request: Request) {
-        if let Err(failure) = self.router.handle(request.clone()) {
-            error!("Dispatching request {:?} failed {:?}", failure, request);
-        } else {
-            info!("Request {:?} successfully dispatched", request)
+    #[test]
+    fn only_the_transitions_selected_by_the_verbosity_are_logged() {
+        let trading_pair = TradingPair::from(Asset::DOT, Asset::USDT);
+        captured_for(trading_pair);
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]).with_verbosity(
+            LogVerbosity {
+                matched: true,
+                ..LogVerbosity::none()
+            },
+        ));
+
+        for side in [OrderSide::Ask, OrderSide::Bid] {
+            let _ = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+                dec!(2.50),
+                4,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            )));
+        }
+
+        let records = captured_for(trading_pair);
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|record| record.starts_with("event=matched")));
+    }
+
+    #[test]
+    fn orders_are_rejected_while_the_engine_is_paused() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+        let place = || {
+            Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                10,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            ))
+        };
+
+        engine.pause();
+        assert_eq!(engine.dispatch(place()), Err(Failure::EnginePaused));
+
+        assert!(engine.resume().is_empty());
+        assert!(engine.dispatch(place()).is_ok());
+    }
+
+    #[test]
+    fn an_order_can_be_canceled_by_the_id_it_was_placed_with() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+        let Ok(Response::Placed { orderid, events }) =
+            engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                10,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            )))
+        else {
+            panic!("the bid should have been placed");
+        };
+        assert_eq!(events[0].orderid, orderid);
+
+        assert_eq!(
+            engine.dispatch(Request::Cancel(CancelOrder::from(orderid, trading_pair))),
+            Ok(Response::Accepted)
+        );
+        assert_eq!(
+            engine.terminal_orders().last(),
+            Some(&(orderid, OrderStatus::Canceled))
+        );
+
+        // the id is given back even for an order that leaves no events behind
+        let Ok(Response::Placed {
+            orderid: unfilled,
+            events,
+        }) = engine.dispatch(Request::PlaceOrder(
+            PlaceOrder::from(
+                dec!(20.00),
+                10,
+                OrderSide::Ask,
+                OrderType::Limit,
+                trading_pair,
+            )
+            .with_time_in_force(TimeInForce::ImmediateOrCancel),
+        ))
+        else {
+            panic!("the ask should have been placed");
+        };
+        assert!(events.is_empty());
+        assert_ne!(unfilled, orderid);
+    }
+
+    #[test]
+    fn only_cancels_are_taken_while_the_kill_switch_is_engaged() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let engine =
+            Engine::new(EngineConfig::build(vec![trading_pair]).with_queue_while_paused(true));
+        let place = |side, price| PlaceOrder::from(price, 10, side, OrderType::Limit, trading_pair);
+        let Ok(Response::Placed { events: placed, .. }) =
+            engine.dispatch(Request::PlaceOrder(place(OrderSide::Bid, dec!(20.00))))
+        else {
+            panic!("the bid should have been placed");
+        };
+
+        engine.engage_kill_switch();
+        assert_eq!(
+            engine.dispatch(Request::PlaceOrder(place(OrderSide::Ask, dec!(20.00)))),
+            Err(Failure::KillSwitchEngaged)
+        );
+        assert_eq!(
+            engine.dispatch(Request::PlaceQuote {
+                bid: place(OrderSide::Bid, dec!(19.00)),
+                ask: place(OrderSide::Ask, dec!(21.00)),
+                account_id: 1,
+            }),
+            Err(Failure::KillSwitchEngaged)
+        );
+        assert_eq!(
+            engine.dispatch(Request::Cancel(CancelOrder::from(
+                placed[0].orderid,
+                trading_pair
+            ))),
+            Ok(Response::Accepted)
+        );
+        let Ok(Response::Book(book)) = engine.dispatch(Request::GetBook { trading_pair }) else {
+            panic!("the book should have been returned");
+        };
+        assert!(book.bids.is_empty() && book.asks.is_empty());
+
+        // nothing rejected was held, even though the engine holds orders while paused
+        engine.disengage_kill_switch();
+        assert!(engine.resume().is_empty());
+        assert!(matches!(
+            engine.dispatch(Request::PlaceOrder(place(OrderSide::Ask, dec!(20.00)))),
+            Ok(Response::Placed { .. })
+        ));
+    }
+
+    #[test]
+    fn a_market_order_against_an_empty_book_reports_no_liquidity() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+
+        let result = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Bid,
+            OrderType::Market,
+            trading_pair,
+        )));
+        assert!(matches!(result, Err(Failure::NoLiquidity(_))));
+    }
+
+    #[test]
+    fn market_orders_are_only_rejected_on_pairs_that_do_not_allow_them() {
+        let liquid = TradingPair::from(Asset::BTC, Asset::USDC);
+        let illiquid = TradingPair::from(Asset::DOT, Asset::USDC);
+        let engine = Engine::new(
+            EngineConfig::build(vec![liquid, illiquid]).with_market_orders(illiquid, false),
+        );
+        let place = |order_type, side, trading_pair| {
+            Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                10,
+                side,
+                order_type,
+                trading_pair,
+            ))
+        };
+
+        for trading_pair in [liquid, illiquid] {
+            assert!(engine
+                .dispatch(place(OrderType::Limit, OrderSide::Ask, trading_pair))
+                .is_ok());
+        }
+        assert!(engine
+            .dispatch(place(OrderType::Market, OrderSide::Bid, liquid))
+            .is_ok());
+        assert_eq!(
+            engine.dispatch(place(OrderType::Market, OrderSide::Bid, illiquid)),
+            Err(Failure::OrderRejected(
+                "Market orders are not accepted for DOT/USDC".to_string()
+            ))
+        );
+        // limit orders still trade on the pair
+        let result = engine.dispatch(place(OrderType::Limit, OrderSide::Bid, illiquid));
+        assert!(matches!(
+            result,
+            Ok(Response::Placed { events, .. }) if events[0].status == OrderStatus::Filled
+        ));
+    }
+
+    fn book_of(engine: &Engine, trading_pair: TradingPair) -> (Vec<Decimal>, Vec<Decimal>) {
+        match engine.dispatch(Request::GetBook { trading_pair }) {
+            Ok(Response::Book(snapshot)) => (
+                snapshot.bids.iter().map(|level| level.price).collect(),
+                snapshot.asks.iter().map(|level| level.price).collect(),
+            ),
+            other => panic!("expected the book of {}, got {:?}", trading_pair, other),
+        }
+    }
+
+    #[test]
+    fn resting_orders_are_migrated_to_the_book_of_a_new_pair() {
+        let from = TradingPair::from(Asset::DOT, Asset::USDT);
+        let to = TradingPair::from(Asset::DOT, Asset::USDC);
+        let mut engine = Engine::new(EngineConfig::build(vec![from]));
+        for (price, side) in [
+            (dec!(19.00), OrderSide::Bid),
+            (dec!(19.50), OrderSide::Bid),
+            (dec!(21.00), OrderSide::Ask),
+        ] {
+            let place = PlaceOrder::from(price, 5, side, OrderType::Limit, from);
+            assert!(engine.dispatch(Request::PlaceOrder(place)).is_ok());
+        }
+
+        assert_eq!(engine.migrate_pair(from, to), Ok(3));
+        assert!(engine.supports(&to));
+        assert_eq!(
+            book_of(&engine, to),
+            (vec![dec!(19.50), dec!(19.00)], vec![dec!(21.00)])
+        );
+        assert_eq!(book_of(&engine, from), (vec![], vec![]));
+
+        // the migrated orders trade on the new pair
+        let ask = PlaceOrder::from(dec!(19.50), 5, OrderSide::Ask, OrderType::Limit, to);
+        let result = engine.dispatch(Request::PlaceOrder(ask));
+        assert!(matches!(
+            result,
+            Ok(Response::Placed { events, .. }) if events[0].status == OrderStatus::Filled
+        ));
+    }
+
+    #[test]
+    fn orders_are_not_migrated_to_a_book_that_already_has_orders() {
+        let from = TradingPair::from(Asset::DOT, Asset::USDT);
+        let to = TradingPair::from(Asset::DOT, Asset::USDC);
+        let mut engine = Engine::new(EngineConfig::build(vec![from, to]));
+        for trading_pair in [from, to] {
+            let place = PlaceOrder::from(
+                dec!(19.00),
+                5,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            );
+            assert!(engine.dispatch(Request::PlaceOrder(place)).is_ok());
+        }
+
+        assert!(matches!(
+            engine.migrate_pair(from, to),
+            Err(Failure::InvalidTradingPair(_))
+        ));
+        assert_eq!(book_of(&engine, from), (vec![dec!(19.00)], vec![]));
+        assert_eq!(book_of(&engine, to), (vec![dec!(19.00)], vec![]));
+    }
+
+    #[test]
+    fn the_engine_reports_the_pairs_it_has_books_for() {
+        let btc_usdc = TradingPair::from(Asset::BTC, Asset::USDC);
+        let eth_usdt = TradingPair::from(Asset::ETH, Asset::USDT);
+        let engine = Engine::new(EngineConfig::build(vec![eth_usdt, btc_usdc]));
+
+        assert_eq!(engine.pairs(), vec![btc_usdc, eth_usdt]);
+        assert!(engine.supports(&btc_usdc));
+        assert!(engine.supports(&eth_usdt));
+        assert!(!engine.supports(&btc_usdc.inverse()));
+        assert!(!engine.supports(&TradingPair::from(Asset::DOT, Asset::USDC)));
+    }
+
+    #[test]
+    fn orders_up_to_the_maximum_quantity_are_accepted() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let engine =
+            Engine::new(EngineConfig::build(vec![trading_pair]).with_max_order_quantity(100));
+        let place = |quantity| {
+            Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                quantity,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            ))
+        };
+
+        assert!(engine.dispatch(place(100)).is_ok());
+        assert_eq!(
+            engine.dispatch(place(101)),
+            Err(Failure::OrderRejected(
+                "Quantity 101 exceeds the maximum of 100 per order".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn orders_of_any_quantity_are_accepted_without_a_maximum() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDC);
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+
+        let result = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+            dec!(20.00),
+            Long::MAX,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        )));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn an_engine_without_books_is_not_ready() {
+        let engine = Engine::new(EngineConfig::build(vec![]));
+        assert!(!engine.is_ready());
+
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let result = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+            dec!(20.00),
+            10,
+            OrderSide::Bid,
+            OrderType::Limit,
+            trading_pair,
+        )));
+        assert!(matches!(result, Err(Failure::BookNotFound(_))));
+
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+        assert!(engine.is_ready());
+    }
+
+    #[test]
+    fn subscribers_see_an_order_fill_leg_by_leg() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let mut engine = Engine::new(EngineConfig::build(vec![trading_pair]));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&events);
+        engine.subscribe(move |event: &EngineEvent| received.lock().unwrap().push(*event));
+
+        for price in [dec!(20.00), dec!(20.50), dec!(21.00)] {
+            let _ = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+                price,
+                4,
+                OrderSide::Ask,
+                OrderType::Limit,
+                trading_pair,
+            )));
+        }
+        let Ok(Response::Placed { events: placed, .. }) =
+            engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+                dec!(21.00),
+                10,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            )))
+        else {
+            panic!("the bid should have been placed");
+        };
+
+        let fills: Vec<Trade> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                EngineEvent::Matched { trade, .. } if trade.orderid == placed[0].orderid => {
+                    Some(*trade)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fills.len(), 3);
+        let statuses: Vec<OrderStatus> = fills.iter().map(|fill| fill.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                OrderStatus::PartialFill,
+                OrderStatus::PartialFill,
+                OrderStatus::Filled
+            ]
+        );
+        assert_eq!(fills.iter().map(|fill| fill.quantity).sum::<Long>(), 10);
+    }
+
+    #[test]
+    fn a_rejected_order_is_reported_with_the_reason_it_failed_for() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let mut engine =
+            Engine::new(EngineConfig::build(vec![trading_pair]).with_rejection_reports(true));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&events);
+        engine.subscribe(move |event: &EngineEvent| received.lock().unwrap().push(*event));
+
+        let result = engine.dispatch(Request::PlaceOrder(
+            PlaceOrder::from(
+                dec!(0.00),
+                4,
+                OrderSide::Bid,
+                OrderType::Limit,
+                trading_pair,
+            )
+            .with_tag(9)
+            .with_client_order_id(12),
+        ));
+
+        let reason =
+            Failure::OrderRejected("Price must be greater than zero, not 0.00".to_string());
+        assert_eq!(result, Err(reason.clone()));
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        let EngineEvent::Rejected {
+            event,
+            trading_pair: rejected_pair,
+            client_order_id,
+            reason: rejected_for,
+        } = events[0]
+        else {
+            panic!("expected the order to be reported as rejected");
+        };
+        assert_eq!(event.status, OrderStatus::Rejected);
+        assert_eq!(
+            (event.at_price, event.quantity, event.tag),
+            (Some(dec!(0.00)), Some(4), Some(9))
+        );
+        assert_eq!(client_order_id, Some(12));
+        assert_eq!((rejected_pair, rejected_for), (trading_pair, reason.kind()));
+    }
+
+    #[test]
+    fn a_channel_sink_hands_events_to_another_thread() {
+        let trading_pair = TradingPair::from(Asset::BTC, Asset::USDC);
+        let (sink, receiver) = ChannelSink::channel();
+        let engine = Engine::new(EngineConfig::build(vec![trading_pair]).with_event_sink(sink));
+        let consumer = std::thread::spawn(move || receiver.iter().collect::<Vec<EngineEvent>>());
+
+        for side in [OrderSide::Ask, OrderSide::Bid] {
+            let _ = engine.dispatch(Request::PlaceOrder(PlaceOrder::from(
+                dec!(20.00),
+                4,
+                side,
+                OrderType::Limit,
+                trading_pair,
+            )));
+        }
+        // the channel closes once the engine, and so its sink, is dropped
+        drop(engine);
+
+        let events = consumer.join().unwrap();
+        let placed = events
+            .iter()
+            .filter(|event| matches!(event, EngineEvent::Placed(_)))
+            .count();
+        let matched = events
+            .iter()
+            .filter(|event| matches!(event, EngineEvent::Matched { .. }))
+            .count();
+        assert_eq!((placed, matched), (2, 2));
+    }
+
+    #[test]
+    fn requests_can_be_dispatched_from_many_threads_through_a_shared_engine() {
+        let trading_pair = TradingPair::from(Asset::ETH, Asset::USDT);
+        let engine = Arc::new(Engine::new(EngineConfig::build(vec![trading_pair])));
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let engine = Arc::clone(&engine);
+                std::thread::spawn(move || {
+                    let side = match thread % 2 {
+                        0 => OrderSide::Bid,
+                        _ => OrderSide::Ask,
+                    };
+                    let price = match side {
+                        OrderSide::Bid => dec!(10),
+                        OrderSide::Ask => dec!(20),
+                    };
+                    for _ in 0..25 {
+                        let request = Request::PlaceOrder(PlaceOrder::from(
+                            price,
+                            1,
+                            side,
+                            OrderType::Limit,
+                            trading_pair,
+                        ));
+                        // the books only take one request at a time, so busy ones are retried
+                        while let Err(Failure::EngineOverCapacity) =
+                            engine.dispatch(request.clone())
+                        {
+                            std::thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let Ok(Response::Book(book)) = engine.dispatch(Request::GetBook { trading_pair }) else {
+            panic!("the book should have been returned");
+        };
+        for levels in [book.bids, book.asks] {
+            assert_eq!(levels.len(), 1);
+            assert_eq!((levels[0].quantity, levels[0].orders), (50, 50));
         }
     }
 }